@@ -0,0 +1,128 @@
+//! Throughput of sending a publisher's packets to many subscribers over UDP, serially from
+//! the media thread versus through FanOut send workers as examples/sync_signal does.
+//!
+//! Run with `cargo bench --bench fan_out`.
+
+use bytes::BytesMut;
+use retty::transport::{TaggedBytesMut, TransportContext};
+use sfu::FanOut;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const SUBSCRIBERS: usize = 256;
+const ROUNDS: usize = 100;
+const PAYLOAD_SIZE: usize = 1200;
+const ITERATIONS: usize = 5;
+
+/// one round is a publisher packet fanned out to every subscriber, as the gateway does
+fn outbound_messages(local_addr: SocketAddr, peer_addrs: &[SocketAddr]) -> Vec<TaggedBytesMut> {
+    let now = Instant::now();
+    (0..ROUNDS)
+        .flat_map(|_| {
+            peer_addrs.iter().map(move |&peer_addr| TaggedBytesMut {
+                now,
+                transport: TransportContext {
+                    local_addr,
+                    peer_addr,
+                    ecn: None,
+                },
+                message: BytesMut::from(&[0u8; PAYLOAD_SIZE][..]),
+            })
+        })
+        .collect()
+}
+
+fn send_serial(socket: &UdpSocket, messages: Vec<TaggedBytesMut>) -> anyhow::Result<()> {
+    for message in messages {
+        socket.send_to(&message.message, message.transport.peer_addr)?;
+    }
+    Ok(())
+}
+
+fn send_fan_out(
+    socket: &UdpSocket,
+    workers: usize,
+    messages: Vec<TaggedBytesMut>,
+) -> anyhow::Result<()> {
+    let fan_out = FanOut::new(workers)?;
+    let batches = fan_out.partition(messages);
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        let handles: Vec<_> = batches
+            .into_iter()
+            .map(|batch| {
+                let socket = socket.try_clone()?;
+                Ok(scope.spawn(move || -> std::io::Result<()> {
+                    for message in batch {
+                        socket.send_to(&message.message, message.transport.peer_addr)?;
+                    }
+                    Ok(())
+                }))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        for handle in handles {
+            handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("fan-out worker panicked"))??;
+        }
+        Ok(())
+    })
+}
+
+/// best elapsed time of a few iterations, each of which sends freshly built messages
+fn bench(
+    name: &str,
+    local_addr: SocketAddr,
+    peer_addrs: &[SocketAddr],
+    mut send: impl FnMut(Vec<TaggedBytesMut>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        let messages = outbound_messages(local_addr, peer_addrs);
+        let start = Instant::now();
+        send(messages)?;
+        best = best.min(start.elapsed());
+    }
+    let packets = SUBSCRIBERS * ROUNDS;
+    println!(
+        "{:<12} {:>8} packets in {:>10.3?}, {:>12.0} packets/s",
+        name,
+        packets,
+        best,
+        packets as f64 / best.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let socket = UdpSocket::bind("127.0.0.1:0")?;
+    let local_addr = socket.local_addr()?;
+    // subscribers never read, so their datagrams are dropped once their buffers are full
+    let subscribers = (0..SUBSCRIBERS)
+        .map(|_| UdpSocket::bind("127.0.0.1:0"))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let peer_addrs = subscribers
+        .iter()
+        .map(UdpSocket::local_addr)
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    println!(
+        "{} subscribers, {} rounds of {} bytes, {} available cores",
+        SUBSCRIBERS,
+        ROUNDS,
+        PAYLOAD_SIZE,
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+    );
+    bench("serial", local_addr, &peer_addrs, |messages| {
+        send_serial(&socket, messages)
+    })?;
+    for workers in [1, 2, 4, 8] {
+        bench(
+            &format!("fan_out({})", workers),
+            local_addr,
+            &peer_addrs,
+            |messages| send_fan_out(&socket, workers, messages),
+        )?;
+    }
+
+    Ok(())
+}
@@ -24,7 +24,7 @@ use waitgroup::{WaitGroup, Worker};
 
 use sfu::{
     DataChannelHandler, DemuxerHandler, DtlsHandler, ExceptionHandler, GatewayHandler,
-    InterceptorHandler, RTCCertificate, SctpHandler, ServerConfig, ServerStates, SrtpHandler,
+    InterceptorHandler, RTCCertificate, SctpHandler, ServerConfig, ShardConfig, SrtpHandler,
     StunHandler,
 };
 
@@ -93,7 +93,8 @@ fn init_meter_provider(
             let _worker = worker;
             let exporter = MetricsExporterBuilder::default()
                 .with_encoder(|writer, data| {
-                    Ok(serde_json::to_writer_pretty(writer, &data).unwrap())
+                    serde_json::to_writer_pretty(writer, &data).unwrap();
+                    Ok(())
                 })
                 .build();
             let reader = PeriodicReader::builder(exporter, runtime::TokioCurrentThread)
@@ -111,8 +112,7 @@ fn init_meter_provider(
         });
     });
 
-    let meter_provider = rx.recv().unwrap();
-    meter_provider
+    rx.recv().unwrap()
 }
 
 fn main() -> anyhow::Result<()> {
@@ -169,7 +169,15 @@ fn main() -> anyhow::Result<()> {
     let wait_group = WaitGroup::new();
     let meter_provider = init_meter_provider(stop_rx.clone(), wait_group.worker());
 
-    for port in media_ports {
+    // one shard of sessions for each media port, which is owned by the port's executor
+    let local_addrs = media_ports
+        .iter()
+        .map(|port| SocketAddr::from_str(&format!("{}:{}", cli.host, port)))
+        .collect::<Result<Vec<SocketAddr>, _>>()?;
+    let shard_configs = ShardConfig::new_shards(server_config, &local_addrs)?;
+
+    for shard_config in shard_configs {
+        let port = shard_config.local_addr().port();
         let worker = wait_group.worker();
         let host = cli.host.clone();
         let meter_provider = meter_provider.clone();
@@ -177,7 +185,6 @@ fn main() -> anyhow::Result<()> {
         let (signaling_tx, signaling_rx) = smol::channel::unbounded::<SignalingMessage>();
         media_port_thread_map.insert(port, signaling_tx);
 
-        let server_config = server_config.clone();
         LocalExecutorBuilder::new()
             .name(format!("media_port_{}", port).as_str())
             .core_id(core_affinity::CoreId {
@@ -185,11 +192,9 @@ fn main() -> anyhow::Result<()> {
             })
             .spawn(move || async move {
                 let _worker = worker;
-                let local_addr = SocketAddr::from_str(&format!("{}:{}", host, port)).unwrap();
+                let local_addr = shard_config.local_addr();
                 let server_states = Rc::new(RefCell::new(
-						ServerStates::new(server_config, local_addr,
-						meter_provider.meter(format!("{}:{}", host, port)),
-						).unwrap()
+						shard_config.build(meter_provider.meter(format!("{}:{}", host, port))).unwrap()
 					));
 
                 info!("listening {}:{}...", host, port);
@@ -7,13 +7,14 @@ use futures::channel::oneshot::Sender;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 use log::{debug, error, info};
-use sfu::{RTCSessionDescription, ServerStates};
+use sfu::{RTCSessionDescription, ServerStates, ShardConfig};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::io::{Error, ErrorKind};
+use std::collections::{HashMap, VecDeque};
+use std::io::Error;
 use std::net::SocketAddr;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio_util::codec::{BytesCodec, FramedRead};
 
@@ -30,6 +31,12 @@ pub enum SignalingProtocolMessage {
     Join {
         session_id: u64,
     },
+    Joined {
+        session_id: u64,
+        endpoint_id: u64,
+        ice_ufrag: String,
+        ice_pwd: String,
+    },
     Offer {
         session_id: u64,
         endpoint_id: u64,
@@ -56,9 +63,52 @@ pub struct SignalingMessage {
     pub response_tx: Sender<SignalingProtocolMessage>,
 }
 
+/// how long an HTTP long-poll request for session events is parked before responding empty
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SessionEvent is pushed to an endpoint by the signaling server,
+/// so that it does not rely on its data channel being open
+#[derive(Debug, Clone)]
+pub enum SessionEvent {
+    /// renegotiation offer for the endpoint, e.g., after a new publisher joined
+    Offer { offer_sdp: Bytes },
+    /// another endpoint joined the session
+    PeerJoined { endpoint_id: u64 },
+    /// another endpoint left the session
+    PeerLeft { endpoint_id: u64 },
+}
+
+impl SessionEvent {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            SessionEvent::Offer { offer_sdp } => serde_json::json!({
+                "type": "offer",
+                "sdp": String::from_utf8_lossy(offer_sdp),
+            }),
+            SessionEvent::PeerJoined { endpoint_id } => serde_json::json!({
+                "type": "peer_joined",
+                "endpoint_id": endpoint_id,
+            }),
+            SessionEvent::PeerLeft { endpoint_id } => serde_json::json!({
+                "type": "peer_left",
+                "endpoint_id": endpoint_id,
+            }),
+        }
+    }
+}
+
+#[derive(Default)]
+struct EndpointEvents {
+    queued: VecDeque<SessionEvent>,
+    long_poll_tx: Option<Sender<Vec<SessionEvent>>>,
+}
+
+type EventQueues = Arc<Mutex<HashMap<(u64, u64), EndpointEvents>>>;
+
 pub struct SignalingServer {
     signal_addr: SocketAddr,
     media_port_thread_map: Arc<HashMap<u16, smol::channel::Sender<SignalingMessage>>>,
+    event_queues: EventQueues,
 }
 
 impl SignalingServer {
@@ -69,22 +119,33 @@ impl SignalingServer {
         Self {
             signal_addr,
             media_port_thread_map: Arc::new(media_port_thread_map),
+            event_queues: Arc::new(Mutex::new(HashMap::new())),
         }
     }
 
+    /// push_event sends the event to the endpoint's pending long-poll request if there is one,
+    /// otherwise queues it for the endpoint's next long-poll request to /events/session_id/endpoint_id
+    pub fn push_event(&self, session_id: u64, endpoint_id: u64, event: SessionEvent) {
+        push_event(&self.event_queues, session_id, endpoint_id, event);
+    }
+
     /// http_sdp_server starts a HTTP Server that consumes SDPs
     pub async fn run(&self, mut stop_rx: Receiver<()>) -> Receiver<()> {
         let (done_tx, done_rx) = broadcast(1);
         let signal_addr = self.signal_addr;
         let media_port_thread_map = self.media_port_thread_map.clone();
+        let event_queues = self.event_queues.clone();
         tokio::spawn(async move {
             let service = make_service_fn(move |_| {
                 let media_port_thread_map = media_port_thread_map.clone();
+                let event_queues = event_queues.clone();
                 async move {
                     Ok::<_, hyper::Error>(service_fn(move |req| {
                         let media_port_thread_map = media_port_thread_map.clone();
+                        let event_queues = event_queues.clone();
                         async move {
-                            let resp = remote_handler(req, media_port_thread_map).await?;
+                            let resp =
+                                remote_handler(req, media_port_thread_map, event_queues).await?;
                             Ok::<_, hyper::Error>(resp)
                         }
                     }))
@@ -112,10 +173,67 @@ impl SignalingServer {
     }
 }
 
+fn push_event(event_queues: &EventQueues, session_id: u64, endpoint_id: u64, event: SessionEvent) {
+    let mut event_queues = event_queues.lock().unwrap();
+    let endpoint_events = event_queues.entry((session_id, endpoint_id)).or_default();
+    // TODO: send event over endpoint's WebSocket connection once signaling server accepts WebSocket upgrade
+    if let Some(long_poll_tx) = endpoint_events.long_poll_tx.take() {
+        // the long-poll request may have timed out or its connection was closed
+        if let Err(events) = long_poll_tx.send(vec![event]) {
+            endpoint_events.queued.extend(events);
+        }
+    } else {
+        endpoint_events.queued.push_back(event);
+    }
+}
+
+// HTTP long-poll for session events
+async fn events_handler(
+    event_queues: EventQueues,
+    session_id: u64,
+    endpoint_id: u64,
+) -> Result<Response<Body>, hyper::Error> {
+    let long_poll_rx = {
+        let mut event_queues = event_queues.lock().unwrap();
+        let endpoint_events = event_queues.entry((session_id, endpoint_id)).or_default();
+        if endpoint_events.queued.is_empty() {
+            let (long_poll_tx, long_poll_rx) = futures::channel::oneshot::channel();
+            endpoint_events.long_poll_tx = Some(long_poll_tx);
+            Some(long_poll_rx)
+        } else {
+            None
+        }
+    };
+
+    let events: Vec<SessionEvent> = if let Some(long_poll_rx) = long_poll_rx {
+        match tokio::time::timeout(LONG_POLL_TIMEOUT, long_poll_rx).await {
+            Ok(Ok(events)) => events,
+            _ => vec![],
+        }
+    } else {
+        let mut event_queues = event_queues.lock().unwrap();
+        event_queues
+            .get_mut(&(session_id, endpoint_id))
+            .map(|endpoint_events| endpoint_events.queued.drain(..).collect())
+            .unwrap_or_default()
+    };
+
+    if events.is_empty() {
+        let mut response = Response::default();
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        return Ok(response);
+    }
+    let events: Vec<serde_json::Value> = events.iter().map(|event| event.to_json()).collect();
+    let mut response = Response::new(Body::from(serde_json::Value::Array(events).to_string()));
+    *response.status_mut() = StatusCode::OK;
+    Ok(response)
+}
+
 // HTTP Listener to get sdp
 async fn remote_handler(
     req: Request<Body>,
     media_port_thread_map: Arc<HashMap<u16, smol::channel::Sender<SignalingMessage>>>,
+    event_queues: EventQueues,
 ) -> Result<Response<Body>, hyper::Error> {
     match (req.method(), req.uri().path()) {
         (&Method::GET, "/") | (&Method::GET, "/index.html") => {
@@ -137,7 +255,10 @@ async fn remote_handler(
     let path: Vec<&str> = req.uri().path().split('/').collect();
     if path.len() < 3
         || path[2].parse::<u64>().is_err()
-        || ((path[1] == "offer" || path[1] == "answer" || path[1] == "leave")
+        || ((path[1] == "offer"
+            || path[1] == "answer"
+            || path[1] == "leave"
+            || path[1] == "events")
             && (path.len() < 4 || path[3].parse::<u64>().is_err()))
     {
         let mut response = Response::new(Body::empty());
@@ -145,10 +266,15 @@ async fn remote_handler(
         return Ok(response);
     }
     let session_id = path[2].parse::<u64>().unwrap();
-    let mut sorted_ports: Vec<u16> = media_port_thread_map.keys().map(|x| *x).collect();
+    if let (&Method::GET, "events") = (req.method(), path[1]) {
+        debug!("remote_handler receive from /events/session_id/endpoint_id");
+        let endpoint_id = path[3].parse::<u64>().unwrap();
+        return events_handler(event_queues, session_id, endpoint_id).await;
+    }
+    let mut sorted_ports: Vec<u16> = media_port_thread_map.keys().copied().collect();
     sorted_ports.sort();
     assert!(!sorted_ports.is_empty());
-    let port = sorted_ports[(session_id as usize) % sorted_ports.len()];
+    let port = sorted_ports[ShardConfig::shard_index(session_id, sorted_ports.len())];
     let event_base = media_port_thread_map.get(&port).unwrap();
     let (response_tx, response_rx) =
         futures::channel::oneshot::channel::<SignalingProtocolMessage>();
@@ -167,12 +293,20 @@ async fn remote_handler(
             {
                 if let Ok(response) = response_rx.await {
                     match response {
-                        SignalingProtocolMessage::Ok {
+                        SignalingProtocolMessage::Joined {
                             session_id: _,
                             endpoint_id,
+                            ice_ufrag,
+                            ice_pwd,
                         } => {
-                            let mut response =
-                                Response::new(Body::from(format!("{}", endpoint_id)));
+                            let mut response = Response::new(Body::from(
+                                serde_json::json!({
+                                    "endpoint_id": endpoint_id,
+                                    "ice_ufrag": ice_ufrag,
+                                    "ice_pwd": ice_pwd,
+                                })
+                                .to_string(),
+                            ));
                             *response.status_mut() = StatusCode::OK;
                             return Ok(response);
                         }
@@ -284,6 +418,11 @@ async fn remote_handler(
             debug!("remote_handler receive from /leave/session_id/endpoint_id");
 
             let endpoint_id = path[3].parse::<u64>().unwrap();
+            // dropping its events also wakes up the pending long-poll request, if any
+            event_queues
+                .lock()
+                .unwrap()
+                .remove(&(session_id, endpoint_id));
 
             if event_base
                 .send(SignalingMessage {
@@ -339,19 +478,7 @@ pub fn handle_signaling_message(
 ) -> Result<()> {
     match signaling_msg.request {
         SignalingProtocolMessage::Join { session_id } => {
-            let endpoint_id: u64 = rand::random();
-            Ok(signaling_msg
-                .response_tx
-                .send(SignalingProtocolMessage::Ok {
-                    session_id,
-                    endpoint_id,
-                })
-                .map_err(|_| {
-                    Error::new(
-                        ErrorKind::Other,
-                        "failed to send back signaling message response".to_string(),
-                    )
-                })?)
+            handle_join_message(server_states, session_id, signaling_msg.response_tx)
         }
         SignalingProtocolMessage::Offer {
             session_id,
@@ -388,6 +515,12 @@ pub fn handle_signaling_message(
             session_id,
             endpoint_id,
         }
+        | SignalingProtocolMessage::Joined {
+            session_id,
+            endpoint_id,
+            ice_ufrag: _,
+            ice_pwd: _,
+        }
         | SignalingProtocolMessage::Err {
             session_id,
             endpoint_id,
@@ -405,10 +538,42 @@ pub fn handle_signaling_message(
                 reason: Bytes::from("Invalid Request"),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+    }
+}
+
+fn handle_join_message(
+    server_states: &Rc<RefCell<ServerStates>>,
+    session_id: u64,
+    response_tx: Sender<SignalingProtocolMessage>,
+) -> Result<()> {
+    let endpoint_id: u64 = rand::random();
+    let try_handle = || -> Result<(String, String)> {
+        info!("handle_join_message: {}/{}", session_id, endpoint_id);
+        let mut server_states = server_states.borrow_mut();
+        Ok(server_states.prepare_candidate(session_id, endpoint_id)?)
+    };
+
+    match try_handle() {
+        Ok((ice_ufrag, ice_pwd)) => Ok(response_tx
+            .send(SignalingProtocolMessage::Joined {
+                session_id,
+                endpoint_id,
+                ice_ufrag,
+                ice_pwd,
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
+            })?),
+        Err(err) => Ok(response_tx
+            .send(SignalingProtocolMessage::Err {
+                session_id,
+                endpoint_id,
+                reason: Bytes::from(err.to_string()),
+            })
+            .map_err(|_| {
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -443,10 +608,7 @@ fn handle_offer_message(
                 answer_sdp,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -455,10 +617,7 @@ fn handle_offer_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -487,10 +646,7 @@ fn handle_answer_message(
                 endpoint_id,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -499,10 +655,7 @@ fn handle_answer_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -525,10 +678,7 @@ fn handle_leave_message(
                 endpoint_id,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -537,10 +687,7 @@ fn handle_leave_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -6,10 +6,10 @@ use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
 use opentelemetry_sdk::{runtime, Resource};
 use opentelemetry_stdout::MetricsExporterBuilder;
 use rouille::Server;
-use sfu::{RTCCertificate, ServerConfig};
+use sfu::{RTCCertificate, ServerConfig, ShardConfig};
 use std::collections::HashMap;
 use std::io::Write;
-use std::net::{IpAddr, UdpSocket};
+use std::net::{IpAddr, SocketAddr, UdpSocket};
 use std::str::FromStr;
 use std::sync::mpsc::{self};
 use std::sync::Arc;
@@ -58,6 +58,9 @@ struct Cli {
     #[arg(long, default_value_t = 3495)]
     media_port_max: u16,
 
+    #[arg(long, default_value_t = 1)]
+    fan_out_workers: usize,
+
     #[arg(short, long)]
     force_local_loop: bool,
     #[arg(short, long)]
@@ -84,7 +87,8 @@ fn init_meter_provider(
             let worker = wait_group.add(1);
             let exporter = MetricsExporterBuilder::default()
                 .with_encoder(|writer, data| {
-                    Ok(serde_json::to_writer_pretty(writer, &data).unwrap())
+                    serde_json::to_writer_pretty(writer, &data).unwrap();
+                    Ok(())
                 })
                 .build();
             let reader = PeriodicReader::builder(exporter, runtime::TokioCurrentThread)
@@ -103,8 +107,7 @@ fn init_meter_provider(
         });
     });
 
-    let meter_provider = rx.recv().unwrap();
-    meter_provider
+    rx.recv().unwrap()
 }
 
 fn main() -> anyhow::Result<()> {
@@ -167,7 +170,15 @@ fn main() -> anyhow::Result<()> {
     let wait_group = WaitGroup::new();
     let meter_provider = init_meter_provider(stop_meter_rx, wait_group.clone());
 
-    for port in media_ports {
+    // one shard of sessions for each media port, which is owned by the port's thread
+    let local_addrs: Vec<SocketAddr> = media_ports
+        .iter()
+        .map(|&port| SocketAddr::new(host_addr, port))
+        .collect();
+    let shard_configs = ShardConfig::new_shards(server_config, &local_addrs)?;
+
+    for shard_config in shard_configs {
+        let port = shard_config.local_addr().port();
         let worker = wait_group.add(1);
         let stop_rx = stop_rx.clone();
         let (signaling_tx, signaling_rx) = mpsc::sync_channel(1);
@@ -175,15 +186,21 @@ fn main() -> anyhow::Result<()> {
         // Spin up a UDP socket for the RTC. All WebRTC traffic is going to be multiplexed over this single
         // server socket. Clients are identified via their respective remote (UDP) socket address.
         let socket = UdpSocket::bind(format!("{host_addr}:{port}"))
-            .expect(&format!("binding to {host_addr}:{port}"));
+            .unwrap_or_else(|_| panic!("binding to {host_addr}:{port}"));
 
         media_port_thread_map.insert(port, signaling_tx);
-        let server_config = server_config.clone();
         let meter_provider = meter_provider.clone();
+        let fan_out_workers = cli.fan_out_workers;
         // The run loop is on a separate thread to the web server.
         std::thread::spawn(move || {
-            if let Err(err) = sync_run(stop_rx, socket, signaling_rx, server_config, meter_provider)
-            {
+            if let Err(err) = sync_run(
+                stop_rx,
+                socket,
+                signaling_rx,
+                shard_config,
+                meter_provider,
+                fan_out_workers,
+            ) {
                 eprintln!("run_sfu got error: {}", err);
             }
             worker.done();
@@ -8,9 +8,9 @@ use retty::channel::{InboundPipeline, Pipeline};
 use retty::transport::{TaggedBytesMut, TransportContext};
 use rouille::{Request, Response, ResponseBody};
 use sfu::{
-    DataChannelHandler, DemuxerHandler, DtlsHandler, ExceptionHandler, GatewayHandler,
-    InterceptorHandler, RTCSessionDescription, SctpHandler, ServerConfig, ServerStates,
-    SrtpHandler, StunHandler,
+    DTLSMessageEvent, DataChannelHandler, DemuxerHandler, DtlsHandler, ExceptionHandler, FanOut,
+    GatewayHandler, InterceptorHandler, MessageEvent, RTCSessionDescription, RTPMessageEvent,
+    SctpHandler, ServerStates, ShardConfig, SrtpHandler, StunHandler,
 };
 use std::cell::RefCell;
 use std::collections::HashMap;
@@ -37,10 +37,10 @@ pub fn web_request(
     }
 
     let session_id = path[2].parse::<u64>().unwrap();
-    let mut sorted_ports: Vec<u16> = media_port_thread_map.keys().map(|x| *x).collect();
+    let mut sorted_ports: Vec<u16> = media_port_thread_map.keys().copied().collect();
     sorted_ports.sort();
     assert!(!sorted_ports.is_empty());
-    let port = sorted_ports[(session_id as usize) % sorted_ports.len()];
+    let port = sorted_ports[ShardConfig::shard_index(session_id, sorted_ports.len())];
     let tx = media_port_thread_map.get(&port);
 
     // Expected POST SDP Offers.
@@ -96,20 +96,43 @@ pub fn sync_run(
     stop_rx: crossbeam_channel::Receiver<()>,
     socket: UdpSocket,
     rx: Receiver<SignalingMessage>,
-    server_config: Arc<ServerConfig>,
+    shard_config: ShardConfig,
     meter_provider: SdkMeterProvider,
+    fan_out_workers: usize,
 ) -> anyhow::Result<()> {
-    let server_states = Rc::new(RefCell::new(ServerStates::new(
-        server_config,
-        socket.local_addr()?,
-        meter_provider.meter(format!("{}", socket.local_addr()?)),
-    )?));
+    let mut buf = vec![0; shard_config.server_config().udp_recv_buffer_size()];
+    // the shard is built and owned by this media thread
+    let server_states = Rc::new(RefCell::new(
+        shard_config.build(meter_provider.meter(format!("{}", socket.local_addr()?)))?,
+    ));
 
     println!("listening {}...", socket.local_addr()?);
 
     let pipeline = build_pipeline(socket.local_addr()?, server_states.clone());
 
-    let mut buf = vec![0; 2000];
+    // with more than one fan-out worker, outbound datagrams are sent by worker threads,
+    // each of which owns a slice of peers, instead of the media thread
+    let fan_out = FanOut::new(fan_out_workers)?;
+    let mut fan_out_txs = vec![];
+    if fan_out.workers() > 1 {
+        for _ in 0..fan_out.workers() {
+            let (fan_out_tx, fan_out_rx) = crossbeam_channel::unbounded::<Vec<TaggedBytesMut>>();
+            let socket = socket.try_clone()?;
+            std::thread::spawn(move || {
+                // exit once the media thread drops its sender
+                for transmits in fan_out_rx {
+                    for transmit in transmits {
+                        if let Err(err) =
+                            socket.send_to(&transmit.message, transmit.transport.peer_addr)
+                        {
+                            error!("fan-out send_to got error:{}", err);
+                        }
+                    }
+                }
+            });
+            fan_out_txs.push(fan_out_tx);
+        }
+    }
 
     pipeline.transport_active();
     loop {
@@ -122,7 +145,11 @@ pub fn sync_run(
             }
         };
 
-        write_socket_output(&socket, &pipeline)?;
+        if fan_out_txs.is_empty() {
+            write_socket_output(&socket, &pipeline)?;
+        } else {
+            fan_out_socket_output(&fan_out, &fan_out_txs, &pipeline)?;
+        }
 
         // Spawn new incoming signal message from the signaling server thread.
         if let Ok(signal_message) = rx.try_recv() {
@@ -155,6 +182,16 @@ pub fn sync_run(
         // Drive time forward in all clients.
         pipeline.handle_timeout(Instant::now());
     }
+
+    // tell all clients the server is going away before exiting
+    let closing_messages = server_states.borrow_mut().close();
+    for transmit in closing_messages {
+        if let MessageEvent::Dtls(DTLSMessageEvent::Raw(message))
+        | MessageEvent::Rtp(RTPMessageEvent::Raw(message)) = transmit.message
+        {
+            socket.send_to(&message, transmit.transport.peer_addr)?;
+        }
+    }
     pipeline.transport_inactive();
 
     println!(
@@ -175,19 +212,32 @@ fn write_socket_output(
     Ok(())
 }
 
+fn fan_out_socket_output(
+    fan_out: &FanOut,
+    fan_out_txs: &[crossbeam_channel::Sender<Vec<TaggedBytesMut>>],
+    pipeline: &Rc<Pipeline<TaggedBytesMut, TaggedBytesMut>>,
+) -> anyhow::Result<()> {
+    let transmits = std::iter::from_fn(|| pipeline.poll_transmit());
+    for (fan_out_tx, batch) in fan_out_txs.iter().zip(fan_out.partition(transmits)) {
+        if !batch.is_empty() {
+            fan_out_tx.send(batch)?;
+        }
+    }
+
+    Ok(())
+}
+
 fn read_socket_input(socket: &UdpSocket, buf: &mut [u8]) -> Option<TaggedBytesMut> {
     match socket.recv_from(buf) {
-        Ok((n, peer_addr)) => {
-            return Some(TaggedBytesMut {
-                now: Instant::now(),
-                transport: TransportContext {
-                    local_addr: socket.local_addr().unwrap(),
-                    peer_addr,
-                    ecn: None,
-                },
-                message: BytesMut::from(&buf[..n]),
-            });
-        }
+        Ok((n, peer_addr)) => Some(TaggedBytesMut {
+            now: Instant::now(),
+            transport: TransportContext {
+                local_addr: socket.local_addr().unwrap(),
+                peer_addr,
+                ecn: None,
+            },
+            message: BytesMut::from(&buf[..n]),
+        }),
 
         Err(e) => match e.kind() {
             // Expected error for set_read_timeout(). One for windows, one for the rest.
@@ -203,7 +253,7 @@ fn build_pipeline(
 ) -> Rc<Pipeline<TaggedBytesMut, TaggedBytesMut>> {
     let pipeline: Pipeline<TaggedBytesMut, TaggedBytesMut> = Pipeline::new();
 
-    let demuxer_handler = DemuxerHandler::new();
+    let demuxer_handler = DemuxerHandler::with_server_states(Rc::clone(&server_states));
     let stun_handler = StunHandler::new();
     // DTLS
     let dtls_handler = DtlsHandler::new(local_addr, Rc::clone(&server_states));
@@ -309,10 +359,7 @@ pub fn handle_signaling_message(
                 reason: Bytes::from("Invalid Request"),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -349,10 +396,7 @@ fn handle_offer_message(
                 answer_sdp,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -361,10 +405,7 @@ fn handle_offer_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -387,10 +428,7 @@ fn handle_leave_message(
                 endpoint_id,
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
         Err(err) => Ok(response_tx
             .send(SignalingProtocolMessage::Err {
@@ -399,10 +437,7 @@ fn handle_leave_message(
                 reason: Bytes::from(err.to_string()),
             })
             .map_err(|_| {
-                Error::new(
-                    ErrorKind::Other,
-                    "failed to send back signaling message response".to_string(),
-                )
+                Error::other("failed to send back signaling message response".to_string())
             })?),
     }
 }
@@ -0,0 +1,33 @@
+use std::net::SocketAddr;
+
+/// ZrtpMode determines how ZRTP packets, i.e., with the first byte in [16..19], are handled,
+/// <https://tools.ietf.org/html/rfc7983#section-7>
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ZrtpMode {
+    /// drop ZRTP packets silently
+    Drop,
+    /// forward ZRTP packets to a ZRTP proxy at the given address
+    Forward(SocketAddr),
+    /// log and drop ZRTP packets
+    #[default]
+    Warn,
+}
+
+/// DemuxerConfig provides customized parameters for DemuxerHandler
+#[derive(Default, Debug, Clone)]
+pub struct DemuxerConfig {
+    pub(crate) zrtp_mode: ZrtpMode,
+}
+
+impl DemuxerConfig {
+    /// create new demuxer config
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// build with handling of ZRTP packets
+    pub fn with_zrtp_mode(mut self, zrtp_mode: ZrtpMode) -> Self {
+        self.zrtp_mode = zrtp_mode;
+        self
+    }
+}
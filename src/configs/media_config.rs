@@ -1,21 +1,30 @@
 use crate::description::{
-    codecs_from_media_description, fmtp,
+    codecs_from_media_description,
+    dependency_descriptor::DEPENDENCY_DESCRIPTOR_URI,
+    fmtp,
+    frame_marking::FRAME_MARKING_URI,
+    playout_delay::{LatencyMode, PlayoutDelay, PLAYOUT_DELAY_URI},
     rtp_codec::{
-        codec_parameters_fuzzy_search, CodecMatch, RTCRtpCodecCapability, RTCRtpCodecParameters,
-        RTCRtpHeaderExtensionCapability, RTCRtpHeaderExtensionParameters, RTCRtpParameters,
-        RTPCodecType,
+        codec_parameters_fuzzy_search, validate_clock_rate, CodecMatch, RTCRtpCodecCapability,
+        RTCRtpCodecParameters, RTCRtpHeaderExtensionCapability, RTCRtpHeaderExtensionParameters,
+        RTCRtpParameters, RTPCodecType,
     },
     rtp_extensions_from_media_description,
-    rtp_transceiver::{PayloadType, RTCPFeedback, TYPE_RTCP_FB_TRANSPORT_CC},
+    rtp_transceiver::{
+        PayloadType, RTCPFeedback, TYPE_RTCP_FB_CCM, TYPE_RTCP_FB_GOOG_REMB, TYPE_RTCP_FB_NACK,
+        TYPE_RTCP_FB_TRANSPORT_CC,
+    },
     rtp_transceiver_direction::RTCRtpTransceiverDirection,
 };
 
 //TODO: use crate::stats::stats_collector::StatsCollector;
 //use crate::stats::CodecStats;
 //use crate::stats::StatsReportType::Codec;
+use crate::interceptors::packet_log::PacketLogger;
 use crate::interceptors::report::receiver_report::ReceiverReport;
 use crate::interceptors::report::sender_report::SenderReport;
-use crate::interceptors::Registry;
+use crate::interceptors::vp9::Vp9KSvcFilter;
+use crate::interceptors::{InterceptorBuilder, Registry};
 use sdp::description::session::SessionDescription;
 use shared::error::{Error, Result};
 use std::collections::HashMap;
@@ -48,6 +57,12 @@ pub const MIME_TYPE_PCMA: &str = "audio/PCMA";
 /// MIME_TYPE_TELEPHONE_EVENT telephone-event MIME type
 /// Note: Matching should be case insensitive.
 pub const MIME_TYPE_TELEPHONE_EVENT: &str = "audio/telephone-event";
+/// MIME_TYPE_ULPFEC ulpfec MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_ULPFEC: &str = "video/ulpfec";
+/// MIME_TYPE_RED RED MIME type
+/// Note: Matching should be case insensitive.
+pub const MIME_TYPE_RED: &str = "video/red";
 
 const VALID_EXT_IDS: Range<isize> = 1..15;
 
@@ -78,6 +93,8 @@ impl RTCRtpHeaderExtension {
 /// PeerConnections.
 pub struct MediaConfig {
     registry: Registry,
+    /// index of ReceiverReport interceptor in the registry, if RTCP reports are configured
+    receiver_report_index: Option<usize>,
 
     // If we have attempted to negotiate a codec type yet.
     pub(crate) negotiated_video: bool,
@@ -91,12 +108,31 @@ pub struct MediaConfig {
     header_extensions: Vec<RTCRtpHeaderExtension>,
     proposed_header_extensions: HashMap<isize, RTCRtpHeaderExtension>,
     pub(crate) negotiated_header_extensions: HashMap<isize, RTCRtpHeaderExtension>,
+
+    playout_delay: Option<PlayoutDelay>,
+    ulpfec_overhead: Option<u8>,
+    opus_max_average_bitrate: Option<u32>,
+    opus_use_dtx: Option<bool>,
+    h264_max_level: Option<u8>,
 }
 
 impl Default for MediaConfig {
     fn default() -> Self {
-        let mut media_config = MediaConfig {
+        let mut media_config = MediaConfig::empty();
+
+        let _ = media_config.register_default_codecs();
+        let _ = media_config.register_default_interceptors();
+
+        media_config
+    }
+}
+
+impl MediaConfig {
+    /// empty returns a MediaConfig without any codecs or interceptors registered
+    fn empty() -> Self {
+        MediaConfig {
             registry: Registry::new(),
+            receiver_report_index: None,
 
             negotiated_video: false,
             negotiated_audio: false,
@@ -107,16 +143,95 @@ impl Default for MediaConfig {
             header_extensions: vec![],
             proposed_header_extensions: HashMap::new(),
             negotiated_header_extensions: HashMap::new(),
-        };
+            playout_delay: None,
+            ulpfec_overhead: None,
+            opus_max_average_bitrate: None,
+            opus_use_dtx: None,
+            h264_max_level: None,
+        }
+    }
 
-        let _ = media_config.register_default_codecs();
+    /// default_video_config returns a MediaConfig with default interceptors and the common video
+    /// codecs at their standard payload types, i.e., VP8 96, H264 97, VP9 98 and AV1 99, each with
+    /// nack, nack pli, ccm fir, transport-cc and goog-remb RTCP feedbacks.
+    pub fn default_video_config() -> MediaConfig {
+        let mut media_config = MediaConfig::empty();
+        let _ = media_config.register_default_interceptors();
+
+        let rtcp_feedbacks = vec![
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_NACK.to_owned(),
+                parameter: "".to_owned(),
+            },
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_NACK.to_owned(),
+                parameter: "pli".to_owned(),
+            },
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_CCM.to_owned(),
+                parameter: "fir".to_owned(),
+            },
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_TRANSPORT_CC.to_owned(),
+                parameter: "".to_owned(),
+            },
+            RTCPFeedback {
+                typ: TYPE_RTCP_FB_GOOG_REMB.to_owned(),
+                parameter: "".to_owned(),
+            },
+        ];
+        for (mime_type, sdp_fmtp_line, payload_type) in [
+            (MIME_TYPE_VP8, "", 96),
+            (
+                MIME_TYPE_H264,
+                "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f",
+                97,
+            ),
+            (MIME_TYPE_VP9, "profile-id=0", 98),
+            (MIME_TYPE_AV1, "", 99),
+        ] {
+            let _ = media_config.register_codec(
+                RTCRtpCodecParameters {
+                    capability: RTCRtpCodecCapability {
+                        mime_type: mime_type.to_owned(),
+                        clock_rate: 90000,
+                        channels: 0,
+                        sdp_fmtp_line: sdp_fmtp_line.to_owned(),
+                        rtcp_feedbacks: rtcp_feedbacks.clone(),
+                    },
+                    payload_type,
+                    ..Default::default()
+                },
+                RTPCodecType::Video,
+            );
+        }
+
+        media_config
+    }
+
+    /// default_audio_config returns a MediaConfig with default interceptors and Opus at its
+    /// standard payload type 111.
+    pub fn default_audio_config() -> MediaConfig {
+        let mut media_config = MediaConfig::empty();
         let _ = media_config.register_default_interceptors();
+        let _ = media_config.register_codec(
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_OPUS.to_owned(),
+                    clock_rate: 48000,
+                    channels: 2,
+                    sdp_fmtp_line: "minptime=10;useinbandfec=1".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 111,
+                ..Default::default()
+            },
+            RTPCodecType::Audio,
+        );
 
         media_config
     }
-}
 
-impl MediaConfig {
     /// get Registry
     pub fn registry(&self) -> &Registry {
         &self.registry
@@ -126,7 +241,7 @@ impl MediaConfig {
     /// register_default_codecs is not safe for concurrent use.
     pub fn register_default_codecs(&mut self) -> Result<()> {
         // Default Audio Codecs
-        for codec in vec![
+        for codec in [
             RTCRtpCodecParameters {
                 capability: RTCRtpCodecCapability {
                     mime_type: MIME_TYPE_OPUS.to_owned(),
@@ -171,6 +286,30 @@ impl MediaConfig {
                 payload_type: 8,
                 ..Default::default()
             },
+            // DTMF events, <https://datatracker.ietf.org/doc/html/rfc4733>,
+            // at the clock rates of opus and narrowband codecs
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_TELEPHONE_EVENT.to_owned(),
+                    clock_rate: 48000,
+                    channels: 0,
+                    sdp_fmtp_line: "0-15".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 110,
+                ..Default::default()
+            },
+            RTCRtpCodecParameters {
+                capability: RTCRtpCodecCapability {
+                    mime_type: MIME_TYPE_TELEPHONE_EVENT.to_owned(),
+                    clock_rate: 8000,
+                    channels: 0,
+                    sdp_fmtp_line: "0-15".to_owned(),
+                    rtcp_feedbacks: vec![],
+                },
+                payload_type: 126,
+                ..Default::default()
+            },
         ] {
             self.register_codec(codec, RTPCodecType::Audio)?;
         }
@@ -318,7 +457,7 @@ impl MediaConfig {
             },
             RTCRtpCodecParameters {
                 capability: RTCRtpCodecCapability {
-                    mime_type: "video/ulpfec".to_owned(),
+                    mime_type: MIME_TYPE_ULPFEC.to_owned(),
                     clock_rate: 90000,
                     channels: 0,
                     sdp_fmtp_line: "".to_owned(),
@@ -346,6 +485,12 @@ impl MediaConfig {
         Ok(())
     }
 
+    /// register_interceptor adds a custom interceptor, which is built for each endpoint
+    /// after the ones registered before it.
+    pub fn register_interceptor(&mut self, builder: Box<dyn InterceptorBuilder + Send + Sync>) {
+        self.registry.add(builder);
+    }
+
     /// register_codec adds codec to the MediaConfig
     /// These are the list of codecs supported by this PeerConnection.
     /// register_codec is not safe for concurrent use.
@@ -361,6 +506,7 @@ impl MediaConfig {
                 .unwrap()
                 .as_nanos()
         );*/
+        validate_clock_rate(typ, codec.capability.clock_rate)?;
         match typ {
             RTPCodecType::Audio => {
                 MediaConfig::add_codec(&mut self.audio_codecs, codec);
@@ -483,10 +629,25 @@ impl MediaConfig {
             video_codecs: self.video_codecs.clone(),
             audio_codecs: self.audio_codecs.clone(),
             header_extensions: self.header_extensions.clone(),
+            playout_delay: self.playout_delay,
+            ulpfec_overhead: self.ulpfec_overhead,
+            opus_max_average_bitrate: self.opus_max_average_bitrate,
+            opus_use_dtx: self.opus_use_dtx,
+            h264_max_level: self.h264_max_level,
             ..Default::default()
         }
     }
 
+    /// get_payload_type_for_mime returns the payload type the server registered for the codec
+    /// of given mime type, e.g., "video/VP8", which is matched case-insensitively
+    pub fn get_payload_type_for_mime(&self, mime_type: &str) -> Option<PayloadType> {
+        self.audio_codecs
+            .iter()
+            .chain(self.video_codecs.iter())
+            .find(|codec| codec.capability.mime_type.eq_ignore_ascii_case(mime_type))
+            .map(|codec| codec.payload_type)
+    }
+
     pub(crate) fn get_codec_by_payload(
         &self,
         payload_type: PayloadType,
@@ -932,13 +1093,198 @@ impl MediaConfig {
 
     /// configure_rtcp_reports will setup everything necessary for generating Sender and Receiver Reports
     pub fn configure_rtcp_reports(&mut self) {
+        self.configure_rtcp_reports_with_reorder_window(0);
+    }
+
+    /// configure_rtcp_reports_with_reorder_window will setup generating Sender and Receiver
+    /// Reports, where missing packets aren't counted as lost until reorder_window packets of
+    /// higher sequence numbers are received, so that transient reordering doesn't inflate loss.
+    /// If RTCP reports are already configured, e.g., by default, only the window is updated.
+    pub fn configure_rtcp_reports_with_reorder_window(&mut self, reorder_window: u16) {
+        let receiver = Box::new(ReceiverReport::builder().with_reorder_window(reorder_window));
+        if let Some(index) = self.receiver_report_index {
+            self.registry.replace(index, receiver);
+            return;
+        }
+
         let sender = Box::new(SenderReport::builder());
         self.registry.add(sender);
 
-        let receiver = Box::new(ReceiverReport::builder());
+        self.receiver_report_index = Some(self.registry.len());
         self.registry.add(receiver);
     }
 
+    /// configure_packet_logging will setup logging RTP header fields of the first packets of each
+    /// newly seen SSRC at debug level, e.g., for diagnosing codec or format mismatches.
+    pub fn configure_packet_logging(&mut self, first_packets: usize) {
+        let logger = Box::new(PacketLogger::builder().with_first_packets(first_packets));
+        self.registry.add(logger);
+    }
+
+    /// configure_vp9_ksvc_filter will setup forwarding VP9 K-SVC streams to subscribers
+    /// only up to the given spatial and temporal layers, for registered VP9 codecs.
+    pub fn configure_vp9_ksvc_filter(&mut self, max_spatial_layer: u8, max_temporal_layer: u8) {
+        let payload_types = self
+            .video_codecs
+            .iter()
+            .filter(|codec| {
+                codec
+                    .capability
+                    .mime_type
+                    .eq_ignore_ascii_case(MIME_TYPE_VP9)
+            })
+            .map(|codec| codec.payload_type)
+            .collect();
+        let filter = Box::new(
+            Vp9KSvcFilter::builder()
+                .with_payload_types(payload_types)
+                .with_max_spatial_layer(max_spatial_layer)
+                .with_max_temporal_layer(max_temporal_layer),
+        );
+        self.registry.add(filter);
+    }
+
+    /// configure_mid_extension will setup negotiating mid header extension, so that RTP packets
+    /// forwarded to subscribers are stamped with subscriber-side mid of their transceivers.
+    pub fn configure_mid_extension(&mut self) -> Result<()> {
+        for typ in [RTPCodecType::Audio, RTPCodecType::Video] {
+            self.register_header_extension(
+                RTCRtpHeaderExtensionCapability {
+                    uri: sdp::extmap::SDES_MID_URI.to_owned(),
+                },
+                typ,
+                None,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// configure_playout_delay will setup injecting playout-delay header extension with the bounds
+    /// of the given latency mode into RTP packets forwarded to subscribers who negotiated it.
+    pub fn configure_playout_delay(&mut self, latency_mode: LatencyMode) -> Result<()> {
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: PLAYOUT_DELAY_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            Some(RTCRtpTransceiverDirection::Sendonly),
+        )?;
+        self.playout_delay = Some(PlayoutDelay::from(latency_mode));
+        Ok(())
+    }
+
+    /// configure_dependency_descriptor will negotiate the AV1 dependency descriptor header
+    /// extension with publishers, by which layers above subscribers' maximum ones are dropped,
+    /// see ServerStates::set_max_spatial_layer and ServerStates::set_max_temporal_layer.
+    pub fn configure_dependency_descriptor(&mut self) -> Result<()> {
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: DEPENDENCY_DESCRIPTOR_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            None,
+        )
+    }
+
+    /// configure_frame_marking will negotiate the frame-marking header extension with publishers,
+    /// by which layers and keyframes are known without inspecting payloads of any codec, e.g., of
+    /// E2EE sessions, see ServerStates::set_e2ee.
+    pub fn configure_frame_marking(&mut self) -> Result<()> {
+        self.register_header_extension(
+            RTCRtpHeaderExtensionCapability {
+                uri: FRAME_MARKING_URI.to_owned(),
+            },
+            RTPCodecType::Video,
+            None,
+        )
+    }
+
+    /// playout_delay returns the configured playout delay, if any
+    pub(crate) fn playout_delay(&self) -> Option<PlayoutDelay> {
+        self.playout_delay
+    }
+
+    /// configure_ulpfec will setup generating ulpfec packets for video streams forwarded to
+    /// subscribers who negotiated ulpfec, adding about overhead percent of packets, while
+    /// ulpfec packets of publishers are stripped since their sequence numbers are shifted.
+    pub fn configure_ulpfec(&mut self, overhead: u8) -> Result<()> {
+        if overhead == 0 || overhead > 100 {
+            return Err(Error::Other(format!(
+                "ErrInvalidUlpfecOverhead {}% is not in 1..=100%",
+                overhead
+            )));
+        }
+        self.ulpfec_overhead = Some(overhead);
+        Ok(())
+    }
+
+    /// ulpfec_overhead returns the configured ulpfec overhead percent, if any
+    pub(crate) fn ulpfec_overhead(&self) -> Option<u8> {
+        self.ulpfec_overhead
+    }
+
+    /// configure_opus will setup maxaveragebitrate and usedtx fmtp parameters of opus codecs
+    /// in generated session descriptions, which ask remote encoders to limit their bitrate and
+    /// to use discontinuous transmission.
+    pub fn configure_opus(
+        &mut self,
+        max_average_bitrate: Option<u32>,
+        use_dtx: bool,
+    ) -> Result<()> {
+        if let Some(max_average_bitrate) = max_average_bitrate {
+            // <https://datatracker.ietf.org/doc/html/rfc7587#section-6.1>
+            if !(6000..=510000).contains(&max_average_bitrate) {
+                return Err(Error::Other(format!(
+                    "ErrInvalidOpusMaxAverageBitrate {} is not in 6000..=510000",
+                    max_average_bitrate
+                )));
+            }
+        }
+        self.opus_max_average_bitrate = max_average_bitrate;
+        self.opus_use_dtx = Some(use_dtx);
+        Ok(())
+    }
+
+    /// configure_h264_max_level will cap the level part of profile-level-id fmtp parameter of
+    /// H264 codecs in generated session descriptions, e.g., 0x1f for level 3.1, while the profile
+    /// part is kept as registered since it must match for H264 codecs to be negotiated.
+    pub fn configure_h264_max_level(&mut self, max_level: u8) {
+        self.h264_max_level = Some(max_level);
+    }
+
+    /// sdp_fmtp_line_for_codec derives the fmtp line of a codec for generated session descriptions
+    /// from its registered fmtp line and the configured codec settings
+    pub fn sdp_fmtp_line_for_codec(&self, codec: &RTCRtpCodecParameters) -> String {
+        let mime_type = &codec.capability.mime_type;
+        let mut parameters = fmtp::split_parameters(&codec.capability.sdp_fmtp_line);
+        if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) {
+            if let Some(max_average_bitrate) = self.opus_max_average_bitrate {
+                fmtp::set_parameter(
+                    &mut parameters,
+                    "maxaveragebitrate",
+                    max_average_bitrate.to_string(),
+                );
+            }
+            if let Some(use_dtx) = self.opus_use_dtx {
+                fmtp::set_parameter(&mut parameters, "usedtx", (use_dtx as u8).to_string());
+            }
+        } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+            if let Some(max_level) = self.h264_max_level {
+                if let Some((_, profile_level_id)) = parameters
+                    .iter_mut()
+                    .find(|(key, value)| key == "profile-level-id" && value.len() == 6)
+                {
+                    if u8::from_str_radix(&profile_level_id[4..], 16)
+                        .is_ok_and(|level| level > max_level)
+                    {
+                        *profile_level_id = format!("{}{:02x}", &profile_level_id[..4], max_level);
+                    }
+                }
+            }
+        }
+        fmtp::join_parameters(parameters)
+    }
+
     /// configure_nack will setup everything necessary for handling generating/responding to nack messages.
     pub fn configure_nack(&mut self) {
         self.register_rtcp_feedback(
@@ -1,3 +1,4 @@
+pub(crate) mod demuxer_config;
 pub(crate) mod media_config;
 pub(crate) mod server_config;
 pub(crate) mod session_config;
@@ -1,8 +1,39 @@
+use crate::configs::demuxer_config::DemuxerConfig;
 use crate::configs::media_config::MediaConfig;
 use crate::server::certificate::RTCCertificate;
+use shared::error::{Error, Result};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 
+/// ICE ufrag must contain at least 4 and at most 256 characters,
+/// <https://tools.ietf.org/html/rfc5245#section-15.4>
+pub(crate) const MIN_ICE_UFRAG_LEN: usize = 4;
+pub(crate) const MAX_ICE_UFRAG_LEN: usize = 256;
+/// ICE pwd must contain at least 22 and at most 256 characters,
+/// <https://tools.ietf.org/html/rfc5245#section-15.4>
+pub(crate) const MIN_ICE_PWD_LEN: usize = 22;
+pub(crate) const MAX_ICE_PWD_LEN: usize = 256;
+
+pub(crate) const DEFAULT_ICE_UFRAG_LEN: usize = 12;
+pub(crate) const DEFAULT_ICE_PWD_LEN: usize = 24;
+
+/// Candidates not connected within 30 seconds are dropped, aligned with the
+/// consent freshness timeout, <https://tools.ietf.org/html/rfc7675#section-5.1>
+pub(crate) const DEFAULT_CANDIDATE_TTL: Duration = Duration::from_secs(30);
+
+/// Renegotiation triggered within 100ms of the previous trigger is batched into the same offer
+pub(crate) const DEFAULT_RENEGOTIATION_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Offers of SFU not answered within 10 seconds are rolled back, so that renegotiation resumes
+pub(crate) const DEFAULT_PENDING_OFFER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SRTP/SRTCP packets received before DTLS handshake completes are buffered up to 16 packets
+pub(crate) const DEFAULT_SRTP_PENDING_BUFFER_SIZE: usize = 16;
+
+/// UDP datagrams are at most 65535 bytes, so a receive buffer of that size never truncates them
+pub(crate) const DEFAULT_UDP_RECV_BUFFER_SIZE: usize = 65535;
+
 /// ServerConfig provides customized parameters for SFU server
 pub struct ServerConfig {
     pub(crate) certificates: Vec<RTCCertificate>,
@@ -10,7 +41,28 @@ pub struct ServerConfig {
     pub(crate) sctp_endpoint_config: Arc<sctp::EndpointConfig>,
     pub(crate) sctp_server_config: Arc<sctp::ServerConfig>,
     pub(crate) media_config: MediaConfig,
+    pub(crate) demuxer_config: DemuxerConfig,
     pub(crate) idle_timeout: Duration,
+    pub(crate) sctp_association_idle_timeout: Duration,
+    pub(crate) sctp_association_heartbeat_timeout: Duration,
+    pub(crate) candidate_ttl: Duration,
+    pub(crate) renegotiation_debounce: Duration,
+    pub(crate) min_renegotiation_interval: Duration,
+    pub(crate) pending_offer_timeout: Duration,
+    pub(crate) srtp_pending_buffer_size: usize,
+    pub(crate) ice_ufrag_len: usize,
+    pub(crate) ice_pwd_len: usize,
+    pub(crate) ice_credentials_rotation: bool,
+    pub(crate) max_sessions: Option<usize>,
+    pub(crate) rtp_rewriting: bool,
+    pub(crate) pacing_bitrate: Option<u64>,
+    pub(crate) sdp_log_capacity: usize,
+    pub(crate) keyframe_cache_size: usize,
+    pub(crate) rtcp_app_name: Option<[u8; 4]>,
+    pub(crate) turn_relay_addr: Option<SocketAddr>,
+    pub(crate) udp_recv_buffer_size: usize,
+    pub(crate) path_mtu: Option<usize>,
+    pub(crate) drop_oversized_rtp: bool,
 }
 
 impl ServerConfig {
@@ -19,10 +71,31 @@ impl ServerConfig {
         Self {
             certificates,
             media_config: MediaConfig::default(),
+            demuxer_config: DemuxerConfig::default(),
             sctp_endpoint_config: Arc::new(sctp::EndpointConfig::default()),
             sctp_server_config: Arc::new(sctp::ServerConfig::default()),
             dtls_handshake_config: Arc::new(dtls::config::HandshakeConfig::default()),
             idle_timeout: Duration::from_secs(30),
+            sctp_association_idle_timeout: Duration::from_secs(60),
+            sctp_association_heartbeat_timeout: Duration::from_secs(30),
+            candidate_ttl: DEFAULT_CANDIDATE_TTL,
+            renegotiation_debounce: DEFAULT_RENEGOTIATION_DEBOUNCE,
+            min_renegotiation_interval: Duration::ZERO,
+            pending_offer_timeout: DEFAULT_PENDING_OFFER_TIMEOUT,
+            srtp_pending_buffer_size: DEFAULT_SRTP_PENDING_BUFFER_SIZE,
+            ice_ufrag_len: DEFAULT_ICE_UFRAG_LEN,
+            ice_pwd_len: DEFAULT_ICE_PWD_LEN,
+            ice_credentials_rotation: false,
+            max_sessions: None,
+            rtp_rewriting: false,
+            pacing_bitrate: None,
+            sdp_log_capacity: 0,
+            keyframe_cache_size: 0,
+            rtcp_app_name: None,
+            turn_relay_addr: None,
+            udp_recv_buffer_size: DEFAULT_UDP_RECV_BUFFER_SIZE,
+            path_mtu: None,
+            drop_oversized_rtp: false,
         }
     }
 
@@ -32,6 +105,12 @@ impl ServerConfig {
         self
     }
 
+    /// build with provided DemuxerConfig
+    pub fn with_demuxer_config(mut self, demuxer_config: DemuxerConfig) -> Self {
+        self.demuxer_config = demuxer_config;
+        self
+    }
+
     /// build with provided sctp::ServerConfig
     pub fn with_sctp_server_config(mut self, sctp_server_config: Arc<sctp::ServerConfig>) -> Self {
         self.sctp_server_config = sctp_server_config;
@@ -61,4 +140,177 @@ impl ServerConfig {
         self.idle_timeout = idle_timeout;
         self
     }
+
+    /// build with idle timeout of SCTP associations, after which orphaned associations,
+    /// e.g., whose DTLS connection was torn down without SCTP SHUTDOWN, are closed
+    pub fn with_sctp_association_idle_timeout(
+        mut self,
+        sctp_association_idle_timeout: Duration,
+    ) -> Self {
+        self.sctp_association_idle_timeout = sctp_association_idle_timeout;
+        self
+    }
+
+    /// build with heartbeat timeout of SCTP associations, after which associations whose remote
+    /// has sent neither HEARTBEAT nor any other chunk are declared failed and their data
+    /// channels closed
+    pub fn with_sctp_association_heartbeat_timeout(
+        mut self,
+        sctp_association_heartbeat_timeout: Duration,
+    ) -> Self {
+        self.sctp_association_heartbeat_timeout = sctp_association_heartbeat_timeout;
+        self
+    }
+
+    /// build with debounce window of renegotiation, within which further changes of an endpoint,
+    /// e.g., more tracks published by others, are batched into the same offer
+    pub fn with_renegotiation_debounce(mut self, renegotiation_debounce: Duration) -> Self {
+        self.renegotiation_debounce = renegotiation_debounce;
+        self
+    }
+
+    /// build with minimum interval between processed renegotiation offers of an endpoint, where
+    /// offers received within the interval are coalesced into the latest one, which is answered
+    /// over the endpoint's data channel once the interval passes, against misbehaving endpoints'
+    /// rapid renegotiations
+    pub fn with_min_renegotiation_interval(mut self, min_renegotiation_interval: Duration) -> Self {
+        self.min_renegotiation_interval = min_renegotiation_interval;
+        self
+    }
+
+    /// build with timeout of offers sent by SFU, where an offer not answered within the timeout,
+    /// e.g., lost with a closed data channel, is rolled back and the endpoint is offered again
+    pub fn with_pending_offer_timeout(mut self, pending_offer_timeout: Duration) -> Self {
+        self.pending_offer_timeout = pending_offer_timeout;
+        self
+    }
+
+    /// build with the number of SRTP/SRTCP packets buffered per transport, which are received
+    /// before its DTLS handshake completes and processed once it does, where 0 rejects them
+    pub fn with_srtp_pending_buffer_size(mut self, srtp_pending_buffer_size: usize) -> Self {
+        self.srtp_pending_buffer_size = srtp_pending_buffer_size;
+        self
+    }
+
+    /// build with time-to-live of candidates created by accepted offers,
+    /// after which they are dropped unless their endpoint has connected
+    pub fn with_candidate_ttl(mut self, candidate_ttl: Duration) -> Self {
+        self.candidate_ttl = candidate_ttl;
+        self
+    }
+
+    /// build with lengths of generated ICE ufrag and pwd, which are validated
+    /// against RFC 5245 bounds when ServerStates is created
+    pub fn with_ice_credential_lengths(mut self, ice_ufrag_len: usize, ice_pwd_len: usize) -> Self {
+        self.ice_ufrag_len = ice_ufrag_len;
+        self.ice_pwd_len = ice_pwd_len;
+        self
+    }
+
+    /// build with rotation of local ICE ufrag and pwd on each offer initiated by SFU, i.e., with
+    /// ICE restart semantics, where the new credentials replace the old ones once the offer is
+    /// answered
+    pub fn with_ice_credentials_rotation(mut self, ice_credentials_rotation: bool) -> Self {
+        self.ice_credentials_rotation = ice_credentials_rotation;
+        self
+    }
+
+    /// build with maximum number of concurrent sessions, beyond which new sessions are rejected
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = Some(max_sessions);
+        self
+    }
+
+    /// build with rewriting of sequence numbers and timestamps of forwarded RTP packets,
+    /// which keeps them continuous per subscriber SSRC across source switches
+    pub fn with_rtp_rewriting(mut self, rtp_rewriting: bool) -> Self {
+        self.rtp_rewriting = rtp_rewriting;
+        self
+    }
+
+    /// build with pacing of outbound RTP packets per subscriber transport toward
+    /// the target bitrate in bits per second, instead of bursting them
+    pub fn with_pacing_bitrate(mut self, pacing_bitrate: u64) -> Self {
+        self.pacing_bitrate = Some(pacing_bitrate);
+        self
+    }
+
+    /// build with per-session log of the latest offers and answers for debugging,
+    /// keeping at most sdp_log_capacity entries, where 0 disables the log
+    pub fn with_sdp_log_capacity(mut self, sdp_log_capacity: usize) -> Self {
+        self.sdp_log_capacity = sdp_log_capacity;
+        self
+    }
+
+    /// build with per-publisher-ssrc cache of the RTP packets since the latest keyframe, keeping
+    /// at most keyframe_cache_size packets, which are sent to a newly joined subscriber along
+    /// with the first packet forwarded to it, instead of waiting for its PLI round-trip,
+    /// where 0 disables the cache
+    pub fn with_keyframe_cache_size(mut self, keyframe_cache_size: usize) -> Self {
+        self.keyframe_cache_size = keyframe_cache_size;
+        self
+    }
+
+    /// build with delivering RTCP APP packets with the name to the application as
+    /// SessionEvent::RtcpApp, instead of forwarding them to other endpoints,
+    /// e.g., for vendor-specific signaling
+    pub fn with_rtcp_app_name(mut self, rtcp_app_name: [u8; 4]) -> Self {
+        self.rtcp_app_name = Some(rtcp_app_name);
+        self
+    }
+
+    /// build with the relay address allocated for the server on an upstream TURN server,
+    /// which is advertised as relay candidate besides the host candidate in generated SDP,
+    /// for clients that can't reach the server directly. The TURN allocation, and relaying
+    /// its traffic to the server's local address, are managed by the operator.
+    pub fn with_turn_relay_addr(mut self, turn_relay_addr: SocketAddr) -> Self {
+        self.turn_relay_addr = Some(turn_relay_addr);
+        self
+    }
+
+    /// build with size of the buffer which datagrams are received into from the UDP socket,
+    /// where datagrams filling the whole buffer are dropped as truncated by DemuxerHandler
+    pub fn with_udp_recv_buffer_size(mut self, udp_recv_buffer_size: usize) -> Self {
+        self.udp_recv_buffer_size = udp_recv_buffer_size;
+        self
+    }
+
+    /// build with path MTU toward subscribers, i.e., the largest IP packet, against which forwarded
+    /// RTP packets are checked, since the SFU can't re-packetize them. It can be overridden per
+    /// transport by ServerStates::set_path_mtu
+    pub fn with_path_mtu(mut self, path_mtu: usize) -> Self {
+        self.path_mtu = Some(path_mtu);
+        self
+    }
+
+    /// build with dropping forwarded RTP packets exceeding the path MTU, instead of only
+    /// counting them and leaving them to IP fragmentation
+    pub fn with_drop_oversized_rtp(mut self, drop_oversized_rtp: bool) -> Self {
+        self.drop_oversized_rtp = drop_oversized_rtp;
+        self
+    }
+
+    /// size of the buffer to receive datagrams into from the UDP socket
+    pub fn udp_recv_buffer_size(&self) -> usize {
+        self.udp_recv_buffer_size
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        if !(MIN_ICE_UFRAG_LEN..=MAX_ICE_UFRAG_LEN).contains(&self.ice_ufrag_len) {
+            return Err(Error::Other(format!(
+                "invalid ICE ufrag length {}, must be in [{}..={}]",
+                self.ice_ufrag_len, MIN_ICE_UFRAG_LEN, MAX_ICE_UFRAG_LEN
+            )));
+        }
+        if !(MIN_ICE_PWD_LEN..=MAX_ICE_PWD_LEN).contains(&self.ice_pwd_len) {
+            return Err(Error::Other(format!(
+                "invalid ICE pwd length {}, must be in [{}..={}]",
+                self.ice_pwd_len, MIN_ICE_PWD_LEN, MAX_ICE_PWD_LEN
+            )));
+        }
+        if self.pacing_bitrate == Some(0) {
+            return Err(Error::Other("invalid pacing bitrate 0".to_string()));
+        }
+        Ok(())
+    }
 }
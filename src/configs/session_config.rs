@@ -1,10 +1,25 @@
 use crate::configs::server_config::ServerConfig;
+use crate::description::rtp_transceiver::SSRC;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
+use std::rc::Rc;
 use std::sync::Arc;
 
 pub(crate) struct SessionConfig {
     pub(crate) server_config: Arc<ServerConfig>,
     pub(crate) local_addr: SocketAddr,
+    /// types of RTCP feedback, e.g., goog-remb, which are not advertised in the session's SDPs
+    pub(crate) disabled_rtcp_feedbacks: HashSet<String>,
+    /// mime types of codecs, e.g., video/VP9, which are listed first in the session's SDPs,
+    /// in the preferred order
+    pub(crate) codec_preferences: Vec<String>,
+    /// whether media payloads are encrypted end-to-end, e.g., by SFrame of insertable streams,
+    /// so that they are forwarded untouched without any inspection
+    pub(crate) e2ee: bool,
+    /// CNAMEs of SSRCs, which are rewritten in forwarded SDES packets by SdesForwarder of the
+    /// session's endpoints, and signaled in a=ssrc cname attributes of forwarded sections
+    pub(crate) sdes_cnames: Rc<RefCell<HashMap<SSRC, String>>>,
 }
 
 impl SessionConfig {
@@ -12,6 +27,20 @@ impl SessionConfig {
         Self {
             server_config,
             local_addr,
+            disabled_rtcp_feedbacks: HashSet::new(),
+            codec_preferences: vec![],
+            e2ee: false,
+            sdes_cnames: Rc::new(RefCell::new(HashMap::new())),
         }
     }
+
+    /// cname_for_ssrc returns the CNAME of the forwarded SSRC, i.e., the one rewritten by
+    /// SdesForwarder if configured, so that SDP and RTCP SDES agree, or else the sender's one
+    pub(crate) fn cname_for_ssrc(&self, ssrc: SSRC, sender_cname: &str) -> String {
+        self.sdes_cnames
+            .borrow()
+            .get(&ssrc)
+            .cloned()
+            .unwrap_or_else(|| sender_cname.to_string())
+    }
 }
@@ -0,0 +1,128 @@
+use shared::error::{Error, Result};
+
+/// DEPENDENCY_DESCRIPTOR_URI is the URI of AV1 dependency descriptor RTP header extension,
+/// <https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension>
+pub const DEPENDENCY_DESCRIPTOR_URI: &str =
+    "https://aomediacodec.github.io/av1-rtp-spec/#dependency-descriptor-rtp-header-extension";
+
+/// size of mandatory descriptor fields, without extended descriptor fields
+const MANDATORY_DESCRIPTOR_FIELDS_SIZE: usize = 3;
+/// frame_dependency_template_id is 6 bits, wrapping around at 64
+const TEMPLATE_ID_MODULO: u8 = 64;
+
+/// next_layer_idc values of template_layers()
+const NEXT_LAYER_IDC_NEXT_TEMPORAL_LAYER: u32 = 1;
+const NEXT_LAYER_IDC_NEXT_SPATIAL_LAYER: u32 = 2;
+const NEXT_LAYER_IDC_NO_MORE_TEMPLATES: u32 = 3;
+
+/// FrameDependencyStructure is the template dependency structure of a dependency descriptor,
+/// of which only the layers of templates are kept, as needed for layer selection
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct FrameDependencyStructure {
+    pub template_id_offset: u8,
+    /// (spatial_id, temporal_id) of each template
+    pub template_layers: Vec<(u8, u8)>,
+}
+
+/// DependencyDescriptor is the mandatory fields of a dependency descriptor,
+/// with its template dependency structure if present
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct DependencyDescriptor {
+    pub start_of_frame: bool,
+    pub end_of_frame: bool,
+    pub frame_dependency_template_id: u8,
+    pub frame_number: u16,
+    pub structure: Option<FrameDependencyStructure>,
+}
+
+impl DependencyDescriptor {
+    /// unmarshal dependency descriptor from header extension payload, skipping what follows
+    /// the layers of templates, i.e., decode target indications, frame diffs, chains and
+    /// resolutions
+    pub fn unmarshal(raw: &[u8]) -> Result<Self> {
+        let mut reader = BitReader::new(raw);
+        let mut descriptor = DependencyDescriptor {
+            start_of_frame: reader.read(1)? == 1,
+            end_of_frame: reader.read(1)? == 1,
+            frame_dependency_template_id: reader.read(6)? as u8,
+            frame_number: reader.read(16)? as u16,
+            structure: None,
+        };
+
+        if raw.len() > MANDATORY_DESCRIPTOR_FIELDS_SIZE {
+            let template_dependency_structure_present_flag = reader.read(1)? == 1;
+            // active_decode_targets_present_flag, custom_dtis_flag, custom_fdiffs_flag and
+            // custom_chains_flag
+            reader.read(4)?;
+            if template_dependency_structure_present_flag {
+                descriptor.structure = Some(FrameDependencyStructure::unmarshal(&mut reader)?);
+            }
+        }
+
+        Ok(descriptor)
+    }
+
+    /// layer returns (spatial_id, temporal_id) of the frame by its template in the structure,
+    /// or None if the structure has no such template
+    pub fn layer(&self, structure: &FrameDependencyStructure) -> Option<(u8, u8)> {
+        let index = (self.frame_dependency_template_id + TEMPLATE_ID_MODULO
+            - structure.template_id_offset)
+            % TEMPLATE_ID_MODULO;
+        structure.template_layers.get(index as usize).copied()
+    }
+}
+
+impl FrameDependencyStructure {
+    fn unmarshal(reader: &mut BitReader<'_>) -> Result<Self> {
+        let template_id_offset = reader.read(6)? as u8;
+        // dt_cnt_minus_one
+        reader.read(5)?;
+
+        let (mut spatial_id, mut temporal_id) = (0u8, 0u8);
+        let mut template_layers = vec![];
+        loop {
+            if template_layers.len() >= TEMPLATE_ID_MODULO as usize {
+                return Err(Error::Other(
+                    "ErrDependencyDescriptorTooManyTemplates".to_owned(),
+                ));
+            }
+            template_layers.push((spatial_id, temporal_id));
+            match reader.read(2)? {
+                NEXT_LAYER_IDC_NEXT_TEMPORAL_LAYER => temporal_id += 1,
+                NEXT_LAYER_IDC_NEXT_SPATIAL_LAYER => {
+                    temporal_id = 0;
+                    spatial_id += 1;
+                }
+                NEXT_LAYER_IDC_NO_MORE_TEMPLATES => break,
+                _ => {}
+            }
+        }
+
+        Ok(FrameDependencyStructure {
+            template_id_offset,
+            template_layers,
+        })
+    }
+}
+
+/// BitReader reads MSB first bit fields of a dependency descriptor
+struct BitReader<'a> {
+    raw: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(raw: &'a [u8]) -> Self {
+        Self { raw, offset: 0 }
+    }
+
+    fn read(&mut self, bits: usize) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..bits {
+            let byte = self.raw.get(self.offset / 8).ok_or(Error::ErrShortBuffer)?;
+            value = (value << 1) | ((byte >> (7 - self.offset % 8)) & 1) as u32;
+            self.offset += 1;
+        }
+        Ok(value)
+    }
+}
@@ -46,7 +46,7 @@ impl Fmtp for H264Fmtp {
     ///     Informative note: The requirement for symmetric use does not
     ///     apply for the level part of profile-level-id and does not apply
     ///     for the other stream properties and capability parameters.
-    fn match_fmtp(&self, f: &(dyn Fmtp)) -> bool {
+    fn match_fmtp(&self, f: &dyn Fmtp) -> bool {
         if let Some(c) = f.as_any().downcast_ref::<H264Fmtp>() {
             // check packetization-mode
             let hpmode = match self.parameters.get("packetization-mode") {
@@ -86,14 +86,14 @@ impl Fmtp for H264Fmtp {
         self.parameters.get(key)
     }
 
-    fn equal(&self, other: &(dyn Fmtp)) -> bool {
+    fn equal(&self, other: &dyn Fmtp) -> bool {
         other
             .as_any()
             .downcast_ref::<H264Fmtp>()
-            .map_or(false, |a| self == a)
+            .is_some_and(|a| self == a)
     }
 
-    fn as_any(&self) -> &(dyn Any) {
+    fn as_any(&self) -> &dyn Any {
         self
     }
 }
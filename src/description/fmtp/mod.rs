@@ -5,6 +5,7 @@ use std::any::Any;
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::configs::media_config::{MIME_TYPE_H264, MIME_TYPE_OPUS};
 use crate::description::fmtp::{generic::GenericFmtp, h264::H264Fmtp};
 
 /// Fmtp interface for implementing custom
@@ -16,14 +17,14 @@ pub trait Fmtp: fmt::Debug {
 
     /// match_fmtp compares two fmtp descriptions for
     /// compatibility based on the mime_type    
-    fn match_fmtp(&self, f: &(dyn Fmtp)) -> bool;
+    fn match_fmtp(&self, f: &dyn Fmtp) -> bool;
 
     /// parameter returns a value for the associated key
     /// if contained in the parsed fmtp string
     fn parameter(&self, key: &str) -> Option<&String>;
 
-    fn equal(&self, other: &(dyn Fmtp)) -> bool;
-    fn as_any(&self) -> &(dyn Any);
+    fn equal(&self, other: &dyn Fmtp) -> bool;
+    fn as_any(&self) -> &dyn Any;
 }
 
 impl PartialEq for dyn Fmtp {
@@ -55,3 +56,113 @@ pub(crate) fn parse(mime_type: &str, line: &str) -> Box<dyn Fmtp> {
         })
     }
 }
+
+/// intersect_fmtp narrows the local fmtp line of a codec to the parameters supported by both
+/// local and remote, which is used in answers instead of echoing the offered fmtp line, e.g.,
+/// opus maxplaybackrate/stereo/usedtx and H264 level of profile-level-id.
+/// Other parameters are kept as configured locally.
+pub fn intersect_fmtp(mime_type: &str, local: &str, remote: &str) -> String {
+    let mut parameters = split_parameters(local);
+    let remote = parse(mime_type, remote);
+
+    if mime_type.eq_ignore_ascii_case(MIME_TYPE_OPUS) {
+        intersect_min(
+            &mut parameters,
+            "maxplaybackrate",
+            remote.parameter("maxplaybackrate"),
+        );
+        intersect_flag(&mut parameters, "stereo", remote.parameter("stereo"));
+        intersect_flag(&mut parameters, "usedtx", remote.parameter("usedtx"));
+    } else if mime_type.eq_ignore_ascii_case(MIME_TYPE_H264) {
+        // packetization-mode and profile must be the same for matched codecs, only level
+        // part of profile-level-id can be narrowed, <https://tools.ietf.org/html/rfc6184#section-8.2.2>
+        if let (Some(local_plid), Some(remote_plid)) = (
+            parameters
+                .iter_mut()
+                .find(|(key, _)| key == "profile-level-id")
+                .map(|(_, value)| value),
+            remote.parameter("profile-level-id"),
+        ) {
+            if local_plid.len() == 6
+                && remote_plid.len() == 6
+                && local_plid[..4].eq_ignore_ascii_case(&remote_plid[..4])
+            {
+                if let (Ok(local_level), Ok(remote_level)) = (
+                    u8::from_str_radix(&local_plid[4..], 16),
+                    u8::from_str_radix(&remote_plid[4..], 16),
+                ) {
+                    if remote_level < local_level {
+                        *local_plid = format!("{}{:02x}", &local_plid[..4], remote_level);
+                    }
+                }
+            }
+        }
+    }
+
+    join_parameters(parameters)
+}
+
+/// split_parameters splits fmtp line into ordered key-value pairs with lowercase keys,
+/// and empty values for parameters without value
+pub(crate) fn split_parameters(line: &str) -> Vec<(String, String)> {
+    line.split(';')
+        .filter_map(|p| {
+            let p = p.trim();
+            if p.is_empty() {
+                return None;
+            }
+            let (key, value) = p.split_once('=').unwrap_or((p, ""));
+            Some((key.to_lowercase(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// join_parameters joins key-value pairs back into fmtp line
+pub(crate) fn join_parameters(parameters: Vec<(String, String)>) -> String {
+    parameters
+        .into_iter()
+        .map(|(key, value)| {
+            if value.is_empty() {
+                key
+            } else {
+                format!("{key}={value}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+/// set_parameter replaces the value of parameter in place, or appends it if missing
+pub(crate) fn set_parameter(parameters: &mut Vec<(String, String)>, key: &str, value: String) {
+    if let Some((_, v)) = parameters.iter_mut().find(|(k, _)| k == key) {
+        *v = value;
+    } else {
+        parameters.push((key.to_owned(), value));
+    }
+}
+
+/// intersect_min narrows numeric parameter to the lower of local and remote values
+fn intersect_min(parameters: &mut Vec<(String, String)>, key: &str, remote: Option<&String>) {
+    let Some(remote) = remote.and_then(|r| r.parse::<u32>().ok()) else {
+        return;
+    };
+    if let Some((_, value)) = parameters.iter_mut().find(|(k, _)| k == key) {
+        if value.parse::<u32>().map_or(true, |local| remote < local) {
+            *value = remote.to_string();
+        }
+    } else {
+        parameters.push((key.to_owned(), remote.to_string()));
+    }
+}
+
+/// intersect_flag enables boolean parameter only if both local and remote enable it
+fn intersect_flag(parameters: &mut Vec<(String, String)>, key: &str, remote: Option<&String>) {
+    let enabled = remote.is_some_and(|r| r == "1");
+    if let Some((_, value)) = parameters.iter_mut().find(|(k, _)| k == key) {
+        if !(enabled && value == "1") {
+            *value = "0".to_owned();
+        }
+    } else if remote.is_some() {
+        parameters.push((key.to_owned(), "0".to_owned()));
+    }
+}
@@ -0,0 +1,101 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use shared::error::{Error, Result};
+
+/// FRAME_MARKING_URI is the URI of frame-marking RTP header extension,
+/// <https://datatracker.ietf.org/doc/html/draft-ietf-avtext-framemarking>
+pub const FRAME_MARKING_URI: &str = "urn:ietf:params:rtp-hdrext:framemarking";
+
+/// S, E, I, D and B flags of the first byte
+const FRAME_MARKING_START_OF_FRAME: u8 = 0x80;
+const FRAME_MARKING_END_OF_FRAME: u8 = 0x40;
+const FRAME_MARKING_INDEPENDENT: u8 = 0x20;
+const FRAME_MARKING_DISCARDABLE: u8 = 0x10;
+const FRAME_MARKING_BASE_LAYER_SYNC: u8 = 0x08;
+/// TID is the lowest 3 bits of the first byte
+const FRAME_MARKING_TID_MASK: u8 = 0x07;
+
+/// FrameMarking is the codec-agnostic frame information of frame-marking extension, either of the
+/// short form for non-scalable streams, or of the long form with layer information for scalable
+/// streams
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FrameMarking {
+    pub start_of_frame: bool,
+    pub end_of_frame: bool,
+    /// whether the frame can be decoded without any previous frame, i.e., a keyframe
+    pub independent: bool,
+    pub discardable: bool,
+    pub base_layer_sync: bool,
+    pub temporal_layer_id: u8,
+    /// codec-specific layer id, e.g., spatial layer id of VP9
+    pub layer_id: u8,
+    pub tl0_pic_idx: Option<u8>,
+    /// whether it is of the long form with layer information
+    pub scalable: bool,
+}
+
+impl FrameMarking {
+    /// marshal frame-marking into header extension payload
+    pub fn marshal(&self) -> Result<Bytes> {
+        if self.temporal_layer_id > FRAME_MARKING_TID_MASK {
+            return Err(Error::Other(format!(
+                "ErrInvalidFrameMarking temporal layer id {}",
+                self.temporal_layer_id
+            )));
+        }
+
+        let mut flags = 0;
+        for (is_set, flag) in [
+            (self.start_of_frame, FRAME_MARKING_START_OF_FRAME),
+            (self.end_of_frame, FRAME_MARKING_END_OF_FRAME),
+            (self.independent, FRAME_MARKING_INDEPENDENT),
+            (self.discardable, FRAME_MARKING_DISCARDABLE),
+        ] {
+            if is_set {
+                flags |= flag;
+            }
+        }
+
+        let mut buf = BytesMut::with_capacity(3);
+        if !self.scalable {
+            buf.put_u8(flags);
+            return Ok(buf.freeze());
+        }
+        if self.base_layer_sync {
+            flags |= FRAME_MARKING_BASE_LAYER_SYNC;
+        }
+        buf.put_u8(flags | self.temporal_layer_id);
+        buf.put_u8(self.layer_id);
+        if let Some(tl0_pic_idx) = self.tl0_pic_idx {
+            buf.put_u8(tl0_pic_idx);
+        }
+        Ok(buf.freeze())
+    }
+
+    /// unmarshal frame-marking from header extension payload, where one byte is the short form
+    pub fn unmarshal(raw: &[u8]) -> Result<Self> {
+        let Some(&flags) = raw.first() else {
+            return Err(Error::ErrShortBuffer);
+        };
+
+        let mut frame_marking = FrameMarking {
+            start_of_frame: flags & FRAME_MARKING_START_OF_FRAME != 0,
+            end_of_frame: flags & FRAME_MARKING_END_OF_FRAME != 0,
+            independent: flags & FRAME_MARKING_INDEPENDENT != 0,
+            discardable: flags & FRAME_MARKING_DISCARDABLE != 0,
+            ..Default::default()
+        };
+        if raw.len() > 1 {
+            frame_marking.scalable = true;
+            frame_marking.base_layer_sync = flags & FRAME_MARKING_BASE_LAYER_SYNC != 0;
+            frame_marking.temporal_layer_id = flags & FRAME_MARKING_TID_MASK;
+            frame_marking.layer_id = raw[1];
+            frame_marking.tl0_pic_idx = raw.get(2).copied();
+        }
+        Ok(frame_marking)
+    }
+
+    /// is_keyframe returns whether the packet starts an independent frame
+    pub fn is_keyframe(&self) -> bool {
+        self.start_of_frame && self.independent
+    }
+}
@@ -0,0 +1,105 @@
+use shared::error::{Error, Result};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// RTCIceCandidateType represents the type of an ICE candidate,
+/// <https://tools.ietf.org/html/rfc8839#section-5.1>
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RTCIceCandidateType {
+    #[default]
+    Unspecified,
+    Host,
+    Srflx,
+    Prflx,
+    Relay,
+}
+
+impl From<&str> for RTCIceCandidateType {
+    fn from(raw: &str) -> Self {
+        match raw {
+            "host" => RTCIceCandidateType::Host,
+            "srflx" => RTCIceCandidateType::Srflx,
+            "prflx" => RTCIceCandidateType::Prflx,
+            "relay" => RTCIceCandidateType::Relay,
+            _ => RTCIceCandidateType::Unspecified,
+        }
+    }
+}
+
+impl fmt::Display for RTCIceCandidateType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            RTCIceCandidateType::Host => "host",
+            RTCIceCandidateType::Srflx => "srflx",
+            RTCIceCandidateType::Prflx => "prflx",
+            RTCIceCandidateType::Relay => "relay",
+            RTCIceCandidateType::Unspecified => super::UNSPECIFIED_STR,
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// RTCIceCandidate represents an a=candidate line of a remote description, e.g.,
+/// `a=candidate:1 1 udp 41885439 203.0.113.1 3478 typ relay raddr 192.0.2.1 rport 50000`
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct RTCIceCandidate {
+    pub foundation: String,
+    pub component: u16,
+    pub protocol: String,
+    pub priority: u32,
+    /// IP address or mDNS hostname
+    pub address: String,
+    pub port: u16,
+    pub typ: RTCIceCandidateType,
+    pub related_address: Option<String>,
+    pub related_port: Option<u16>,
+}
+
+impl TryFrom<&str> for RTCIceCandidate {
+    type Error = Error;
+
+    /// parse the value of an a=candidate line, without the `a=candidate:` prefix
+    fn try_from(value: &str) -> Result<Self> {
+        let err = || Error::Other(format!("ErrInvalidIceCandidate {}", value));
+        let fields: Vec<&str> = value.split_whitespace().collect();
+        if fields.len() < 8 || fields[6] != "typ" {
+            return Err(err());
+        }
+
+        let mut candidate = RTCIceCandidate {
+            foundation: fields[0].to_string(),
+            component: fields[1].parse().map_err(|_| err())?,
+            protocol: fields[2].to_lowercase(),
+            priority: fields[3].parse().map_err(|_| err())?,
+            address: fields[4].to_string(),
+            port: fields[5].parse().map_err(|_| err())?,
+            typ: RTCIceCandidateType::from(fields[7]),
+            related_address: None,
+            related_port: None,
+        };
+        if candidate.typ == RTCIceCandidateType::Unspecified {
+            return Err(err());
+        }
+
+        // extension attributes, e.g., tcptype, generation or ufrag, are ignored
+        for pair in fields[8..].chunks(2) {
+            match pair {
+                ["raddr", address] => candidate.related_address = Some(address.to_string()),
+                ["rport", port] => candidate.related_port = Some(port.parse().map_err(|_| err())?),
+                _ => {}
+            }
+        }
+
+        Ok(candidate)
+    }
+}
+
+impl RTCIceCandidate {
+    /// socket address of the candidate, or None if its address is an mDNS hostname
+    pub fn socket_addr(&self) -> Option<SocketAddr> {
+        self.address
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, self.port))
+    }
+}
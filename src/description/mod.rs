@@ -1,17 +1,28 @@
+pub(crate) mod dependency_descriptor;
 pub(crate) mod fmtp;
+pub(crate) mod frame_marking;
+pub(crate) mod ice_candidate;
+pub(crate) mod playout_delay;
 pub(crate) mod rtp_codec;
 pub(crate) mod rtp_transceiver;
 pub(crate) mod rtp_transceiver_direction;
 pub(crate) mod sdp_type;
+pub(crate) mod simulcast;
 
 use crate::configs::session_config::SessionConfig;
 use crate::description::{
-    rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionParameters},
+    fmtp::intersect_fmtp,
+    ice_candidate::RTCIceCandidate,
+    rtp_codec::{
+        codec_parameters_fuzzy_search, validate_clock_rate, CodecMatch, RTCRtpCodecCapability,
+        RTCRtpCodecParameters, RTCRtpHeaderExtensionParameters, RTPCodecType,
+    },
     rtp_transceiver::{
         MediaStreamId, PayloadType, RTCPFeedback, RTCRtpTransceiver, SsrcGroup, SSRC,
     },
     rtp_transceiver_direction::RTCRtpTransceiverDirection,
     sdp_type::RTCSdpType,
+    simulcast::{RTCRtpRid, RTCRtpSimulcast, SimulcastDirection},
 };
 use crate::endpoint::candidate::RTCIceParameters;
 use crate::server::certificate::RTCDtlsFingerprint;
@@ -27,13 +38,20 @@ use sdp::util::ConnectionRole;
 use sdp::{MediaDescription, SessionDescription};
 use serde::{Deserialize, Serialize};
 use shared::error::{Error, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::{BufReader, Cursor};
 use std::net::SocketAddr;
 use url::Url;
 
 pub(crate) const UNSPECIFIED_STR: &str = "Unspecified";
 pub(crate) const SDP_ATTRIBUTE_RID: &str = "rid";
+pub(crate) const SDP_ATTRIBUTE_SIMULCAST: &str = "simulcast";
+pub(crate) const ATTR_KEY_EXTMAP_ALLOW_MIXED: &str = "extmap-allow-mixed";
+
+/// valid ids of one-byte RTP header extensions, <https://www.rfc-editor.org/rfc/rfc8285#section-4.2>
+const ONE_BYTE_HEADER_EXTENSION_IDS: std::ops::RangeInclusive<isize> = 1..=14;
+/// valid ids of two-byte RTP header extensions, <https://www.rfc-editor.org/rfc/rfc8285#section-4.3>
+const TWO_BYTE_HEADER_EXTENSION_IDS: std::ops::RangeInclusive<isize> = 1..=255;
 
 /// RTCSessionDescription is used to expose local and remote session descriptions.
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
@@ -60,6 +78,7 @@ impl RTCSessionDescription {
 
         let parsed = desc.unmarshal()?;
         desc.parsed = Some(parsed);
+        desc.validate()?;
 
         Ok(desc)
     }
@@ -75,6 +94,7 @@ impl RTCSessionDescription {
 
         let parsed = desc.unmarshal()?;
         desc.parsed = Some(parsed);
+        desc.validate()?;
 
         Ok(desc)
     }
@@ -91,6 +111,7 @@ impl RTCSessionDescription {
 
         let parsed = desc.unmarshal()?;
         desc.parsed = Some(parsed);
+        desc.validate()?;
 
         Ok(desc)
     }
@@ -102,17 +123,160 @@ impl RTCSessionDescription {
             .map_err(|err| Error::Other(err.to_string()))?;
         Ok(parsed)
     }
+
+    /// Validate checks the invariants of a parsed session description before it is used:
+    /// at least one media section, non-empty and unique MIDs, ICE credentials, a parseable
+    /// DTLS fingerprint, and a DTLS setup role consistent with its sdp_type.
+    ///
+    /// The sdp_type is checked against the setup role rather than the media directions, since
+    /// an answer's directions are only the complements of the offered ones, which a single
+    /// description doesn't tell, and offers with recvonly or inactive sections only, e.g., of
+    /// a subscribing endpoint, are valid. An answer or pranswer must not use setup:actpass.
+    pub fn validate(&self) -> Result<()> {
+        let parsed = self
+            .parsed
+            .as_ref()
+            .ok_or(Error::Other("ErrSessionDescriptionNotParsed".to_string()))?;
+
+        if parsed.media_descriptions.is_empty() {
+            return Err(Error::Other(
+                "ErrSessionDescriptionNoMediaSection".to_string(),
+            ));
+        }
+
+        let mut mids = HashSet::new();
+        for media in &parsed.media_descriptions {
+            match get_mid_value(media) {
+                Some(mid) if !mid.is_empty() => {
+                    if !mids.insert(mid) {
+                        return Err(Error::Other(format!(
+                            "ErrSessionDescriptionDuplicatedMid {}",
+                            mid
+                        )));
+                    }
+                }
+                _ => {
+                    return Err(Error::Other(
+                        "ErrPeerConnRemoteDescriptionWithoutMidValue".to_string(),
+                    ))
+                }
+            }
+
+            // validate codecs, e.g., clock rates, before any of them is negotiated
+            if media.media_name.media != MEDIA_SECTION_APPLICATION {
+                codecs_from_media_description(media)?;
+            }
+        }
+
+        let has_attribute = |key: &str| -> bool {
+            parsed.attribute(key).is_some()
+                || parsed
+                    .media_descriptions
+                    .iter()
+                    .any(|m| m.attribute(key).flatten().is_some())
+        };
+        if !has_attribute("ice-ufrag") {
+            return Err(Error::Other(
+                "ErrSessionDescriptionMissingIceUfrag".to_string(),
+            ));
+        }
+        if !has_attribute("ice-pwd") {
+            return Err(Error::Other(
+                "ErrSessionDescriptionMissingIcePwd".to_string(),
+            ));
+        }
+
+        let fingerprint = if let Some(fingerprint) = parsed.attribute("fingerprint") {
+            fingerprint
+        } else {
+            parsed
+                .media_descriptions
+                .iter()
+                .find_map(|m| m.attribute("fingerprint").flatten())
+                .ok_or(Error::Other(
+                    "ErrSessionDescriptionNoFingerprint".to_string(),
+                ))?
+        };
+        let _ = RTCDtlsFingerprint::try_from(fingerprint)?;
+
+        match self.sdp_type {
+            RTCSdpType::Offer => {}
+            RTCSdpType::Answer | RTCSdpType::Pranswer => {
+                // The answerer MUST use either a setup attribute value of setup:active
+                // or setup:passive, <https://tools.ietf.org/html/rfc5763#section-5>
+                let is_actpass = parsed.media_descriptions.iter().any(|m| {
+                    m.attribute(ATTR_KEY_CONNECTION_SETUP).flatten()
+                        == Some(ConnectionRole::Actpass.to_string().as_str())
+                });
+                if is_actpass {
+                    return Err(Error::Other(
+                        "ErrSessionDescriptionAnswerWithActpass".to_string(),
+                    ));
+                }
+            }
+            _ => {
+                return Err(Error::Other(format!(
+                    "ErrSessionDescriptionInvalidType {}",
+                    self.sdp_type
+                )))
+            }
+        }
+
+        Ok(())
+    }
 }
 
 pub(crate) const MEDIA_SECTION_APPLICATION: &str = "application";
 
-pub(crate) fn get_rids(media: &MediaDescription) -> HashMap<String, String> {
+/// get_rids returns rids of the media section's a=rid lines, in the order of its a=simulcast
+/// line if any, otherwise in the order of the a=rid lines
+pub(crate) fn get_rids(media: &MediaDescription) -> Vec<String> {
+    let mut rids: Vec<String> = vec![];
+    for attr in &media.attributes {
+        if attr.key.as_str() == SDP_ATTRIBUTE_RID {
+            if let Some(rid) = attr
+                .value
+                .as_ref()
+                .and_then(|value| value.split_whitespace().next())
+            {
+                if !rids.iter().any(|r| r == rid) {
+                    rids.push(rid.to_owned());
+                }
+            }
+        }
+    }
+    if let Some(simulcast) = get_simulcast(media) {
+        let order = simulcast.rids(SimulcastDirection::Send);
+        rids.sort_by_key(|rid| order.iter().position(|r| r == rid).unwrap_or(order.len()));
+    }
+    rids
+}
+
+/// get_simulcast returns the parsed a=simulcast line of the media section;
+/// a malformed one is skipped with a warning.
+pub(crate) fn get_simulcast(media: &MediaDescription) -> Option<RTCRtpSimulcast> {
+    let value = media.attribute(SDP_ATTRIBUTE_SIMULCAST).flatten()?;
+    match RTCRtpSimulcast::try_from(value) {
+        Ok(simulcast) => Some(simulcast),
+        Err(err) => {
+            log::warn!("skip malformed simulcast {}: {}", value, err);
+            None
+        }
+    }
+}
+
+/// get_rtp_rids returns rid lines with their parsed restrictions, keyed by rid;
+/// malformed rid lines are skipped.
+pub(crate) fn get_rtp_rids(media: &MediaDescription) -> HashMap<String, RTCRtpRid> {
     let mut rids = HashMap::new();
     for attr in &media.attributes {
         if attr.key.as_str() == SDP_ATTRIBUTE_RID {
-            if let Some(value) = &attr.value {
-                let split: Vec<&str> = value.split(' ').collect();
-                rids.insert(split[0].to_owned(), value.to_owned());
+            if let Some(rid) = attr
+                .value
+                .as_ref()
+                .and_then(|value| RTCRtpRid::try_from(value.as_str()).ok())
+            {
+                rids.insert(rid.id.clone(), rid);
             }
         }
     }
@@ -145,6 +309,29 @@ fn append_candidate_if_new(
     m: MediaDescription,
 ) -> MediaDescription {
     let marshaled = format!("1 {} UDP 1 {} {} typ host", component, c.ip(), c.port());
+    append_candidate_attribute_if_new(marshaled, m)
+}
+
+/// append relay candidate of the address allocated on TURN server, which relays to
+/// the host candidate, <https://datatracker.ietf.org/doc/html/rfc8839#section-5.1>
+fn append_relay_candidate_if_new(
+    relay: &SocketAddr,
+    c: &SocketAddr,
+    component: u16,
+    m: MediaDescription,
+) -> MediaDescription {
+    let marshaled = format!(
+        "2 {} UDP 1 {} {} typ relay raddr {} rport {}",
+        component,
+        relay.ip(),
+        relay.port(),
+        c.ip(),
+        c.port()
+    );
+    append_candidate_attribute_if_new(marshaled, m)
+}
+
+fn append_candidate_attribute_if_new(marshaled: String, m: MediaDescription) -> MediaDescription {
     for a in &m.attributes {
         if let Some(value) = &a.value {
             if &marshaled == value {
@@ -156,11 +343,15 @@ fn append_candidate_if_new(
 }
 
 pub(crate) fn add_candidate_to_media_descriptions(
-    candidate: &SocketAddr,
+    session_config: &SessionConfig,
     mut m: MediaDescription,
     ice_gathering_state: RTCIceGatheringState,
 ) -> Result<MediaDescription> {
+    let candidate = &session_config.local_addr;
     m = append_candidate_if_new(candidate, 1, m); // 1: RTP
+    if let Some(relay) = &session_config.server_config.turn_relay_addr {
+        m = append_relay_candidate_if_new(relay, candidate, 1, m);
+    }
 
     //TODO: m = append_candidate_if_new(candidate, 2, m); // 2: RTCP
 
@@ -179,6 +370,8 @@ pub(crate) fn add_candidate_to_media_descriptions(
 pub(crate) struct AddDataMediaSectionParams {
     should_add_candidates: bool,
     mid_value: String,
+    /// whether the section is rejected with port 0, e.g., when its mid conflicts with media
+    rejected: bool,
     ice_params: RTCIceParameters,
     dtls_role: ConnectionRole,
     ice_gathering_state: RTCIceGatheringState,
@@ -194,7 +387,7 @@ pub(crate) fn add_data_media_section(
         media_name: MediaName {
             media: MEDIA_SECTION_APPLICATION.to_owned(),
             port: RangedPort {
-                value: 9,
+                value: if params.rejected { 0 } else { 9 },
                 range: None,
             },
             protos: vec!["UDP".to_owned(), "DTLS".to_owned(), "SCTP".to_owned()],
@@ -248,11 +441,8 @@ pub(crate) fn add_data_media_section(
     }
 
     if params.should_add_candidates {
-        media = add_candidate_to_media_descriptions(
-            &session_config.local_addr,
-            media,
-            params.ice_gathering_state,
-        )?;
+        media =
+            add_candidate_to_media_descriptions(session_config, media, params.ice_gathering_state)?;
     }
 
     Ok(d.with_media(media))
@@ -292,6 +482,10 @@ pub(crate) fn add_transceiver_sdp(
             )
             .with_property_attribute(ATTR_KEY_RTCPMUX.to_owned())
             .with_property_attribute(ATTR_KEY_RTCPRSIZE.to_owned());
+    if transceiver.is_stopped() {
+        // <https://datatracker.ietf.org/doc/html/rfc8829#section-5.2.2>
+        media.media_name.port.value = 0;
+    }
 
     for fingerprint in dtls_fingerprints {
         media = media.with_fingerprint(
@@ -301,33 +495,53 @@ pub(crate) fn add_transceiver_sdp(
     }
 
     if should_add_candidates {
-        media = add_candidate_to_media_descriptions(
-            &session_config.local_addr,
-            media,
-            ice_gathering_state,
-        )?;
+        media = add_candidate_to_media_descriptions(session_config, media, ice_gathering_state)?;
     }
 
-    let codecs = session_config
-        .server_config
-        .media_config
-        .get_codecs_by_kind(transceiver.kind);
-    for codec in codecs {
+    let media_config = &session_config.server_config.media_config;
+    let mut codecs = transceiver.get_codecs(media_config);
+    // preferred codecs go first, the others keep their registration order
+    codecs.sort_by_key(|codec| {
+        session_config
+            .codec_preferences
+            .iter()
+            .position(|mime_type| mime_type.eq_ignore_ascii_case(&codec.capability.mime_type))
+            .unwrap_or(session_config.codec_preferences.len())
+    });
+    for codec in &codecs {
         let name = codec
             .capability
             .mime_type
             .trim_start_matches("audio/")
             .trim_start_matches("video/")
             .to_owned();
+
+        // when answering, narrow fmtp to what both we and the remote support
+        let local_sdp_fmtp_line = media_config.sdp_fmtp_line_for_codec(codec);
+        let sdp_fmtp_line = match params.offered_direction {
+            Some(_) => match codec_parameters_fuzzy_search(codec, &transceiver.rtp_params.codecs) {
+                (remote_codec, CodecMatch::Exact | CodecMatch::Partial) => intersect_fmtp(
+                    &codec.capability.mime_type,
+                    &local_sdp_fmtp_line,
+                    &remote_codec.capability.sdp_fmtp_line,
+                ),
+                (_, CodecMatch::None) => local_sdp_fmtp_line,
+            },
+            None => local_sdp_fmtp_line,
+        };
         media = media.with_codec(
             codec.payload_type,
             name,
             codec.capability.clock_rate,
             codec.capability.channels,
-            codec.capability.sdp_fmtp_line.clone(),
+            sdp_fmtp_line,
         );
 
-        for feedback in &codec.capability.rtcp_feedbacks {
+        for feedback in codec.capability.rtcp_feedbacks.iter().filter(|feedback| {
+            !session_config
+                .disabled_rtcp_feedbacks
+                .contains(&feedback.typ)
+        }) {
             media = media.with_value_attribute(
                 "rtcp-fb".to_owned(),
                 format!(
@@ -342,7 +556,16 @@ pub(crate) fn add_transceiver_sdp(
         .server_config
         .media_config
         .get_rtp_parameters_by_kind(transceiver.kind, transceiver.direction);
-    for rtp_extension in parameters.header_extensions {
+    // when answering, take the remote's ids and avoid the ones it already uses
+    let header_extensions = match params.offered_direction {
+        Some(_) => resolve_header_extension_ids(
+            &parameters.header_extensions,
+            &transceiver.rtp_params.header_extensions,
+            media_section.extmap_allow_mixed,
+        ),
+        None => parameters.header_extensions,
+    };
+    for rtp_extension in header_extensions {
         let ext_url = Url::parse(rtp_extension.uri.as_str())?;
         media = media.with_extmap(ExtMap {
             value: rtp_extension.id,
@@ -351,18 +574,15 @@ pub(crate) fn add_transceiver_sdp(
         });
     }
 
-    if !media_section.rid_map.is_empty() {
-        let mut recv_rids: Vec<String> = vec![];
-
-        for rid in media_section.rid_map.keys() {
+    if !media_section.rids.is_empty() {
+        for rid in &media_section.rids {
             media =
                 media.with_value_attribute(SDP_ATTRIBUTE_RID.to_owned(), rid.to_owned() + " recv");
-            recv_rids.push(rid.to_owned());
         }
         // Simulcast
         media = media.with_value_attribute(
-            "simulcast".to_owned(),
-            "recv ".to_owned() + recv_rids.join(";").as_str(),
+            SDP_ATTRIBUTE_SIMULCAST.to_owned(),
+            "recv ".to_owned() + media_section.rids.join(";").as_str(),
         );
     }
 
@@ -429,7 +649,7 @@ pub(crate) fn add_transceiver_sdp(
             for ssrc in &sender.ssrcs {
                 media = media.with_media_source(
                     *ssrc,
-                    sender.cname.clone(),
+                    session_config.cname_for_ssrc(*ssrc, &sender.cname),
                     sender.msid.stream_id.clone(),
                     sender.msid.track_id.clone(),
                 );
@@ -441,15 +661,19 @@ pub(crate) fn add_transceiver_sdp(
         }
     }
 
-    Ok((d.with_media(media), true))
+    // rejected media sections are not bundled,
+    // <https://datatracker.ietf.org/doc/html/rfc8843#section-7.3.3>
+    Ok((d.with_media(media), !transceiver.is_stopped()))
 }
 
 #[derive(Default)]
 pub(crate) struct MediaSection {
     pub(crate) mid: Mid,
     pub(crate) data: bool,
-    pub(crate) rid_map: HashMap<String, String>,
+    /// rids sent by the remote, in the order of its simulcast streams
+    pub(crate) rids: Vec<String>,
     pub(crate) offered_direction: Option<RTCRtpTransceiverDirection>,
+    pub(crate) extmap_allow_mixed: bool,
 }
 
 /// populate_sdp serializes a PeerConnections state into an SDP
@@ -478,24 +702,30 @@ pub(crate) fn populate_sdp(
     };
 
     for (i, m) in media_sections.iter().enumerate() {
-        if m.data && transceivers.get(&m.mid).is_some() {
-            return Err(Error::Other(
-                "ErrSDPMediaSectionMediaDataChanInvalid".to_string(),
-            ));
-        }
-
         let should_add_candidates = i == 0;
 
         let should_add_id = if m.data {
+            // a data section whose mid is also used by media, e.g., by a buggy remote,
+            // is rejected alone instead of failing the whole SDP
+            let rejected = transceivers.contains_key(&m.mid);
+            if rejected {
+                log::warn!(
+                    "reject data media section whose mid {} is used by media too",
+                    m.mid
+                );
+            }
             let params = AddDataMediaSectionParams {
                 should_add_candidates,
                 mid_value: m.mid.clone(),
+                rejected,
                 ice_params: ice_params.clone(),
                 dtls_role: connection_role,
                 ice_gathering_state: RTCIceGatheringState::Complete,
             };
             d = add_data_media_section(d, &media_dtls_fingerprints, session_config, params)?;
-            true
+            // rejected media sections are not bundled,
+            // <https://datatracker.ietf.org/doc/html/rfc8843#section-7.3.3>
+            !rejected
         } else {
             let params = AddTransceiverSdpParams {
                 should_add_candidates,
@@ -533,6 +763,10 @@ pub(crate) fn populate_sdp(
         }
     }
 
+    if media_sections.iter().any(|m| m.extmap_allow_mixed) {
+        d = d.with_property_attribute(ATTR_KEY_EXTMAP_ALLOW_MIXED.to_owned());
+    }
+
     // is_ice_lite for SFU
     // RFC 5245 S15.3
     d = d.with_property_attribute(ATTR_KEY_ICELITE.to_owned());
@@ -607,7 +841,9 @@ pub(crate) fn get_msid(media: &MediaDescription) -> Option<MediaStreamId> {
     None
 }
 
-pub(crate) fn get_ssrc_groups(media: &MediaDescription) -> Result<Vec<SsrcGroup>> {
+/// get_ssrc_groups returns ssrc groups of the media description, where malformed ssrcs are
+/// skipped with a warning, and so are the groups left with less than two ssrcs
+pub(crate) fn get_ssrc_groups(media: &MediaDescription) -> Vec<SsrcGroup> {
     let mut ssrc_groups = vec![];
 
     for a in &media.attributes {
@@ -617,37 +853,51 @@ pub(crate) fn get_ssrc_groups(media: &MediaDescription) -> Result<Vec<SsrcGroup>
                 if fields.len() >= 3 {
                     let mut ssrcs = vec![];
                     for field in fields.iter().skip(1) {
-                        let ssrc = field.parse::<u32>()?;
-                        ssrcs.push(ssrc);
+                        match field.parse::<u32>() {
+                            Ok(ssrc) => ssrcs.push(ssrc),
+                            Err(err) => {
+                                log::warn!("skip malformed ssrc-group ssrc {}: {}", field, err)
+                            }
+                        }
+                    }
+                    if ssrcs.len() >= 2 {
+                        ssrc_groups.push(SsrcGroup {
+                            name: fields[0].to_string(),
+                            ssrcs,
+                        });
                     }
-                    ssrc_groups.push(SsrcGroup {
-                        name: fields[0].to_string(),
-                        ssrcs,
-                    });
                 }
             }
         }
     }
 
-    Ok(ssrc_groups)
+    ssrc_groups
 }
 
-pub(crate) fn get_ssrcs(media: &MediaDescription) -> Result<Vec<SSRC>> {
+/// get_ssrcs returns ssrcs of the media description, where malformed ones are skipped with
+/// a warning, so that a single bad a=ssrc line doesn't fail the whole negotiation
+pub(crate) fn get_ssrcs(media: &MediaDescription) -> Vec<SSRC> {
     let mut ssrcs = Vec::new();
     for a in &media.attributes {
         if a.key == "ssrc" {
             if let Some(value) = a.value.as_ref() {
                 let fields: Vec<&str> = value.split_whitespace().collect();
                 if !fields.is_empty() {
-                    let ssrc = fields[0].parse::<u32>()?;
-                    if !ssrcs.iter().any(|&s| s == ssrc) {
+                    let ssrc = match fields[0].parse::<u32>() {
+                        Ok(ssrc) => ssrc,
+                        Err(err) => {
+                            log::warn!("skip malformed ssrc {}: {}", fields[0], err);
+                            continue;
+                        }
+                    };
+                    if !ssrcs.contains(&ssrc) {
                         ssrcs.push(ssrc);
                     };
                 }
             }
         }
     }
-    Ok(ssrcs)
+    ssrcs
 }
 
 pub(crate) fn extract_fingerprint(desc: &SessionDescription) -> Result<(String, String)> {
@@ -687,61 +937,24 @@ pub(crate) fn extract_fingerprint(desc: &SessionDescription) -> Result<(String,
     Ok((parts[1].to_owned(), parts[0].to_owned()))
 }
 
-/*
-pub(crate) fn extract_ice_details(
-    desc: &SessionDescription,
-) -> Result<(String, String, Vec<RTCIceCandidate>)> {
+/// extract_ice_candidates returns the a=candidate lines of all media sections,
+/// skipping malformed ones, since the SFU is ice-lite and waits for STUN anyway
+pub(crate) fn extract_ice_candidates(desc: &SessionDescription) -> Vec<RTCIceCandidate> {
     let mut candidates = vec![];
-    let mut remote_pwds = vec![];
-    let mut remote_ufrags = vec![];
-
-    if let Some(ufrag) = desc.attribute("ice-ufrag") {
-        remote_ufrags.push(ufrag.clone());
-    }
-    if let Some(pwd) = desc.attribute("ice-pwd") {
-        remote_pwds.push(pwd.clone());
-    }
-
     for m in &desc.media_descriptions {
-        if let Some(ufrag) = m.attribute("ice-ufrag").and_then(|o| o) {
-            remote_ufrags.push(ufrag.to_owned());
-        }
-        if let Some(pwd) = m.attribute("ice-pwd").and_then(|o| o) {
-            remote_pwds.push(pwd.to_owned());
-        }
-
         for a in &m.attributes {
             if a.is_ice_candidate() {
                 if let Some(value) = &a.value {
-                    let c: Arc<dyn Candidate + Send + Sync> = Arc::new(unmarshal_candidate(value)?);
-                    let candidate = RTCIceCandidate::from(&c);
-                    candidates.push(candidate);
+                    match RTCIceCandidate::try_from(value.as_str()) {
+                        Ok(candidate) => candidates.push(candidate),
+                        Err(err) => log::warn!("{}", err),
+                    }
                 }
             }
         }
     }
-
-    if remote_ufrags.is_empty() {
-        return Err(Error::ErrSessionDescriptionMissingIceUfrag);
-    } else if remote_pwds.is_empty() {
-        return Err(Error::ErrSessionDescriptionMissingIcePwd);
-    }
-
-    for m in 1..remote_ufrags.len() {
-        if remote_ufrags[m] != remote_ufrags[0] {
-            return Err(Error::ErrSessionDescriptionConflictingIceUfrag);
-        }
-    }
-
-    for m in 1..remote_pwds.len() {
-        if remote_pwds[m] != remote_pwds[0] {
-            return Err(Error::ErrSessionDescriptionConflictingIcePwd);
-        }
-    }
-
-    Ok((remote_ufrags[0].clone(), remote_pwds[0].clone(), candidates))
+    candidates
 }
-*/
 
 pub(crate) fn have_application_media_section(desc: &SessionDescription) -> bool {
     for m in &desc.media_descriptions {
@@ -789,6 +1002,7 @@ pub(crate) fn codecs_from_media_description(
         ..Default::default()
     };
 
+    let typ = RTPCodecType::from(m.media_name.media.as_str());
     let mut out = vec![];
     for payload_str in &m.media_name.formats {
         let payload_type: PayloadType = payload_str.parse::<u8>()?;
@@ -802,6 +1016,8 @@ pub(crate) fn codecs_from_media_description(
             }
         };
 
+        validate_clock_rate(typ, codec.clock_rate)?;
+
         let channels = codec.encoding_parameters.parse::<u16>().unwrap_or(0);
 
         let mut feedback = vec![];
@@ -863,6 +1079,56 @@ pub(crate) fn rtp_extensions_from_media_description(
     Ok(out)
 }
 
+/// resolve_header_extension_ids resolves ids of local header extensions against the remote's ones,
+/// where matched uris take the remote's id, and the other local extensions keep their ids unless
+/// taken by the remote, otherwise get the lowest free id. Ids are limited to one-byte header range
+/// unless extmap-allow-mixed is negotiated, and extensions without any free id are dropped.
+pub fn resolve_header_extension_ids(
+    local: &[RTCRtpHeaderExtensionParameters],
+    remote: &[RTCRtpHeaderExtensionParameters],
+    extmap_allow_mixed: bool,
+) -> Vec<RTCRtpHeaderExtensionParameters> {
+    let valid_ids = if extmap_allow_mixed {
+        TWO_BYTE_HEADER_EXTENSION_IDS
+    } else {
+        ONE_BYTE_HEADER_EXTENSION_IDS
+    };
+
+    let mut used_ids: HashSet<isize> = remote.iter().map(|extension| extension.id).collect();
+    let mut resolved: Vec<Option<RTCRtpHeaderExtensionParameters>> = local
+        .iter()
+        .map(|local_extension| {
+            remote
+                .iter()
+                .find(|remote_extension| remote_extension.uri == local_extension.uri)
+                .cloned()
+        })
+        .collect();
+
+    for (local_extension, resolved_extension) in local.iter().zip(resolved.iter_mut()) {
+        if resolved_extension.is_some() {
+            continue;
+        }
+        let id =
+            if valid_ids.contains(&local_extension.id) && !used_ids.contains(&local_extension.id) {
+                Some(local_extension.id)
+            } else {
+                valid_ids.clone().find(|id| !used_ids.contains(id))
+            };
+        if let Some(id) = id {
+            used_ids.insert(id);
+            *resolved_extension = Some(RTCRtpHeaderExtensionParameters {
+                uri: local_extension.uri.clone(),
+                id,
+            });
+        } else {
+            log::warn!("No available RTP extension ID for {}", local_extension.uri);
+        }
+    }
+
+    resolved.into_iter().flatten().collect()
+}
+
 /// update_sdp_origin saves sdp.Origin in PeerConnection when creating 1st local SDP;
 /// for subsequent calling, it updates Origin for SessionDescription from saved one
 /// and increments session version by one.
@@ -886,6 +1152,6 @@ pub(crate) fn update_sdp_origin(origin: &mut Origin, d: &mut SessionDescription)
 
         //d.Origin.SessionVersion = atomic.AddUint64(&origin.SessionVersion, 1)
         origin.session_version += 1;
-        d.origin.session_version += 1;
+        d.origin.session_version = origin.session_version;
     }
 }
@@ -0,0 +1,82 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use shared::error::{Error, Result};
+
+/// PLAYOUT_DELAY_URI is the URI of playout-delay RTP header extension,
+/// <https://webrtc.googlesource.com/src/+/refs/heads/main/docs/native-code/rtp-hdrext/playout-delay>
+pub const PLAYOUT_DELAY_URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/playout-delay";
+
+/// playout-delay payload is 3 bytes: 12 bits of min delay and 12 bits of max delay
+const PLAYOUT_DELAY_EXTENSION_SIZE: usize = 3;
+/// max value of 12 bits delay, in 10 ms granularity
+const PLAYOUT_DELAY_MAX_VALUE: u16 = 0x0fff;
+
+/// LatencyMode controls the playout delay hinted to subscribers
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LatencyMode {
+    /// Realtime asks subscribers to render frames as soon as possible
+    #[default]
+    Realtime,
+    /// Smooth allows subscribers to buffer frames for smoother playout
+    Smooth,
+}
+
+/// PlayoutDelay represents the minimum/maximum playout delay, in 10 ms granularity
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PlayoutDelay {
+    pub min_delay: u16,
+    pub max_delay: u16,
+}
+
+impl From<LatencyMode> for PlayoutDelay {
+    fn from(latency_mode: LatencyMode) -> Self {
+        match latency_mode {
+            LatencyMode::Realtime => PlayoutDelay {
+                min_delay: 0,
+                max_delay: 0,
+            },
+            // 100ms ~ 1s
+            LatencyMode::Smooth => PlayoutDelay {
+                min_delay: 10,
+                max_delay: 100,
+            },
+        }
+    }
+}
+
+impl PlayoutDelay {
+    /// marshal playout-delay into header extension payload
+    pub fn marshal(&self) -> Result<Bytes> {
+        if self.min_delay > PLAYOUT_DELAY_MAX_VALUE
+            || self.max_delay > PLAYOUT_DELAY_MAX_VALUE
+            || self.min_delay > self.max_delay
+        {
+            return Err(Error::Other(format!(
+                "ErrInvalidPlayoutDelay min {} max {}",
+                self.min_delay, self.max_delay
+            )));
+        }
+
+        let mut buf = BytesMut::with_capacity(PLAYOUT_DELAY_EXTENSION_SIZE);
+        buf.put_u8((self.min_delay >> 4) as u8);
+        buf.put_u8(((self.min_delay << 4) as u8) | ((self.max_delay >> 8) as u8));
+        buf.put_u8(self.max_delay as u8);
+        Ok(buf.freeze())
+    }
+
+    /// unmarshal playout-delay from header extension payload
+    pub fn unmarshal(raw: &[u8]) -> Result<Self> {
+        if raw.len() < PLAYOUT_DELAY_EXTENSION_SIZE {
+            return Err(Error::ErrShortBuffer);
+        }
+
+        Ok(PlayoutDelay {
+            min_delay: ((raw[0] as u16) << 4) | ((raw[1] as u16) >> 4),
+            max_delay: (((raw[1] & 0x0f) as u16) << 8) | raw[2] as u16,
+        })
+    }
+
+    /// set playout-delay header extension with the negotiated id on the RTP header
+    pub fn set_extension(&self, header: &mut rtp::header::Header, id: u8) -> Result<()> {
+        header.set_extension(id, self.marshal()?)
+    }
+}
@@ -6,7 +6,7 @@ use crate::description::{
 use shared::error::{Error, Result};
 
 /// RTPCodecType determines the type of a codec
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum RTPCodecType {
     #[default]
     Unspecified = 0,
@@ -49,6 +49,25 @@ impl std::fmt::Display for RTPCodecType {
     }
 }
 
+/// VIDEO_CLOCK_RATE is the clock rate of all video codecs,
+/// <https://tools.ietf.org/html/rfc3551#section-5>
+pub(crate) const VIDEO_CLOCK_RATE: u32 = 90000;
+/// AUDIO_CLOCK_RATES are the IANA-registered sample rates accepted for audio codecs
+pub(crate) const AUDIO_CLOCK_RATES: [u32; 5] = [8000, 16000, 24000, 32000, 48000];
+
+/// validate_clock_rate checks the clock rate of a codec against its codec type
+pub(crate) fn validate_clock_rate(typ: RTPCodecType, clock_rate: u32) -> Result<()> {
+    match typ {
+        RTPCodecType::Video if clock_rate != VIDEO_CLOCK_RATE => {
+            Err(Error::Other("video clock rate must be 90000".to_string()))
+        }
+        RTPCodecType::Audio if !AUDIO_CLOCK_RATES.contains(&clock_rate) => Err(Error::Other(
+            format!("audio clock rate {} is not supported", clock_rate),
+        )),
+        _ => Ok(()),
+    }
+}
+
 /// RTPCodecCapability provides information about codec capabilities.
 /// <https://w3c.github.io/webrtc-pc/#dictionary-rtcrtpcodeccapability-members>
 #[derive(Default, Debug, Clone, PartialEq, Eq)]
@@ -1,7 +1,13 @@
+use crate::configs::media_config::MediaConfig;
 use crate::description::{
-    rtp_codec::{RTCRtpParameters, RTPCodecType},
+    rtp_codec::{
+        codec_parameters_fuzzy_search, CodecMatch, RTCRtpCodecParameters, RTCRtpParameters,
+        RTPCodecType,
+    },
     rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    simulcast::RTCRtpRid,
 };
+use std::collections::HashMap;
 
 /// SSRC represents a synchronization source
 /// A synchronization source is a randomly chosen
@@ -77,8 +83,12 @@ pub struct RTCRtpTransceiver {
     pub(crate) current_direction: RTCRtpTransceiverDirection,
 
     pub(crate) rtp_params: RTCRtpParameters,
+    pub(crate) rids: HashMap<String, RTCRtpRid>,
 
     pub(crate) kind: RTPCodecType,
+
+    /// stopped transceivers are kept in place, but rejected with port 0 in session descriptions
+    pub(crate) stopped: bool,
 }
 
 impl RTCRtpTransceiver {
@@ -90,4 +100,44 @@ impl RTCRtpTransceiver {
     pub(crate) fn set_current_direction(&mut self, d: RTCRtpTransceiverDirection) {
         self.current_direction = d;
     }
+
+    /// stop stops sending and receiving media of the transceiver for good
+    pub(crate) fn stop(&mut self) {
+        self.stopped = true;
+        self.direction = RTCRtpTransceiverDirection::Inactive;
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// get_header_extension_id returns negotiated id of the header extension, if any
+    /// get_codecs returns the server's codecs of the transceiver's kind, which are supported by
+    /// the remote too, i.e., matching its codecs by mime type and fmtp, in the server's order
+    pub(crate) fn get_codecs(&self, media_config: &MediaConfig) -> Vec<RTCRtpCodecParameters> {
+        media_config
+            .get_codecs_by_kind(self.kind)
+            .iter()
+            .filter(|codec| {
+                !matches!(
+                    codec_parameters_fuzzy_search(codec, &self.rtp_params.codecs),
+                    (_, CodecMatch::None)
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn get_header_extension_id(&self, uri: &str) -> Option<u8> {
+        self.rtp_params
+            .header_extensions
+            .iter()
+            .find(|ext| ext.uri == uri)
+            .and_then(|ext| u8::try_from(ext.id).ok())
+    }
+
+    /// rids returns simulcast rids with their restrictions, e.g., for layer selection
+    pub(crate) fn rids(&self) -> &HashMap<String, RTCRtpRid> {
+        &self.rids
+    }
 }
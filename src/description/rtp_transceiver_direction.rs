@@ -2,7 +2,7 @@ use crate::description::UNSPECIFIED_STR;
 use std::fmt;
 
 /// RTPTransceiverDirection indicates the direction of the RTPTransceiver.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
 pub enum RTCRtpTransceiverDirection {
     #[default]
     Unspecified,
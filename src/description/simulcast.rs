@@ -0,0 +1,171 @@
+use crate::description::rtp_transceiver::PayloadType;
+use shared::error::{Error, Result};
+use std::fmt;
+
+/// SimulcastDirection indicates whether a rid is sent or received by the SDP author,
+/// <https://tools.ietf.org/html/rfc8851#section-4>
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SimulcastDirection {
+    #[default]
+    Unspecified,
+    Send,
+    Recv,
+}
+
+impl From<&str> for SimulcastDirection {
+    fn from(raw: &str) -> Self {
+        match raw {
+            "send" => SimulcastDirection::Send,
+            "recv" => SimulcastDirection::Recv,
+            _ => SimulcastDirection::Unspecified,
+        }
+    }
+}
+
+impl fmt::Display for SimulcastDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match *self {
+            SimulcastDirection::Send => "send",
+            SimulcastDirection::Recv => "recv",
+            SimulcastDirection::Unspecified => super::UNSPECIFIED_STR,
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// RTCRtpRidRestrictions holds the restrictions of a rid, which are useful for layer selection,
+/// <https://tools.ietf.org/html/rfc8851#section-5>
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RTCRtpRidRestrictions {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_fps: Option<f64>,
+    pub max_fs: Option<u32>,
+    pub max_br: Option<u32>,
+    pub max_pps: Option<u32>,
+    pub max_bpp: Option<f64>,
+    pub depend: Vec<String>,
+}
+
+/// RTCRtpRid represents an a=rid line of a media section,
+/// e.g., `a=rid:hi recv pt=96;max-width=1280;max-height=720`
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct RTCRtpRid {
+    pub id: String,
+    pub direction: SimulcastDirection,
+    pub payload_types: Vec<PayloadType>,
+    pub restrictions: RTCRtpRidRestrictions,
+}
+
+impl TryFrom<&str> for RTCRtpRid {
+    type Error = Error;
+
+    /// parse the value of an a=rid line, without the `a=rid:` prefix
+    fn try_from(value: &str) -> Result<Self> {
+        let mut fields = value.split_whitespace();
+        let id = fields
+            .next()
+            .ok_or(Error::Other(format!("ErrInvalidRid {}", value)))?
+            .to_string();
+        let direction = SimulcastDirection::from(fields.next().unwrap_or_default());
+        if direction == SimulcastDirection::Unspecified {
+            return Err(Error::Other(format!("ErrInvalidRidDirection {}", value)));
+        }
+
+        let mut rid = RTCRtpRid {
+            id,
+            direction,
+            ..Default::default()
+        };
+
+        for param in fields.flat_map(|params| params.split(';')) {
+            let (key, val) = param.split_once('=').unwrap_or((param, ""));
+            let parse_err = || Error::Other(format!("ErrInvalidRidRestriction {}", param));
+            let restrictions = &mut rid.restrictions;
+            match key {
+                "pt" => {
+                    for pt in val.split(',') {
+                        rid.payload_types
+                            .push(pt.parse::<PayloadType>().map_err(|_| parse_err())?);
+                    }
+                }
+                "max-width" => restrictions.max_width = Some(val.parse().map_err(|_| parse_err())?),
+                "max-height" => {
+                    restrictions.max_height = Some(val.parse().map_err(|_| parse_err())?)
+                }
+                "max-fps" => restrictions.max_fps = Some(val.parse().map_err(|_| parse_err())?),
+                "max-fs" => restrictions.max_fs = Some(val.parse().map_err(|_| parse_err())?),
+                "max-br" => restrictions.max_br = Some(val.parse().map_err(|_| parse_err())?),
+                "max-pps" => restrictions.max_pps = Some(val.parse().map_err(|_| parse_err())?),
+                "max-bpp" => restrictions.max_bpp = Some(val.parse().map_err(|_| parse_err())?),
+                "depend" => restrictions.depend = val.split(',').map(|d| d.to_string()).collect(),
+                // unknown restrictions are ignored, <https://tools.ietf.org/html/rfc8851#section-5>
+                _ => {}
+            }
+        }
+
+        Ok(rid)
+    }
+}
+
+/// RTCRtpSimulcast represents an a=simulcast line, e.g., `a=simulcast:send hi;mid,~lo recv r0`,
+/// where each direction lists its simulcast streams in order, and each stream lists its
+/// alternative rids, <https://tools.ietf.org/html/rfc8853#section-5.1>
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct RTCRtpSimulcast {
+    pub send: Vec<Vec<String>>,
+    pub recv: Vec<Vec<String>>,
+}
+
+impl RTCRtpSimulcast {
+    /// rids returns all rids of the direction in order, with alternatives of a stream in order
+    pub fn rids(&self, direction: SimulcastDirection) -> Vec<String> {
+        let streams = match direction {
+            SimulcastDirection::Send => &self.send,
+            SimulcastDirection::Recv => &self.recv,
+            SimulcastDirection::Unspecified => return vec![],
+        };
+        streams.iter().flatten().cloned().collect()
+    }
+}
+
+impl TryFrom<&str> for RTCRtpSimulcast {
+    type Error = Error;
+
+    /// parse the value of an a=simulcast line, without the `a=simulcast:` prefix,
+    /// where the paused `~` prefix of rids is dropped
+    fn try_from(value: &str) -> Result<Self> {
+        let mut simulcast = RTCRtpSimulcast::default();
+        let mut fields = value.split_whitespace();
+        while let Some(direction) = fields.next() {
+            let streams = match SimulcastDirection::from(direction) {
+                SimulcastDirection::Send if simulcast.send.is_empty() => &mut simulcast.send,
+                SimulcastDirection::Recv if simulcast.recv.is_empty() => &mut simulcast.recv,
+                _ => {
+                    return Err(Error::Other(format!(
+                        "ErrInvalidSimulcastDirection {}",
+                        value
+                    )))
+                }
+            };
+            let list = fields
+                .next()
+                .ok_or(Error::Other(format!("ErrInvalidSimulcast {}", value)))?;
+            for alternatives in list.split(';') {
+                let stream: Vec<String> = alternatives
+                    .split(',')
+                    .map(|rid| rid.trim_start_matches('~').to_string())
+                    .collect();
+                if stream.iter().any(|rid| rid.is_empty()) {
+                    return Err(Error::Other(format!("ErrInvalidSimulcastRid {}", value)));
+                }
+                streams.push(stream);
+            }
+        }
+
+        if simulcast.send.is_empty() && simulcast.recv.is_empty() {
+            return Err(Error::Other(format!("ErrInvalidSimulcast {}", value)));
+        }
+        Ok(simulcast)
+    }
+}
@@ -1,14 +1,14 @@
 use crate::description::{RTCSessionDescription, UNSPECIFIED_STR};
 use crate::server::certificate::RTCDtlsFingerprint;
 use crate::types::{EndpointId, SessionId, UserName};
-use base64::{prelude::BASE64_STANDARD, Engine};
 use ring::rand::{SecureRandom, SystemRandom};
 use sdp::util::ConnectionRole;
 use sdp::SessionDescription;
 use serde::{Deserialize, Serialize};
 use shared::error::{Error, Result};
+use std::cell::Cell;
 use std::fmt;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 /// DtlsRole indicates the role of the DTLS transport.
 #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -92,6 +92,23 @@ impl DTLSRole {
     }
 }
 
+/// ice-char = ALPHA / DIGIT / "+" / "/", <https://tools.ietf.org/html/rfc5245#section-15.1>
+const RUNES_ICE_CHAR: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789+/";
+
+/// generate_ice_chars generates a crypto random ice-char sequence of the requested length.
+fn generate_ice_chars(n: usize) -> String {
+    let rng = SystemRandom::new();
+
+    let mut random = vec![0u8; n];
+    let _ = rng.fill(&mut random);
+
+    // RUNES_ICE_CHAR has 64 runes, so each random byte maps uniformly by its lower 6 bits
+    random
+        .iter()
+        .map(|b| RUNES_ICE_CHAR[(b & 0x3f) as usize] as char)
+        .collect()
+}
+
 /// ICEParameters includes the ICE username fragment
 /// and password and other ICE-related parameters.
 #[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -100,6 +117,15 @@ pub(crate) struct RTCIceParameters {
     pub(crate) password: String,
 }
 
+impl RTCIceParameters {
+    pub(crate) fn new(ice_ufrag_len: usize, ice_pwd_len: usize) -> Self {
+        Self {
+            username_fragment: generate_ice_chars(ice_ufrag_len),
+            password: generate_ice_chars(ice_pwd_len),
+        }
+    }
+}
+
 /// DTLSParameters holds information relating to DTLS configuration.
 #[derive(Default, Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub(crate) struct DTLSParameters {
@@ -114,19 +140,14 @@ pub(crate) struct ConnectionCredentials {
 }
 
 impl ConnectionCredentials {
-    pub(crate) fn new(fingerprints: Vec<RTCDtlsFingerprint>, remote_role: DTLSRole) -> Self {
-        let rng = SystemRandom::new();
-
-        let mut user = [0u8; 9];
-        let _ = rng.fill(&mut user);
-        let mut password = [0u8; 18];
-        let _ = rng.fill(&mut password);
-
+    pub(crate) fn new(
+        fingerprints: Vec<RTCDtlsFingerprint>,
+        remote_role: DTLSRole,
+        ice_ufrag_len: usize,
+        ice_pwd_len: usize,
+    ) -> Self {
         Self {
-            ice_params: RTCIceParameters {
-                username_fragment: BASE64_STANDARD.encode(&user[..]),
-                password: BASE64_STANDARD.encode(&password[..]),
-            },
+            ice_params: RTCIceParameters::new(ice_ufrag_len, ice_pwd_len),
             dtls_params: DTLSParameters {
                 fingerprints,
                 role: if remote_role == DTLSRole::Server {
@@ -185,6 +206,16 @@ impl ConnectionCredentials {
     }
 }
 
+/// RTCIceRole is the ICE role of SFU for a candidate, which starts as controlled as ICE-lite
+/// agent, but may be switched by resolving role conflicts,
+/// <https://www.rfc-editor.org/rfc/rfc8445#section-7.3.1.1>
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RTCIceRole {
+    #[default]
+    Controlled,
+    Controlling,
+}
+
 #[derive(Debug)]
 pub(crate) struct Candidate {
     session_id: SessionId,
@@ -193,18 +224,21 @@ pub(crate) struct Candidate {
     local_conn_cred: ConnectionCredentials,
     remote_description: RTCSessionDescription,
     local_description: RTCSessionDescription,
-    expired_time: Instant,
+    created_at: Instant,
+    ttl: Duration,
+    ice_role: Cell<RTCIceRole>,
+    ice_tiebreaker: u64,
 }
 
 impl Candidate {
-    pub(crate) fn new(
+    pub(crate) fn new_with_ttl(
         session_id: SessionId,
         endpoint_id: EndpointId,
         remote_conn_cred: ConnectionCredentials,
         local_conn_cred: ConnectionCredentials,
         remote_description: RTCSessionDescription,
         local_description: RTCSessionDescription,
-        expired_time: Instant,
+        ttl: Duration,
     ) -> Self {
         Self {
             session_id,
@@ -213,7 +247,10 @@ impl Candidate {
             remote_conn_cred,
             remote_description,
             local_description,
-            expired_time,
+            created_at: Instant::now(),
+            ttl,
+            ice_role: Cell::new(RTCIceRole::default()),
+            ice_tiebreaker: rand::random(),
         }
     }
 
@@ -239,6 +276,22 @@ impl Candidate {
         self.session_id
     }
 
+    /// with_session_id returns a copy of this candidate bound to another session
+    pub(crate) fn with_session_id(&self, session_id: SessionId) -> Self {
+        Self {
+            session_id,
+            endpoint_id: self.endpoint_id,
+            remote_conn_cred: self.remote_conn_cred.clone(),
+            local_conn_cred: self.local_conn_cred.clone(),
+            remote_description: self.remote_description.clone(),
+            local_description: self.local_description.clone(),
+            created_at: self.created_at,
+            ttl: self.ttl,
+            ice_role: self.ice_role.clone(),
+            ice_tiebreaker: self.ice_tiebreaker,
+        }
+    }
+
     pub(crate) fn endpoint_id(&self) -> EndpointId {
         self.endpoint_id
     }
@@ -259,7 +312,21 @@ impl Candidate {
         &self.local_description
     }
 
-    pub(crate) fn expired_time(&self) -> Instant {
-        self.expired_time
+    pub(crate) fn ice_role(&self) -> RTCIceRole {
+        self.ice_role.get()
+    }
+
+    pub(crate) fn set_ice_role(&self, ice_role: RTCIceRole) {
+        self.ice_role.set(ice_role);
+    }
+
+    /// ice_tiebreaker is the random number to resolve ICE role conflicts
+    pub(crate) fn ice_tiebreaker(&self) -> u64 {
+        self.ice_tiebreaker
+    }
+
+    /// is_expired returns whether the candidate outlived its time-to-live
+    pub(crate) fn is_expired(&self) -> bool {
+        Instant::now() > self.created_at + self.ttl
     }
 }
@@ -0,0 +1,229 @@
+use crate::description::rtp_transceiver::PayloadType;
+use bytes::{BufMut, Bytes, BytesMut};
+use shared::marshal::Marshal;
+
+/// size of the fixed RTP header which is not covered by the FEC bit string except its first 8 bytes
+const RTP_HEADER_SIZE: usize = 12;
+/// maximum number of media packets protected by an FEC packet with a short, 16 bits, mask
+const ULPFEC_MAX_GROUP_SIZE: usize = 16;
+/// the F bit of a RED block header, set on all but the last block,
+/// <https://datatracker.ietf.org/doc/html/rfc2198#section-3>
+const RED_FOLLOWING_BLOCK: u8 = 0x80;
+/// size of a RED block header which is followed by another block
+const RED_BLOCK_HEADER_SIZE: usize = 4;
+
+/// red_primary_payload_type returns the payload type of the primary, i.e., last,
+/// block of a RED payload, or None if the payload is truncated
+pub(crate) fn red_primary_payload_type(payload: &[u8]) -> Option<PayloadType> {
+    let mut offset = 0;
+    loop {
+        let header = *payload.get(offset)?;
+        if header & RED_FOLLOWING_BLOCK == 0 {
+            return Some(header & 0x7F);
+        }
+        offset += RED_BLOCK_HEADER_SIZE;
+    }
+}
+
+/// UlpfecEncoder generates ULPFEC packets, <https://datatracker.ietf.org/doc/html/rfc5109>,
+/// for a subscriber's SSRC, each of which protects a group of consecutive media packets with
+/// level 0 XOR parity, so that the subscriber can recover any single lost packet of the group.
+///
+/// FEC packets share the sequence number space of the media, so sequence numbers of media packets
+/// are shifted by the number of FEC packets generated before them.
+pub(crate) struct UlpfecEncoder {
+    payload_type: PayloadType,
+    red_payload_type: Option<PayloadType>,
+    group_size: usize,
+
+    sequence_number_offset: u16,
+    group: Vec<Bytes>,
+    base_sequence_number: u16,
+    last_timestamp: u32,
+}
+
+impl UlpfecEncoder {
+    /// create new encoder with ulpfec payload type, which generates an FEC packet per group of
+    /// media packets, sized so that FEC packets add about overhead percent to the media packets
+    pub(crate) fn new(payload_type: PayloadType, overhead: u8) -> Self {
+        let group_size = (100 / overhead.clamp(1, 100) as usize).clamp(1, ULPFEC_MAX_GROUP_SIZE);
+        Self {
+            payload_type,
+            red_payload_type: None,
+            group_size,
+
+            sequence_number_offset: 0,
+            group: Vec::with_capacity(group_size),
+            base_sequence_number: 0,
+            last_timestamp: 0,
+        }
+    }
+
+    /// with_red_payload_type wraps generated FEC packets into RED with the given payload type,
+    /// <https://datatracker.ietf.org/doc/html/rfc2198>
+    pub(crate) fn with_red_payload_type(mut self, red_payload_type: PayloadType) -> Self {
+        self.red_payload_type = Some(red_payload_type);
+        self
+    }
+
+    /// group_size is the number of media packets protected by each FEC packet
+    pub(crate) fn group_size(&self) -> usize {
+        self.group_size
+    }
+
+    /// protect shifts the sequence number of the media packet in place, and returns an FEC packet
+    /// to be sent right after it once the packet completes a group
+    pub(crate) fn protect(
+        &mut self,
+        packet: &mut rtp::packet::Packet,
+    ) -> Option<rtp::packet::Packet> {
+        packet.header.sequence_number = packet
+            .header
+            .sequence_number
+            .wrapping_add(self.sequence_number_offset);
+
+        // a group only protects consecutive packets, so that a lost or reordered packet
+        // starts a new group
+        let expected_sequence_number = self
+            .base_sequence_number
+            .wrapping_add(self.group.len() as u16);
+        if self.group.is_empty() || packet.header.sequence_number != expected_sequence_number {
+            self.group.clear();
+            self.base_sequence_number = packet.header.sequence_number;
+        }
+        self.group.push(packet.marshal().ok()?.freeze());
+        self.last_timestamp = packet.header.timestamp;
+
+        if self.group.len() < self.group_size {
+            return None;
+        }
+
+        let fec_packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: self.red_payload_type.unwrap_or(self.payload_type),
+                sequence_number: packet.header.sequence_number.wrapping_add(1),
+                timestamp: self.last_timestamp,
+                ssrc: packet.header.ssrc,
+                ..Default::default()
+            },
+            payload: self.encode(),
+        };
+        self.group.clear();
+        self.sequence_number_offset = self.sequence_number_offset.wrapping_add(1);
+        Some(fec_packet)
+    }
+
+    /// encode the FEC header, level 0 header and payload of the current group,
+    /// <https://datatracker.ietf.org/doc/html/rfc5109#section-7.3>
+    fn encode(&self) -> Bytes {
+        let mut header_recovery = [0u8; 8];
+        let mut length_recovery = 0u16;
+        let mut mask = 0u16;
+        let protection_length = self
+            .group
+            .iter()
+            .map(|raw| raw.len() - RTP_HEADER_SIZE)
+            .max()
+            .unwrap_or_default();
+        let mut payload_recovery = vec![0u8; protection_length];
+        for (index, raw) in self.group.iter().enumerate() {
+            for (recovery, byte) in header_recovery.iter_mut().zip(&raw[..8]) {
+                *recovery ^= byte;
+            }
+            length_recovery ^= (raw.len() - RTP_HEADER_SIZE) as u16;
+            for (recovery, byte) in payload_recovery.iter_mut().zip(&raw[RTP_HEADER_SIZE..]) {
+                *recovery ^= byte;
+            }
+            mask |= 0x8000 >> index;
+        }
+
+        let mut fec = BytesMut::new();
+        if self.red_payload_type.is_some() {
+            // a single, primary, RED block header
+            fec.put_u8(self.payload_type & 0x7F);
+        }
+        // E(0) | L(0) | P X CC recovery, M PT recovery
+        fec.put_u8(header_recovery[0] & 0x3F);
+        fec.put_u8(header_recovery[1]);
+        fec.put_u16(self.base_sequence_number);
+        fec.put_slice(&header_recovery[4..8]);
+        fec.put_u16(length_recovery);
+        fec.put_u16(protection_length as u16);
+        fec.put_u16(mask);
+        fec.put_slice(&payload_recovery);
+        fec.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_packet(sequence_number: u16, payload: &'static [u8]) -> rtp::packet::Packet {
+        rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                timestamp: 3000,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(payload),
+        }
+    }
+
+    #[test]
+    fn test_ulpfec_encoder_protects_group() {
+        // 25% overhead protects every 4 media packets
+        let mut encoder = UlpfecEncoder::new(116, 25);
+        assert_eq!(encoder.group_size(), 4);
+
+        let mut fec_packets = vec![];
+        for sequence_number in 1..=8 {
+            let mut packet = new_packet(sequence_number, b"loopback");
+            let fec_packet = encoder.protect(&mut packet);
+            // media packets after an FEC packet are shifted in the same sequence space
+            assert_eq!(
+                packet.header.sequence_number,
+                sequence_number + fec_packets.len() as u16
+            );
+            fec_packets.extend(fec_packet);
+        }
+        assert_eq!(fec_packets.len(), 2);
+        assert_eq!(fec_packets[0].header.payload_type, 116);
+        assert_eq!(fec_packets[0].header.sequence_number, 5);
+        assert_eq!(fec_packets[1].header.sequence_number, 10);
+
+        // FEC header with SN base and 4 bits mask, followed by XOR of equally sized payloads
+        let fec = &fec_packets[0].payload;
+        assert_eq!(&fec[2..4], &1u16.to_be_bytes());
+        assert_eq!(&fec[12..14], &0xF000u16.to_be_bytes());
+        assert_eq!(&fec[14..], &[0u8; 8]);
+        assert_eq!(&fec_packets[1].payload[2..4], &6u16.to_be_bytes());
+    }
+
+    #[test]
+    fn test_ulpfec_encoder_red_and_gap() {
+        let mut encoder = UlpfecEncoder::new(116, 50).with_red_payload_type(115);
+        assert_eq!(encoder.group_size(), 2);
+
+        // a lost packet starts a new group
+        assert!(encoder.protect(&mut new_packet(1, b"a")).is_none());
+        assert!(encoder.protect(&mut new_packet(3, b"b")).is_none());
+        let fec_packet = encoder
+            .protect(&mut new_packet(4, b"c"))
+            .expect("no FEC packet for a complete group");
+
+        // wrapped into RED whose primary block is ulpfec
+        assert_eq!(fec_packet.header.payload_type, 115);
+        assert_eq!(red_primary_payload_type(&fec_packet.payload), Some(116));
+        assert_eq!(&fec_packet.payload[3..5], &3u16.to_be_bytes());
+        assert_eq!(
+            &fec_packet.payload[1 + 12..1 + 14],
+            &0xC000u16.to_be_bytes()
+        );
+        assert_eq!(&fec_packet.payload[1 + 14..], &[b'b' ^ b'c']);
+    }
+}
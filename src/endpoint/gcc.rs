@@ -0,0 +1,525 @@
+use rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, SymbolSizeTypeTcc, SymbolTypeTcc, TransportLayerCc,
+};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// packets sent within a burst of 5 ms are grouped together for delay gradients
+const BURST_TIME: Duration = Duration::from_millis(5);
+/// number of delay samples of the trendline filter
+const TRENDLINE_WINDOW_SIZE: usize = 20;
+const TRENDLINE_SMOOTHING: f64 = 0.9;
+const TRENDLINE_THRESHOLD_GAIN: f64 = 4.0;
+const MAX_NUM_DELTAS: f64 = 60.0;
+/// adaptive overuse threshold in ms, <https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02#section-5.4>
+const INITIAL_THRESHOLD: f64 = 12.5;
+const MIN_THRESHOLD: f64 = 6.0;
+const MAX_THRESHOLD: f64 = 600.0;
+const THRESHOLD_GAIN_UP: f64 = 0.0087;
+const THRESHOLD_GAIN_DOWN: f64 = 0.039;
+const MAX_THRESHOLD_ADAPT_DEVIATION: f64 = 15.0;
+/// rate control, <https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02#section-5.5>
+const DECREASE_FACTOR: f64 = 0.85;
+const INCREASE_FACTOR_PER_SECOND: f64 = 1.08;
+/// loss-based control, <https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02#section-6>
+const HIGH_LOSS_RATIO: f64 = 0.1;
+const LOW_LOSS_RATIO: f64 = 0.02;
+const LOSS_INCREASE_FACTOR: f64 = 1.05;
+/// max number of sent packets remembered for matching TWCC feedback
+const MAX_SENT_PACKETS: usize = 1 << 12;
+
+pub(crate) const DEFAULT_INITIAL_BITRATE: u64 = 1_000_000;
+pub(crate) const DEFAULT_MIN_BITRATE: u64 = 30_000;
+pub(crate) const DEFAULT_MAX_BITRATE: u64 = 10_000_000;
+
+/// BandwidthUsage is the signal of the overuse detector
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum BandwidthUsage {
+    #[default]
+    Normal,
+    Overusing,
+    Underusing,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct PacketGroup {
+    first_send_time: Instant,
+    last_send_time: Instant,
+    last_arrival_time: i64,
+}
+
+/// GccEstimator estimates available bandwidth toward a subscriber from TWCC feedback of the
+/// packets sent to it, as the minimum of a delay-based and a loss-based estimate of Google
+/// Congestion Control, <https://datatracker.ietf.org/doc/html/draft-ietf-rmcat-gcc-02>
+///
+/// The delay-based estimate feeds delay gradients of packet groups into a trendline filter,
+/// whose slope is compared against an adaptive threshold to detect overuse, in which case the
+/// bitrate is decreased to 85% of the acknowledged one, or is otherwise slowly increased.
+/// The loss-based estimate is decreased on high loss and increased on low loss.
+pub(crate) struct GccEstimator {
+    min_bitrate: u64,
+    max_bitrate: u64,
+    delay_based_bitrate: f64,
+    loss_based_bitrate: f64,
+    sent_packets: HashMap<u16, (Instant, usize)>,
+    sent_order: VecDeque<u16>,
+
+    previous_group: Option<PacketGroup>,
+    current_group: Option<PacketGroup>,
+    first_arrival_time: Option<i64>,
+    accumulated_delay: f64,
+    smoothed_delay: f64,
+    num_deltas: f64,
+    samples: VecDeque<(f64, f64)>,
+
+    threshold: f64,
+    last_threshold_update: Option<i64>,
+    usage: BandwidthUsage,
+    last_update: Option<Instant>,
+}
+
+impl Default for GccEstimator {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_INITIAL_BITRATE,
+            DEFAULT_MIN_BITRATE,
+            DEFAULT_MAX_BITRATE,
+        )
+    }
+}
+
+impl GccEstimator {
+    /// create new estimator with initial, min and max bitrates in bits per second
+    pub(crate) fn new(initial_bitrate: u64, min_bitrate: u64, max_bitrate: u64) -> Self {
+        let initial_bitrate = initial_bitrate.clamp(min_bitrate, max_bitrate) as f64;
+        Self {
+            min_bitrate,
+            max_bitrate,
+            delay_based_bitrate: initial_bitrate,
+            loss_based_bitrate: initial_bitrate,
+            sent_packets: HashMap::new(),
+            sent_order: VecDeque::new(),
+
+            previous_group: None,
+            current_group: None,
+            first_arrival_time: None,
+            accumulated_delay: 0.0,
+            smoothed_delay: 0.0,
+            num_deltas: 0.0,
+            samples: VecDeque::with_capacity(TRENDLINE_WINDOW_SIZE),
+
+            threshold: INITIAL_THRESHOLD,
+            last_threshold_update: None,
+            usage: BandwidthUsage::Normal,
+            last_update: None,
+        }
+    }
+
+    /// estimated bitrate in bits per second
+    pub(crate) fn bitrate(&self) -> u64 {
+        (self.delay_based_bitrate.min(self.loss_based_bitrate) as u64)
+            .clamp(self.min_bitrate, self.max_bitrate)
+    }
+
+    /// latest signal of the overuse detector
+    pub(crate) fn usage(&self) -> BandwidthUsage {
+        self.usage
+    }
+
+    /// remember a packet sent with the transport-wide sequence number and its size in bytes
+    pub(crate) fn on_packet_sent(
+        &mut self,
+        transport_sequence_number: u16,
+        size: usize,
+        now: Instant,
+    ) {
+        if self.sent_order.len() == MAX_SENT_PACKETS {
+            if let Some(oldest) = self.sent_order.pop_front() {
+                self.sent_packets.remove(&oldest);
+            }
+        }
+        self.sent_packets
+            .insert(transport_sequence_number, (now, size));
+        self.sent_order.push_back(transport_sequence_number);
+    }
+
+    /// update the estimate with TWCC feedback from the subscriber, and return it
+    pub(crate) fn on_feedback(&mut self, feedback: &TransportLayerCc, now: Instant) -> u64 {
+        // reference time is in multiples of 64 ms, and recv deltas are in us
+        let mut arrival_time = feedback.reference_time as i64 * 64_000;
+        let mut recv_deltas = feedback.recv_deltas.iter();
+        let (mut received, mut lost) = (0usize, 0usize);
+        let (mut received_bytes, mut first_arrival, mut last_arrival) = (0usize, None, None);
+        for (i, symbol) in GccEstimator::packet_status_symbols(feedback)
+            .into_iter()
+            .enumerate()
+        {
+            let sequence_number = feedback.base_sequence_number.wrapping_add(i as u16);
+            let sent = self.sent_packets.remove(&sequence_number);
+            match symbol {
+                SymbolTypeTcc::PacketNotReceived => {
+                    if sent.is_some() {
+                        lost += 1;
+                    }
+                }
+                SymbolTypeTcc::PacketReceivedSmallDelta
+                | SymbolTypeTcc::PacketReceivedLargeDelta => {
+                    let Some(recv_delta) = recv_deltas.next() else {
+                        break;
+                    };
+                    arrival_time += recv_delta.delta;
+                    if let Some((send_time, size)) = sent {
+                        received += 1;
+                        received_bytes += size;
+                        first_arrival.get_or_insert(arrival_time);
+                        last_arrival = Some(arrival_time);
+                        self.on_packet_arrived(send_time, arrival_time);
+                    }
+                }
+                SymbolTypeTcc::PacketReceivedWithoutDelta => {
+                    if sent.is_some() {
+                        received += 1;
+                    }
+                }
+            }
+        }
+
+        let acked_bitrate = match (first_arrival, last_arrival) {
+            (Some(first), Some(last)) if last > first => {
+                Some(received_bytes as f64 * 8.0 * 1_000_000.0 / (last - first) as f64)
+            }
+            _ => None,
+        };
+        self.update_delay_based_bitrate(acked_bitrate, now);
+        if received + lost > 0 {
+            self.update_loss_based_bitrate(lost as f64 / (received + lost) as f64);
+        }
+
+        self.bitrate()
+    }
+
+    fn packet_status_symbols(feedback: &TransportLayerCc) -> Vec<SymbolTypeTcc> {
+        let mut symbols = Vec::with_capacity(feedback.packet_status_count as usize);
+        for chunk in &feedback.packet_chunks {
+            match chunk {
+                PacketStatusChunk::RunLengthChunk(chunk) => {
+                    symbols.extend(std::iter::repeat_n(
+                        chunk.packet_status_symbol,
+                        chunk.run_length as usize,
+                    ));
+                }
+                PacketStatusChunk::StatusVectorChunk(chunk) => {
+                    symbols.extend(chunk.symbol_list.iter().map(|&symbol| {
+                        // one bit symbols only tell whether the packet is received with small delta
+                        if chunk.symbol_size == SymbolSizeTypeTcc::OneBit
+                            && symbol != SymbolTypeTcc::PacketNotReceived
+                        {
+                            SymbolTypeTcc::PacketReceivedSmallDelta
+                        } else {
+                            symbol
+                        }
+                    }));
+                }
+            }
+        }
+        symbols.truncate(feedback.packet_status_count as usize);
+        symbols
+    }
+
+    /// group packets sent within a burst, and feed delay gradient between groups to trendline
+    fn on_packet_arrived(&mut self, send_time: Instant, arrival_time: i64) {
+        if let Some(group) = self.current_group.as_mut() {
+            if send_time < group.first_send_time {
+                // reordered packet of a previous group
+                return;
+            }
+            if send_time.duration_since(group.first_send_time) <= BURST_TIME {
+                group.last_send_time = group.last_send_time.max(send_time);
+                group.last_arrival_time = group.last_arrival_time.max(arrival_time);
+                return;
+            }
+
+            let current_group = *group;
+            if let Some(previous_group) = self.previous_group {
+                let send_delta = current_group
+                    .last_send_time
+                    .duration_since(previous_group.last_send_time)
+                    .as_micros() as f64
+                    / 1000.0;
+                let arrival_delta = (current_group.last_arrival_time
+                    - previous_group.last_arrival_time) as f64
+                    / 1000.0;
+                self.update_trendline(arrival_delta - send_delta, current_group.last_arrival_time);
+            }
+            self.previous_group = Some(current_group);
+        }
+
+        self.current_group = Some(PacketGroup {
+            first_send_time: send_time,
+            last_send_time: send_time,
+            last_arrival_time: arrival_time,
+        });
+    }
+
+    /// trendline filter estimates the slope of accumulated delay over arrival time,
+    /// which is then compared against the adaptive threshold by the overuse detector
+    fn update_trendline(&mut self, delay_delta: f64, arrival_time: i64) {
+        let first_arrival_time = *self.first_arrival_time.get_or_insert(arrival_time);
+        self.num_deltas = (self.num_deltas + 1.0).min(MAX_NUM_DELTAS);
+        self.accumulated_delay += delay_delta;
+        self.smoothed_delay = TRENDLINE_SMOOTHING * self.smoothed_delay
+            + (1.0 - TRENDLINE_SMOOTHING) * self.accumulated_delay;
+
+        if self.samples.len() == TRENDLINE_WINDOW_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((
+            (arrival_time - first_arrival_time) as f64 / 1000.0,
+            self.smoothed_delay,
+        ));
+        if self.samples.len() < TRENDLINE_WINDOW_SIZE {
+            return;
+        }
+
+        let n = self.samples.len() as f64;
+        let mean_x = self.samples.iter().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = self.samples.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let (numerator, denominator) =
+            self.samples
+                .iter()
+                .fold((0.0, 0.0), |(numerator, denominator), (x, y)| {
+                    (
+                        numerator + (x - mean_x) * (y - mean_y),
+                        denominator + (x - mean_x) * (x - mean_x),
+                    )
+                });
+        if denominator == 0.0 {
+            return;
+        }
+
+        let trend = self.num_deltas * numerator / denominator * TRENDLINE_THRESHOLD_GAIN;
+        self.usage = if trend > self.threshold {
+            BandwidthUsage::Overusing
+        } else if trend < -self.threshold {
+            BandwidthUsage::Underusing
+        } else {
+            BandwidthUsage::Normal
+        };
+        self.update_threshold(trend, arrival_time);
+    }
+
+    fn update_threshold(&mut self, trend: f64, arrival_time: i64) {
+        let last_threshold_update = self.last_threshold_update.replace(arrival_time);
+        let deviation = trend.abs() - self.threshold;
+        if deviation > MAX_THRESHOLD_ADAPT_DEVIATION {
+            return;
+        }
+        let elapsed_ms = last_threshold_update
+            .map(|last| ((arrival_time - last) as f64 / 1000.0).min(100.0))
+            .unwrap_or_default();
+        let gain = if trend.abs() < self.threshold {
+            THRESHOLD_GAIN_DOWN
+        } else {
+            THRESHOLD_GAIN_UP
+        };
+        self.threshold =
+            (self.threshold + gain * deviation * elapsed_ms).clamp(MIN_THRESHOLD, MAX_THRESHOLD);
+    }
+
+    fn update_delay_based_bitrate(&mut self, acked_bitrate: Option<f64>, now: Instant) {
+        let elapsed = self
+            .last_update
+            .replace(now)
+            .map(|last_update| {
+                now.saturating_duration_since(last_update)
+                    .min(Duration::from_secs(1))
+            })
+            .unwrap_or_default();
+        match self.usage {
+            BandwidthUsage::Overusing => {
+                let decreased = DECREASE_FACTOR * acked_bitrate.unwrap_or(self.delay_based_bitrate);
+                self.delay_based_bitrate = self.delay_based_bitrate.min(decreased);
+            }
+            BandwidthUsage::Normal => {
+                self.delay_based_bitrate *= INCREASE_FACTOR_PER_SECOND.powf(elapsed.as_secs_f64());
+                if let Some(acked_bitrate) = acked_bitrate {
+                    // don't probe far beyond what the subscriber actually receives
+                    self.delay_based_bitrate = self
+                        .delay_based_bitrate
+                        .min(1.5 * acked_bitrate.max(self.min_bitrate as f64));
+                }
+            }
+            BandwidthUsage::Underusing => {}
+        }
+        self.delay_based_bitrate = self
+            .delay_based_bitrate
+            .clamp(self.min_bitrate as f64, self.max_bitrate as f64);
+    }
+
+    fn update_loss_based_bitrate(&mut self, loss_ratio: f64) {
+        if loss_ratio > HIGH_LOSS_RATIO {
+            self.loss_based_bitrate *= 1.0 - 0.5 * loss_ratio;
+        } else if loss_ratio < LOW_LOSS_RATIO {
+            self.loss_based_bitrate *= LOSS_INCREASE_FACTOR;
+        }
+        self.loss_based_bitrate = self
+            .loss_based_bitrate
+            .clamp(self.min_bitrate as f64, self.max_bitrate as f64);
+    }
+}
+
+/// window over which received bitrate of a publisher's ssrc is measured
+const BITRATE_METER_WINDOW: Duration = Duration::from_secs(1);
+
+/// BitrateMeter measures the bitrate of packets received from a publisher over a sliding window,
+/// which is compared against the estimates toward its subscribers for TMMBR generation
+#[derive(Default, Debug)]
+pub(crate) struct BitrateMeter {
+    received: VecDeque<(Instant, usize)>,
+    received_bytes: usize,
+}
+
+impl BitrateMeter {
+    /// record a packet received with its size in bytes
+    pub(crate) fn on_packet_received(&mut self, size: usize, now: Instant) {
+        self.received.push_back((now, size));
+        self.received_bytes += size;
+        self.expire(now);
+    }
+
+    /// received bitrate in bits per second over the window until now
+    pub(crate) fn bitrate(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        (self.received_bytes as f64 * 8.0 / BITRATE_METER_WINDOW.as_secs_f64()) as u64
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(received_at, size)) = self.received.front() {
+            if now.saturating_duration_since(received_at) <= BITRATE_METER_WINDOW {
+                break;
+            }
+            self.received.pop_front();
+            self.received_bytes -= size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rtcp::transport_feedbacks::transport_layer_cc::{
+        RecvDelta, RunLengthChunk, StatusChunkTypeTcc,
+    };
+
+    /// send packets every 10ms and build TWCC feedback with their inter-arrival times
+    fn feed(
+        estimator: &mut GccEstimator,
+        start: Instant,
+        arrival_time: &mut Duration,
+        base_sequence_number: u16,
+        count: u16,
+        arrival_interval: Duration,
+    ) -> u64 {
+        // reference time is in multiples of 64ms, and the first recv delta is relative to it
+        let reference_time = (*arrival_time + arrival_interval).as_millis() as u32 / 64;
+        let mut previous_arrival_time = Duration::from_millis(reference_time as u64 * 64);
+        let mut recv_deltas = vec![];
+        for i in 0..count {
+            estimator.on_packet_sent(
+                base_sequence_number + i,
+                1200,
+                start + Duration::from_millis(10 * (base_sequence_number + i) as u64),
+            );
+            *arrival_time += arrival_interval;
+            recv_deltas.push(RecvDelta {
+                type_tcc_packet: SymbolTypeTcc::PacketReceivedSmallDelta,
+                delta: (*arrival_time - previous_arrival_time).as_micros() as i64,
+            });
+            previous_arrival_time = *arrival_time;
+        }
+        let feedback = TransportLayerCc {
+            base_sequence_number,
+            packet_status_count: count,
+            reference_time,
+            packet_chunks: vec![PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                packet_status_symbol: SymbolTypeTcc::PacketReceivedSmallDelta,
+                run_length: count,
+            })],
+            recv_deltas,
+            ..Default::default()
+        };
+        estimator.on_feedback(
+            &feedback,
+            start + Duration::from_millis(10 * (base_sequence_number + count) as u64),
+        )
+    }
+
+    #[test]
+    fn test_gcc_estimate_decreases_with_increasing_delay() {
+        let start = Instant::now();
+        let mut estimator = GccEstimator::new(1_000_000, 30_000, 10_000_000);
+
+        // packets arrive as they are sent, so the estimate doesn't decrease
+        let mut arrival_time = Duration::ZERO;
+        let bitrate = feed(
+            &mut estimator,
+            start,
+            &mut arrival_time,
+            0,
+            50,
+            Duration::from_millis(10),
+        );
+        assert_eq!(estimator.usage(), BandwidthUsage::Normal);
+        assert!(bitrate >= 1_000_000);
+
+        // each packet is queued 2ms longer than the previous one
+        let bitrate = feed(
+            &mut estimator,
+            start,
+            &mut arrival_time,
+            50,
+            50,
+            Duration::from_millis(12),
+        );
+        assert_eq!(estimator.usage(), BandwidthUsage::Overusing);
+        // decreased to 85% of the acknowledged bitrate of 1200 bytes per 12ms
+        assert!(bitrate < 700_000);
+    }
+
+    #[test]
+    fn test_gcc_estimate_decreases_with_loss() {
+        let start = Instant::now();
+        let mut estimator = GccEstimator::new(1_000_000, 30_000, 10_000_000);
+        for i in 0..10 {
+            estimator.on_packet_sent(i, 1200, start + Duration::from_millis(10 * i as u64));
+        }
+
+        // half of the packets are lost
+        let feedback = TransportLayerCc {
+            base_sequence_number: 0,
+            packet_status_count: 10,
+            packet_chunks: vec![
+                PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                    type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                    packet_status_symbol: SymbolTypeTcc::PacketReceivedSmallDelta,
+                    run_length: 5,
+                }),
+                PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+                    type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+                    packet_status_symbol: SymbolTypeTcc::PacketNotReceived,
+                    run_length: 5,
+                }),
+            ],
+            recv_deltas: (0..5)
+                .map(|_| RecvDelta {
+                    type_tcc_packet: SymbolTypeTcc::PacketReceivedSmallDelta,
+                    delta: 10_000,
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let bitrate = estimator.on_feedback(&feedback, start + Duration::from_millis(100));
+        assert!(bitrate < 1_000_000);
+    }
+}
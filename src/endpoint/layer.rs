@@ -0,0 +1,142 @@
+use crate::description::rtp_transceiver::SSRC;
+use std::collections::HashMap;
+
+/// VP8 payload descriptor flags,
+/// <https://datatracker.ietf.org/doc/html/rfc7741#section-4.2>
+const VP8_EXTENDED_CONTROL_BITS_PRESENT: u8 = 0x80;
+const VP8_START_OF_PARTITION: u8 = 0x10;
+const VP8_PARTITION_INDEX_MASK: u8 = 0x07;
+const VP8_PICTURE_ID_PRESENT: u8 = 0x80;
+const VP8_TL0PICIDX_PRESENT: u8 = 0x40;
+const VP8_TID_PRESENT: u8 = 0x20;
+const VP8_KEYIDX_PRESENT: u8 = 0x10;
+/// extended 15 bits picture id flag
+const VP8_PICTURE_ID_EXTENDED: u8 = 0x80;
+/// inverse key frame flag of VP8 payload header,
+/// <https://datatracker.ietf.org/doc/html/rfc7741#section-4.3>
+const VP8_INTER_FRAME: u8 = 0x01;
+
+/// parse the temporal layer id of a VP8 payload descriptor,
+/// or None if the payload is truncated or has no TID
+pub(crate) fn vp8_temporal_layer_id(payload: &[u8]) -> Option<u8> {
+    if *payload.first()? & VP8_EXTENDED_CONTROL_BITS_PRESENT == 0 {
+        return None;
+    }
+    let extended_control_bits = *payload.get(1)?;
+    if extended_control_bits & VP8_TID_PRESENT == 0 {
+        return None;
+    }
+
+    let mut offset = 2;
+    if extended_control_bits & VP8_PICTURE_ID_PRESENT != 0 {
+        offset += if *payload.get(offset)? & VP8_PICTURE_ID_EXTENDED != 0 {
+            2
+        } else {
+            1
+        };
+    }
+    if extended_control_bits & VP8_TL0PICIDX_PRESENT != 0 {
+        offset += 1;
+    }
+
+    // TID(2) | Y(1) | KEYIDX(5)
+    Some(*payload.get(offset)? >> 6)
+}
+
+/// vp8_is_keyframe returns whether the VP8 payload starts a keyframe, by its payload header
+/// following the payload descriptor
+pub(crate) fn vp8_is_keyframe(payload: &[u8]) -> bool {
+    let Some(&flags) = payload.first() else {
+        return false;
+    };
+    if flags & VP8_START_OF_PARTITION == 0 || flags & VP8_PARTITION_INDEX_MASK != 0 {
+        return false;
+    }
+
+    let mut offset = 1;
+    if flags & VP8_EXTENDED_CONTROL_BITS_PRESENT != 0 {
+        let Some(&extended_control_bits) = payload.get(offset) else {
+            return false;
+        };
+        offset += 1;
+        if extended_control_bits & VP8_PICTURE_ID_PRESENT != 0 {
+            offset += match payload.get(offset) {
+                Some(picture_id) if picture_id & VP8_PICTURE_ID_EXTENDED != 0 => 2,
+                Some(_) => 1,
+                None => return false,
+            };
+        }
+        if extended_control_bits & VP8_TL0PICIDX_PRESENT != 0 {
+            offset += 1;
+        }
+        if extended_control_bits & (VP8_TID_PRESENT | VP8_KEYIDX_PRESENT) != 0 {
+            offset += 1;
+        }
+    }
+
+    payload
+        .get(offset)
+        .is_some_and(|payload_header| payload_header & VP8_INTER_FRAME == 0)
+}
+
+/// LayerIndex is the spatial and temporal layer of a forwarded packet
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct LayerIndex {
+    pub(crate) spatial_layer_id: u8,
+    pub(crate) temporal_layer_id: u8,
+    /// whether the packet ends the layer frame, if known
+    pub(crate) is_end_of_frame: bool,
+}
+
+/// LayerSelector drops packets of spatial and temporal layers above a subscriber's maximum ones.
+/// Sequence numbers are shifted by the number of dropped packets per SSRC, so that subscribers
+/// don't see the dropped packets as lost, and the marker bit is set at the end of the highest
+/// forwarded spatial layer, so that subscribers see complete frames.
+pub(crate) struct LayerSelector {
+    max_spatial_layer: u8,
+    max_temporal_layer: u8,
+    dropped_packets: HashMap<SSRC, u16>,
+}
+
+impl LayerSelector {
+    pub(crate) fn new() -> Self {
+        Self {
+            max_spatial_layer: u8::MAX,
+            max_temporal_layer: u8::MAX,
+            dropped_packets: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn set_max_spatial_layer(&mut self, max_spatial_layer: u8) {
+        self.max_spatial_layer = max_spatial_layer;
+    }
+
+    pub(crate) fn set_max_temporal_layer(&mut self, max_temporal_layer: u8) {
+        self.max_temporal_layer = max_temporal_layer;
+    }
+
+    /// select returns whether the packet of the layer, if known, is forwarded,
+    /// rewriting its header if so
+    pub(crate) fn select(
+        &mut self,
+        layer_index: Option<LayerIndex>,
+        header: &mut rtp::header::Header,
+    ) -> bool {
+        let dropped_packets = self.dropped_packets.entry(header.ssrc).or_default();
+        if layer_index.is_some_and(|layer_index| {
+            layer_index.spatial_layer_id > self.max_spatial_layer
+                || layer_index.temporal_layer_id > self.max_temporal_layer
+        }) {
+            *dropped_packets = dropped_packets.wrapping_add(1);
+            return false;
+        }
+
+        header.sequence_number = header.sequence_number.wrapping_sub(*dropped_packets);
+        if layer_index.is_some_and(|layer_index| {
+            layer_index.is_end_of_frame && layer_index.spatial_layer_id == self.max_spatial_layer
+        }) {
+            header.marker = true;
+        }
+        true
+    }
+}
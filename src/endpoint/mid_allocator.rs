@@ -0,0 +1,68 @@
+use crate::types::Mid;
+use std::collections::HashSet;
+
+/// MidAllocator allocates mids of media sections generated by SFU, which never reuses a mid.
+///
+/// Per JSEP, media sections of removed transceivers stay in descriptions as rejected ones,
+/// so their mids can't be reused by new transceivers,
+/// <https://www.rfc-editor.org/rfc/rfc8829#section-5.2.2>
+#[derive(Default, Debug, Clone)]
+pub(crate) struct MidAllocator {
+    next: u64,
+    used: HashSet<Mid>,
+}
+
+impl MidAllocator {
+    /// create new mid allocator
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// reserve a mid assigned elsewhere, e.g., by remote offers, which is never allocated then
+    pub(crate) fn reserve(&mut self, mid: &str) {
+        if !self.used.contains(mid) {
+            self.used.insert(mid.to_string());
+        }
+    }
+
+    /// whether the mid has been allocated or reserved
+    pub(crate) fn is_used(&self, mid: &str) -> bool {
+        self.used.contains(mid)
+    }
+
+    /// allocate a fresh mid which has never been allocated or reserved
+    pub(crate) fn allocate(&mut self) -> Mid {
+        loop {
+            let mid = self.next.to_string();
+            self.next += 1;
+            if self.used.insert(mid.clone()) {
+                return mid;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mid_allocator_never_reuses_removed_mid() {
+        let mut mid_allocator = MidAllocator::new();
+        mid_allocator.reserve("0");
+        mid_allocator.reserve("2");
+
+        let data_mid = mid_allocator.allocate();
+        assert_eq!(data_mid, "1");
+
+        // the transceiver with mid 2 is removed, and its media section stays as rejected one,
+        // so that a new transceiver gets a fresh mid instead
+        let new_mid = mid_allocator.allocate();
+        assert_eq!(new_mid, "3");
+        assert!(mid_allocator.is_used("2"));
+        assert_ne!(new_mid, data_mid);
+
+        mid_allocator.reserve("4");
+        assert_eq!(mid_allocator.allocate(), "5");
+    }
+}
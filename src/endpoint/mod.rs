@@ -1,40 +1,190 @@
 pub(crate) mod candidate;
+pub(crate) mod fec;
+pub(crate) mod gcc;
+pub(crate) mod layer;
+pub(crate) mod mid_allocator;
+pub(crate) mod pacer;
+pub(crate) mod rewriter;
 pub(crate) mod transport;
 
-use crate::description::{rtp_transceiver::RTCRtpTransceiver, RTCSessionDescription};
-use crate::endpoint::transport::Transport;
+use crate::configs::media_config::{MIME_TYPE_RED, MIME_TYPE_ULPFEC};
+use crate::description::{
+    codecs_from_media_description,
+    dependency_descriptor::{
+        DependencyDescriptor, FrameDependencyStructure, DEPENDENCY_DESCRIPTOR_URI,
+    },
+    extract_ice_candidates,
+    frame_marking::{FrameMarking, FRAME_MARKING_URI},
+    get_mid_value,
+    ice_candidate::RTCIceCandidate,
+    rtp_codec::RTCRtpCodecParameters,
+    rtp_transceiver::{PayloadType, RTCRtpTransceiver, SSRC, TYPE_RTCP_FB_CCM},
+    rtp_transceiver_direction::RTCRtpTransceiverDirection,
+    sdp_type::RTCSdpType,
+    update_sdp_origin, RTCSessionDescription,
+};
+use crate::endpoint::candidate::RTCIceParameters;
+use crate::endpoint::fec::UlpfecEncoder;
+use crate::endpoint::gcc::BitrateMeter;
+use crate::endpoint::layer::{LayerIndex, LayerSelector};
+use crate::endpoint::mid_allocator::MidAllocator;
+use crate::endpoint::rewriter::RtpRewriter;
+use crate::endpoint::transport::{Transport, TransportStats};
 use crate::interceptors::Interceptor;
+use crate::messages::TaggedMessageEvent;
 use crate::types::{EndpointId, FourTuple, Mid};
-use std::collections::HashMap;
+use log::{debug, trace};
+use rtcp::payload_feedbacks::full_intra_request::{FirEntry, FullIntraRequest};
+use rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use sdp::description::session::{Origin, SessionDescription};
+use shared::error::{Error, Result};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+/// clock rate used for rewriting timestamps of streams with unknown codec
+const DEFAULT_VIDEO_CLOCK_RATE: u32 = 90000;
+
+/// NegotiationState is the JSEP signaling state of an endpoint,
+/// <https://www.rfc-editor.org/rfc/rfc8829#section-3.2>
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum NegotiationState {
+    #[default]
+    Stable,
+    HaveLocalOffer,
+    HaveRemoteOffer,
+    HaveLocalPranswer,
+    HaveRemotePranswer,
+}
+
+impl NegotiationState {
+    /// next returns the state after applying a local or remote description of sdp_type,
+    /// or an error for illegal combinations, e.g., a remote offer in have-local-offer state,
+    /// which needs the local offer to be rolled back first
+    pub(crate) fn next(self, is_local: bool, sdp_type: RTCSdpType) -> Result<Self> {
+        use NegotiationState::*;
+        use RTCSdpType::{Answer, Offer, Pranswer, Rollback};
+
+        match (self, is_local, sdp_type) {
+            (Stable, true, Offer) | (HaveLocalOffer, true, Offer) => Ok(HaveLocalOffer),
+            (Stable, false, Offer) | (HaveRemoteOffer, false, Offer) => Ok(HaveRemoteOffer),
+            (HaveLocalOffer, false, Answer) | (HaveRemotePranswer, false, Answer) => Ok(Stable),
+            (HaveLocalOffer, false, Pranswer) | (HaveRemotePranswer, false, Pranswer) => {
+                Ok(HaveRemotePranswer)
+            }
+            (HaveRemoteOffer, true, Answer) | (HaveLocalPranswer, true, Answer) => Ok(Stable),
+            (HaveRemoteOffer, true, Pranswer) | (HaveLocalPranswer, true, Pranswer) => {
+                Ok(HaveLocalPranswer)
+            }
+            (HaveLocalOffer, true, Rollback) | (HaveRemoteOffer, false, Rollback) => Ok(Stable),
+            _ => Err(Error::Other("invalid state transition".to_string())),
+        }
+    }
+}
+
+/// ThrottledOffer is the latest offer of an endpoint received within its minimum renegotiation
+/// interval, which is answered over the data channel of its transport once the interval passes
+pub(crate) struct ThrottledOffer {
+    pub(crate) four_tuple: FourTuple,
+    pub(crate) offer: RTCSessionDescription,
+}
 
 pub(crate) struct Endpoint {
     endpoint_id: EndpointId,
     interceptor: Box<dyn Interceptor>,
 
     is_renegotiation_needed: bool,
+    renegotiation_debounce: Duration,
+    renegotiation_debounce_until: Option<Instant>,
+    min_renegotiation_interval: Duration,
+    pending_offer_timeout: Duration,
+    /// when the pending offer is sent to remote
+    pending_offer_sent_at: Option<Instant>,
+    last_offer_processed_at: Option<Instant>,
+    throttled_offer: Option<ThrottledOffer>,
+    negotiation_state: NegotiationState,
+    /// origin of offers, whose session version is the generation of the latest offer
+    sdp_origin: Origin,
+    /// generation of the pending offer, which its answer must carry
+    pending_offer_generation: Option<u64>,
     remote_description: Option<RTCSessionDescription>,
     local_description: Option<RTCSessionDescription>,
+    pending_local_description: Option<RTCSessionDescription>,
+    /// rotated local ICE parameters of the pending offer
+    pending_ice_params: Option<RTCIceParameters>,
+    remote_candidates: Vec<RTCIceCandidate>,
 
     transports: HashMap<FourTuple, Transport>,
 
     mids: Vec<Mid>,
     transceivers: HashMap<Mid, RTCRtpTransceiver>,
+    mid_allocator: MidAllocator,
+    data_mid: Option<Mid>,
+    negotiated_codecs: HashMap<Mid, RTCRtpCodecParameters>,
+    ulpfec_payload_type: Option<PayloadType>,
+    red_payload_type: Option<PayloadType>,
+
+    rtp_rewriters: HashMap<SSRC, RtpRewriter>,
+    ulpfec_encoders: HashMap<SSRC, UlpfecEncoder>,
+    layer_selector: LayerSelector,
+    /// ssrcs of other endpoints ever forwarded to the endpoint
+    forwarded_ssrcs: HashSet<SSRC>,
+    fir_sequence_numbers: HashMap<SSRC, u8>,
+    dependency_structures: HashMap<SSRC, FrameDependencyStructure>,
+    /// bitrates of ssrcs published by the endpoint
+    received_bitrates: HashMap<SSRC, BitrateMeter>,
+
+    /// application-specific attributes, e.g., display name or role
+    metadata: HashMap<String, String>,
 }
 
 impl Endpoint {
-    pub(crate) fn new(endpoint_id: EndpointId, interceptor: Box<dyn Interceptor>) -> Self {
+    pub(crate) fn new(
+        endpoint_id: EndpointId,
+        interceptor: Box<dyn Interceptor>,
+        renegotiation_debounce: Duration,
+        min_renegotiation_interval: Duration,
+        pending_offer_timeout: Duration,
+    ) -> Self {
         Self {
             endpoint_id,
             interceptor,
 
             is_renegotiation_needed: false,
+            renegotiation_debounce,
+            renegotiation_debounce_until: None,
+            min_renegotiation_interval,
+            pending_offer_timeout,
+            pending_offer_sent_at: None,
+            last_offer_processed_at: None,
+            throttled_offer: None,
+            negotiation_state: NegotiationState::Stable,
+            sdp_origin: Origin::default(),
+            pending_offer_generation: None,
             remote_description: None,
             local_description: None,
+            pending_local_description: None,
+            pending_ice_params: None,
+            remote_candidates: vec![],
 
             transports: HashMap::new(),
 
             mids: vec![],
             transceivers: HashMap::new(),
+            mid_allocator: MidAllocator::new(),
+            data_mid: None,
+            negotiated_codecs: HashMap::new(),
+            ulpfec_payload_type: None,
+            red_payload_type: None,
+
+            rtp_rewriters: HashMap::new(),
+            ulpfec_encoders: HashMap::new(),
+            layer_selector: LayerSelector::new(),
+            forwarded_ssrcs: HashSet::new(),
+            fir_sequence_numbers: HashMap::new(),
+            dependency_structures: HashMap::new(),
+            received_bitrates: HashMap::new(),
+
+            metadata: HashMap::new(),
         }
     }
 
@@ -58,14 +208,61 @@ impl Endpoint {
         &self.transports
     }
 
+    /// is_connected returns true if at least one transport has both local and remote
+    /// SRTP contexts set, i.e., media can be exchanged with this endpoint.
+    pub(crate) fn is_connected(&self) -> bool {
+        self.transports
+            .values()
+            .any(|transport| transport.is_srtp_context_ready())
+    }
+
+    pub(crate) fn get_transport_stats(&self, four_tuple: &FourTuple) -> Option<TransportStats> {
+        self.transports
+            .get(four_tuple)
+            .map(|transport| transport.stats())
+    }
+
     pub(crate) fn get_mut_transports(&mut self) -> &mut HashMap<FourTuple, Transport> {
         &mut self.transports
     }
 
+    pub(crate) fn metadata(&self) -> &HashMap<String, String> {
+        &self.metadata
+    }
+
+    pub(crate) fn set_metadata(&mut self, key: String, value: String) {
+        self.metadata.insert(key, value);
+    }
+
     pub(crate) fn get_mut_interceptor(&mut self) -> &mut Box<dyn Interceptor> {
         &mut self.interceptor
     }
 
+    pub(crate) fn set_interceptor(&mut self, interceptor: Box<dyn Interceptor>) {
+        self.interceptor = interceptor;
+        self.bind_remote_clock_rates();
+    }
+
+    /// bind the clock rates of the codecs negotiated by the remote description to the
+    /// interceptor, e.g., for jitter of the streams received from remote
+    fn bind_remote_clock_rates(&mut self) {
+        let Some(parsed) = self
+            .remote_description
+            .as_ref()
+            .and_then(|description| description.parsed.as_ref())
+        else {
+            return;
+        };
+        let clock_rates: HashMap<PayloadType, u32> = parsed
+            .media_descriptions
+            .iter()
+            .filter_map(|media| codecs_from_media_description(media).ok())
+            .flatten()
+            .map(|codec| (codec.payload_type, codec.capability.clock_rate))
+            .collect();
+        self.interceptor.bind_remote_clock_rates(&clock_rates);
+    }
+
     pub(crate) fn get_mids(&self) -> &Vec<Mid> {
         &self.mids
     }
@@ -74,10 +271,332 @@ impl Endpoint {
         &mut self.mids
     }
 
+    /// mid of the data channel media section offered by SFU, if any
+    pub(crate) fn data_mid(&self) -> Option<&Mid> {
+        self.data_mid.as_ref()
+    }
+
+    /// get mid of the data channel media section offered by SFU, which is allocated on first use
+    /// without colliding with any mid of the endpoint's transceivers
+    pub(crate) fn get_or_allocate_data_mid(&mut self) -> &Mid {
+        for mid in &self.mids {
+            self.mid_allocator.reserve(mid);
+        }
+        self.data_mid
+            .get_or_insert_with(|| self.mid_allocator.allocate())
+    }
+
     pub(crate) fn get_transceivers(&self) -> &HashMap<Mid, RTCRtpTransceiver> {
         &self.transceivers
     }
 
+    /// request a keyframe of the track the endpoint publishes on the mid by PLI of its media
+    /// ssrcs, and by FIR too if negotiated, as fallback for publishers which ignore PLI; the
+    /// returned messages are encrypted, so that they can be sent out right away
+    pub(crate) fn request_keyframe(
+        &mut self,
+        now: Instant,
+        mid: &str,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let transceiver = self
+            .transceivers
+            .get(mid)
+            .ok_or(Error::Other(format!("can't find mid {}", mid)))?;
+        let sender = transceiver
+            .sender
+            .as_ref()
+            .ok_or(Error::Other(format!("ErrNoSenderForMid {}", mid)))?;
+        // skip repair ssrcs, i.e., non-first ssrcs of groups, e.g., FID
+        let media_ssrcs: Vec<SSRC> = sender
+            .ssrcs
+            .iter()
+            .copied()
+            .filter(|ssrc| {
+                !sender
+                    .ssrc_groups
+                    .iter()
+                    .any(|group| group.ssrcs.iter().skip(1).any(|s| s == ssrc))
+            })
+            .collect();
+        let is_fir_negotiated = transceiver.rtp_params.codecs.iter().any(|codec| {
+            codec
+                .capability
+                .rtcp_feedbacks
+                .iter()
+                .any(|feedback| feedback.typ == TYPE_RTCP_FB_CCM && feedback.parameter == "fir")
+        });
+
+        let transport = self
+            .transports
+            .values_mut()
+            .find(|transport| transport.is_srtp_context_ready());
+        let sender_ssrc = transport
+            .as_ref()
+            .map_or(0, |transport| transport.rtcp_sender_ssrc());
+
+        let mut packets: Vec<Box<dyn rtcp::packet::Packet>> = vec![];
+        for &media_ssrc in &media_ssrcs {
+            packets.push(Box::new(PictureLossIndication {
+                sender_ssrc,
+                media_ssrc,
+            }));
+            if is_fir_negotiated {
+                let sequence_number = self.fir_sequence_numbers.entry(media_ssrc).or_default();
+                *sequence_number = sequence_number.wrapping_add(1);
+                packets.push(Box::new(FullIntraRequest {
+                    sender_ssrc,
+                    media_ssrc,
+                    fir: vec![FirEntry {
+                        ssrc: media_ssrc,
+                        sequence_number: *sequence_number,
+                    }],
+                }));
+            }
+        }
+        if packets.is_empty() {
+            return Err(Error::Other(format!("ErrNoSsrcForMid {}", mid)));
+        }
+
+        let message = transport
+            .and_then(|transport| transport.encrypt_rtcp(now, &packets))
+            .ok_or(Error::Other(format!(
+                "ErrNoSrtpTransport for endpoint id {}",
+                self.endpoint_id
+            )))?;
+        Ok(vec![message])
+    }
+
+    /// get transceiver which sends the given ssrc
+    pub(crate) fn get_transceiver_by_ssrc(&self, ssrc: SSRC) -> Option<&RTCRtpTransceiver> {
+        // a track moved to another media section keeps its ssrc in the stopped one
+        let mut transceivers = self.transceivers.values().filter(|transceiver| {
+            transceiver
+                .sender
+                .as_ref()
+                .is_some_and(|sender| sender.ssrcs.contains(&ssrc))
+        });
+        let first = transceivers.next()?;
+        if first.is_stopped() {
+            transceivers
+                .find(|transceiver| !transceiver.is_stopped())
+                .or(Some(first))
+        } else {
+            Some(first)
+        }
+    }
+
+    /// get_all_recv_mids returns mids of tracks the endpoint publishes to SFU,
+    /// i.e., of its recvonly or sendrecv transceivers from SFU's point of view
+    pub(crate) fn get_all_recv_mids(&self) -> Vec<Mid> {
+        self.transceivers
+            .iter()
+            .filter(|(_, transceiver)| {
+                matches!(
+                    transceiver.direction,
+                    RTCRtpTransceiverDirection::Recvonly | RTCRtpTransceiverDirection::Sendrecv
+                )
+            })
+            .map(|(mid, _)| mid.clone())
+            .collect()
+    }
+
+    /// get_all_recv_ssrcs returns ssrcs the endpoint publishes to SFU,
+    /// i.e., of its recvonly or sendrecv transceivers from SFU's point of view
+    pub(crate) fn get_all_recv_ssrcs(&self) -> Vec<SSRC> {
+        self.get_all_ssrcs(|direction| {
+            matches!(
+                direction,
+                RTCRtpTransceiverDirection::Recvonly | RTCRtpTransceiverDirection::Sendrecv
+            )
+        })
+    }
+
+    /// get_all_send_ssrcs returns ssrcs SFU forwards to the endpoint,
+    /// i.e., of its sendonly transceivers from SFU's point of view
+    pub(crate) fn get_all_send_ssrcs(&self) -> Vec<SSRC> {
+        self.get_all_ssrcs(|direction| direction == RTCRtpTransceiverDirection::Sendonly)
+    }
+
+    fn get_all_ssrcs(&self, is_matching: impl Fn(RTCRtpTransceiverDirection) -> bool) -> Vec<SSRC> {
+        self.transceivers
+            .values()
+            .filter(|transceiver| is_matching(transceiver.direction))
+            .filter_map(|transceiver| transceiver.sender.as_ref())
+            .flat_map(|sender| sender.ssrcs.iter().copied())
+            .collect()
+    }
+
+    /// get negotiated id of the header extension on the transceiver which sends the given ssrc
+    pub(crate) fn get_header_extension_id(&self, ssrc: SSRC, uri: &str) -> Option<u8> {
+        self.get_transceiver_by_ssrc(ssrc)?
+            .get_header_extension_id(uri)
+    }
+
+    /// rewrite forwarded RTP header with the rewriter of its SSRC, which is created on first use
+    pub(crate) fn rewrite_rtp(&mut self, now: Instant, header: &mut rtp::header::Header) {
+        self.get_or_insert_rtp_rewriter(header).rewrite(now, header);
+    }
+
+    /// rewrite forwarded DTMF event header with the rewriter of its SSRC, which is shared with
+    /// the audio of the same SSRC
+    pub(crate) fn rewrite_rtp_event(&mut self, now: Instant, header: &mut rtp::header::Header) {
+        self.get_or_insert_rtp_rewriter(header)
+            .rewrite_event(now, header);
+    }
+
+    /// set the highest spatial layer forwarded to the endpoint, or None for all layers
+    pub(crate) fn set_max_spatial_layer(&mut self, max_spatial_layer: Option<u8>) {
+        self.layer_selector
+            .set_max_spatial_layer(max_spatial_layer.unwrap_or(u8::MAX));
+    }
+
+    /// set the highest temporal layer forwarded to the endpoint, or None for all layers
+    pub(crate) fn set_max_temporal_layer(&mut self, max_temporal_layer: Option<u8>) {
+        self.layer_selector
+            .set_max_temporal_layer(max_temporal_layer.unwrap_or(u8::MAX));
+    }
+
+    /// select returns whether the forwarded packet of the layer, if known, is sent to the
+    /// endpoint, shifting its sequence number over the dropped ones if so
+    pub(crate) fn select_layer(
+        &mut self,
+        layer_index: Option<LayerIndex>,
+        header: &mut rtp::header::Header,
+    ) -> bool {
+        self.layer_selector.select(layer_index, header)
+    }
+
+    /// mark the ssrc as forwarded to the endpoint, returning whether it is the first time
+    pub(crate) fn mark_forwarded(&mut self, ssrc: SSRC) -> bool {
+        self.forwarded_ssrcs.insert(ssrc)
+    }
+
+    /// record a packet of the ssrc published by the endpoint with its size in bytes
+    pub(crate) fn record_received_rtp(&mut self, ssrc: SSRC, size: usize, now: Instant) {
+        self.received_bitrates
+            .entry(ssrc)
+            .or_default()
+            .on_packet_received(size, now);
+    }
+
+    /// received bitrate of the ssrc published by the endpoint in bits per second
+    pub(crate) fn received_bitrate(&mut self, ssrc: SSRC, now: Instant) -> u64 {
+        self.received_bitrates
+            .get_mut(&ssrc)
+            .map(|meter| meter.bitrate(now))
+            .unwrap_or_default()
+    }
+
+    /// get the layer of the published packet by its dependency descriptor extension, keeping the
+    /// latest template dependency structure of its ssrc, which comes with keyframes
+    pub(crate) fn get_dependency_descriptor_layer(
+        &mut self,
+        header: &rtp::header::Header,
+    ) -> Option<LayerIndex> {
+        let id = self.get_header_extension_id(header.ssrc, DEPENDENCY_DESCRIPTOR_URI)?;
+        let descriptor = match DependencyDescriptor::unmarshal(&header.get_extension(id)?) {
+            Ok(descriptor) => descriptor,
+            Err(err) => {
+                trace!("ssrc {} dependency descriptor error {}", header.ssrc, err);
+                return None;
+            }
+        };
+        if let Some(structure) = descriptor.structure.clone() {
+            self.dependency_structures.insert(header.ssrc, structure);
+        }
+        let (spatial_layer_id, temporal_layer_id) =
+            descriptor.layer(self.dependency_structures.get(&header.ssrc)?)?;
+        Some(LayerIndex {
+            spatial_layer_id,
+            temporal_layer_id,
+            is_end_of_frame: descriptor.end_of_frame,
+        })
+    }
+
+    /// get the frame-marking extension of the published packet, if negotiated
+    pub(crate) fn get_frame_marking(&self, header: &rtp::header::Header) -> Option<FrameMarking> {
+        let id = self.get_header_extension_id(header.ssrc, FRAME_MARKING_URI)?;
+        match FrameMarking::unmarshal(&header.get_extension(id)?) {
+            Ok(frame_marking) => Some(frame_marking),
+            Err(err) => {
+                trace!("ssrc {} frame-marking error {}", header.ssrc, err);
+                None
+            }
+        }
+    }
+
+    fn get_or_insert_rtp_rewriter(&mut self, header: &rtp::header::Header) -> &mut RtpRewriter {
+        let transceivers = &self.transceivers;
+        self.rtp_rewriters.entry(header.ssrc).or_insert_with(|| {
+            let clock_rate = transceivers
+                .values()
+                .find(|transceiver| {
+                    transceiver
+                        .sender
+                        .as_ref()
+                        .is_some_and(|sender| sender.ssrcs.contains(&header.ssrc))
+                })
+                .and_then(|transceiver| {
+                    transceiver
+                        .rtp_params
+                        .codecs
+                        .iter()
+                        .find(|codec| codec.payload_type == header.payload_type)
+                })
+                .map(|codec| codec.capability.clock_rate)
+                .unwrap_or(DEFAULT_VIDEO_CLOCK_RATE);
+            RtpRewriter::new(header.ssrc, clock_rate)
+        })
+    }
+
+    /// ulpfec_payload_type returns the payload type of ulpfec, if it is negotiated by the latest answer
+    pub(crate) fn ulpfec_payload_type(&self) -> Option<PayloadType> {
+        self.ulpfec_payload_type
+    }
+
+    /// protect_rtp feeds forwarded RTP packet into the ulpfec encoder of its SSRC, which is created
+    /// on first use, and returns an FEC packet to be sent after it, if any
+    pub(crate) fn protect_rtp(
+        &mut self,
+        overhead: u8,
+        rtp_packet: &mut rtp::packet::Packet,
+    ) -> Option<rtp::packet::Packet> {
+        let payload_type = self.ulpfec_payload_type?;
+        let red_payload_type = self.red_payload_type;
+        self.ulpfec_encoders
+            .entry(rtp_packet.header.ssrc)
+            .or_insert_with(|| {
+                let encoder = UlpfecEncoder::new(payload_type, overhead);
+                if let Some(red_payload_type) = red_payload_type {
+                    encoder.with_red_payload_type(red_payload_type)
+                } else {
+                    encoder
+                }
+            })
+            .protect(rtp_packet)
+    }
+
+    /// update_fec_payload_types records ulpfec and RED payload types negotiated by an answer
+    fn update_fec_payload_types(&mut self, description: &RTCSessionDescription) {
+        let Some(parsed) = description.parsed.as_ref() else {
+            return;
+        };
+        let codecs: Vec<RTCRtpCodecParameters> = parsed
+            .media_descriptions
+            .iter()
+            .filter_map(|media| codecs_from_media_description(media).ok())
+            .flatten()
+            .collect();
+        let payload_type = |mime_type: &str| {
+            codecs
+                .iter()
+                .find(|codec| codec.capability.mime_type.eq_ignore_ascii_case(mime_type))
+                .map(|codec| codec.payload_type)
+        };
+        self.ulpfec_payload_type = payload_type(MIME_TYPE_ULPFEC);
+        self.red_payload_type = payload_type(MIME_TYPE_RED);
+    }
+
     pub(crate) fn get_mut_transceivers(&mut self) -> &mut HashMap<Mid, RTCRtpTransceiver> {
         &mut self.transceivers
     }
@@ -96,19 +615,236 @@ impl Endpoint {
         self.local_description.as_ref()
     }
 
+    /// pending_local_description returns the offer that is sent to remote,
+    /// but its answer is not accepted yet
+    pub(crate) fn pending_local_description(&self) -> Option<&RTCSessionDescription> {
+        self.pending_local_description.as_ref()
+    }
+
+    pub(crate) fn set_pending_local_description(
+        &mut self,
+        now: Instant,
+        description: RTCSessionDescription,
+    ) {
+        self.pending_offer_sent_at = Some(now);
+        self.pending_offer_generation = description
+            .parsed
+            .as_ref()
+            .map(|parsed| parsed.origin.session_version);
+        self.pending_local_description = Some(description);
+    }
+
+    pub(crate) fn set_pending_ice_params(&mut self, ice_params: RTCIceParameters) {
+        self.pending_ice_params = Some(ice_params);
+    }
+
+    /// take the rotated local ICE parameters of the pending offer, once it is answered
+    pub(crate) fn take_pending_ice_params(&mut self) -> Option<RTCIceParameters> {
+        self.pending_ice_params.take()
+    }
+
+    /// commit_pending_local_description moves pending offer to local_description once
+    /// its answer is accepted. It returns false if there is no pending offer.
+    pub(crate) fn commit_pending_local_description(&mut self) -> bool {
+        if let Some(description) = self.pending_local_description.take() {
+            self.pending_offer_generation = None;
+            self.pending_offer_sent_at = None;
+            self.local_description = Some(description);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// pending_offer_expires_at returns when the pending offer is rolled back unless answered
+    pub(crate) fn pending_offer_expires_at(&self) -> Option<Instant> {
+        self.pending_local_description.as_ref()?;
+        self.pending_offer_sent_at
+            .map(|sent_at| sent_at + self.pending_offer_timeout)
+    }
+
+    /// rollback_pending_offer discards the pending offer and moves back to stable state, e.g., on
+    /// glare or once the offer expires, where the changes it carried are offered again later,
+    /// <https://www.rfc-editor.org/rfc/rfc8829#section-4.1.10.2>
+    pub(crate) fn rollback_pending_offer(&mut self) -> Result<()> {
+        self.apply_negotiation(true, RTCSdpType::Rollback)?;
+        self.pending_local_description = None;
+        self.pending_offer_generation = None;
+        self.pending_offer_sent_at = None;
+        self.pending_ice_params = None;
+        self.set_renegotiation_needed(true);
+        Ok(())
+    }
+
+    /// pending_offer_generation returns the generation of the pending offer, if any
+    pub(crate) fn pending_offer_generation(&self) -> Option<u64> {
+        self.pending_offer_generation
+    }
+
+    /// update_offer_origin stamps the offer with the endpoint's origin by update_sdp_origin,
+    /// which bumps its session version, i.e., the generation of the offer, for every offer after
+    /// the first one
+    pub(crate) fn update_offer_origin(&mut self, d: &mut SessionDescription) {
+        update_sdp_origin(&mut self.sdp_origin, d);
+    }
+
+    /// is_answer_to_pending_offer returns whether the answer carries the generation of the
+    /// pending offer in its session version, rather than the one of an offer superseded in the
+    /// meantime. It returns true without pending offer.
+    pub(crate) fn is_answer_to_pending_offer(&self, answer: &RTCSessionDescription) -> bool {
+        self.pending_offer_generation.is_none_or(|generation| {
+            answer
+                .parsed
+                .as_ref()
+                .is_some_and(|parsed| parsed.origin.session_version == generation)
+        })
+    }
+
+    /// has_pending_offer returns whether an offer is sent to remote and not answered yet
+    pub(crate) fn has_pending_offer(&self) -> bool {
+        self.pending_local_description.is_some()
+    }
+
     pub(crate) fn set_remote_description(&mut self, description: RTCSessionDescription) {
+        if let Some(parsed) = description.parsed.as_ref() {
+            for candidate in extract_ice_candidates(parsed) {
+                if !self.remote_candidates.contains(&candidate) {
+                    self.remote_candidates.push(candidate);
+                }
+            }
+        }
+        if description.sdp_type == RTCSdpType::Answer {
+            self.update_fec_payload_types(&description);
+        }
         self.remote_description = Some(description);
+        self.bind_remote_clock_rates();
     }
 
+    /// remote_candidates returns the ICE candidates signaled in remote descriptions so far
+    pub(crate) fn remote_candidates(&self) -> &[RTCIceCandidate] {
+        &self.remote_candidates
+    }
+
+    /// is_remote_description_changed returns whether the description differs from the stored
+    /// remote description, e.g., false for an answer received again over another channel
+    pub(crate) fn is_remote_description_changed(
+        &self,
+        description: &RTCSessionDescription,
+    ) -> bool {
+        self.remote_description
+            .as_ref()
+            .is_none_or(|remote_description| {
+                remote_description.sdp_type != description.sdp_type
+                    || remote_description.sdp != description.sdp
+            })
+    }
+
+    /// set_local_description stores the description, and the codecs negotiated per mid
+    /// if it is an answer generated by SFU
     pub(crate) fn set_local_description(&mut self, description: RTCSessionDescription) {
+        if let (RTCSdpType::Answer, Some(parsed)) =
+            (description.sdp_type, description.parsed.as_ref())
+        {
+            for media in &parsed.media_descriptions {
+                let Some(mid_value) = get_mid_value(media) else {
+                    continue;
+                };
+                // the first codec of the answer is the one to be sent and received
+                if let Some(codec) = codecs_from_media_description(media)
+                    .ok()
+                    .and_then(|codecs| codecs.into_iter().next())
+                {
+                    self.negotiated_codecs.insert(mid_value.to_string(), codec);
+                }
+            }
+            self.update_fec_payload_types(&description);
+        }
         self.local_description = Some(description);
     }
 
+    /// negotiated_codec returns the codec negotiated by the latest answer for the mid
+    pub(crate) fn negotiated_codec(&self, mid: &str) -> Option<&RTCRtpCodecParameters> {
+        self.negotiated_codecs.get(mid)
+    }
+
+    pub(crate) fn negotiation_state(&self) -> NegotiationState {
+        self.negotiation_state
+    }
+
+    /// apply_negotiation validates sdp_type of a local or remote description against
+    /// negotiation state, and moves to the next state if it is legal
+    pub(crate) fn apply_negotiation(&mut self, is_local: bool, sdp_type: RTCSdpType) -> Result<()> {
+        self.negotiation_state = self.negotiation_state.next(is_local, sdp_type)?;
+        Ok(())
+    }
+
     pub(crate) fn is_renegotiation_needed(&self) -> bool {
         self.is_renegotiation_needed
     }
 
+    /// set whether renegotiation is needed, where each trigger extends the debounce window,
+    /// so that rapid changes, e.g., audio and video tracks added together, are batched into one offer
     pub(crate) fn set_renegotiation_needed(&mut self, is_renegotiation_needed: bool) {
         self.is_renegotiation_needed = is_renegotiation_needed;
+        self.renegotiation_debounce_until =
+            is_renegotiation_needed.then(|| Instant::now() + self.renegotiation_debounce);
+    }
+
+    pub(crate) fn renegotiation_debounce_until(&self) -> Option<Instant> {
+        self.renegotiation_debounce_until
+    }
+
+    /// throttle the offer, returning whether it is kept as the latest throttled offer, replacing
+    /// any earlier one, since it is received within the minimum renegotiation interval since the
+    /// last processed offer, or otherwise recording it as processed now
+    pub(crate) fn throttle_offer(
+        &mut self,
+        now: Instant,
+        four_tuple: FourTuple,
+        offer: &RTCSessionDescription,
+    ) -> bool {
+        if self
+            .last_offer_processed_at
+            .is_some_and(|last| now < last + self.min_renegotiation_interval)
+        {
+            let throttled_offer = ThrottledOffer {
+                four_tuple,
+                offer: offer.clone(),
+            };
+            if self.throttled_offer.replace(throttled_offer).is_some() {
+                debug!(
+                    "endpoint {} coalesces throttled offers into the latest one",
+                    self.endpoint_id
+                );
+            }
+            return true;
+        }
+
+        self.throttled_offer = None;
+        self.last_offer_processed_at = Some(now);
+        false
+    }
+
+    /// when the throttled offer can be processed, if any
+    pub(crate) fn throttled_offer_due_at(&self) -> Option<Instant> {
+        self.throttled_offer.as_ref()?;
+        self.last_offer_processed_at
+            .map(|last| last + self.min_renegotiation_interval)
+    }
+
+    /// take the throttled offer if the minimum renegotiation interval has passed
+    pub(crate) fn take_due_throttled_offer(&mut self, now: Instant) -> Option<ThrottledOffer> {
+        if self.throttled_offer_due_at()? > now {
+            return None;
+        }
+        self.throttled_offer.take()
+    }
+
+    /// whether renegotiation is needed and its debounce window has passed
+    pub(crate) fn is_renegotiation_due(&self, now: Instant) -> bool {
+        self.is_renegotiation_needed
+            && self
+                .renegotiation_debounce_until
+                .is_none_or(|debounce_until| debounce_until <= now)
     }
 }
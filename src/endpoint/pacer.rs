@@ -0,0 +1,200 @@
+use crate::description::rtp_codec::RTPCodecType;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// max burst allowed by the pacer, in duration of the target bitrate
+const PACER_MAX_BURST: Duration = Duration::from_millis(5);
+
+/// SendPriority of queued packets, where packets of higher priority are released first,
+/// so that audio keeps conversations intelligible when the queue is congested
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) enum SendPriority {
+    /// audio
+    High = 0,
+    /// video
+    #[default]
+    Normal = 1,
+    /// data
+    Low = 2,
+}
+
+impl From<RTPCodecType> for SendPriority {
+    fn from(kind: RTPCodecType) -> Self {
+        match kind {
+            RTPCodecType::Audio => SendPriority::High,
+            RTPCodecType::Video | RTPCodecType::Unspecified => SendPriority::Normal,
+        }
+    }
+}
+
+/// Pacer is a leaky bucket which spreads queued outbound packets over time toward
+/// the target bitrate, instead of sending a burst of them at once.
+///
+/// The bucket is refilled at the target bitrate up to a small burst budget. A packet is
+/// released whenever the budget is not negative, after which its size is taken from the
+/// budget, so that the next packet waits until the debt is paid off.
+///
+/// Packets are queued per SendPriority, and released in priority order.
+pub(crate) struct Pacer<T> {
+    bitrate: u64,
+    max_budget: i64,
+    budget: i64,
+    last_refill: Option<Instant>,
+    queues: [VecDeque<(usize, T)>; 3],
+}
+
+impl<T> Pacer<T> {
+    /// create new pacer with target bitrate in bits per second
+    pub(crate) fn new(bitrate: u64) -> Self {
+        let max_budget = (bitrate / 8 * PACER_MAX_BURST.as_micros() as u64 / 1_000_000) as i64;
+        Self {
+            bitrate,
+            max_budget,
+            budget: max_budget,
+            last_refill: None,
+            queues: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+        }
+    }
+
+    /// target bitrate in bits per second
+    pub(crate) fn bitrate(&self) -> u64 {
+        self.bitrate
+    }
+
+    /// number of queued packets
+    pub(crate) fn len(&self) -> usize {
+        self.queues.iter().map(|queue| queue.len()).sum()
+    }
+
+    /// whether there is no queued packet
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queues.iter().all(|queue| queue.is_empty())
+    }
+
+    /// enqueue a packet with its size in bytes and normal priority
+    pub(crate) fn enqueue(&mut self, size: usize, packet: T) {
+        self.enqueue_with_priority(size, SendPriority::Normal, packet);
+    }
+
+    /// enqueue a packet with its size in bytes and send priority
+    pub(crate) fn enqueue_with_priority(&mut self, size: usize, priority: SendPriority, packet: T) {
+        self.queues[priority as usize].push_back((size, packet));
+    }
+
+    /// poll a packet which can be released at now
+    pub(crate) fn poll(&mut self, now: Instant) -> Option<T> {
+        self.refill(now);
+        if self.budget < 0 {
+            return None;
+        }
+        let (size, packet) = self.queues.iter_mut().find_map(|queue| queue.pop_front())?;
+        self.budget -= size as i64;
+        Some(packet)
+    }
+
+    /// poll the time when the next queued packet can be released,
+    /// which is only known after the pacer has been polled
+    pub(crate) fn poll_timeout(&self) -> Option<Instant> {
+        if self.is_empty() {
+            return None;
+        }
+        let last_refill = self.last_refill?;
+        if self.budget >= 0 {
+            return Some(last_refill);
+        }
+        let debt_bits = (-self.budget) as u64 * 8;
+        Some(last_refill + Duration::from_micros((debt_bits * 1_000_000).div_ceil(self.bitrate)))
+    }
+
+    fn refill(&mut self, now: Instant) {
+        if let Some(last_refill) = self.last_refill {
+            let elapsed = now.saturating_duration_since(last_refill);
+            let refill = (self.bitrate as u128 * elapsed.as_micros() / 8_000_000) as i64;
+            if refill > 0 {
+                self.budget = (self.budget + refill).min(self.max_budget);
+                self.last_refill = Some(now);
+            }
+        } else {
+            self.last_refill = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pacer_releases_burst_across_ticks() {
+        // 1 Mbps, i.e., 125 bytes per millisecond
+        let mut pacer = Pacer::new(1_000_000);
+        let start = Instant::now();
+        for i in 0..10 {
+            pacer.enqueue(1250, i);
+        }
+
+        // only the burst budget is released at once
+        let mut released = vec![];
+        while let Some(packet) = pacer.poll(start) {
+            released.push((packet, Duration::ZERO));
+        }
+        assert_eq!(released.len(), 1);
+
+        // then one 1250 bytes packet per 10ms tick, after the 5ms burst budget is paid off
+        let mut now = start;
+        while !pacer.is_empty() {
+            let timeout = pacer.poll_timeout().expect("pacer without timeout");
+            assert!(timeout > now);
+            now = timeout;
+            while let Some(packet) = pacer.poll(now) {
+                released.push((packet, now - start));
+            }
+            assert!(now - start <= Duration::from_millis(100));
+        }
+
+        assert_eq!(
+            released
+                .iter()
+                .map(|(packet, _)| *packet)
+                .collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        for (i, (_, elapsed)) in released.iter().enumerate().skip(1) {
+            assert_eq!(*elapsed, Duration::from_millis(10 * i as u64 - 5));
+        }
+        assert_eq!(pacer.poll_timeout(), None);
+    }
+
+    #[test]
+    fn test_pacer_releases_audio_before_video_under_congestion() {
+        // 1 Mbps with 1250 bytes packets, so that only one packet is released per 10ms tick
+        let mut pacer = Pacer::new(1_000_000);
+        let start = Instant::now();
+        for i in 0..4 {
+            pacer.enqueue_with_priority(1250, SendPriority::Normal, format!("video{}", i));
+            pacer.enqueue_with_priority(1250, SendPriority::High, format!("audio{}", i));
+        }
+        pacer.enqueue_with_priority(1250, SendPriority::Low, "data0".to_string());
+        assert_eq!(pacer.len(), 9);
+
+        let mut released = vec![];
+        let mut now = start;
+        while let Some(packet) = pacer.poll(now) {
+            released.push(packet);
+        }
+        while !pacer.is_empty() {
+            now = pacer.poll_timeout().expect("pacer without timeout");
+            while let Some(packet) = pacer.poll(now) {
+                released.push(packet);
+            }
+        }
+
+        assert_eq!(
+            released,
+            vec![
+                "audio0", "audio1", "audio2", "audio3", "video0", "video1", "video2", "video3",
+                "data0"
+            ]
+        );
+    }
+}
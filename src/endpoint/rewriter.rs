@@ -0,0 +1,194 @@
+use crate::description::rtp_transceiver::SSRC;
+use std::time::Instant;
+
+/// RtpRewriter rewrites sequence numbers and timestamps of RTP packets forwarded to a subscriber's
+/// SSRC, so that they stay continuous when the forwarded source (publisher or layer) switches.
+///
+/// When a new source SSRC is seen, offsets are computed at switch time so that the next
+/// sequence number follows the last forwarded one, and the next timestamp advances from the last
+/// forwarded one by the wall clock time elapsed since then.
+pub(crate) struct RtpRewriter {
+    ssrc: SSRC,
+    clock_rate: u32,
+
+    source_ssrc: Option<SSRC>,
+    sequence_number_offset: u16,
+    timestamp_offset: u32,
+
+    last_sequence_number: u16,
+    last_timestamp: u32,
+    last_time: Option<Instant>,
+}
+
+impl RtpRewriter {
+    /// create new rewriter for the subscriber's SSRC with the clock rate of its codec
+    pub(crate) fn new(ssrc: SSRC, clock_rate: u32) -> Self {
+        Self {
+            ssrc,
+            clock_rate,
+
+            source_ssrc: None,
+            sequence_number_offset: 0,
+            timestamp_offset: 0,
+
+            last_sequence_number: 0,
+            last_timestamp: 0,
+            last_time: None,
+        }
+    }
+
+    /// ssrc of the subscriber stream
+    pub(crate) fn ssrc(&self) -> SSRC {
+        self.ssrc
+    }
+
+    /// source_ssrc of the currently forwarded source, if any
+    pub(crate) fn source_ssrc(&self) -> Option<SSRC> {
+        self.source_ssrc
+    }
+
+    /// rewrite ssrc, sequence number and timestamp of the header in place
+    pub(crate) fn rewrite(&mut self, now: Instant, header: &mut rtp::header::Header) {
+        self.rewrite_with(now, header, true);
+    }
+
+    /// rewrite ssrc, sequence number and timestamp of the header of a DTMF event packet,
+    /// <https://datatracker.ietf.org/doc/html/rfc4733>, in place. All packets of an event carry
+    /// the timestamp of its start, so that they are shifted by the same offset as media, but
+    /// don't move the timestamp tracked for source switches backward.
+    pub(crate) fn rewrite_event(&mut self, now: Instant, header: &mut rtp::header::Header) {
+        self.rewrite_with(now, header, false);
+    }
+
+    fn rewrite_with(
+        &mut self,
+        now: Instant,
+        header: &mut rtp::header::Header,
+        is_tracking_timestamp: bool,
+    ) {
+        if self.source_ssrc != Some(header.ssrc) {
+            self.switch_source(now, header);
+        }
+
+        let sequence_number = header
+            .sequence_number
+            .wrapping_add(self.sequence_number_offset);
+        let timestamp = header.timestamp.wrapping_add(self.timestamp_offset);
+
+        // only track the newest packet, so that reordered packets don't move offsets backward
+        if self.last_time.is_none()
+            || (sequence_number.wrapping_sub(self.last_sequence_number) as i16) > 0
+        {
+            self.last_sequence_number = sequence_number;
+            if is_tracking_timestamp || self.last_time.is_none() {
+                self.last_timestamp = timestamp;
+                self.last_time = Some(now);
+            }
+        }
+
+        header.ssrc = self.ssrc;
+        header.sequence_number = sequence_number;
+        header.timestamp = timestamp;
+    }
+
+    fn switch_source(&mut self, now: Instant, header: &rtp::header::Header) {
+        if let Some(last_time) = self.last_time {
+            let elapsed = now.saturating_duration_since(last_time);
+            let timestamp_delta = ((elapsed.as_secs_f64() * self.clock_rate as f64) as u32).max(1);
+
+            self.sequence_number_offset = self
+                .last_sequence_number
+                .wrapping_add(1)
+                .wrapping_sub(header.sequence_number);
+            self.timestamp_offset = self
+                .last_timestamp
+                .wrapping_add(timestamp_delta)
+                .wrapping_sub(header.timestamp);
+        }
+        self.source_ssrc = Some(header.ssrc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn new_header(ssrc: u32, sequence_number: u16, timestamp: u32) -> rtp::header::Header {
+        rtp::header::Header {
+            version: 2,
+            ssrc,
+            sequence_number,
+            timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_rtp_rewriter_source_switch() {
+        let now = Instant::now();
+        let mut rewriter = RtpRewriter::new(1000, 90000);
+
+        // first source is forwarded as is, except for ssrc
+        let mut forwarded = vec![];
+        for i in 0..3u16 {
+            let mut header = new_header(1, 65534u16.wrapping_add(i), 3000 * i as u32);
+            rewriter.rewrite(now + Duration::from_millis(33 * i as u64), &mut header);
+            forwarded.push((header.sequence_number, header.timestamp));
+            assert_eq!(header.ssrc, 1000);
+        }
+        assert_eq!(forwarded[0], (65534, 0));
+
+        // switch to a source with unrelated sequence number and timestamp bases
+        for i in 0..3u16 {
+            let mut header = new_header(2, 100 + i, 4_000_000_000 + 3000 * i as u32);
+            rewriter.rewrite(
+                now + Duration::from_millis(100 + 33 * i as u64),
+                &mut header,
+            );
+            forwarded.push((header.sequence_number, header.timestamp));
+            assert_eq!(header.ssrc, 1000);
+        }
+        assert_eq!(rewriter.source_ssrc(), Some(2));
+
+        for pair in forwarded.windows(2) {
+            let ((prev_seq, prev_ts), (seq, ts)) = (pair[0], pair[1]);
+            assert_eq!(seq, prev_seq.wrapping_add(1));
+            assert!(ts.wrapping_sub(prev_ts) > 0 && ts.wrapping_sub(prev_ts) < 90000);
+        }
+        // 34 ms elapsed between last packet of first source and first packet of second source
+        assert_eq!(forwarded[3].1 - forwarded[2].1, 3060);
+
+        // reordered packet of current source keeps its relative position
+        let mut header = new_header(2, 101, 4_000_003_000);
+        rewriter.rewrite(now + Duration::from_millis(200), &mut header);
+        assert_eq!(
+            (header.sequence_number, header.timestamp),
+            (forwarded[4].0, forwarded[4].1)
+        );
+    }
+
+    #[test]
+    fn test_rtp_rewriter_event() {
+        let now = Instant::now();
+        let mut rewriter = RtpRewriter::new(1000, 48000);
+
+        // audio every 20ms, then a DTMF event started at the last audio timestamp,
+        // whose packets all carry the timestamp of its start
+        let mut header = new_header(1, 1, 0);
+        rewriter.rewrite(now, &mut header);
+        let mut header = new_header(1, 2, 960);
+        rewriter.rewrite(now + Duration::from_millis(20), &mut header);
+        for i in 0..3u16 {
+            let mut header = new_header(1, 3 + i, 960);
+            rewriter.rewrite_event(now + Duration::from_millis(40 + 20 * i as u64), &mut header);
+            assert_eq!((header.sequence_number, header.timestamp), (3 + i, 960));
+        }
+
+        // switching source 20ms after the last audio packet continues from its timestamp,
+        // instead of the timestamp of the event packets received later
+        let mut header = new_header(2, 500, 1_000_000);
+        rewriter.rewrite(now + Duration::from_millis(40), &mut header);
+        assert_eq!((header.sequence_number, header.timestamp), (6, 960 + 960));
+    }
+}
@@ -1,12 +1,98 @@
+use crate::description::rtp_transceiver::SSRC;
 use crate::endpoint::candidate::Candidate;
+use crate::endpoint::gcc::GccEstimator;
+use crate::endpoint::pacer::Pacer;
+use crate::messages::{DTLSMessageEvent, MessageEvent, RTPMessageEvent, TaggedMessageEvent};
 use crate::types::FourTuple;
-use sctp::{Association, AssociationHandle};
+use bytes::{BufMut, Bytes, BytesMut};
+use crc::{Crc, CRC_32_ISCSI};
+use log::warn;
+use retty::transport::TransportContext;
+use rtcp::goodbye::Goodbye;
+use rtp::extension::transport_cc_extension::TransportCcExtension;
+use sctp::{Association, AssociationHandle, Payload};
+use shared::error::Result;
+use shared::marshal::{Marshal, MarshalSize, Unmarshal};
 use srtp::context::Context;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Instant;
 
+/// TransportStats accounts SRTP/SRTCP packets and bytes on the wire of a transport,
+/// and when it last received each category of packets, e.g., for debugging stuck endpoints
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TransportStats {
+    /// number of SRTP/SRTCP packets received
+    pub packets_received: u64,
+    /// number of SRTP/SRTCP bytes received
+    pub bytes_received: u64,
+    /// number of SRTP/SRTCP packets sent
+    pub packets_sent: u64,
+    /// number of SRTP/SRTCP bytes sent
+    pub bytes_sent: u64,
+    /// number of RTP packets sent exceeding the path MTU
+    pub oversized_rtp_packets: u64,
+    /// when a STUN binding request was last received
+    pub stun_last_seen: Option<Instant>,
+    /// when a DTLS record was last received
+    pub dtls_last_seen: Option<Instant>,
+    /// when a SRTP/SRTCP packet was last received
+    pub media_last_seen: Option<Instant>,
+}
+
+/// ports and verification tag of the remote side of an SCTP association, learned from
+/// its INIT chunk, which are needed to address an ABORT chunk to it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SctpPeer {
+    local_port: u16,
+    remote_port: u16,
+    verification_tag: u32,
+}
+
+impl SctpPeer {
+    const COMMON_HEADER_SIZE: usize = 12;
+    const CHUNK_HEADER_SIZE: usize = 4;
+    const CHUNK_TYPE_INIT: u8 = 1;
+    const CHUNK_TYPE_ABORT: u8 = 6;
+
+    /// parse the remote side of an association from the SCTP packet carrying its INIT chunk
+    pub(crate) fn from_init(packet: &[u8]) -> Option<Self> {
+        let init = packet.get(Self::COMMON_HEADER_SIZE..)?;
+        if init.len() < Self::CHUNK_HEADER_SIZE + 4 || init[0] != Self::CHUNK_TYPE_INIT {
+            return None;
+        }
+        Some(Self {
+            local_port: u16::from_be_bytes([packet[2], packet[3]]),
+            remote_port: u16::from_be_bytes([packet[0], packet[1]]),
+            verification_tag: u32::from_be_bytes([init[4], init[5], init[6], init[7]]),
+        })
+    }
+
+    /// marshal an SCTP packet with a single ABORT chunk without error causes, whose
+    /// verification tag is the remote's initiate tag, so the T bit is not set
+    pub(crate) fn marshal_abort(&self) -> Bytes {
+        let mut packet =
+            BytesMut::with_capacity(Self::COMMON_HEADER_SIZE + Self::CHUNK_HEADER_SIZE);
+        packet.put_u16(self.local_port);
+        packet.put_u16(self.remote_port);
+        packet.put_u32(self.verification_tag);
+        packet.put_u32(0);
+        packet.put_u8(Self::CHUNK_TYPE_ABORT);
+        packet.put_u8(0);
+        packet.put_u16(Self::CHUNK_HEADER_SIZE as u16);
+
+        // CRC32c is computed with a zero checksum field, and stored in little endian
+        // as rtc-sctp does
+        let checksum = Crc::<u32>::new(&CRC_32_ISCSI).checksum(&packet);
+        packet[8..12].copy_from_slice(&checksum.to_le_bytes());
+        packet.freeze()
+    }
+}
+
+/// SRTP/SRTCP packet received before the remote SRTP context is ready, with its receiving time
+pub(crate) type PendingSrtpPacket = (Instant, BytesMut);
+
 pub(crate) struct Transport {
     four_tuple: FourTuple,
     last_activity: Instant,
@@ -16,10 +102,15 @@ pub(crate) struct Transport {
 
     // DTLS
     dtls_endpoint: dtls::endpoint::Endpoint,
+    /// whether an ICE-authenticated binding request is received since the DTLS handshake
+    /// completed, which allows the peer to restart the handshake once
+    is_dtls_restart_authorized: bool,
 
     // SCTP
     sctp_endpoint: sctp::Endpoint,
     sctp_associations: HashMap<AssociationHandle, Association>,
+    sctp_associations_last_activity: HashMap<AssociationHandle, Instant>,
+    sctp_associations_peer: HashMap<AssociationHandle, SctpPeer>,
 
     // DataChannel
     association_handle: Option<usize>,
@@ -28,6 +119,19 @@ pub(crate) struct Transport {
     // SRTP
     local_srtp_context: Option<Context>,
     remote_srtp_context: Option<Context>,
+    /// SRTP/SRTCP packets received before the remote SRTP context is ready
+    pending_srtp_packets: VecDeque<PendingSrtpPacket>,
+
+    stats: TransportStats,
+    /// SFU-owned ssrc of the RTCP feedback originated by the SFU on the transport, e.g.,
+    /// PLI and TMMBR, so that it is told apart from the feedback relayed from other endpoints
+    rtcp_sender_ssrc: SSRC,
+    pacer: Option<Pacer<TaggedMessageEvent>>,
+    bandwidth_estimator: GccEstimator,
+    twcc_sequence_number: u16,
+    /// id of transport-cc extension stamped into outbound RTP packets, if negotiated
+    transport_cc_id: Option<u8>,
+    path_mtu: Option<usize>,
 }
 
 impl Transport {
@@ -45,15 +149,27 @@ impl Transport {
             candidate,
 
             dtls_endpoint: dtls::endpoint::Endpoint::new(Some(dtls_handshake_config)),
+            is_dtls_restart_authorized: false,
 
             sctp_endpoint: sctp::Endpoint::new(sctp_endpoint_config, Some(sctp_server_config)),
             sctp_associations: HashMap::new(),
+            sctp_associations_last_activity: HashMap::new(),
+            sctp_associations_peer: HashMap::new(),
 
             association_handle: None,
             stream_id: None,
 
             local_srtp_context: None,
             remote_srtp_context: None,
+            pending_srtp_packets: VecDeque::new(),
+
+            stats: TransportStats::default(),
+            rtcp_sender_ssrc: rand::random::<u32>(),
+            pacer: None,
+            bandwidth_estimator: GccEstimator::default(),
+            twcc_sequence_number: 0,
+            transport_cc_id: None,
+            path_mtu: None,
         }
     }
 
@@ -61,10 +177,18 @@ impl Transport {
         &self.four_tuple
     }
 
+    pub(crate) fn rtcp_sender_ssrc(&self) -> SSRC {
+        self.rtcp_sender_ssrc
+    }
+
     pub(crate) fn candidate(&self) -> &Rc<Candidate> {
         &self.candidate
     }
 
+    pub(crate) fn set_candidate(&mut self, candidate: Rc<Candidate>) {
+        self.candidate = candidate;
+    }
+
     pub(crate) fn get_mut_dtls_endpoint(&mut self) -> &mut dtls::endpoint::Endpoint {
         &mut self.dtls_endpoint
     }
@@ -96,6 +220,58 @@ impl Transport {
         (&mut self.sctp_endpoint, &mut self.sctp_associations)
     }
 
+    pub(crate) fn keep_sctp_association_alive(&mut self, ch: AssociationHandle, now: Instant) {
+        self.sctp_associations_last_activity.insert(ch, now);
+    }
+
+    pub(crate) fn set_sctp_association_peer(&mut self, ch: AssociationHandle, peer: SctpPeer) {
+        self.sctp_associations_peer.insert(ch, peer);
+    }
+
+    /// close and remove SCTP associations which have received nothing since the deadline,
+    /// and return their handles with the ABORT packets to notify their peers, if known
+    pub(crate) fn remove_idle_sctp_associations(
+        &mut self,
+        deadline: Instant,
+    ) -> Vec<(AssociationHandle, Option<Bytes>)> {
+        let idle_associations: Vec<AssociationHandle> = self
+            .sctp_associations
+            .keys()
+            .filter(|ch| {
+                self.sctp_associations_last_activity
+                    .get(ch)
+                    .is_some_and(|last_activity| *last_activity <= deadline)
+            })
+            .copied()
+            .collect();
+
+        let mut aborts = Vec::with_capacity(idle_associations.len());
+        for ch in idle_associations {
+            if let Some(mut conn) = self.sctp_associations.remove(&ch) {
+                // rtc-sctp can't send ABORT chunk on behalf of users, so close it locally
+                // and abort the peer with a hand-made ABORT chunk
+                let _ = conn.close();
+            }
+            self.sctp_endpoint
+                .handle_event(ch, sctp::EndpointEvent::drained());
+            self.sctp_associations_last_activity.remove(&ch);
+            let abort = self
+                .sctp_associations_peer
+                .remove(&ch)
+                .map(|peer| peer.marshal_abort());
+            aborts.push((ch, abort));
+        }
+
+        // forget associations which have been drained in the meantime
+        let sctp_associations = &self.sctp_associations;
+        self.sctp_associations_last_activity
+            .retain(|ch, _| sctp_associations.contains_key(ch));
+        self.sctp_associations_peer
+            .retain(|ch, _| sctp_associations.contains_key(ch));
+
+        aborts
+    }
+
     pub(crate) fn get_sctp_associations(&self) -> &HashMap<AssociationHandle, Association> {
         &self.sctp_associations
     }
@@ -108,6 +284,26 @@ impl Transport {
         self.remote_srtp_context.as_mut()
     }
 
+    /// buffer the packet until the remote SRTP context is ready, and return true if the oldest
+    /// pending packet is dropped to keep at most capacity packets
+    pub(crate) fn buffer_pending_srtp_packet(
+        &mut self,
+        now: Instant,
+        packet: BytesMut,
+        capacity: usize,
+    ) -> bool {
+        let is_full = self.pending_srtp_packets.len() >= capacity;
+        if is_full {
+            self.pending_srtp_packets.pop_front();
+        }
+        self.pending_srtp_packets.push_back((now, packet));
+        is_full
+    }
+
+    pub(crate) fn take_pending_srtp_packets(&mut self) -> VecDeque<PendingSrtpPacket> {
+        std::mem::take(&mut self.pending_srtp_packets)
+    }
+
     pub(crate) fn set_local_srtp_context(&mut self, local_srtp_context: Context) {
         self.local_srtp_context = Some(local_srtp_context);
     }
@@ -116,6 +312,21 @@ impl Transport {
         self.remote_srtp_context = Some(remote_srtp_context);
     }
 
+    /// set_srtp_contexts replaces both local and remote SRTP contexts at once, so that
+    /// keys derived from a DTLS renegotiation never mix with the stale ones.
+    /// It returns true if previous contexts were replaced.
+    pub(crate) fn set_srtp_contexts(
+        &mut self,
+        local_srtp_context: Context,
+        remote_srtp_context: Context,
+    ) -> bool {
+        let is_rekeyed = self.local_srtp_context.is_some() || self.remote_srtp_context.is_some();
+        self.is_dtls_restart_authorized = false;
+        self.local_srtp_context = Some(local_srtp_context);
+        self.remote_srtp_context = Some(remote_srtp_context);
+        is_rekeyed
+    }
+
     pub(crate) fn set_association_handle_and_stream_id(
         &mut self,
         association_handle: usize,
@@ -129,8 +340,185 @@ impl Transport {
         (self.association_handle, self.stream_id)
     }
 
-    pub(crate) fn is_local_srtp_context_ready(&self) -> bool {
-        self.local_srtp_context.is_some()
+    pub(crate) fn is_srtp_context_ready(&self) -> bool {
+        self.local_srtp_context.is_some() && self.remote_srtp_context.is_some()
+    }
+
+    pub(crate) fn pacer(&self) -> Option<&Pacer<TaggedMessageEvent>> {
+        self.pacer.as_ref()
+    }
+
+    pub(crate) fn get_mut_pacer(&mut self) -> Option<&mut Pacer<TaggedMessageEvent>> {
+        self.pacer.as_mut()
+    }
+
+    /// get pacer of outbound RTP packets, which is created with the bitrate on first use
+    pub(crate) fn get_or_insert_pacer(&mut self, bitrate: u64) -> &mut Pacer<TaggedMessageEvent> {
+        self.pacer.get_or_insert_with(|| Pacer::new(bitrate))
+    }
+
+    pub(crate) fn bandwidth_estimator(&self) -> &GccEstimator {
+        &self.bandwidth_estimator
+    }
+
+    /// get estimator of available bandwidth toward the endpoint, fed by its TWCC feedback
+    pub(crate) fn get_mut_bandwidth_estimator(&mut self) -> &mut GccEstimator {
+        &mut self.bandwidth_estimator
+    }
+
+    /// stamp the next transport-wide sequence number into the transport-cc extension of
+    /// an outbound RTP packet, which is remembered for the bandwidth estimator once the packet
+    /// is sent by on_rtp_sent
+    pub(crate) fn stamp_transport_cc(
+        &mut self,
+        id: u8,
+        rtp_packet: &mut rtp::packet::Packet,
+    ) -> Result<()> {
+        let transport_cc = TransportCcExtension {
+            transport_sequence: self.twcc_sequence_number,
+        };
+        rtp_packet
+            .header
+            .set_extension(id, transport_cc.marshal()?.freeze())?;
+        self.transport_cc_id = Some(id);
+        self.twcc_sequence_number = self.twcc_sequence_number.wrapping_add(1);
+        Ok(())
+    }
+
+    /// on_rtp_sent remembers the send time of an outbound RTP packet stamped by
+    /// stamp_transport_cc for the bandwidth estimator, when the packet is released to the
+    /// network, e.g., by the pacer, so that queuing delay isn't taken as network delay
+    pub(crate) fn on_rtp_sent(&mut self, rtp_packet: &rtp::packet::Packet, now: Instant) {
+        let Some(mut extension) = self
+            .transport_cc_id
+            .and_then(|id| rtp_packet.header.get_extension(id))
+        else {
+            return;
+        };
+        if let Ok(transport_cc) = TransportCcExtension::unmarshal(&mut extension) {
+            self.bandwidth_estimator.on_packet_sent(
+                transport_cc.transport_sequence,
+                rtp_packet.marshal_size(),
+                now,
+            );
+        }
+    }
+
+    pub(crate) fn stats(&self) -> TransportStats {
+        self.stats
+    }
+
+    pub(crate) fn record_received(&mut self, bytes: usize, now: Instant) {
+        self.stats.packets_received += 1;
+        self.stats.bytes_received += bytes as u64;
+        self.stats.media_last_seen = Some(now);
+    }
+
+    /// record_stun_received records an ICE-authenticated binding request, which also authorizes
+    /// a DTLS restart once the handshake is completed
+    pub(crate) fn record_stun_received(&mut self, now: Instant) {
+        self.stats.stun_last_seen = Some(now);
+        if self.is_srtp_context_ready() {
+            self.is_dtls_restart_authorized = true;
+        }
+    }
+
+    /// take_dtls_restart_authorization returns whether a DTLS restart is authorized by a binding
+    /// request since the handshake completed, and consumes the authorization
+    pub(crate) fn take_dtls_restart_authorization(&mut self) -> bool {
+        std::mem::take(&mut self.is_dtls_restart_authorized)
+    }
+
+    pub(crate) fn record_dtls_received(&mut self, now: Instant) {
+        self.stats.dtls_last_seen = Some(now);
+    }
+
+    pub(crate) fn record_sent(&mut self, bytes: usize) {
+        self.stats.packets_sent += 1;
+        self.stats.bytes_sent += bytes as u64;
+    }
+
+    pub(crate) fn path_mtu(&self) -> Option<usize> {
+        self.path_mtu
+    }
+
+    pub(crate) fn set_path_mtu(&mut self, path_mtu: usize) {
+        self.path_mtu = Some(path_mtu);
+    }
+
+    pub(crate) fn record_oversized_rtp(&mut self) {
+        self.stats.oversized_rtp_packets += 1;
+    }
+
+    /// close returns RTCP BYE of the given forwarded ssrcs, SCTP SHUTDOWN of its associations
+    /// and DTLS close_notify, which are encrypted for the wire already, and closes the transport
+    pub(crate) fn close(&mut self, now: Instant, ssrcs: &[SSRC]) -> Vec<TaggedMessageEvent> {
+        let transport = TransportContext {
+            local_addr: self.four_tuple.local_addr,
+            peer_addr: self.four_tuple.peer_addr,
+            ecn: None,
+        };
+        let mut messages = vec![];
+
+        if !ssrcs.is_empty() {
+            let goodbye: Box<dyn rtcp::packet::Packet> = Box::new(Goodbye {
+                sources: ssrcs.to_vec(),
+                reason: Bytes::from_static(b"shutdown"),
+            });
+            messages.extend(self.encrypt_rtcp(now, &[goodbye]));
+        }
+
+        let mut sctp_payloads = vec![];
+        for association in self.sctp_associations.values_mut() {
+            if association.shutdown().is_ok() {
+                while let Some(transmit) = association.poll_transmit(now) {
+                    if let Payload::RawEncode(contents) = transmit.payload {
+                        sctp_payloads.extend(contents);
+                    }
+                }
+            }
+        }
+        for payload in sctp_payloads {
+            if let Err(err) = self.dtls_endpoint.write(transport.peer_addr, &payload) {
+                warn!("write sctp shutdown with error {}", err);
+            }
+        }
+        self.dtls_endpoint.close(transport.peer_addr);
+        while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+            messages.push(TaggedMessageEvent {
+                now,
+                transport,
+                message: MessageEvent::Dtls(DTLSMessageEvent::Raw(transmit.payload)),
+            });
+        }
+
+        messages
+    }
+
+    /// encrypt the compound RTCP packet with the local SRTP context into a message to the peer,
+    /// so that it can be sent out without going through the pipeline, or None if SRTP isn't
+    /// ready yet
+    pub(crate) fn encrypt_rtcp(
+        &mut self,
+        now: Instant,
+        packets: &[Box<dyn rtcp::packet::Packet>],
+    ) -> Option<TaggedMessageEvent> {
+        let context = self.local_srtp_context.as_mut()?;
+        match rtcp::packet::marshal(packets).and_then(|packet| context.encrypt_rtcp(&packet)) {
+            Ok(packet) => Some(TaggedMessageEvent {
+                now,
+                transport: TransportContext {
+                    local_addr: self.four_tuple.local_addr,
+                    peer_addr: self.four_tuple.peer_addr,
+                    ecn: None,
+                },
+                message: MessageEvent::Rtp(RTPMessageEvent::Raw(packet)),
+            }),
+            Err(err) => {
+                warn!("encrypt rtcp with error {}", err);
+                None
+            }
+        }
     }
 
     pub(crate) fn keep_alive(&mut self) {
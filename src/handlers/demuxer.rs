@@ -1,9 +1,14 @@
+use crate::configs::demuxer_config::{DemuxerConfig, ZrtpMode};
 use crate::messages::{
     DTLSMessageEvent, MessageEvent, RTPMessageEvent, STUNMessageEvent, TaggedMessageEvent,
 };
-use log::{debug, error};
+use crate::server::states::ServerStates;
+use log::{debug, error, warn};
 use retty::channel::{Context, Handler};
-use retty::transport::TaggedBytesMut;
+use retty::transport::{TaggedBytesMut, TransportContext};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
 
 /// match_range is a MatchFunc that accepts packets with the first byte in [lower..upper]
 fn match_range(lower: u8, upper: u8, buf: &[u8]) -> bool {
@@ -27,6 +32,12 @@ fn match_range(lower: u8, upper: u8, buf: &[u8]) -> bool {
 ///              |                |
 ///              |    [128..191] -+--> forward to RTP/RTCP
 ///              +----------------+
+/// match_zrtp is a MatchFunc that accepts packets with the first byte in [16..19]
+/// as defied in RFC7983
+fn match_zrtp(b: &[u8]) -> bool {
+    match_range(16, 19, b)
+}
+
 /// match_dtls is a MatchFunc that accepts packets with the first byte in [20..63]
 /// as defied in RFC7983
 fn match_dtls(b: &[u8]) -> bool {
@@ -41,11 +52,89 @@ fn match_srtp(b: &[u8]) -> bool {
 
 /// DemuxerHandler implements demuxing of STUN/DTLS/RTP/RTCP Protocol packets
 #[derive(Default)]
-pub struct DemuxerHandler;
+pub struct DemuxerHandler {
+    demuxer_config: DemuxerConfig,
+    udp_recv_buffer_size: Option<usize>,
+    server_states: Option<Rc<RefCell<ServerStates>>>,
+    transmits: VecDeque<TaggedBytesMut>,
+}
 
 impl DemuxerHandler {
     pub fn new() -> Self {
-        DemuxerHandler
+        DemuxerHandler::default()
+    }
+
+    /// create DemuxerHandler with DemuxerConfig of the server, which records metrics of ZRTP packets
+    /// and drops datagrams truncated by the server's UDP receive buffer
+    pub fn with_server_states(server_states: Rc<RefCell<ServerStates>>) -> Self {
+        let (demuxer_config, udp_recv_buffer_size) = {
+            let server_states = server_states.borrow();
+            let server_config = server_states.server_config();
+            (
+                server_config.demuxer_config.clone(),
+                server_config.udp_recv_buffer_size,
+            )
+        };
+        DemuxerHandler {
+            demuxer_config,
+            udp_recv_buffer_size: Some(udp_recv_buffer_size),
+            server_states: Some(server_states),
+            transmits: VecDeque::new(),
+        }
+    }
+
+    /// datagrams filling the whole receive buffer may have been truncated by the UDP socket
+    fn is_truncated(&self, msg: &TaggedBytesMut) -> bool {
+        let Some(udp_recv_buffer_size) = self.udp_recv_buffer_size else {
+            return false;
+        };
+        if msg.message.len() < udp_recv_buffer_size {
+            return false;
+        }
+        if let Some(server_states) = &self.server_states {
+            let server_states = server_states.borrow();
+            let attributes = server_states.metrics_attributes(&(&msg.transport).into());
+            server_states
+                .metrics()
+                .record_truncated_packet_dropped_count(1, &attributes);
+        }
+        true
+    }
+
+    fn handle_zrtp(&mut self, msg: TaggedBytesMut) {
+        if let Some(server_states) = &self.server_states {
+            let server_states = server_states.borrow();
+            let attributes = server_states.metrics_attributes(&(&msg.transport).into());
+            server_states
+                .metrics()
+                .record_zrtp_packet_in_count(1, &attributes);
+        }
+
+        match self.demuxer_config.zrtp_mode {
+            ZrtpMode::Drop => {
+                debug!("drop zrtp packet from {:?}", msg.transport.peer_addr);
+            }
+            ZrtpMode::Forward(proxy_addr) => {
+                debug!(
+                    "forward zrtp packet from {:?} to {:?}",
+                    msg.transport.peer_addr, proxy_addr
+                );
+                self.transmits.push_back(TaggedBytesMut {
+                    now: msg.now,
+                    transport: TransportContext {
+                        peer_addr: proxy_addr,
+                        ..msg.transport
+                    },
+                    message: msg.message,
+                });
+            }
+            ZrtpMode::Warn => {
+                warn!(
+                    "drop unsupported zrtp packet from {:?}",
+                    msg.transport.peer_addr
+                );
+            }
+        }
     }
 }
 
@@ -66,6 +155,14 @@ impl Handler for DemuxerHandler {
     ) {
         if msg.message.is_empty() {
             error!("drop invalid packet due to zero length");
+        } else if self.is_truncated(&msg) {
+            warn!(
+                "drop truncated packet of {} bytes from {:?}",
+                msg.message.len(),
+                msg.transport.peer_addr
+            );
+        } else if match_zrtp(&msg.message) {
+            self.handle_zrtp(msg);
         } else if match_dtls(&msg.message) {
             ctx.fire_read(TaggedMessageEvent {
                 now: msg.now,
@@ -91,6 +188,9 @@ impl Handler for DemuxerHandler {
         &mut self,
         ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
     ) -> Option<Self::Wout> {
+        if let Some(transmit) = self.transmits.pop_front() {
+            return Some(transmit);
+        }
         if let Some(msg) = ctx.fire_poll_write() {
             match msg.message {
                 MessageEvent::Stun(STUNMessageEvent::Raw(message))
@@ -6,12 +6,13 @@ use std::net::SocketAddr;
 use std::rc::Rc;
 use std::time::Instant;
 
-use crate::messages::{DTLSMessageEvent, MessageEvent, TaggedMessageEvent};
+use crate::endpoint::transport::PendingSrtpPacket;
+use crate::messages::{DTLSMessageEvent, MessageEvent, RTPMessageEvent, TaggedMessageEvent};
 use crate::server::states::ServerStates;
 use dtls::endpoint::EndpointEvent;
 use dtls::extension::extension_use_srtp::SrtpProtectionProfile;
 use dtls::state::State;
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
 use retty::transport::TransportContext;
 use shared::error::{Error, Result};
 use srtp::option::{srtcp_replay_protection, srtp_replay_protection};
@@ -53,7 +54,7 @@ impl Handler for DtlsHandler {
             debug!("recv dtls RAW {:?}", msg.transport.peer_addr);
             let four_tuple = (&msg.transport).into();
 
-            let try_read = || -> Result<Vec<BytesMut>> {
+            let try_read = || -> Result<(Vec<BytesMut>, VecDeque<PendingSrtpPacket>)> {
                 let mut server_states = self.server_states.borrow_mut();
                 let transport = match server_states.get_mut_transport(&four_tuple) {
                     Ok(transport) => transport,
@@ -62,11 +63,34 @@ impl Handler for DtlsHandler {
                         return Err(err);
                     }
                 };
+                transport.record_dtls_received(msg.now);
                 let mut messages = vec![];
                 let mut contexts = vec![];
 
+                // a peer starting over with a new handshake on a completed transport, e.g., to
+                // rekey, replaces its DTLS connection, while the current SRTP contexts are kept
+                // until the new handshake completes. Since a ClientHello is unauthenticated, the
+                // restart is only accepted after an ICE-authenticated binding request of the
+                // four-tuple, so that a spoofed one can't tear down the live connection
+                let is_restarted = transport.is_srtp_context_ready()
+                    && DtlsHandler::is_initial_client_hello(&dtls_message);
+                if is_restarted && !transport.take_dtls_restart_authorization() {
+                    warn!(
+                        "drop dtls restart without binding request {:?}",
+                        msg.transport.peer_addr
+                    );
+                    return Ok((vec![], VecDeque::new()));
+                }
+
                 {
                     let dtls_endpoint = transport.get_mut_dtls_endpoint();
+                    if is_restarted {
+                        debug!("restart dtls handshake {:?}", msg.transport.peer_addr);
+                        // the close_notify of the replaced connection is not sent, since the
+                        // peer has already dropped it
+                        dtls_endpoint.close(msg.transport.peer_addr);
+                        while dtls_endpoint.poll_transmit().is_some() {}
+                    }
 
                     for message in dtls_endpoint.read(
                         msg.now,
@@ -111,16 +135,29 @@ impl Handler for DtlsHandler {
                     }
                 }
 
-                for (local_context, remote_context) in contexts {
-                    transport.set_local_srtp_context(local_context);
-                    transport.set_remote_srtp_context(remote_context);
+                // only the latest handshake's keys are valid, e.g., after DTLS renegotiation
+                let mut pending_srtp_packets = VecDeque::new();
+                if let Some((local_context, remote_context)) = contexts.pop() {
+                    if transport.set_srtp_contexts(local_context, remote_context) {
+                        debug!("rekey srtp contexts for {:?}", four_tuple);
+                    }
+                    pending_srtp_packets = transport.take_pending_srtp_packets();
                 }
 
-                Ok(messages)
+                Ok((messages, pending_srtp_packets))
             };
 
             match try_read() {
-                Ok(messages) => {
+                Ok((messages, pending_srtp_packets)) => {
+                    // SRTP packets received before handshake completion are processed now
+                    for (now, packet) in pending_srtp_packets {
+                        debug!("replay pending srtp packet {:?}", msg.transport.peer_addr);
+                        ctx.fire_read(TaggedMessageEvent {
+                            now,
+                            transport: msg.transport,
+                            message: MessageEvent::Rtp(RTPMessageEvent::Raw(packet)),
+                        });
+                    }
                     for message in messages {
                         debug!("recv dtls application RAW {:?}", msg.transport.peer_addr);
                         ctx.fire_read(TaggedMessageEvent {
@@ -133,7 +170,25 @@ impl Handler for DtlsHandler {
                 Err(err) => {
                     error!("try_read with error {}", err);
                     if err == Error::ErrAlertFatalOrClose {
+                        // tear down the endpoint right away on close_notify or fatal alert,
+                        // instead of waiting for idle timeout
                         let mut server_states = self.server_states.borrow_mut();
+                        if let Ok(transport) = server_states.get_mut_transport(&four_tuple) {
+                            // flush the close_notify reply, which is queued but not transmitted
+                            // when reading the alert fails
+                            let dtls_endpoint = transport.get_mut_dtls_endpoint();
+                            dtls_endpoint.close(msg.transport.peer_addr);
+                            while let Some(transmit) = dtls_endpoint.poll_transmit() {
+                                self.transmits.push_back(TaggedMessageEvent {
+                                    now: msg.now,
+                                    transport: msg.transport,
+                                    message: MessageEvent::Dtls(DTLSMessageEvent::Raw(
+                                        transmit.payload,
+                                    )),
+                                });
+                            }
+                        }
+                        info!("dtls connection of {:?} is closed by alert", four_tuple);
                         server_states.remove_transport(four_tuple);
                     } else {
                         ctx.fire_exception(Box::new(err))
@@ -266,6 +321,27 @@ impl Handler for DtlsHandler {
 impl DtlsHandler {
     const DEFAULT_SESSION_SRTP_REPLAY_PROTECTION_WINDOW: usize = 64;
     const DEFAULT_SESSION_SRTCP_REPLAY_PROTECTION_WINDOW: usize = 64;
+
+    /// is_initial_client_hello returns whether the datagram starts with the unencrypted
+    /// ClientHello of a new handshake, i.e., a handshake record of epoch 0 whose handshake type is
+    /// 1 and whose cookie is empty, unlike the ClientHello replying to a HelloVerifyRequest
+    fn is_initial_client_hello(dtls_message: &[u8]) -> bool {
+        const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+        const HANDSHAKE_TYPE_CLIENT_HELLO: u8 = 1;
+        // record header of 13 bytes, handshake header of 12 bytes, then version and random
+        const SESSION_ID_OFFSET: usize = 13 + 12 + 2 + 32;
+
+        if dtls_message.len() <= SESSION_ID_OFFSET
+            || dtls_message[0] != CONTENT_TYPE_HANDSHAKE
+            || dtls_message[3..5] != [0, 0]
+            || dtls_message[13] != HANDSHAKE_TYPE_CLIENT_HELLO
+        {
+            return false;
+        }
+        let cookie_offset = SESSION_ID_OFFSET + 1 + dtls_message[SESSION_ID_OFFSET] as usize;
+        dtls_message.get(cookie_offset) == Some(&0)
+    }
+
     pub(crate) fn update_srtp_contexts(
         state: &State,
     ) -> Result<(srtp::context::Context, srtp::context::Context)> {
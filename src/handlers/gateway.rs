@@ -1,18 +1,46 @@
+use crate::configs::media_config::{
+    MediaConfig, MIME_TYPE_RED, MIME_TYPE_TELEPHONE_EVENT, MIME_TYPE_ULPFEC, MIME_TYPE_VP8,
+    MIME_TYPE_VP9,
+};
 use crate::description::{
+    playout_delay::PLAYOUT_DELAY_URI, rtp_codec::RTPCodecType, rtp_transceiver::SSRC,
     rtp_transceiver_direction::RTCRtpTransceiverDirection, sdp_type::RTCSdpType,
     RTCSessionDescription,
 };
-use crate::endpoint::candidate::Candidate;
+use crate::endpoint::{
+    candidate::{Candidate, RTCIceParameters, RTCIceRole},
+    fec::red_primary_payload_type,
+    layer::{vp8_is_keyframe, vp8_temporal_layer_id, LayerIndex},
+    pacer::SendPriority,
+    Endpoint, NegotiationState,
+};
+use crate::interceptors::vp9::Vp9LayerIndex;
 use crate::messages::{
     ApplicationMessage, DTLSMessageEvent, DataChannelEvent, MessageEvent, RTPMessageEvent,
-    STUNMessageEvent, TaggedMessageEvent,
+    STUNMessageEvent, SessionEvent, TaggedMessageEvent,
 };
 use crate::server::states::ServerStates;
-use bytes::BytesMut;
-use log::{debug, info, trace, warn};
+use crate::session::Session;
+use crate::types::{EndpointId, FourTuple, SessionId};
+use bytes::{BufMut, Bytes, BytesMut};
+use log::{debug, error, info, trace, warn};
 use retty::channel::{Context, Handler};
 use retty::transport::TransportContext;
+use rtcp::goodbye::Goodbye;
+use rtcp::header::PacketType;
+use rtcp::payload_feedbacks::{
+    full_intra_request::FullIntraRequest, picture_loss_indication::PictureLossIndication,
+    receiver_estimated_maximum_bitrate::ReceiverEstimatedMaximumBitrate,
+};
+use rtcp::raw_packet::RawPacket;
+use rtcp::sender_report::SenderReport;
+use rtcp::source_description::SourceDescription;
+use rtcp::transport_feedbacks::{
+    transport_layer_cc::TransportLayerCc, transport_layer_nack::TransportLayerNack,
+};
+use sdp::extmap::{SDES_MID_URI, TRANSPORT_CC_URI};
 use shared::error::{Error, Result};
+use shared::marshal::MarshalSize;
 use std::cell::RefCell;
 use std::collections::VecDeque;
 use std::ops::{Add, Sub};
@@ -20,15 +48,47 @@ use std::rc::Rc;
 use std::time::Duration;
 use std::time::Instant;
 use stun::attributes::{
-    ATTR_ICE_CONTROLLED, ATTR_ICE_CONTROLLING, ATTR_NETWORK_COST, ATTR_PRIORITY, ATTR_USERNAME,
-    ATTR_USE_CANDIDATE,
+    AttrType, ATTR_ERROR_CODE, ATTR_ICE_CONTROLLED, ATTR_ICE_CONTROLLING, ATTR_MAPPED_ADDRESS,
+    ATTR_MESSAGE_INTEGRITY, ATTR_MESSAGE_INTEGRITY_SHA256, ATTR_NONCE, ATTR_PASSWORD_ALGORITHM,
+    ATTR_PRIORITY, ATTR_REALM, ATTR_UNKNOWN_ATTRIBUTES, ATTR_USERNAME, ATTR_USER_HASH,
+    ATTR_USE_CANDIDATE, ATTR_XORMAPPED_ADDRESS,
 };
+use stun::error_code::{ErrorCodeAttribute, CODE_ROLE_CONFLICT, CODE_UNKNOWN_ATTRIBUTE};
 use stun::fingerprint::FINGERPRINT;
 use stun::integrity::MessageIntegrity;
-use stun::message::{Setter, TransactionId, BINDING_SUCCESS};
+use stun::message::{Setter, TransactionId, BINDING_ERROR, BINDING_SUCCESS};
 use stun::textattrs::TextAttribute;
+use stun::uattrs::UnknownAttributes;
 use stun::xoraddr::XorMappedAddress;
 
+/// comprehension-required STUN attributes understood by SFU, where any other attribute in
+/// comprehension-required range fails the request, while comprehension-optional ones are ignored,
+/// <https://www.rfc-editor.org/rfc/rfc5389#section-7.3.1>
+const KNOWN_COMPREHENSION_REQUIRED_ATTRIBUTES: [AttrType; 13] = [
+    ATTR_MAPPED_ADDRESS,
+    ATTR_USERNAME,
+    ATTR_MESSAGE_INTEGRITY,
+    ATTR_ERROR_CODE,
+    ATTR_UNKNOWN_ATTRIBUTES,
+    ATTR_REALM,
+    ATTR_NONCE,
+    ATTR_MESSAGE_INTEGRITY_SHA256,
+    ATTR_PASSWORD_ALGORITHM,
+    ATTR_USER_HASH,
+    ATTR_XORMAPPED_ADDRESS,
+    ATTR_PRIORITY,
+    ATTR_USE_CANDIDATE,
+];
+
+/// type of the data channel message notifying other endpoints that an endpoint joins the session,
+/// i.e., `{"type":"join","endpoint_id":1}`
+const SESSION_JOIN_TYPE: &str = "join";
+
+/// Temporary Maximum Media Stream Bit Rate Request/Notification feedback message types,
+/// <https://tools.ietf.org/html/rfc5104#section-4.2>
+pub(crate) const FORMAT_TMMBR: u8 = 3;
+pub(crate) const FORMAT_TMMBN: u8 = 4;
+
 /// GatewayHandler implements Data/Media Selective Forward handling
 pub struct GatewayHandler {
     server_states: Rc<RefCell<ServerStates>>,
@@ -143,6 +203,191 @@ impl Handler for GatewayHandler {
         now: Instant,
     ) {
         // terminate timeout here, no more ctx.fire_handle_timeout(now);
+        {
+            let mut server_states = self.server_states.borrow_mut();
+            for session in server_states.get_mut_sessions().values_mut() {
+                for endpoint in session.get_mut_endpoints().values_mut() {
+                    for transport in endpoint.get_mut_transports().values_mut() {
+                        let mut released_messages = vec![];
+                        if let Some(pacer) = transport.get_mut_pacer() {
+                            while let Some(message) = pacer.poll(now) {
+                                released_messages.push(message);
+                            }
+                        }
+                        for mut message in released_messages {
+                            if let MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)) =
+                                &message.message
+                            {
+                                transport.on_rtp_sent(rtp_packet, now);
+                            }
+                            message.now = now;
+                            self.transmits.push_back(message);
+                        }
+                    }
+                }
+            }
+        }
+
+        // signal publishers to stop sending ssrcs dropped by ServerStates::drop_ssrc
+        {
+            let mut server_states = self.server_states.borrow_mut();
+            for session in server_states.get_mut_sessions().values_mut() {
+                for (endpoint_id, ssrc) in session.take_pending_dropped_ssrcs() {
+                    let Some((four_tuple, sender_ssrc)) =
+                        session.get_endpoint(&endpoint_id).and_then(|endpoint| {
+                            endpoint
+                                .get_transports()
+                                .iter()
+                                .find(|(_, transport)| transport.is_srtp_context_ready())
+                                .map(|(four_tuple, transport)| {
+                                    (*four_tuple, transport.rtcp_sender_ssrc())
+                                })
+                        })
+                    else {
+                        continue;
+                    };
+                    self.transmits.push_back(TaggedMessageEvent {
+                        now,
+                        transport: TransportContext {
+                            local_addr: four_tuple.local_addr,
+                            peer_addr: four_tuple.peer_addr,
+                            ecn: None,
+                        },
+                        message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(vec![
+                            Box::new(PictureLossIndication {
+                                sender_ssrc,
+                                media_ssrc: ssrc,
+                            }),
+                            Box::new(Goodbye {
+                                sources: vec![ssrc],
+                                reason: Bytes::from_static(b"dropped"),
+                            }),
+                        ])),
+                    });
+                }
+            }
+        }
+
+        // answer the latest offers throttled by the minimum renegotiation interval
+        {
+            let mut server_states = self.server_states.borrow_mut();
+            let mut offers = vec![];
+            for (&session_id, session) in server_states.get_mut_sessions().iter_mut() {
+                for (&endpoint_id, endpoint) in session.get_mut_endpoints().iter_mut() {
+                    let Some(offer) = endpoint.take_due_throttled_offer(now) else {
+                        continue;
+                    };
+                    // the transport which the offer was received on may be gone by now, e.g.,
+                    // by idle timeout or migration, or not have its data channel open
+                    let data_channel = endpoint
+                        .get_transports()
+                        .get(&offer.four_tuple)
+                        .map(|transport| transport.association_handle_and_stream_id());
+                    if let Some((Some(association_handle), Some(stream_id))) = data_channel {
+                        offers.push((
+                            session_id,
+                            endpoint_id,
+                            offer,
+                            association_handle,
+                            stream_id,
+                        ));
+                    } else {
+                        debug!(
+                            "{}/{} drops throttled offer without data channel of {:?}",
+                            session_id, endpoint_id, offer.four_tuple
+                        );
+                    }
+                }
+            }
+            for (session_id, endpoint_id, offer, association_handle, stream_id) in offers {
+                match GatewayHandler::create_answer_message_event(
+                    &mut server_states,
+                    now,
+                    session_id,
+                    endpoint_id,
+                    offer.four_tuple,
+                    association_handle,
+                    stream_id,
+                    offer.offer,
+                ) {
+                    Ok(message) => self.transmits.push_back(message),
+                    Err(err) => error!("create_answer_message_event error: {}", err),
+                }
+            }
+        }
+
+        // roll back offers left unanswered, e.g., lost with a closed data channel, so that
+        // the endpoints are renegotiated again
+        {
+            let mut server_states = self.server_states.borrow_mut();
+            for session in server_states.get_mut_sessions().values_mut() {
+                let session_id = session.session_id();
+                for endpoint in session.get_mut_endpoints().values_mut() {
+                    if endpoint
+                        .pending_offer_expires_at()
+                        .is_some_and(|expires_at| expires_at <= now)
+                    {
+                        warn!(
+                            "{}/{} rolls back offer not answered in time",
+                            session_id,
+                            endpoint.endpoint_id()
+                        );
+                        if let Err(err) = endpoint.rollback_pending_offer() {
+                            error!("rollback_pending_offer error: {}", err);
+                        }
+                    }
+                }
+            }
+        }
+
+        // renegotiate endpoints whose transceivers are changed, e.g., by other endpoints' offers
+        // or endpoint migration, once their data channels are ready and debounce windows passed,
+        // but never while a previous offer still waits for its answer
+        {
+            let mut server_states = self.server_states.borrow_mut();
+            let mut peers = vec![];
+            for session in server_states.get_sessions().values() {
+                for endpoint in session.get_endpoints().values() {
+                    if !endpoint.is_renegotiation_due(now)
+                        || endpoint.has_pending_offer()
+                        || endpoint.negotiation_state() != NegotiationState::Stable
+                    {
+                        continue;
+                    }
+                    if let Some((four_tuple, (association_handle, stream_id))) = endpoint
+                        .get_transports()
+                        .iter()
+                        .find_map(|(four_tuple, transport)| {
+                            match transport.association_handle_and_stream_id() {
+                                (Some(association_handle), Some(stream_id)) => {
+                                    Some((*four_tuple, (association_handle, stream_id)))
+                                }
+                                _ => None,
+                            }
+                        })
+                    {
+                        peers.push((four_tuple, association_handle, stream_id));
+                    }
+                }
+            }
+            for (four_tuple, association_handle, stream_id) in peers {
+                match GatewayHandler::create_offer_message_event(
+                    &mut server_states,
+                    now,
+                    TransportContext {
+                        local_addr: four_tuple.local_addr,
+                        peer_addr: four_tuple.peer_addr,
+                        ecn: None,
+                    },
+                    association_handle,
+                    stream_id,
+                ) {
+                    Ok(message) => self.transmits.push_back(message),
+                    Err(err) => error!("create_offer_message_event error: {}", err),
+                }
+            }
+        }
+
         if self.next_timeout <= now {
             let mut four_tuples = vec![];
             let mut server_states = self.server_states.borrow_mut();
@@ -171,6 +416,40 @@ impl Handler for GatewayHandler {
         if self.next_timeout < *eto {
             *eto = self.next_timeout;
         }
+        {
+            let server_states = self.server_states.borrow();
+            for session in server_states.get_sessions().values() {
+                for endpoint in session.get_endpoints().values() {
+                    if let Some(debounce_until) = endpoint
+                        .renegotiation_debounce_until()
+                        .filter(|_| endpoint.is_renegotiation_needed())
+                    {
+                        if debounce_until < *eto {
+                            *eto = debounce_until;
+                        }
+                    }
+                    if let Some(due_at) = endpoint.throttled_offer_due_at() {
+                        if due_at < *eto {
+                            *eto = due_at;
+                        }
+                    }
+                    if let Some(expires_at) = endpoint.pending_offer_expires_at() {
+                        if expires_at < *eto {
+                            *eto = expires_at;
+                        }
+                    }
+                    for transport in endpoint.get_transports().values() {
+                        if let Some(timeout) =
+                            transport.pacer().and_then(|pacer| pacer.poll_timeout())
+                        {
+                            if timeout < *eto {
+                                *eto = timeout;
+                            }
+                        }
+                    }
+                }
+            }
+        }
         ctx.fire_poll_timeout(eto);
     }
 
@@ -192,6 +471,22 @@ impl GatewayHandler {
         transport_context: TransportContext,
         mut request: stun::message::Message,
     ) -> Result<Vec<TaggedMessageEvent>> {
+        let unknown_attributes: Vec<AttrType> = request
+            .attributes
+            .0
+            .iter()
+            .map(|attribute| attribute.typ)
+            .filter(|typ| typ.required() && !KNOWN_COMPREHENSION_REQUIRED_ATTRIBUTES.contains(typ))
+            .collect();
+        if !unknown_attributes.is_empty() {
+            return GatewayHandler::create_unknown_attributes_message_event(
+                now,
+                transport_context,
+                &request,
+                unknown_attributes,
+            );
+        }
+
         let candidate = match GatewayHandler::check_stun_message(server_states, &mut request)? {
             Some(candidate) => candidate,
             None => {
@@ -203,7 +498,19 @@ impl GatewayHandler {
             }
         };
 
+        if GatewayHandler::is_ice_role_conflict(&request, &candidate)? {
+            return GatewayHandler::create_role_conflict_message_event(
+                now,
+                transport_context,
+                &request,
+                &candidate,
+            );
+        }
+
         GatewayHandler::add_endpoint(server_states, &request, &candidate, &transport_context)?;
+        if let Ok(transport) = server_states.get_mut_transport(&(&transport_context).into()) {
+            transport.record_stun_received(now);
+        }
 
         let mut response = stun::message::Message::new();
         response.build(&[
@@ -285,10 +592,14 @@ impl GatewayHandler {
                 session_id
             )))?;
 
+        // mids of forwarded transceivers are prefixed by their publisher's endpoint id,
+        // which are unique within the session, so they never collide with each other
         let mut new_transceivers = vec![];
-        let endpoints = session.get_endpoints();
-        for (&other_endpoint_id, other_endpoint) in endpoints.iter() {
+        for other_endpoint_id in session.endpoint_ids() {
             if other_endpoint_id != endpoint_id {
+                let Some(other_endpoint) = session.get_endpoint(&other_endpoint_id) else {
+                    continue;
+                };
                 let other_transceivers = other_endpoint.get_transceivers();
                 for (other_mid_value, other_transceiver) in other_transceivers.iter() {
                     if other_transceiver.direction == RTCRtpTransceiverDirection::Recvonly {
@@ -320,25 +631,27 @@ impl GatewayHandler {
             endpoint_id,
             transport.four_tuple()
         );
+        // the offer for new transceivers is sent by handle_timeout after the debounce window
         endpoint.set_renegotiation_needed(!new_transceivers.is_empty());
-
-        let (mids, transceivers) = endpoint.get_mut_mids_and_transceivers();
-        for transceiver in new_transceivers {
-            mids.push(transceiver.mid.clone());
-            transceivers.insert(transceiver.mid.clone(), transceiver);
-        }
-
-        if endpoint.is_renegotiation_needed() {
-            Ok(vec![GatewayHandler::create_offer_message_event(
-                server_states,
-                now,
-                transport_context,
-                association_handle,
-                stream_id,
-            )?])
-        } else {
-            Ok(vec![])
-        }
+        session.add_forwarded_transceivers(endpoint_id, new_transceivers);
+
+        // notify the other endpoints of the session that this endpoint joins
+        let join = serde_json::json!({
+            "type": SESSION_JOIN_TYPE,
+            "endpoint_id": endpoint_id,
+        });
+        Ok(server_states
+            .broadcast_to_session(session_id, BytesMut::from(join.to_string().as_str()))?
+            .into_iter()
+            .filter(|message| {
+                message.transport.local_addr != transport_context.local_addr
+                    || message.transport.peer_addr != transport_context.peer_addr
+            })
+            .map(|mut message| {
+                message.now = now;
+                message
+            })
+            .collect())
     }
 
     fn handle_datachannel_close(
@@ -372,55 +685,24 @@ impl GatewayHandler {
 
         match request_sdp.sdp_type {
             RTCSdpType::Offer => {
-                let answer = server_states.accept_offer(
-                    session_id,
-                    endpoint_id,
-                    Some(four_tuple),
-                    request_sdp,
-                )?;
-                let answer_str =
-                    serde_json::to_string(&answer).map_err(|err| Error::Other(err.to_string()))?;
-
-                let peers = GatewayHandler::get_other_datachannel_transport_contexts(
+                // other endpoints needing renegotiation are sent offers by handle_timeout
+                // after their debounce windows
+                match GatewayHandler::create_answer_message_event(
                     server_states,
-                    &transport_context,
-                )?;
-                let mut messages = Vec::with_capacity(peers.len() + 1);
-
-                messages.push(TaggedMessageEvent {
                     now,
-                    transport: transport_context,
-                    message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(
-                        ApplicationMessage {
-                            association_handle,
-                            stream_id,
-                            data_channel_event: DataChannelEvent::Message(BytesMut::from(
-                                answer_str.as_str(),
-                            )),
-                        },
-                    )),
-                });
-
-                // trigger other endpoints' create_offer()
-                for (
-                    other_transport_context,
+                    session_id,
+                    endpoint_id,
+                    four_tuple,
                     association_handle,
                     stream_id,
-                    is_renegotiation_needed,
-                ) in peers
-                {
-                    if is_renegotiation_needed {
-                        messages.push(GatewayHandler::create_offer_message_event(
-                            server_states,
-                            now,
-                            other_transport_context,
-                            association_handle,
-                            stream_id,
-                        )?);
-                    }
+                    request_sdp,
+                ) {
+                    Ok(message) => Ok(vec![message]),
+                    // throttled offer is answered by handle_timeout once the minimum
+                    // renegotiation interval passes
+                    Err(Error::ErrTryAgain) => Ok(vec![]),
+                    Err(err) => Err(err),
                 }
-
-                Ok(messages)
             }
             RTCSdpType::Answer => {
                 server_states.accept_answer(session_id, endpoint_id, four_tuple, request_sdp)?;
@@ -433,33 +715,446 @@ impl GatewayHandler {
         }
     }
 
+    /// accept the offer received over data channel, and return its answer to be sent back
+    #[allow(clippy::too_many_arguments)]
+    fn create_answer_message_event(
+        server_states: &mut ServerStates,
+        now: Instant,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        four_tuple: FourTuple,
+        association_handle: usize,
+        stream_id: u16,
+        offer: RTCSessionDescription,
+    ) -> Result<TaggedMessageEvent> {
+        let answer =
+            server_states.accept_offer_at(now, session_id, endpoint_id, Some(four_tuple), offer)?;
+        let answer_str =
+            serde_json::to_string(&answer).map_err(|err| Error::Other(err.to_string()))?;
+
+        Ok(TaggedMessageEvent {
+            now,
+            transport: TransportContext {
+                local_addr: four_tuple.local_addr,
+                peer_addr: four_tuple.peer_addr,
+                ecn: None,
+            },
+            message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(ApplicationMessage {
+                association_handle,
+                stream_id,
+                data_channel_event: DataChannelEvent::Message(BytesMut::from(answer_str.as_str())),
+            })),
+        })
+    }
+
     fn handle_rtp_message(
         server_states: &mut ServerStates,
         now: Instant,
         transport_context: TransportContext,
-        rtp_packet: rtp::packet::Packet,
+        mut rtp_packet: rtp::packet::Packet,
     ) -> Result<Vec<TaggedMessageEvent>> {
         debug!("handle_rtp_message {}", transport_context.peer_addr);
         server_states
             .get_mut_transport(&(&transport_context).into())?
             .keep_alive();
 
+        // rewrite ssrc if it collides with other publisher's one
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&(&transport_context).into())
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        if let Some(endpoint) = server_states
+            .get_mut_session(&session_id)
+            .and_then(|session| session.get_mut_endpoint(&endpoint_id))
+        {
+            endpoint.record_received_rtp(rtp_packet.header.ssrc, rtp_packet.marshal_size(), now);
+        }
+        // layer by dependency descriptor, which is parsed with the publisher's negotiated
+        // extension id and template dependency structure of its original ssrc
+        let dependency_descriptor_layer = server_states
+            .get_mut_session(&session_id)
+            .and_then(|session| session.get_mut_endpoint(&endpoint_id))
+            .and_then(|endpoint| endpoint.get_dependency_descriptor_layer(&rtp_packet.header));
+        // codec-agnostic layer and keyframe by frame-marking, e.g., of E2EE sessions
+        let frame_marking = server_states
+            .get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .and_then(|endpoint| endpoint.get_frame_marking(&rtp_packet.header));
+        let frame_marking_layer = frame_marking
+            .filter(|frame_marking| frame_marking.scalable)
+            .map(|frame_marking| LayerIndex {
+                spatial_layer_id: frame_marking.layer_id,
+                temporal_layer_id: frame_marking.temporal_layer_id,
+                is_end_of_frame: frame_marking.end_of_frame,
+            });
+        if let Some(session) = server_states.get_session(&session_id) {
+            // tracks of rejected media sections are not forwarded any more
+            if session
+                .get_endpoint(&endpoint_id)
+                .and_then(|endpoint| endpoint.get_transceiver_by_ssrc(rtp_packet.header.ssrc))
+                .is_some_and(|transceiver| transceiver.is_stopped())
+            {
+                trace!(
+                    "{}/{} ssrc {} is stopped",
+                    session_id,
+                    endpoint_id,
+                    rtp_packet.header.ssrc
+                );
+                return Ok(vec![]);
+            }
+            rtp_packet.header.ssrc = session.forwarded_ssrc(endpoint_id, rtp_packet.header.ssrc);
+            if session.is_ssrc_dropped(rtp_packet.header.ssrc) {
+                trace!(
+                    "{}/{} ssrc {} is dropped",
+                    session_id,
+                    endpoint_id,
+                    rtp_packet.header.ssrc
+                );
+                return Ok(vec![]);
+            }
+        }
+
         //TODO: Selective Forwarding RTP Packets
         let peers =
             GatewayHandler::get_other_media_transport_contexts(server_states, &transport_context)?;
 
+        let playout_delay = server_states.server_config().media_config.playout_delay();
+        let rtp_rewriting = server_states.server_config().rtp_rewriting;
+        let ulpfec_overhead = server_states.server_config().media_config.ulpfec_overhead();
+        // payloads of E2EE sessions are opaque, so only RTP headers are inspected
+        let is_e2ee = server_states
+            .get_session(&session_id)
+            .is_some_and(|session| session.is_e2ee());
+        let is_ulpfec = GatewayHandler::is_ulpfec_packet(
+            &server_states.server_config().media_config,
+            &rtp_packet,
+            is_e2ee,
+        );
+        let is_telephone_event = GatewayHandler::is_telephone_event_packet(
+            &server_states.server_config().media_config,
+            &rtp_packet,
+        );
+        let layer_index = dependency_descriptor_layer
+            .or(frame_marking_layer)
+            .or_else(|| {
+                if is_e2ee {
+                    None
+                } else {
+                    GatewayHandler::layer_index(
+                        &server_states.server_config().media_config,
+                        &rtp_packet,
+                    )
+                }
+            });
+
+        let is_keyframe = frame_marking.map_or_else(
+            || {
+                !is_e2ee
+                    && GatewayHandler::is_keyframe_packet(
+                        &server_states.server_config().media_config,
+                        &rtp_packet,
+                    )
+            },
+            |frame_marking| frame_marking.is_keyframe(),
+        );
+        let keyframe_cache_enabled = server_states.server_config().keyframe_cache_size > 0;
+        if keyframe_cache_enabled && !is_ulpfec {
+            if let Some(session) = server_states.get_mut_session(&session_id) {
+                session.cache_keyframe(endpoint_id, &rtp_packet, layer_index, is_keyframe);
+            }
+        }
+
         let mut outgoing_messages = Vec::with_capacity(peers.len());
         for transport in peers {
-            outgoing_messages.push(TaggedMessageEvent {
+            // subscribers who are forwarded the ssrc for the first time get the cached packets
+            // since its latest keyframe, which end with this packet, instead of waiting for
+            // their PLI round-trip
+            let is_first_forwarded = keyframe_cache_enabled
+                && server_states
+                    .find_endpoint(&(&transport).into())
+                    .and_then(|(session_id, endpoint_id)| {
+                        server_states
+                            .get_mut_session(&session_id)?
+                            .get_mut_endpoint(&endpoint_id)
+                    })
+                    .is_some_and(|endpoint| endpoint.mark_forwarded(rtp_packet.header.ssrc));
+            let cached_packets = if is_first_forwarded {
+                server_states
+                    .get_session(&session_id)
+                    .map(|session| {
+                        session
+                            .get_cached_keyframe(endpoint_id, rtp_packet.header.ssrc)
+                            .to_vec()
+                    })
+                    .unwrap_or_default()
+            } else {
+                vec![]
+            };
+            let packets = if cached_packets.is_empty() {
+                vec![(rtp_packet.clone(), layer_index)]
+            } else {
+                cached_packets
+            };
+
+            for (mut rtp_packet, layer_index) in packets {
+                let mut fec_packet = None;
+                if let Some(endpoint) = server_states.find_endpoint(&(&transport).into()).and_then(
+                    |(session_id, endpoint_id)| {
+                        server_states
+                            .get_mut_session(&session_id)?
+                            .get_mut_endpoint(&endpoint_id)
+                    },
+                ) {
+                    // strip ulpfec packets of publishers for subscribers who didn't negotiate it,
+                    // or who get ulpfec packets generated by SFU instead
+                    if is_ulpfec
+                        && (ulpfec_overhead.is_some() || endpoint.ulpfec_payload_type().is_none())
+                    {
+                        continue;
+                    }
+
+                    // drop layers above the subscriber's maximum ones
+                    if !endpoint.select_layer(layer_index, &mut rtp_packet.header) {
+                        continue;
+                    }
+
+                    // inject playout-delay only for subscribers who negotiated it
+                    if let Some((playout_delay, id)) = playout_delay.zip(
+                        endpoint.get_header_extension_id(rtp_packet.header.ssrc, PLAYOUT_DELAY_URI),
+                    ) {
+                        if let Err(err) = playout_delay.set_extension(&mut rtp_packet.header, id) {
+                            warn!("set playout-delay extension with error {}", err);
+                        }
+                    }
+
+                    // stamp subscriber-side mid for subscribers who negotiated mid extension
+                    if let Some((id, mid)) = endpoint
+                        .get_transceiver_by_ssrc(rtp_packet.header.ssrc)
+                        .and_then(|transceiver| {
+                            let id = transceiver.get_header_extension_id(SDES_MID_URI)?;
+                            Some((id, transceiver.mid.clone()))
+                        })
+                    {
+                        if let Err(err) = rtp_packet.header.set_extension(id, Bytes::from(mid)) {
+                            warn!("set mid extension with error {}", err);
+                        }
+                    }
+
+                    if rtp_rewriting {
+                        // DTMF events keep their start timestamps and marker/end bits as published
+                        if is_telephone_event {
+                            endpoint.rewrite_rtp_event(now, &mut rtp_packet.header);
+                        } else {
+                            endpoint.rewrite_rtp(now, &mut rtp_packet.header);
+                        }
+                    }
+
+                    // stamp transport-wide sequence numbers, counted per subscriber transport,
+                    // for subscribers who negotiated transport-cc extension
+                    let transport_cc_id =
+                        endpoint.get_header_extension_id(rtp_packet.header.ssrc, TRANSPORT_CC_URI);
+                    let stamp_transport_cc =
+                        |endpoint: &mut Endpoint, packet: &mut rtp::packet::Packet| {
+                            let Some(id) = transport_cc_id else {
+                                return;
+                            };
+                            if let Some(subscriber_transport) =
+                                endpoint.get_mut_transports().get_mut(&(&transport).into())
+                            {
+                                if let Err(err) =
+                                    subscriber_transport.stamp_transport_cc(id, packet)
+                                {
+                                    warn!("set transport-cc extension with error {}", err);
+                                }
+                            }
+                        };
+                    stamp_transport_cc(endpoint, &mut rtp_packet);
+
+                    let is_audio = endpoint
+                        .get_transceiver_by_ssrc(rtp_packet.header.ssrc)
+                        .is_some_and(|transceiver| transceiver.kind == RTPCodecType::Audio);
+                    if let Some(overhead) = ulpfec_overhead.filter(|_| !is_audio) {
+                        fec_packet = endpoint.protect_rtp(overhead, &mut rtp_packet);
+                        if let Some(fec_packet) = fec_packet.as_mut() {
+                            stamp_transport_cc(endpoint, fec_packet);
+                        }
+                    }
+                }
+
+                outgoing_messages.push(TaggedMessageEvent {
+                    now,
+                    transport,
+                    message: MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)),
+                });
+                if let Some(fec_packet) = fec_packet {
+                    outgoing_messages.push(TaggedMessageEvent {
+                        now,
+                        transport,
+                        message: MessageEvent::Rtp(RTPMessageEvent::Rtp(fec_packet)),
+                    });
+                }
+            }
+        }
+
+        if let Some(pacing_bitrate) = server_states.server_config().pacing_bitrate {
+            outgoing_messages = GatewayHandler::pace_rtp_messages(
+                server_states,
                 now,
-                transport,
-                message: MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet.clone())),
-            });
+                pacing_bitrate,
+                outgoing_messages,
+            );
+        }
+
+        // packets which are sent right now, instead of being queued in pacers
+        for message in &outgoing_messages {
+            if let MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)) = &message.message {
+                if let Ok(transport) = server_states.get_mut_transport(&(&message.transport).into())
+                {
+                    transport.on_rtp_sent(rtp_packet, now);
+                }
+            }
         }
 
         Ok(outgoing_messages)
     }
 
+    /// is_ulpfec_packet returns whether the packet carries ulpfec of the server's codecs,
+    /// either plain or as the primary block of RED, unless the payload is end-to-end encrypted
+    fn is_ulpfec_packet(
+        media_config: &MediaConfig,
+        rtp_packet: &rtp::packet::Packet,
+        is_e2ee: bool,
+    ) -> bool {
+        let Some(ulpfec_payload_type) = media_config.get_payload_type_for_mime(MIME_TYPE_ULPFEC)
+        else {
+            return false;
+        };
+        rtp_packet.header.payload_type == ulpfec_payload_type
+            || (!is_e2ee
+                && media_config.get_payload_type_for_mime(MIME_TYPE_RED)
+                    == Some(rtp_packet.header.payload_type)
+                && red_primary_payload_type(&rtp_packet.payload) == Some(ulpfec_payload_type))
+    }
+
+    /// is_keyframe_packet returns whether the packet of the server's VP8 or VP9 codecs is of a
+    /// keyframe by its payload
+    fn is_keyframe_packet(media_config: &MediaConfig, rtp_packet: &rtp::packet::Packet) -> bool {
+        let Some(codec) = media_config
+            .get_codecs_by_kind(RTPCodecType::Video)
+            .iter()
+            .find(|codec| codec.payload_type == rtp_packet.header.payload_type)
+        else {
+            return false;
+        };
+        if codec
+            .capability
+            .mime_type
+            .eq_ignore_ascii_case(MIME_TYPE_VP8)
+        {
+            vp8_is_keyframe(&rtp_packet.payload)
+        } else if codec
+            .capability
+            .mime_type
+            .eq_ignore_ascii_case(MIME_TYPE_VP9)
+        {
+            Vp9LayerIndex::parse(&rtp_packet.payload)
+                .is_some_and(|index| index.is_keyframe && index.spatial_layer_id == 0)
+        } else {
+            false
+        }
+    }
+
+    /// is_telephone_event_packet returns whether the packet carries DTMF events of the server's
+    /// telephone-event codecs
+    fn is_telephone_event_packet(
+        media_config: &MediaConfig,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> bool {
+        media_config
+            .get_codecs_by_kind(RTPCodecType::Audio)
+            .iter()
+            .any(|codec| {
+                codec.payload_type == rtp_packet.header.payload_type
+                    && codec
+                        .capability
+                        .mime_type
+                        .eq_ignore_ascii_case(MIME_TYPE_TELEPHONE_EVENT)
+            })
+    }
+
+    /// layer_index returns the layer index of the packet of the server's VP8 or VP9 codecs by its
+    /// payload descriptor, or None if it has no layer index
+    fn layer_index(
+        media_config: &MediaConfig,
+        rtp_packet: &rtp::packet::Packet,
+    ) -> Option<LayerIndex> {
+        let codec = media_config
+            .get_codecs_by_kind(RTPCodecType::Video)
+            .iter()
+            .find(|codec| codec.payload_type == rtp_packet.header.payload_type)?;
+        if codec
+            .capability
+            .mime_type
+            .eq_ignore_ascii_case(MIME_TYPE_VP8)
+        {
+            vp8_temporal_layer_id(&rtp_packet.payload).map(|temporal_layer_id| LayerIndex {
+                temporal_layer_id,
+                ..Default::default()
+            })
+        } else if codec
+            .capability
+            .mime_type
+            .eq_ignore_ascii_case(MIME_TYPE_VP9)
+        {
+            Vp9LayerIndex::parse(&rtp_packet.payload).map(|index| LayerIndex {
+                spatial_layer_id: index.spatial_layer_id,
+                temporal_layer_id: index.temporal_layer_id,
+                is_end_of_frame: index.is_end_of_frame,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// enqueue RTP messages into pacers of their transports, and return the ones which can be
+    /// released right now, the others are released in handle_timeout
+    fn pace_rtp_messages(
+        server_states: &mut ServerStates,
+        now: Instant,
+        pacing_bitrate: u64,
+        messages: Vec<TaggedMessageEvent>,
+    ) -> Vec<TaggedMessageEvent> {
+        let mut released_messages = vec![];
+        for message in messages {
+            let (size, ssrc) = match &message.message {
+                MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)) => {
+                    (rtp_packet.marshal_size(), rtp_packet.header.ssrc)
+                }
+                _ => {
+                    released_messages.push(message);
+                    continue;
+                }
+            };
+            let four_tuple = (&message.transport).into();
+            let priority = server_states
+                .get_mut_endpoint(&four_tuple)
+                .ok()
+                .and_then(|endpoint| endpoint.get_transceiver_by_ssrc(ssrc))
+                .map(|transceiver| SendPriority::from(transceiver.kind))
+                .unwrap_or_default();
+            match server_states.get_mut_transport(&four_tuple) {
+                Ok(transport) => {
+                    let pacer = transport.get_or_insert_pacer(pacing_bitrate);
+                    pacer.enqueue_with_priority(size, priority, message);
+                    while let Some(message) = pacer.poll(now) {
+                        released_messages.push(message);
+                    }
+                }
+                Err(_) => released_messages.push(message),
+            }
+        }
+        released_messages
+    }
+
     fn handle_rtcp_message(
         server_states: &mut ServerStates,
         now: Instant,
@@ -467,28 +1162,210 @@ impl GatewayHandler {
         rtcp_packets: Vec<Box<dyn rtcp::packet::Packet>>,
     ) -> Result<Vec<TaggedMessageEvent>> {
         debug!("handle_rtcp_message {}", transport_context.peer_addr);
-        server_states
-            .get_mut_transport(&(&transport_context).into())?
-            .keep_alive();
+        let mut messages = vec![];
+        {
+            let transport = server_states.get_mut_transport(&(&transport_context).into())?;
+            transport.keep_alive();
+            let mut has_feedback = false;
+            for rtcp_packet in &rtcp_packets {
+                if let Some(feedback) = rtcp_packet.as_any().downcast_ref::<TransportLayerCc>() {
+                    //TODO: feed estimated bitrate to simulcast/SVC layer selection
+                    let bitrate = transport
+                        .get_mut_bandwidth_estimator()
+                        .on_feedback(feedback, now);
+                    trace!(
+                        "estimated bitrate {} toward {}",
+                        bitrate,
+                        transport_context.peer_addr
+                    );
+                    has_feedback = true;
+                }
+            }
+            if has_feedback {
+                messages.extend(GatewayHandler::generate_tmmbrs(
+                    server_states,
+                    now,
+                    &transport_context,
+                )?);
+            }
+        }
+
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&(&transport_context).into())
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let session = server_states
+            .get_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+
+        // split compound packet, route each sub-packet, and rebuild compound packet per destination
+        let mut routes: Vec<(TransportContext, Vec<Box<dyn rtcp::packet::Packet>>)> = vec![];
+        let mut add_route = |peer: TransportContext, rtcp_packet: Box<dyn rtcp::packet::Packet>| {
+            if let Some((_, packets)) = routes.iter_mut().find(|(transport, _)| {
+                transport.local_addr == peer.local_addr && transport.peer_addr == peer.peer_addr
+            }) {
+                packets.push(rtcp_packet);
+            } else {
+                routes.push((peer, vec![rtcp_packet]));
+            }
+        };
 
-        //TODO: Selective Forwarding RTCP Packets
         let peers =
             GatewayHandler::get_other_media_transport_contexts(server_states, &transport_context)?;
+        let rtcp_app_name = server_states.server_config().rtcp_app_name;
+        let mut session_events = vec![];
+        let mut tmmbr_requesters = vec![];
+        for rtcp_packet in &rtcp_packets {
+            // APP packets with the configured name go to the application instead of other endpoints
+            if let Some((ssrc, data)) = rtcp_app_name
+                .and_then(|name| GatewayHandler::parse_rtcp_app(rtcp_packet.as_ref(), &name))
+            {
+                session_events.push(SessionEvent::RtcpApp {
+                    session_id,
+                    endpoint_id,
+                    ssrc,
+                    data,
+                });
+                continue;
+            }
 
-        let mut outgoing_messages = Vec::with_capacity(peers.len());
-        for transport in peers {
-            outgoing_messages.push(TaggedMessageEvent {
-                now,
-                transport,
-                message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets.clone())),
-            });
+            match rtcp_packet.header().packet_type {
+                PacketType::ReceiverReport => {
+                    // ReceiverReport is hop by hop report, instead of end to end report, so it
+                    // is terminated rather than translated: a subscriber's report describes the
+                    // SFU to subscriber leg, whose sequence numbers and timestamps are rewritten,
+                    // while publishers get the SFU's own reports of the legs it receives from
+                    // the ReceiverReport interceptor
+                    trace!("drop ReceiverReport from {}", transport_context.peer_addr);
+                }
+                PacketType::TransportSpecificFeedback
+                    if rtcp_packet
+                        .as_any()
+                        .downcast_ref::<TransportLayerCc>()
+                        .is_some() =>
+                {
+                    // TWCC is hop by hop feedback, instead of end to end feedback,
+                    // which has been consumed by the bandwidth estimator of the transport
+                    trace!("drop TransportLayerCc from {}", transport_context.peer_addr);
+                }
+                PacketType::TransportSpecificFeedback
+                    if rtcp_packet.header().count == FORMAT_TMMBN =>
+                {
+                    // TMMBN goes back to the endpoints which sent TMMBR owning its bounding
+                    // tuples, while the ones owned by SFU's own TMMBR are consumed here
+                    let Some((_, entries)) =
+                        GatewayHandler::parse_tmmbx(rtcp_packet.as_ref(), FORMAT_TMMBN)
+                    else {
+                        continue;
+                    };
+                    let mut requester_endpoint_ids = vec![];
+                    for (owner_ssrc, _) in entries {
+                        match session.get_tmmbr_requester(endpoint_id, owner_ssrc) {
+                            Some(requester_endpoint_id) => {
+                                if !requester_endpoint_ids.contains(&requester_endpoint_id) {
+                                    requester_endpoint_ids.push(requester_endpoint_id);
+                                }
+                            }
+                            None => trace!(
+                                "consume TMMBN of ssrc {} from {}",
+                                owner_ssrc,
+                                transport_context.peer_addr
+                            ),
+                        }
+                    }
+                    for requester_endpoint_id in requester_endpoint_ids {
+                        for peer in GatewayHandler::get_endpoint_media_transport_contexts(
+                            session,
+                            requester_endpoint_id,
+                        ) {
+                            add_route(peer, rtcp_packet.cloned());
+                        }
+                    }
+                }
+                PacketType::TransportSpecificFeedback | PacketType::PayloadSpecificFeedback => {
+                    // feedback, e.g., NACK, PLI, FIR, REMB, TMMBR, goes to the publisher of media ssrc
+                    let (publishers, unhandled_ssrcs) =
+                        GatewayHandler::get_publisher_media_transport_contexts(
+                            server_states,
+                            &transport_context,
+                            &GatewayHandler::get_destination_ssrcs(rtcp_packet.as_ref()),
+                        )?;
+                    for ssrc in unhandled_ssrcs {
+                        warn!(
+                            "Incoming unhandled RTCP ssrc({}) from {}, it will not be forwarded",
+                            ssrc, transport_context.peer_addr
+                        );
+                    }
+                    for (publisher_endpoint_id, peer) in publishers {
+                        if let Some((sender_ssrc, _)) =
+                            GatewayHandler::parse_tmmbx(rtcp_packet.as_ref(), FORMAT_TMMBR)
+                        {
+                            tmmbr_requesters.push((publisher_endpoint_id, sender_ssrc));
+                        }
+                        add_route(
+                            peer,
+                            GatewayHandler::remap_rtcp_ssrcs(rtcp_packet.as_ref(), |ssrc| {
+                                session.original_ssrc(publisher_endpoint_id, ssrc)
+                            }),
+                        );
+                    }
+                }
+                _ => {
+                    // reports and descriptions, e.g., SR, SDES, BYE, go to other endpoints
+                    for peer in &peers {
+                        add_route(
+                            *peer,
+                            GatewayHandler::remap_rtcp_ssrcs(rtcp_packet.as_ref(), |ssrc| {
+                                session.forwarded_ssrc(endpoint_id, ssrc)
+                            }),
+                        );
+                    }
+                }
+            }
         }
 
-        Ok(outgoing_messages)
+        messages.extend(
+            routes
+                .into_iter()
+                .map(|(transport, packets)| TaggedMessageEvent {
+                    now,
+                    transport,
+                    message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(packets)),
+                }),
+        );
+        if let Some(session) = server_states.get_mut_session(&session_id) {
+            for (publisher_endpoint_id, sender_ssrc) in tmmbr_requesters {
+                session.set_tmmbr_requester(publisher_endpoint_id, sender_ssrc, endpoint_id);
+            }
+        }
+        for session_event in session_events {
+            server_states.push_session_event(session_event);
+        }
+        Ok(messages)
+    }
+
+    /// parse_rtcp_app returns ssrc and application-dependent data of RTCP APP packet
+    /// with the name, <https://datatracker.ietf.org/doc/html/rfc3550#section-6.7>
+    fn parse_rtcp_app(
+        rtcp_packet: &dyn rtcp::packet::Packet,
+        name: &[u8; 4],
+    ) -> Option<(SSRC, BytesMut)> {
+        if rtcp_packet.header().packet_type != PacketType::ApplicationDefined {
+            return None;
+        }
+        // header, ssrc/csrc and name, followed by application-dependent data
+        let raw = &rtcp_packet.as_any().downcast_ref::<RawPacket>()?.0;
+        if raw.len() < 12 || &raw[8..12] != name {
+            return None;
+        }
+        let ssrc = u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]);
+        Some((ssrc, BytesMut::from(&raw[12..])))
     }
 
     fn check_stun_message(
-        server_states: &ServerStates,
+        server_states: &mut ServerStates,
         request: &mut stun::message::Message,
     ) -> Result<Option<Rc<Candidate>>> {
         match TextAttribute::get_from_as(request, ATTR_USERNAME) {
@@ -524,12 +1401,9 @@ impl GatewayHandler {
                 }
             }
             Err(_) => {
-                if request.contains(ATTR_ICE_CONTROLLED)
-                    || request.contains(ATTR_ICE_CONTROLLING)
-                    || request.contains(ATTR_NETWORK_COST)
-                    || request.contains(ATTR_PRIORITY)
-                    || request.contains(ATTR_USE_CANDIDATE)
-                {
+                // comprehension-optional ICE attributes, e.g., ICE-CONTROLLING or NETWORK-COST,
+                // are ignored in binding requests without username
+                if request.contains(ATTR_PRIORITY) || request.contains(ATTR_USE_CANDIDATE) {
                     Err(Error::Other("unexpected attribute".to_string()))
                 } else {
                     Ok(None)
@@ -538,10 +1412,111 @@ impl GatewayHandler {
         }
     }
 
-    fn get_other_datachannel_transport_contexts(
-        server_states: &mut ServerStates,
+    /// is_ice_role_conflict resolves ICE role conflict with the remote by their tiebreakers,
+    /// and returns true if the remote has to switch its role instead of SFU,
+    /// <https://www.rfc-editor.org/rfc/rfc8445#section-7.3.1.1>
+    fn is_ice_role_conflict(
+        request: &stun::message::Message,
+        candidate: &Rc<Candidate>,
+    ) -> Result<bool> {
+        let (ice_role_attr, switched_ice_role) = match candidate.ice_role() {
+            RTCIceRole::Controlling => (ATTR_ICE_CONTROLLING, RTCIceRole::Controlled),
+            RTCIceRole::Controlled => (ATTR_ICE_CONTROLLED, RTCIceRole::Controlling),
+        };
+        let Ok(remote_tiebreaker) = request.get(ice_role_attr) else {
+            return Ok(false);
+        };
+        let remote_tiebreaker = u64::from_be_bytes(
+            remote_tiebreaker
+                .as_slice()
+                .try_into()
+                .map_err(|_| Error::Other("invalid ICE tiebreaker".to_string()))?,
+        );
+
+        // the agent with larger tiebreaker is controlling
+        let is_conflict = (candidate.ice_role() == RTCIceRole::Controlling)
+            == (candidate.ice_tiebreaker() >= remote_tiebreaker);
+        if !is_conflict {
+            debug!(
+                "switch ICE role to {:?} for {}",
+                switched_ice_role,
+                candidate.username()
+            );
+            candidate.set_ice_role(switched_ice_role);
+        }
+        Ok(is_conflict)
+    }
+
+    fn create_role_conflict_message_event(
+        now: Instant,
+        transport_context: TransportContext,
+        request: &stun::message::Message,
+        candidate: &Rc<Candidate>,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let mut response = stun::message::Message::new();
+        response.build(&[
+            Box::new(BINDING_ERROR),
+            Box::new(request.transaction_id),
+            Box::new(ErrorCodeAttribute {
+                code: CODE_ROLE_CONFLICT,
+                reason: b"Role Conflict".to_vec(),
+            }),
+        ])?;
+        let integrity = MessageIntegrity::new_short_term_integrity(
+            candidate.get_local_parameters().password.clone(),
+        );
+        integrity.add_to(&mut response)?;
+        FINGERPRINT.add_to(&mut response)?;
+
+        debug!(
+            "ICE role conflict response sent to {}",
+            transport_context.peer_addr
+        );
+
+        Ok(vec![TaggedMessageEvent {
+            now,
+            transport: transport_context,
+            message: MessageEvent::Stun(STUNMessageEvent::Stun(response)),
+        }])
+    }
+
+    /// create_unknown_attributes_message_event answers a request with unknown
+    /// comprehension-required attributes by 420 Unknown Attribute error,
+    /// <https://www.rfc-editor.org/rfc/rfc5389#section-15.9>
+    fn create_unknown_attributes_message_event(
+        now: Instant,
+        transport_context: TransportContext,
+        request: &stun::message::Message,
+        unknown_attributes: Vec<AttrType>,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let mut response = stun::message::Message::new();
+        response.build(&[
+            Box::new(BINDING_ERROR),
+            Box::new(request.transaction_id),
+            Box::new(ErrorCodeAttribute {
+                code: CODE_UNKNOWN_ATTRIBUTE,
+                reason: b"Unknown Attribute".to_vec(),
+            }),
+            Box::new(UnknownAttributes(unknown_attributes)),
+        ])?;
+        FINGERPRINT.add_to(&mut response)?;
+
+        debug!(
+            "unknown attributes response sent to {}",
+            transport_context.peer_addr
+        );
+
+        Ok(vec![TaggedMessageEvent {
+            now,
+            transport: transport_context,
+            message: MessageEvent::Stun(STUNMessageEvent::Stun(response)),
+        }])
+    }
+
+    fn get_other_media_transport_contexts(
+        server_states: &ServerStates,
         transport_context: &TransportContext,
-    ) -> Result<Vec<(TransportContext, usize, u16, bool)>> {
+    ) -> Result<Vec<TransportContext>> {
         let four_tuple = transport_context.into();
         let (session_id, endpoint_id) = server_states
             .find_endpoint(&four_tuple)
@@ -557,26 +1532,27 @@ impl GatewayHandler {
         let endpoints = session.get_endpoints();
         for (&other_endpoint_id, other_endpoint) in endpoints.iter() {
             if other_endpoint_id != endpoint_id {
+                if !other_endpoint.is_connected() {
+                    trace!(
+                        "{}/{} is not connected yet since its srtp contexts are still setup",
+                        session_id,
+                        other_endpoint_id,
+                    );
+                    continue;
+                }
                 let transports = other_endpoint.get_transports();
                 for (other_four_tuple, other_transport) in transports.iter() {
-                    if let (Some(association_handle), Some(stream_id)) =
-                        other_transport.association_handle_and_stream_id()
-                    {
-                        peers.push((
-                            TransportContext {
-                                local_addr: other_four_tuple.local_addr,
-                                peer_addr: other_four_tuple.peer_addr,
-                                ecn: transport_context.ecn,
-                            },
-                            association_handle,
-                            stream_id,
-                            other_endpoint.is_renegotiation_needed(),
-                        ));
+                    if other_transport.is_srtp_context_ready() {
+                        peers.push(TransportContext {
+                            local_addr: other_four_tuple.local_addr,
+                            peer_addr: other_four_tuple.peer_addr,
+                            ecn: transport_context.ecn,
+                        });
                     } else {
-                        // data channel is not ready yet for other_endpoint_id's other_four_tuple.
-                        // this transport just joins, but data channel is still setup
+                        // srtp contexts are not ready yet for other_endpoint_id's other_four_tuple.
+                        // this transport just joins, but srtp contexts are still setup
                         trace!(
-                            "{}/{}'s data channel is not ready yet for {:?} since it is still setup",
+                            "{}/{}'s srtp contexts are not ready yet for {:?} since it is still setup",
                             session_id,
                             other_endpoint_id,
                             other_four_tuple,
@@ -588,10 +1564,247 @@ impl GatewayHandler {
         Ok(peers)
     }
 
-    fn get_other_media_transport_contexts(
+    /// get_destination_ssrcs returns the media ssrcs that a feedback packet targets. Since TMMBR
+    /// is not parsed by rtcp crate, its ssrcs are read from FCI entries,
+    /// <https://tools.ietf.org/html/rfc5104#section-4.2.1>
+    fn get_destination_ssrcs(rtcp_packet: &dyn rtcp::packet::Packet) -> Vec<u32> {
+        if let Some((_, entries)) = GatewayHandler::parse_tmmbx(rtcp_packet, FORMAT_TMMBR) {
+            return entries.into_iter().map(|(ssrc, _)| ssrc).collect();
+        }
+        rtcp_packet.destination_ssrc()
+    }
+
+    /// parse_tmmbx returns packet sender ssrc and FCI entries of TMMBR or TMMBN with the format,
+    /// where each entry is an ssrc and its raw MxTBR/overhead word, i.e., the requested media
+    /// ssrc for TMMBR, or the ssrc owning the bounding tuple for TMMBN
+    fn parse_tmmbx(
+        rtcp_packet: &dyn rtcp::packet::Packet,
+        format: u8,
+    ) -> Option<(SSRC, Vec<(SSRC, u32)>)> {
+        let header = rtcp_packet.header();
+        if header.packet_type != PacketType::TransportSpecificFeedback || header.count != format {
+            return None;
+        }
+        // header (4 bytes), packet sender ssrc (4 bytes), media source ssrc (4 bytes),
+        // followed by FCI entries of ssrc (4 bytes) and MxTBR/overhead (4 bytes)
+        let raw = &rtcp_packet.as_any().downcast_ref::<RawPacket>()?.0;
+        let sender_ssrc = u32::from_be_bytes(raw.get(4..8)?.try_into().ok()?);
+        let entries = raw
+            .get(12..)
+            .unwrap_or_default()
+            .chunks_exact(8)
+            .map(|fci| {
+                (
+                    u32::from_be_bytes([fci[0], fci[1], fci[2], fci[3]]),
+                    u32::from_be_bytes([fci[4], fci[5], fci[6], fci[7]]),
+                )
+            })
+            .collect();
+        Some((sender_ssrc, entries))
+    }
+
+    /// marshal_tmmbx returns TMMBR or TMMBN with the format, packet sender ssrc and FCI entries
+    fn marshal_tmmbx(format: u8, sender_ssrc: SSRC, entries: &[(SSRC, u32)]) -> RawPacket {
+        let mut raw = BytesMut::with_capacity(12 + 8 * entries.len());
+        raw.put_u8(0x80 | format);
+        raw.put_u8(PacketType::TransportSpecificFeedback as u8);
+        raw.put_u16((2 + 2 * entries.len()) as u16);
+        raw.put_u32(sender_ssrc);
+        raw.put_u32(0);
+        for &(ssrc, mxtbr) in entries {
+            raw.put_u32(ssrc);
+            raw.put_u32(mxtbr);
+        }
+        RawPacket(raw.freeze())
+    }
+
+    /// tmmbr_mxtbr encodes the bitrate in bits per second into the MxTBR word of 6 bits exponent
+    /// and 17 bits mantissa, with zero measured overhead
+    fn tmmbr_mxtbr(bitrate: u64) -> u32 {
+        let (mut exponent, mut mantissa) = (0u32, bitrate);
+        while mantissa > 0x1FFFF {
+            mantissa >>= 1;
+            exponent += 1;
+        }
+        (exponent << 26) | ((mantissa as u32) << 9)
+    }
+
+    /// generate_tmmbrs returns TMMBR toward publishers of ssrcs forwarded to the subscriber with
+    /// the transport, whose received bitrate exceeds the lowest bandwidth estimate toward their
+    /// subscribers, so that the publishers cap their encoders
+    fn generate_tmmbrs(
         server_states: &mut ServerStates,
+        now: Instant,
         transport_context: &TransportContext,
-    ) -> Result<Vec<TransportContext>> {
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let (session_id, endpoint_id) = server_states
+            .find_endpoint(&transport_context.into())
+            .ok_or(Error::ErrClientTransportNotSet)?;
+        let session = server_states
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let Some(forwarded_ssrcs) = session
+            .get_endpoint(&endpoint_id)
+            .map(|endpoint| endpoint.get_all_send_ssrcs())
+        else {
+            return Ok(vec![]);
+        };
+
+        let active_sender_ssrcs = session.get_active_sender_ssrcs();
+        let mut messages = vec![];
+        for forwarded_ssrc in forwarded_ssrcs {
+            let Some(&publisher_endpoint_id) = active_sender_ssrcs.get(&forwarded_ssrc) else {
+                continue;
+            };
+            // the lowest estimate of the subscribers' transports bounds the publisher's ssrc
+            let Some(limit) = session
+                .get_endpoints()
+                .values()
+                .filter(|endpoint| endpoint.get_all_send_ssrcs().contains(&forwarded_ssrc))
+                .flat_map(|endpoint| endpoint.get_transports().values())
+                .map(|transport| transport.bandwidth_estimator().bitrate())
+                .min()
+            else {
+                continue;
+            };
+            let ssrc = session.original_ssrc(publisher_endpoint_id, forwarded_ssrc);
+            let Some(received_bitrate) = session
+                .get_mut_endpoint(&publisher_endpoint_id)
+                .map(|endpoint| endpoint.received_bitrate(ssrc, now))
+            else {
+                continue;
+            };
+            if !session.update_tmmbr_limit(publisher_endpoint_id, ssrc, received_bitrate, limit) {
+                continue;
+            }
+
+            debug!(
+                "{}/{} ssrc {} received at {} is limited to {} by TMMBR",
+                session_id, publisher_endpoint_id, ssrc, received_bitrate, limit
+            );
+            let Some(publisher) = session.get_endpoint(&publisher_endpoint_id) else {
+                continue;
+            };
+            for (four_tuple, transport) in publisher
+                .get_transports()
+                .iter()
+                .filter(|(_, transport)| transport.is_srtp_context_ready())
+            {
+                messages.push(TaggedMessageEvent {
+                    now,
+                    transport: TransportContext {
+                        local_addr: four_tuple.local_addr,
+                        peer_addr: four_tuple.peer_addr,
+                        ecn: None,
+                    },
+                    message: MessageEvent::Rtp(RTPMessageEvent::Rtcp(vec![Box::new(
+                        GatewayHandler::marshal_tmmbx(
+                            FORMAT_TMMBR,
+                            transport.rtcp_sender_ssrc(),
+                            &[(ssrc, GatewayHandler::tmmbr_mxtbr(limit))],
+                        ),
+                    )])),
+                });
+            }
+        }
+        Ok(messages)
+    }
+
+    /// get_endpoint_media_transport_contexts returns transport contexts of the endpoint whose
+    /// srtp contexts are ready
+    fn get_endpoint_media_transport_contexts(
+        session: &Session,
+        endpoint_id: EndpointId,
+    ) -> Vec<TransportContext> {
+        session
+            .get_endpoint(&endpoint_id)
+            .map(|endpoint| {
+                endpoint
+                    .get_transports()
+                    .iter()
+                    .filter(|(_, transport)| transport.is_srtp_context_ready())
+                    .map(|(four_tuple, _)| TransportContext {
+                        local_addr: four_tuple.local_addr,
+                        peer_addr: four_tuple.peer_addr,
+                        ecn: None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// remap_rtcp_ssrcs returns a copy of rtcp packet with its media ssrcs remapped,
+    /// e.g., when forwarded ssrc differs from the original one due to ssrc collision
+    fn remap_rtcp_ssrcs(
+        rtcp_packet: &dyn rtcp::packet::Packet,
+        remap: impl Fn(u32) -> u32,
+    ) -> Box<dyn rtcp::packet::Packet> {
+        let packet = rtcp_packet.as_any();
+        if let Some(sr) = packet.downcast_ref::<SenderReport>() {
+            let mut sr = sr.clone();
+            sr.ssrc = remap(sr.ssrc);
+            Box::new(sr)
+        } else if let Some(sdes) = packet.downcast_ref::<SourceDescription>() {
+            let mut sdes = sdes.clone();
+            for chunk in sdes.chunks.iter_mut() {
+                chunk.source = remap(chunk.source);
+            }
+            Box::new(sdes)
+        } else if let Some(bye) = packet.downcast_ref::<Goodbye>() {
+            let mut bye = bye.clone();
+            for source in bye.sources.iter_mut() {
+                *source = remap(*source);
+            }
+            Box::new(bye)
+        } else if let Some(pli) = packet.downcast_ref::<PictureLossIndication>() {
+            let mut pli = pli.clone();
+            pli.media_ssrc = remap(pli.media_ssrc);
+            Box::new(pli)
+        } else if let Some(fir) = packet.downcast_ref::<FullIntraRequest>() {
+            let mut fir = fir.clone();
+            fir.media_ssrc = remap(fir.media_ssrc);
+            for entry in fir.fir.iter_mut() {
+                entry.ssrc = remap(entry.ssrc);
+            }
+            Box::new(fir)
+        } else if let Some(nack) = packet.downcast_ref::<TransportLayerNack>() {
+            let mut nack = nack.clone();
+            nack.media_ssrc = remap(nack.media_ssrc);
+            Box::new(nack)
+        } else if let Some((sender_ssrc, entries)) =
+            GatewayHandler::parse_tmmbx(rtcp_packet, FORMAT_TMMBR)
+        {
+            let entries: Vec<(SSRC, u32)> = entries
+                .into_iter()
+                .map(|(ssrc, mxtbr)| (remap(ssrc), mxtbr))
+                .collect();
+            Box::new(GatewayHandler::marshal_tmmbx(
+                FORMAT_TMMBR,
+                sender_ssrc,
+                &entries,
+            ))
+        } else if let Some(remb) = packet.downcast_ref::<ReceiverEstimatedMaximumBitrate>() {
+            let mut remb = remb.clone();
+            for ssrc in remb.ssrcs.iter_mut() {
+                *ssrc = remap(*ssrc);
+            }
+            Box::new(remb)
+        } else {
+            rtcp_packet.cloned()
+        }
+    }
+
+    /// get_publisher_media_transport_contexts returns transport contexts of other endpoints,
+    /// which publish any of the given ssrcs, and the ssrcs without active sender
+    #[allow(clippy::type_complexity)]
+    fn get_publisher_media_transport_contexts(
+        server_states: &ServerStates,
+        transport_context: &TransportContext,
+        ssrcs: &[u32],
+    ) -> Result<(Vec<(EndpointId, TransportContext)>, Vec<u32>)> {
         let four_tuple = transport_context.into();
         let (session_id, endpoint_id) = server_states
             .find_endpoint(&four_tuple)
@@ -603,32 +1816,40 @@ impl GatewayHandler {
                 session_id
             )))?;
 
-        let mut peers = vec![];
-        let endpoints = session.get_endpoints();
-        for (&other_endpoint_id, other_endpoint) in endpoints.iter() {
-            if other_endpoint_id != endpoint_id {
-                let transports = other_endpoint.get_transports();
-                for (other_four_tuple, other_transport) in transports.iter() {
-                    if other_transport.is_local_srtp_context_ready() {
-                        peers.push(TransportContext {
-                            local_addr: other_four_tuple.local_addr,
-                            peer_addr: other_four_tuple.peer_addr,
-                            ecn: transport_context.ecn,
-                        });
-                    } else {
-                        // local_srtp_context is not ready yet for other_endpoint_id's other_four_tuple.
-                        // this transport just joins, but local_srtp_context is still setup
-                        trace!(
-                            "{}/{}'s local_srtp_context is not ready yet for {:?} since it is still setup",
-                            session_id,
-                            other_endpoint_id,
-                            other_four_tuple,
-                        );
+        let active_sender_ssrcs = session.get_active_sender_ssrcs();
+        let mut publisher_endpoint_ids = vec![];
+        let mut unhandled_ssrcs = vec![];
+        for ssrc in ssrcs {
+            match active_sender_ssrcs.get(ssrc) {
+                Some(&publisher_endpoint_id) => {
+                    if publisher_endpoint_id != endpoint_id
+                        && !publisher_endpoint_ids.contains(&publisher_endpoint_id)
+                    {
+                        publisher_endpoint_ids.push(publisher_endpoint_id);
                     }
                 }
+                None => unhandled_ssrcs.push(*ssrc),
             }
         }
-        Ok(peers)
+
+        let mut publishers = vec![];
+        for publisher_endpoint_id in publisher_endpoint_ids {
+            if let Some(publisher_endpoint) = session.get_endpoint(&publisher_endpoint_id) {
+                for (other_four_tuple, other_transport) in publisher_endpoint.get_transports() {
+                    if other_transport.is_srtp_context_ready() {
+                        publishers.push((
+                            publisher_endpoint_id,
+                            TransportContext {
+                                local_addr: other_four_tuple.local_addr,
+                                peer_addr: other_four_tuple.peer_addr,
+                                ecn: transport_context.ecn,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+        Ok((publishers, unhandled_ssrcs))
     }
 
     fn create_server_reflective_address_message_event(
@@ -703,6 +1924,15 @@ impl GatewayHandler {
         let (session_id, endpoint_id) = server_states
             .find_endpoint(&four_tuple)
             .ok_or(Error::ErrClientTransportNotSet)?;
+        server_states.invalidate_answer_cache(session_id, endpoint_id);
+        let (ice_credentials_rotation, ice_ufrag_len, ice_pwd_len) = {
+            let server_config = server_states.server_config();
+            (
+                server_config.ice_credentials_rotation,
+                server_config.ice_ufrag_len,
+                server_config.ice_pwd_len,
+            )
+        };
         let session = server_states
             .get_mut_session(&session_id)
             .ok_or(Error::Other(format!(
@@ -716,6 +1946,7 @@ impl GatewayHandler {
                 "can't find endpoint id {}",
                 endpoint_id
             )))?;
+        endpoint.apply_negotiation(true, RTCSdpType::Offer)?;
         endpoint.set_renegotiation_needed(false); //clean renegotiation_needed flag
 
         let remote_description = endpoint
@@ -731,13 +1962,22 @@ impl GatewayHandler {
             )))?;
             transport.candidate().local_connection_credentials().clone()
         };
-
-        let offer = session.create_offer(
-            endpoint_id,
-            &remote_description,
-            &local_conn_cred.ice_params,
-        )?;
+        // fresh local ICE credentials replace the current ones once the offer is answered
+        let rotated_ice_params =
+            ice_credentials_rotation.then(|| RTCIceParameters::new(ice_ufrag_len, ice_pwd_len));
+        let ice_params = rotated_ice_params
+            .as_ref()
+            .unwrap_or(&local_conn_cred.ice_params);
+
+        let offer = session.create_offer(endpoint_id, &remote_description, ice_params)?;
         session.set_local_description(endpoint_id, &offer)?;
+        if let Some(endpoint) = session.get_mut_endpoint(&endpoint_id) {
+            endpoint.set_pending_local_description(now, offer.clone());
+            if let Some(ice_params) = rotated_ice_params {
+                endpoint.set_pending_ice_params(ice_params);
+            }
+        }
+        session.record_sdp(endpoint_id, true, &offer);
 
         let offer_str =
             serde_json::to_string(&offer).map_err(|err| Error::Other(err.to_string()))?;
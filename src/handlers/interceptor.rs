@@ -1,6 +1,6 @@
-use crate::interceptors::InterceptorEvent;
+use crate::interceptors::{InterceptorEvent, Registry};
 use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
-use crate::types::FourTuple;
+use crate::types::{EndpointId, FourTuple, SessionId};
 use crate::ServerStates;
 use log::{debug, error};
 use retty::channel::{Context, Handler};
@@ -23,6 +23,19 @@ impl InterceptorHandler {
             transmits: VecDeque::new(),
         }
     }
+
+    /// update_interceptor replaces the interceptor chain of the endpoint with a newly built one
+    /// from registry, see ServerStates::update_interceptor
+    pub fn update_interceptor(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        registry: &Registry,
+    ) -> Result<()> {
+        self.server_states
+            .borrow_mut()
+            .update_interceptor(session_id, endpoint_id, registry)
+    }
 }
 
 impl Handler for InterceptorHandler {
@@ -51,6 +64,7 @@ impl Handler for InterceptorHandler {
                 Ok(interceptor.read(&mut msg))
             };
 
+            let mut is_dropped = false;
             match try_read() {
                 Ok(events) => {
                     for event in events {
@@ -62,6 +76,9 @@ impl Handler for InterceptorHandler {
                             InterceptorEvent::Outbound(outbound) => {
                                 self.transmits.push_back(outbound);
                             }
+                            InterceptorEvent::Dropped => {
+                                is_dropped = true;
+                            }
                             InterceptorEvent::Error(err) => {
                                 error!("try_read got error {}", err);
                                 ctx.fire_exception(err);
@@ -75,6 +92,11 @@ impl Handler for InterceptorHandler {
                 }
             };
 
+            if is_dropped {
+                debug!("interceptor drops read {:?}", msg.transport.peer_addr);
+                return;
+            }
+
             if let MessageEvent::Rtp(RTPMessageEvent::Rtcp(_)) = &msg.message {
                 // RTCP message read must end here in SFU case. If any rtcp packet needs to be forwarded to other Endpoints,
                 // just add a new interceptor to forward it.
@@ -125,6 +147,9 @@ impl Handler for InterceptorHandler {
                         InterceptorEvent::Outbound(outbound) => {
                             self.transmits.push_back(outbound);
                         }
+                        InterceptorEvent::Dropped => {
+                            error!("unexpected dropped message from try_handle_timeout");
+                        }
                         InterceptorEvent::Error(err) => {
                             error!("try_read got error {}", err);
                             ctx.fire_exception(err);
@@ -166,6 +191,7 @@ impl Handler for InterceptorHandler {
         ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
     ) -> Option<Self::Wout> {
         if let Some(mut msg) = ctx.fire_poll_write() {
+            let mut is_dropped = false;
             if let MessageEvent::Rtp(RTPMessageEvent::Rtp(_))
             | MessageEvent::Rtp(RTPMessageEvent::Rtcp(_)) = &msg.message
             {
@@ -187,6 +213,9 @@ impl Handler for InterceptorHandler {
                                 InterceptorEvent::Outbound(outbound) => {
                                     self.transmits.push_back(outbound);
                                 }
+                                InterceptorEvent::Dropped => {
+                                    is_dropped = true;
+                                }
                                 InterceptorEvent::Error(err) => {
                                     error!("try_write got error {}", err);
                                     ctx.fire_exception(err);
@@ -201,8 +230,12 @@ impl Handler for InterceptorHandler {
                 };
             }
 
-            debug!("interceptor write {:?}", msg.transport.peer_addr);
-            self.transmits.push_back(msg);
+            if is_dropped {
+                debug!("interceptor drops write {:?}", msg.transport.peer_addr);
+            } else {
+                debug!("interceptor write {:?}", msg.transport.peer_addr);
+                self.transmits.push_back(msg);
+            }
         }
 
         self.transmits.pop_front()
@@ -1,9 +1,11 @@
+use crate::endpoint::transport::SctpPeer;
 use crate::messages::{
-    DTLSMessageEvent, DataChannelMessage, DataChannelMessageParams, DataChannelMessageType,
-    MessageEvent, TaggedMessageEvent,
+    ApplicationMessage, DTLSMessageEvent, DataChannelEvent, DataChannelMessage,
+    DataChannelMessageParams, DataChannelMessageType, MessageEvent, TaggedMessageEvent,
 };
 use crate::server::states::ServerStates;
-use bytes::BytesMut;
+use crate::types::FourTuple;
+use bytes::{Bytes, BytesMut};
 use log::{debug, error};
 use retty::channel::{Context, Handler};
 use retty::transport::TransportContext;
@@ -27,6 +29,9 @@ pub struct SctpHandler {
     transmits: VecDeque<TaggedMessageEvent>,
 }
 
+/// SCTP association of the transport which is declared failed by heartbeat timeout
+type FailedAssociation = (FourTuple, AssociationHandle);
+
 enum SctpMessage {
     Inbound(DataChannelMessage),
     Outbound(Transmit),
@@ -79,6 +84,9 @@ impl Handler for SctpHandler {
 
                 let mut sctp_events: HashMap<AssociationHandle, VecDeque<AssociationEvent>> =
                     HashMap::new();
+                let mut active_ch = None;
+                let mut new_ch = None;
+                let peer = SctpPeer::from_init(&dtls_message);
                 if let Some((ch, event)) = sctp_endpoint.handle(
                     msg.now,
                     msg.transport.peer_addr,
@@ -86,9 +94,11 @@ impl Handler for SctpHandler {
                     msg.transport.ecn,
                     dtls_message.freeze(), //TODO: switch API Bytes to BytesMut
                 ) {
+                    active_ch = Some(ch);
                     match event {
                         DatagramEvent::NewAssociation(conn) => {
                             sctp_associations.insert(ch, conn);
+                            new_ch = Some(ch);
                         }
                         DatagramEvent::AssociationEvent(event) => {
                             sctp_events.entry(ch).or_default().push_back(event);
@@ -143,6 +153,15 @@ impl Handler for SctpHandler {
                     }
                 }
 
+                if let (Some(ch), Some(peer)) = (new_ch, peer) {
+                    transport.set_sctp_association_peer(ch, peer);
+                }
+                if let Some(ch) = active_ch {
+                    if transport.get_sctp_associations().contains_key(&ch) {
+                        transport.keep_sctp_association_alive(ch, msg.now);
+                    }
+                }
+
                 Ok(messages)
             };
             match try_read() {
@@ -197,13 +216,43 @@ impl Handler for SctpHandler {
         ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
         now: Instant,
     ) {
-        let try_timeout = || -> Result<Vec<Transmit>> {
+        let try_timeout = || -> Result<(Vec<Transmit>, Vec<FailedAssociation>)> {
             let mut transmits = vec![];
+            let mut failed_associations = vec![];
             let mut server_states = self.server_states.borrow_mut();
+            let idle_deadline =
+                now.checked_sub(server_states.server_config().sctp_association_idle_timeout);
+            // rtc-sctp only answers HEARTBEAT without sending its own, so liveness is judged by
+            // the remote's HEARTBEAT or any other chunk received within the heartbeat timeout
+            let heartbeat_deadline = now.checked_sub(
+                server_states
+                    .server_config()
+                    .sctp_association_heartbeat_timeout,
+            );
+            let (mut idle_associations, mut active_associations) = (0, 0);
 
             for session in server_states.get_mut_sessions().values_mut() {
                 for endpoint in session.get_mut_endpoints().values_mut() {
-                    for transport in endpoint.get_mut_transports().values_mut() {
+                    for (four_tuple, transport) in endpoint.get_mut_transports().iter_mut() {
+                        if let Some(heartbeat_deadline) = heartbeat_deadline {
+                            for (ch, abort) in
+                                transport.remove_idle_sctp_associations(heartbeat_deadline)
+                            {
+                                failed_associations.push((*four_tuple, ch));
+                                transmits.extend(
+                                    abort.map(|abort| abort_transmit(now, four_tuple, abort)),
+                                );
+                            }
+                        }
+                        if let Some(idle_deadline) = idle_deadline {
+                            for (_, abort) in transport.remove_idle_sctp_associations(idle_deadline)
+                            {
+                                idle_associations += 1;
+                                transmits.extend(
+                                    abort.map(|abort| abort_transmit(now, four_tuple, abort)),
+                                );
+                            }
+                        }
                         let (sctp_endpoint, sctp_associations) =
                             transport.get_mut_sctp_endpoint_associations();
 
@@ -224,14 +273,46 @@ impl Handler for SctpHandler {
                             sctp_endpoint.handle_event(ch, event); // handle drain event
                             sctp_associations.remove(&ch);
                         }
+                        active_associations += sctp_associations.len();
                     }
                 }
             }
 
-            Ok(transmits)
+            if idle_associations > 0 {
+                debug!("close {} idle sctp associations", idle_associations);
+            }
+            if !failed_associations.is_empty() {
+                debug!(
+                    "close {} sctp associations without heartbeat",
+                    failed_associations.len()
+                );
+            }
+            server_states
+                .metrics()
+                .record_sctp_associations_active(active_associations as u64, &[]);
+
+            Ok((transmits, failed_associations))
         };
         match try_timeout() {
-            Ok(transmits) => {
+            Ok((transmits, failed_associations)) => {
+                // the whole association is failed, so its data channels are closed at once
+                for (four_tuple, ch) in failed_associations {
+                    ctx.fire_read(TaggedMessageEvent {
+                        now,
+                        transport: TransportContext {
+                            local_addr: four_tuple.local_addr,
+                            peer_addr: four_tuple.peer_addr,
+                            ecn: None,
+                        },
+                        message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(
+                            ApplicationMessage {
+                                association_handle: ch.0,
+                                stream_id: 0,
+                                data_channel_event: DataChannelEvent::Close,
+                            },
+                        )),
+                    });
+                }
                 for transmit in transmits {
                     if let Payload::RawEncode(raw_data) = transmit.payload {
                         for raw in raw_data {
@@ -394,6 +475,16 @@ fn split_transmit(transmit: Transmit) -> Vec<Transmit> {
     transmits
 }
 
+fn abort_transmit(now: Instant, four_tuple: &FourTuple, abort: Bytes) -> Transmit {
+    Transmit {
+        now,
+        remote: four_tuple.peer_addr,
+        payload: Payload::RawEncode(vec![abort]),
+        ecn: None,
+        local_ip: Some(four_tuple.local_addr.ip()),
+    }
+}
+
 fn to_data_message_type(ppid: PayloadProtocolIdentifier) -> DataChannelMessageType {
     match ppid {
         PayloadProtocolIdentifier::Dcep => DataChannelMessageType::Control,
@@ -1,7 +1,9 @@
 use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
 use crate::server::states::ServerStates;
+use crate::types::FourTuple;
 use bytes::BytesMut;
-use log::{debug, error};
+use log::{debug, error, warn};
+use opentelemetry::KeyValue;
 use retty::channel::{Context, Handler};
 use shared::{
     error::{Error, Result},
@@ -9,9 +11,20 @@ use shared::{
     util::is_rtcp,
 };
 use std::cell::RefCell;
+use std::ops::RangeInclusive;
 use std::rc::Rc;
 use std::time::Instant;
 
+/// RTP version of the first two bits of RTP header
+const RTP_VERSION: u8 = 2;
+/// RTCP packet types of the second octet, from SR (200) to XR (207), so that RTP packets with
+/// high payload types, which is_rtcp mistakes for RTCP, are rejected after decryption
+const RTCP_PACKET_TYPES: RangeInclusive<u8> = 200..=207;
+/// IPv4 (20 bytes) and UDP (8 bytes) header sizes counted against the path MTU
+const IPV4_UDP_HEADER_SIZE: usize = 28;
+/// IPv6 (40 bytes) and UDP (8 bytes) header sizes counted against the path MTU
+const IPV6_UDP_HEADER_SIZE: usize = 48;
+
 /// SrtpHandler implements SRTP/RTP/RTCP Protocols handling
 pub struct SrtpHandler {
     server_states: Rc<RefCell<ServerStates>>,
@@ -40,26 +53,64 @@ impl Handler for SrtpHandler {
     ) {
         if let MessageEvent::Rtp(RTPMessageEvent::Raw(message)) = msg.message {
             debug!("srtp read {:?}", msg.transport.peer_addr);
-            let try_read = || -> Result<MessageEvent> {
+            let try_read = || -> Result<Option<MessageEvent>> {
                 let four_tuple = (&msg.transport).into();
                 let mut server_states = self.server_states.borrow_mut();
+                let attributes = server_states.metrics_attributes(&four_tuple);
+                let srtp_pending_buffer_size =
+                    server_states.server_config().srtp_pending_buffer_size;
                 let transport = server_states.get_mut_transport(&four_tuple)?;
+                transport.record_received(message.len(), msg.now);
+
+                // packets racing the end of DTLS handshake are replayed by DtlsHandler once
+                // the remote SRTP context is ready
+                if transport.remote_srtp_context().is_none() && srtp_pending_buffer_size > 0 {
+                    if transport.buffer_pending_srtp_packet(
+                        msg.now,
+                        message.clone(),
+                        srtp_pending_buffer_size,
+                    ) {
+                        server_states
+                            .metrics()
+                            .record_remote_srtp_context_not_set_count(1, &attributes);
+                        debug!(
+                            "drop the oldest pending srtp packet for four_tuple {:?}",
+                            four_tuple
+                        );
+                    }
+                    return Ok(None);
+                }
 
                 if is_rtcp(&message) {
                     let mut remote_context = transport.remote_srtp_context();
                     if let Some(context) = remote_context.as_mut() {
                         let mut decrypted = context.decrypt_rtcp(&message)?;
+                        if !decrypted
+                            .get(1)
+                            .is_some_and(|packet_type| RTCP_PACKET_TYPES.contains(packet_type))
+                        {
+                            server_states
+                                .metrics()
+                                .record_srtp_parse_error_count(1, &attributes);
+                            return Err(Error::Other(format!(
+                                "ErrInvalidRtcpPacketType {:?} for four_tuple {:?}",
+                                decrypted.get(1),
+                                four_tuple
+                            )));
+                        }
                         let rtcp_packets = rtcp::packet::unmarshal(&mut decrypted)?;
                         if rtcp_packets.is_empty() {
                             return Err(Error::Other("empty rtcp_packets".to_string()));
                         }
 
-                        server_states.metrics().record_rtcp_packet_in_count(1, &[]);
-                        Ok(MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets)))
+                        server_states
+                            .metrics()
+                            .record_rtcp_packet_in_count(1, &attributes);
+                        Ok(Some(MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets))))
                     } else {
                         server_states
                             .metrics()
-                            .record_remote_srtp_context_not_set_count(1, &[]);
+                            .record_remote_srtp_context_not_set_count(1, &attributes);
                         Err(Error::Other(format!(
                             "remote_srtp_context is not set yet for four_tuple {:?}",
                             four_tuple
@@ -69,14 +120,25 @@ impl Handler for SrtpHandler {
                     let mut remote_context = transport.remote_srtp_context();
                     if let Some(context) = remote_context.as_mut() {
                         let mut decrypted = context.decrypt_rtp(&message)?;
+                        if decrypted.first().map(|b| b >> 6) != Some(RTP_VERSION) {
+                            server_states
+                                .metrics()
+                                .record_srtp_parse_error_count(1, &attributes);
+                            return Err(Error::Other(format!(
+                                "ErrInvalidRtpVersion for four_tuple {:?}",
+                                four_tuple
+                            )));
+                        }
                         let rtp_packet = rtp::Packet::unmarshal(&mut decrypted)?;
 
-                        server_states.metrics().record_rtp_packet_in_count(1, &[]);
-                        Ok(MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)))
+                        server_states
+                            .metrics()
+                            .record_rtp_packet_in_count(1, &attributes);
+                        Ok(Some(MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet))))
                     } else {
                         server_states
                             .metrics()
-                            .record_remote_srtp_context_not_set_count(1, &[]);
+                            .record_remote_srtp_context_not_set_count(1, &attributes);
                         Err(Error::Other(format!(
                             "remote_srtp_context is not set yet for four_tuple {:?}",
                             four_tuple
@@ -86,10 +148,11 @@ impl Handler for SrtpHandler {
             };
 
             match try_read() {
-                Ok(message) => {
+                Ok(Some(message)) => {
                     msg.message = message;
                     ctx.fire_read(msg);
                 }
+                Ok(None) => {}
                 Err(err) => {
                     error!("try_read got error {}", err);
                     ctx.fire_exception(Box::new(err))
@@ -105,15 +168,17 @@ impl Handler for SrtpHandler {
         &mut self,
         ctx: &Context<Self::Rin, Self::Rout, Self::Win, Self::Wout>,
     ) -> Option<Self::Wout> {
-        if let Some(mut msg) = ctx.fire_poll_write() {
+        while let Some(mut msg) = ctx.fire_poll_write() {
             if let MessageEvent::Rtp(message) = msg.message {
                 debug!("srtp write {:?}", msg.transport.peer_addr);
-                let try_write = || -> Result<BytesMut> {
+                let try_write = || -> Result<Option<BytesMut>> {
                     let four_tuple = (&msg.transport).into();
                     let mut server_states = self.server_states.borrow_mut();
+                    let attributes = server_states.metrics_attributes(&four_tuple);
+                    let is_rtp = matches!(message, RTPMessageEvent::Rtp(_));
                     let transport = server_states.get_mut_transport(&four_tuple)?;
 
-                    match message {
+                    let encrypted = match message {
                         RTPMessageEvent::Rtcp(rtcp_packets) => {
                             if rtcp_packets.is_empty() {
                                 return Err(Error::Other("empty rtcp_packets".to_string()));
@@ -124,16 +189,18 @@ impl Handler for SrtpHandler {
                                 let packet = rtcp::packet::marshal(&rtcp_packets)?;
                                 let rtcp_packet = context.encrypt_rtcp(&packet);
 
-                                server_states.metrics().record_rtcp_packet_out_count(1, &[]);
+                                server_states
+                                    .metrics()
+                                    .record_rtcp_packet_out_count(1, &attributes);
                                 server_states.metrics().record_rtcp_packet_processing_time(
                                     Instant::now().duration_since(msg.now).as_micros() as u64,
-                                    &[],
+                                    &attributes,
                                 );
                                 rtcp_packet
                             } else {
                                 server_states
                                     .metrics()
-                                    .record_local_srtp_context_not_set_count(1, &[]);
+                                    .record_local_srtp_context_not_set_count(1, &attributes);
 
                                 Err(Error::Other(format!(
                                     "local_srtp_context is not set yet for four_tuple {:?}",
@@ -147,16 +214,18 @@ impl Handler for SrtpHandler {
                                 let packet = rtp_message.marshal()?;
                                 let rtp_packet = context.encrypt_rtp(&packet);
 
-                                server_states.metrics().record_rtp_packet_out_count(1, &[]);
+                                server_states
+                                    .metrics()
+                                    .record_rtp_packet_out_count(1, &attributes);
                                 server_states.metrics().record_rtp_packet_processing_time(
                                     Instant::now().duration_since(msg.now).as_micros() as u64,
-                                    &[],
+                                    &attributes,
                                 );
                                 rtp_packet
                             } else {
                                 server_states
                                     .metrics()
-                                    .record_local_srtp_context_not_set_count(1, &[]);
+                                    .record_local_srtp_context_not_set_count(1, &attributes);
 
                                 Err(Error::Other(format!(
                                     "local_srtp_context is not set yet for four_tuple {:?}",
@@ -169,27 +238,74 @@ impl Handler for SrtpHandler {
                             debug!("Bypass srtp write {:?}", msg.transport.peer_addr);
                             Ok(raw_packet)
                         }
+                    }?;
+
+                    if is_rtp
+                        && is_oversized(&mut server_states, &four_tuple, &attributes, &encrypted)?
+                        && server_states.server_config().drop_oversized_rtp
+                    {
+                        return Ok(None);
                     }
+
+                    server_states
+                        .get_mut_transport(&four_tuple)?
+                        .record_sent(encrypted.len());
+                    Ok(Some(encrypted))
                 };
 
                 match try_write() {
-                    Ok(encrypted) => {
+                    Ok(Some(encrypted)) => {
                         msg.message = MessageEvent::Rtp(RTPMessageEvent::Raw(encrypted));
-                        Some(msg)
+                        return Some(msg);
                     }
+                    Ok(None) => {}
                     Err(err) => {
                         error!("try_write with error {}", err);
                         ctx.fire_exception(Box::new(err));
-                        None
+                        return None;
                     }
                 }
             } else {
                 // Bypass
                 debug!("Bypass srtp write {:?}", msg.transport.peer_addr);
-                Some(msg)
+                return Some(msg);
             }
-        } else {
-            None
         }
+        None
+    }
+}
+
+/// checks whether an outgoing SRTP packet plus IP/UDP headers exceeds the path MTU of its transport,
+/// counting and logging it if so
+fn is_oversized(
+    server_states: &mut ServerStates,
+    four_tuple: &FourTuple,
+    attributes: &[KeyValue],
+    packet: &[u8],
+) -> Result<bool> {
+    let default_path_mtu = server_states.server_config().path_mtu;
+    let transport = server_states.get_mut_transport(four_tuple)?;
+    let Some(path_mtu) = transport.path_mtu().or(default_path_mtu) else {
+        return Ok(false);
+    };
+    let overhead = if four_tuple.peer_addr.is_ipv4() {
+        IPV4_UDP_HEADER_SIZE
+    } else {
+        IPV6_UDP_HEADER_SIZE
+    };
+    if packet.len() + overhead <= path_mtu {
+        return Ok(false);
     }
+
+    transport.record_oversized_rtp();
+    server_states
+        .metrics()
+        .record_oversized_rtp_packet_count(1, attributes);
+    warn!(
+        "rtp packet of {} bytes exceeds path mtu {} for four_tuple {:?}",
+        packet.len() + overhead,
+        path_mtu,
+        four_tuple
+    );
+    Ok(true)
 }
@@ -1,14 +1,24 @@
 use crate::messages::TaggedMessageEvent;
 use crate::types::FourTuple;
+use log::error;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
 use std::time::Instant;
 
 pub(crate) mod nack;
+pub(crate) mod packet_log;
 pub(crate) mod report;
+pub(crate) mod sdes;
 pub(crate) mod twcc;
+pub(crate) mod vp9;
 
 pub enum InterceptorEvent {
     Inbound(TaggedMessageEvent),
     Outbound(TaggedMessageEvent),
+    /// the read or written message is dropped instead of being passed on
+    Dropped,
     Error(Box<dyn std::error::Error>),
 }
 
@@ -44,6 +54,22 @@ pub trait Interceptor {
             next.poll_timeout(eto);
         }
     }
+
+    /// bind_remote_clock_rates tells the clock rates of the payload types negotiated by the
+    /// remote description
+    fn bind_remote_clock_rates(&mut self, clock_rates: &HashMap<u8, u32>) {
+        if let Some(next) = self.next() {
+            next.bind_remote_clock_rates(clock_rates);
+        }
+    }
+
+    /// unbind_remote_stream tells the remote stream of ssrc is removed, e.g., by rejecting its
+    /// media section
+    fn unbind_remote_stream(&mut self, ssrc: u32) {
+        if let Some(next) = self.next() {
+            next.unbind_remote_stream(ssrc);
+        }
+    }
 }
 
 /// InterceptorBuilder provides an interface for constructing interceptors
@@ -67,11 +93,26 @@ impl Registry {
         self.builders.push(builder);
     }
 
-    /// build a single Interceptor from an InterceptorRegistry
+    /// replace the InterceptorBuilder at the index of the registry.
+    pub(crate) fn replace(
+        &mut self,
+        index: usize,
+        builder: Box<dyn InterceptorBuilder + Send + Sync>,
+    ) {
+        self.builders[index] = builder;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.builders.len()
+    }
+
+    /// build a single Interceptor from an InterceptorRegistry, where each interceptor
+    /// is isolated by PanicGuard, so that a panicking one is disabled instead of
+    /// crashing the media thread
     pub fn build(&self, id: &str) -> Box<dyn Interceptor> {
         let mut next = Box::new(NoOp) as Box<dyn Interceptor>;
-        for interceptor in self.builders.iter().rev().map(|b| b.build(id)) {
-            next = interceptor.chain(next);
+        for (index, interceptor) in self.builders.iter().map(|b| b.build(id)).enumerate().rev() {
+            next = PanicGuard::new(index, interceptor).chain(next);
         }
         next
     }
@@ -90,3 +131,160 @@ impl Interceptor for NoOp {
         None
     }
 }
+
+/// SharedNext lets PanicGuard keep the rest of the chain after its guarded interceptor,
+/// so that the chain can be resumed without the guarded interceptor once it panics, unless
+/// the rest of the chain has already been invoked by the guarded interceptor.
+#[derive(Clone)]
+struct SharedNext {
+    next: Rc<RefCell<Option<Box<dyn Interceptor>>>>,
+    invoked: Rc<Cell<bool>>,
+}
+
+impl SharedNext {
+    fn new() -> Self {
+        Self {
+            next: Rc::new(RefCell::new(None)),
+            invoked: Rc::new(Cell::new(false)),
+        }
+    }
+
+    fn invoke<T: Default>(&self, f: impl FnOnce(&mut Box<dyn Interceptor>) -> T) -> T {
+        self.invoked.set(true);
+        self.next.borrow_mut().as_mut().map(f).unwrap_or_default()
+    }
+}
+
+impl Interceptor for SharedNext {
+    fn chain(self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        *self.next.borrow_mut() = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        None
+    }
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        self.invoke(|next| next.read(msg))
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        self.invoke(|next| next.write(msg))
+    }
+
+    fn handle_timeout(&mut self, now: Instant, four_tuples: &[FourTuple]) -> Vec<InterceptorEvent> {
+        self.invoke(|next| next.handle_timeout(now, four_tuples))
+    }
+
+    fn poll_timeout(&mut self, eto: &mut Instant) {
+        self.invoke(|next| next.poll_timeout(eto))
+    }
+
+    fn bind_remote_clock_rates(&mut self, clock_rates: &HashMap<u8, u32>) {
+        self.invoke(|next| next.bind_remote_clock_rates(clock_rates))
+    }
+
+    fn unbind_remote_stream(&mut self, ssrc: u32) {
+        self.invoke(|next| next.unbind_remote_stream(ssrc))
+    }
+}
+
+/// PanicGuard catches panics of its guarded interceptor, after which the guarded interceptor
+/// is dropped and the rest of the chain is invoked directly.
+struct PanicGuard {
+    index: usize,
+    interceptor: Option<Box<dyn Interceptor>>,
+    next: SharedNext,
+}
+
+impl PanicGuard {
+    fn new(index: usize, interceptor: Box<dyn Interceptor>) -> Box<Self> {
+        let next = SharedNext::new();
+        Box::new(Self {
+            index,
+            interceptor: Some(interceptor.chain(Box::new(next.clone()))),
+            next,
+        })
+    }
+
+    /// guard runs f on the guarded interceptor, or returns None if it is disabled or panics,
+    /// and the rest of the chain is to be invoked without it
+    fn guard<T: Default>(&mut self, f: impl FnOnce(&mut Box<dyn Interceptor>) -> T) -> Option<T> {
+        let interceptor = self.interceptor.as_mut()?;
+        self.next.invoked.set(false);
+        match catch_unwind(AssertUnwindSafe(|| f(interceptor))) {
+            Ok(result) => Some(result),
+            Err(_) => {
+                error!("interceptor #{} panicked and is disabled", self.index);
+                self.interceptor = None;
+                // the message has already been passed on by the guarded interceptor before it
+                // panicked, so that the rest of the chain must not handle it twice
+                if self.next.invoked.get() {
+                    Some(T::default())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+impl Interceptor for PanicGuard {
+    fn chain(self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        *self.next.next.borrow_mut() = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        None
+    }
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        match self.guard(|interceptor| interceptor.read(msg)) {
+            Some(events) => events,
+            None => self.next.read(msg),
+        }
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        match self.guard(|interceptor| interceptor.write(msg)) {
+            Some(events) => events,
+            None => self.next.write(msg),
+        }
+    }
+
+    fn handle_timeout(&mut self, now: Instant, four_tuples: &[FourTuple]) -> Vec<InterceptorEvent> {
+        match self.guard(|interceptor| interceptor.handle_timeout(now, four_tuples)) {
+            Some(events) => events,
+            None => self.next.handle_timeout(now, four_tuples),
+        }
+    }
+
+    fn poll_timeout(&mut self, eto: &mut Instant) {
+        if self
+            .guard(|interceptor| interceptor.poll_timeout(eto))
+            .is_none()
+        {
+            self.next.poll_timeout(eto);
+        }
+    }
+
+    fn bind_remote_clock_rates(&mut self, clock_rates: &HashMap<u8, u32>) {
+        if self
+            .guard(|interceptor| interceptor.bind_remote_clock_rates(clock_rates))
+            .is_none()
+        {
+            self.next.bind_remote_clock_rates(clock_rates);
+        }
+    }
+
+    fn unbind_remote_stream(&mut self, ssrc: u32) {
+        if self
+            .guard(|interceptor| interceptor.unbind_remote_stream(ssrc))
+            .is_none()
+        {
+            self.next.unbind_remote_stream(ssrc);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::description::rtp_transceiver::SSRC;
+use crate::interceptors::{Interceptor, InterceptorBuilder, InterceptorEvent};
+use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
+use log::debug;
+use std::collections::HashMap;
+
+/// PacketLoggerBuilder can be used to configure PacketLogger Interceptor.
+#[derive(Default)]
+pub struct PacketLoggerBuilder {
+    first_packets: usize,
+}
+
+impl PacketLoggerBuilder {
+    /// with_first_packets sets how many packets of each newly seen SSRC are logged.
+    pub fn with_first_packets(mut self, first_packets: usize) -> PacketLoggerBuilder {
+        self.first_packets = first_packets;
+        self
+    }
+}
+
+impl InterceptorBuilder for PacketLoggerBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(PacketLogger {
+            first_packets: self.first_packets,
+            logged_packets: HashMap::new(),
+            next: None,
+        })
+    }
+}
+
+/// PacketLogger logs RTP header fields of the first packets of each newly seen SSRC read from
+/// publishers at debug level, then goes quiet, e.g., for diagnosing codec or format mismatches.
+pub(crate) struct PacketLogger {
+    first_packets: usize,
+    logged_packets: HashMap<SSRC, usize>,
+    next: Option<Box<dyn Interceptor>>,
+}
+
+impl PacketLogger {
+    pub(crate) fn builder() -> PacketLoggerBuilder {
+        PacketLoggerBuilder::default()
+    }
+}
+
+impl Interceptor for PacketLogger {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtp(packet)) = &msg.message {
+            let logged_packets = self.logged_packets.entry(packet.header.ssrc).or_default();
+            if *logged_packets < self.first_packets {
+                *logged_packets += 1;
+                let header = &packet.header;
+                debug!(
+                    "{:?} ssrc {} packet {}/{}: payload_type={} sequence_number={} timestamp={} marker={} extensions={:?} payload_len={}",
+                    msg.transport.peer_addr,
+                    header.ssrc,
+                    logged_packets,
+                    self.first_packets,
+                    header.payload_type,
+                    header.sequence_number,
+                    header.timestamp,
+                    header.marker,
+                    header
+                        .extensions
+                        .iter()
+                        .map(|extension| (extension.id, extension.payload.len()))
+                        .collect::<Vec<_>>(),
+                    packet.payload.len()
+                );
+            }
+        }
+
+        if let Some(next) = self.next() {
+            next.read(msg)
+        } else {
+            vec![]
+        }
+    }
+}
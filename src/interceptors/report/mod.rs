@@ -14,6 +14,7 @@ use sender_report::SenderReport;
 pub struct ReportBuilder {
     is_rr: bool,
     interval: Option<Duration>,
+    reorder_window: u16,
 }
 
 impl ReportBuilder {
@@ -23,6 +24,13 @@ impl ReportBuilder {
         self
     }
 
+    /// with_reorder_window sets how many packets of higher sequence numbers are received before
+    /// a missing packet is reported as lost, so that reordered packets don't count as loss.
+    pub fn with_reorder_window(mut self, reorder_window: u16) -> ReportBuilder {
+        self.reorder_window = reorder_window;
+        self
+    }
+
     fn build_rr(&self) -> ReceiverReport {
         ReceiverReport {
             interval: if let Some(interval) = &self.interval {
@@ -31,6 +39,8 @@ impl ReportBuilder {
                 Duration::from_secs(1) //TODO: make it configurable
             },
             eto: Instant::now(),
+            reorder_window: self.reorder_window,
+            clock_rates: HashMap::new(),
             streams: HashMap::new(),
             next: None,
         }
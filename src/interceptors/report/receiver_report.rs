@@ -10,6 +10,9 @@ use std::time::{Duration, Instant};
 pub(crate) struct ReceiverReport {
     pub(super) interval: Duration,
     pub(super) eto: Instant,
+    pub(super) reorder_window: u16,
+    /// clock rates of the payload types negotiated by remote, for jitter of streams
+    pub(super) clock_rates: HashMap<u8, u32>,
     pub(crate) streams: HashMap<u32, ReceiverStream>,
     pub(super) next: Option<Box<dyn Interceptor>>,
 }
@@ -43,11 +46,25 @@ impl Interceptor for ReceiverReport {
                     if let Some(stream) = self.streams.get_mut(&sr.ssrc) {
                         stream.process_sender_report(msg.now, sr);
                     }
+                } else if let Some(bye) = rtcp_packet
+                    .as_any()
+                    .downcast_ref::<rtcp::goodbye::Goodbye>()
+                {
+                    for ssrc in &bye.sources {
+                        self.streams.remove(ssrc);
+                    }
                 }
             }
         } else if let MessageEvent::Rtp(RTPMessageEvent::Rtp(rtp_packet)) = &msg.message {
-            if let Some(stream) = self.streams.get_mut(&rtp_packet.header.ssrc) {
+            let ssrc = rtp_packet.header.ssrc;
+            if let Some(stream) = self.streams.get_mut(&ssrc) {
+                stream.process_rtp(msg.now, rtp_packet);
+            } else if let Some(&clock_rate) = self.clock_rates.get(&rtp_packet.header.payload_type)
+            {
+                // streams of payload types not negotiated are left unreported
+                let mut stream = ReceiverStream::new(ssrc, clock_rate, self.reorder_window);
                 stream.process_rtp(msg.now, rtp_packet);
+                self.streams.insert(ssrc, stream);
             }
         }
 
@@ -89,6 +106,22 @@ impl Interceptor for ReceiverReport {
         interceptor_events
     }
 
+    fn bind_remote_clock_rates(&mut self, clock_rates: &HashMap<u8, u32>) {
+        self.clock_rates.clone_from(clock_rates);
+
+        if let Some(next) = self.next() {
+            next.bind_remote_clock_rates(clock_rates);
+        }
+    }
+
+    fn unbind_remote_stream(&mut self, ssrc: u32) {
+        self.streams.remove(&ssrc);
+
+        if let Some(next) = self.next() {
+            next.unbind_remote_stream(ssrc);
+        }
+    }
+
     fn poll_timeout(&mut self, eto: &mut Instant) {
         if self.eto < *eto {
             *eto = self.eto
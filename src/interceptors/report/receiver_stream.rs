@@ -4,6 +4,7 @@ pub(crate) struct ReceiverStream {
     ssrc: u32,
     receiver_ssrc: u32,
     clock_rate: f64,
+    reorder_window: u16,
 
     packets: Vec<u64>,
     started: bool,
@@ -19,11 +20,14 @@ pub(crate) struct ReceiverStream {
 }
 
 impl ReceiverStream {
-    pub(crate) fn new(ssrc: u32, clock_rate: u32) -> Self {
+    /// create new stream, where missing packets aren't reported as lost until reorder_window
+    /// packets of higher sequence numbers are received, since they may just be reordered
+    pub(crate) fn new(ssrc: u32, clock_rate: u32, reorder_window: u16) -> Self {
         Self {
             ssrc,
             receiver_ssrc: rand::random::<u32>(),
             clock_rate: clock_rate as f64,
+            reorder_window,
 
             packets: vec![0u64; 128],
             started: false,
@@ -104,22 +108,14 @@ impl ReceiverStream {
         &mut self,
         now: Instant,
     ) -> rtcp::receiver_report::ReceiverReport {
-        let total_since_report = (self.last_seq_num - self.last_report_seq_num) as u16;
-        let mut total_lost_since_report = {
-            if self.last_seq_num == self.last_report_seq_num {
-                0
-            } else {
-                let mut ret = 0u32;
-                let mut i = (self.last_report_seq_num + 1) as u16;
-                while i != self.last_seq_num as u16 {
-                    if !self.get_received(i) {
-                        ret += 1;
-                    }
-                    i = i.wrapping_add(1);
-                }
-                ret
-            }
-        };
+        // packets within the reorder window of the last one are left to the next reports
+        let last_report_seq_num = self.last_report_seq_num as u16;
+        let total_since_report = (self.last_seq_num as u16)
+            .wrapping_sub(last_report_seq_num)
+            .saturating_sub(self.reorder_window);
+        let mut total_lost_since_report = (1..=total_since_report)
+            .filter(|i| !self.get_received(last_report_seq_num.wrapping_add(*i)))
+            .count() as u32;
 
         self.total_lost += total_lost_since_report;
 
@@ -150,7 +146,7 @@ impl ReceiverStream {
             ..Default::default()
         };
 
-        self.last_report_seq_num = self.last_seq_num;
+        self.last_report_seq_num = last_report_seq_num.wrapping_add(total_since_report) as i32;
 
         r
     }
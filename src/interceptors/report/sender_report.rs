@@ -1,7 +1,12 @@
+use crate::handlers::gateway::{FORMAT_TMMBN, FORMAT_TMMBR};
 use crate::interceptors::report::ReportBuilder;
 use crate::interceptors::{Interceptor, InterceptorEvent};
 use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
-use rtcp::header::PacketType;
+use rtcp::header::{PacketType, FORMAT_TCC};
+
+/// formats of TransportSpecificFeedback read by gateway, i.e., TWCC feeding the bandwidth
+/// estimator, and TMMBR/TMMBN
+const GATEWAY_TRANSPORT_FEEDBACK_FORMATS: [u8; 3] = [FORMAT_TMMBR, FORMAT_TMMBN, FORMAT_TCC];
 
 pub(crate) struct SenderReport {
     pub(super) next: Option<Box<dyn Interceptor>>,
@@ -33,12 +38,14 @@ impl Interceptor for SenderReport {
             let mut inbound_rtcp_packets = vec![];
 
             for rtcp_packet in rtcp_packets {
-                let packet_type = rtcp_packet.header().packet_type;
-                if packet_type == PacketType::ReceiverReport
-                    || (packet_type == PacketType::TransportSpecificFeedback)
+                let header = rtcp_packet.header();
+                if header.packet_type == PacketType::ReceiverReport
+                    || (header.packet_type == PacketType::TransportSpecificFeedback
+                        && !GATEWAY_TRANSPORT_FEEDBACK_FORMATS.contains(&header.count))
                 {
                     // let's not forward ReceiverReport and TransportSpecificFeedback
-                    // since they are hop by hop reports, instead of end to end reports
+                    // since they are hop by hop reports, instead of end to end reports,
+                    // except the ones which gateway handles
                     continue;
                 } else {
                     inbound_rtcp_packets.push(rtcp_packet.clone());
@@ -0,0 +1,81 @@
+use crate::description::rtp_transceiver::SSRC;
+use crate::interceptors::{Interceptor, InterceptorEvent};
+use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
+use bytes::Bytes;
+use rtcp::source_description::{SdesType, SourceDescription};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// SdesForwarder forwards RTCP SourceDescription packets to subscribers intact,
+/// except for CNAME items of the session's configured SSRCs, which are rewritten, e.g., to a
+/// canonical per-session value for server-side mixing. It heads the interceptor chain of each
+/// endpoint, and shares the CNAMEs with its session, so that they can be changed at any time.
+pub(crate) struct SdesForwarder {
+    cnames: Rc<RefCell<HashMap<SSRC, String>>>,
+    next: Option<Box<dyn Interceptor>>,
+}
+
+impl SdesForwarder {
+    pub(crate) fn new(cnames: Rc<RefCell<HashMap<SSRC, String>>>) -> Self {
+        Self { cnames, next: None }
+    }
+
+    /// rewrite returns a copy of SourceDescription with rewritten CNAMEs,
+    /// or None if none of its chunks has configured SSRC
+    fn rewrite(&self, sdes: &SourceDescription) -> Option<SourceDescription> {
+        let cnames = self.cnames.borrow();
+        if !sdes
+            .chunks
+            .iter()
+            .any(|chunk| cnames.contains_key(&chunk.source))
+        {
+            return None;
+        }
+
+        let mut sdes = sdes.clone();
+        for chunk in sdes.chunks.iter_mut() {
+            if let Some(cname) = cnames.get(&chunk.source) {
+                for item in chunk.items.iter_mut() {
+                    if item.sdes_type == SdesType::SdesCname {
+                        item.text = Bytes::from(cname.clone());
+                    }
+                }
+            }
+        }
+        Some(sdes)
+    }
+}
+
+impl Interceptor for SdesForwarder {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if !self.cnames.borrow().is_empty() {
+            if let MessageEvent::Rtp(RTPMessageEvent::Rtcp(rtcp_packets)) = &mut msg.message {
+                for rtcp_packet in rtcp_packets.iter_mut() {
+                    if let Some(sdes) = rtcp_packet
+                        .as_any()
+                        .downcast_ref::<SourceDescription>()
+                        .and_then(|sdes| self.rewrite(sdes))
+                    {
+                        *rtcp_packet = Box::new(sdes);
+                    }
+                }
+            }
+        }
+
+        if let Some(next) = self.next() {
+            next.write(msg)
+        } else {
+            vec![]
+        }
+    }
+}
@@ -0,0 +1,162 @@
+use crate::description::rtp_transceiver::{PayloadType, SSRC};
+use crate::interceptors::{Interceptor, InterceptorBuilder, InterceptorEvent};
+use crate::messages::{MessageEvent, RTPMessageEvent, TaggedMessageEvent};
+use std::collections::{HashMap, HashSet};
+
+/// VP9 payload descriptor flags of the first octet,
+/// <https://datatracker.ietf.org/doc/html/rfc9628#section-4.2>
+const VP9_PICTURE_ID_PRESENT: u8 = 0x80;
+const VP9_INTER_PICTURE_PREDICTED: u8 = 0x40;
+const VP9_LAYER_INDICES_PRESENT: u8 = 0x20;
+const VP9_END_OF_FRAME: u8 = 0x04;
+/// extended 15 bits picture id flag
+const VP9_PICTURE_ID_EXTENDED: u8 = 0x80;
+
+/// Vp9LayerIndex is the layer index of a VP9 payload descriptor
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Vp9LayerIndex {
+    pub(crate) spatial_layer_id: u8,
+    pub(crate) temporal_layer_id: u8,
+    /// whether the frame is not inter-picture predicted, i.e., part of a keyframe
+    pub(crate) is_keyframe: bool,
+    /// whether the packet ends the layer frame
+    pub(crate) is_end_of_frame: bool,
+}
+
+impl Vp9LayerIndex {
+    /// parse the layer index of a VP9 payload descriptor,
+    /// or None if the payload is truncated or has no layer indices
+    pub(crate) fn parse(payload: &[u8]) -> Option<Self> {
+        let flags = *payload.first()?;
+        if flags & VP9_LAYER_INDICES_PRESENT == 0 {
+            return None;
+        }
+
+        let mut offset = 1;
+        if flags & VP9_PICTURE_ID_PRESENT != 0 {
+            offset += if *payload.get(offset)? & VP9_PICTURE_ID_EXTENDED != 0 {
+                2
+            } else {
+                1
+            };
+        }
+
+        // TID(3) | U(1) | SID(3) | D(1)
+        let layer_index = *payload.get(offset)?;
+        Some(Self {
+            spatial_layer_id: (layer_index >> 1) & 0x07,
+            temporal_layer_id: layer_index >> 5,
+            is_keyframe: flags & VP9_INTER_PICTURE_PREDICTED == 0,
+            is_end_of_frame: flags & VP9_END_OF_FRAME != 0,
+        })
+    }
+}
+
+/// Vp9KSvcFilterBuilder can be used to configure Vp9KSvcFilter Interceptor.
+#[derive(Default)]
+pub struct Vp9KSvcFilterBuilder {
+    payload_types: HashSet<PayloadType>,
+    max_spatial_layer: Option<u8>,
+    max_temporal_layer: Option<u8>,
+}
+
+impl Vp9KSvcFilterBuilder {
+    /// with_payload_types sets payload types of VP9 codecs whose packets are filtered.
+    pub fn with_payload_types(mut self, payload_types: Vec<PayloadType>) -> Vp9KSvcFilterBuilder {
+        self.payload_types = payload_types.into_iter().collect();
+        self
+    }
+
+    /// with_max_spatial_layer sets the highest spatial layer forwarded to subscribers.
+    pub fn with_max_spatial_layer(mut self, max_spatial_layer: u8) -> Vp9KSvcFilterBuilder {
+        self.max_spatial_layer = Some(max_spatial_layer);
+        self
+    }
+
+    /// with_max_temporal_layer sets the highest temporal layer forwarded to subscribers.
+    pub fn with_max_temporal_layer(mut self, max_temporal_layer: u8) -> Vp9KSvcFilterBuilder {
+        self.max_temporal_layer = Some(max_temporal_layer);
+        self
+    }
+}
+
+impl InterceptorBuilder for Vp9KSvcFilterBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(Vp9KSvcFilter {
+            payload_types: self.payload_types.clone(),
+            max_spatial_layer: self.max_spatial_layer.unwrap_or(u8::MAX),
+            max_temporal_layer: self.max_temporal_layer.unwrap_or(u8::MAX),
+            dropped_packets: HashMap::new(),
+            next: None,
+        })
+    }
+}
+
+/// Vp9KSvcFilter drops VP9 K-SVC packets of spatial and temporal layers above the subscriber's
+/// maximum ones, except for spatial layer 0 of keyframes which all other layers depend on.
+/// Sequence numbers are shifted by the number of dropped packets, and the marker bit is set at
+/// the end of the highest forwarded spatial layer, so that subscribers see complete frames.
+pub(crate) struct Vp9KSvcFilter {
+    payload_types: HashSet<PayloadType>,
+    max_spatial_layer: u8,
+    max_temporal_layer: u8,
+    dropped_packets: HashMap<SSRC, u16>,
+    next: Option<Box<dyn Interceptor>>,
+}
+
+impl Vp9KSvcFilter {
+    pub(crate) fn builder() -> Vp9KSvcFilterBuilder {
+        Vp9KSvcFilterBuilder::default()
+    }
+
+    /// filter returns whether the packet is forwarded, rewriting its header if so
+    fn filter(&mut self, packet: &mut rtp::packet::Packet) -> bool {
+        if !self.payload_types.contains(&packet.header.payload_type) {
+            return true;
+        }
+        let Some(layer_index) = Vp9LayerIndex::parse(&packet.payload) else {
+            return true;
+        };
+
+        let is_forwarded = (layer_index.spatial_layer_id <= self.max_spatial_layer
+            && layer_index.temporal_layer_id <= self.max_temporal_layer)
+            || (layer_index.spatial_layer_id == 0 && layer_index.is_keyframe);
+        let dropped_packets = self.dropped_packets.entry(packet.header.ssrc).or_default();
+        if !is_forwarded {
+            *dropped_packets = dropped_packets.wrapping_add(1);
+            return false;
+        }
+
+        packet.header.sequence_number =
+            packet.header.sequence_number.wrapping_sub(*dropped_packets);
+        if layer_index.is_end_of_frame && layer_index.spatial_layer_id == self.max_spatial_layer {
+            packet.header.marker = true;
+        }
+        true
+    }
+}
+
+impl Interceptor for Vp9KSvcFilter {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtp(packet)) = &mut msg.message {
+            if !self.filter(packet) {
+                return vec![InterceptorEvent::Dropped];
+            }
+        }
+
+        if let Some(next) = self.next() {
+            next.write(msg)
+        } else {
+            vec![]
+        }
+    }
+}
@@ -12,11 +12,41 @@ pub(crate) mod server;
 pub(crate) mod session;
 pub(crate) mod types;
 
-pub use configs::{media_config::MediaConfig, server_config::ServerConfig};
-pub use description::RTCSessionDescription;
+pub use configs::{
+    demuxer_config::{DemuxerConfig, ZrtpMode},
+    media_config::MediaConfig,
+    server_config::ServerConfig,
+};
+pub use description::{
+    dependency_descriptor::{
+        DependencyDescriptor, FrameDependencyStructure, DEPENDENCY_DESCRIPTOR_URI,
+    },
+    fmtp::intersect_fmtp,
+    frame_marking::{FrameMarking, FRAME_MARKING_URI},
+    ice_candidate::{RTCIceCandidate, RTCIceCandidateType},
+    playout_delay::{LatencyMode, PlayoutDelay, PLAYOUT_DELAY_URI},
+    resolve_header_extension_ids,
+    rtp_codec::{RTCRtpCodecCapability, RTCRtpCodecParameters, RTCRtpHeaderExtensionParameters},
+    sdp_type::RTCSdpType,
+    simulcast::{RTCRtpRid, RTCRtpRidRestrictions, RTCRtpSimulcast, SimulcastDirection},
+    RTCSessionDescription,
+};
+pub use endpoint::transport::TransportStats;
 pub use handlers::{
     datachannel::DataChannelHandler, demuxer::DemuxerHandler, dtls::DtlsHandler,
     exception::ExceptionHandler, gateway::GatewayHandler, interceptor::InterceptorHandler,
     sctp::SctpHandler, srtp::SrtpHandler, stun::StunHandler,
 };
-pub use server::{certificate::RTCCertificate, states::ServerStates};
+pub use interceptors::{Interceptor, InterceptorBuilder, InterceptorEvent, Registry};
+pub use messages::{
+    DTLSMessageEvent, MessageEvent, RTPMessageEvent, SessionEvent, TaggedMessageEvent,
+};
+pub use server::{
+    certificate::RTCCertificate,
+    fan_out::FanOut,
+    pipeline::ServerPipeline,
+    sharded::{ShardConfig, ShardedServerStates},
+    states::ServerStates,
+};
+pub use session::sdp_log::SdpLogEntry;
+pub use types::FourTuple;
@@ -1,6 +1,9 @@
+use crate::description::rtp_transceiver::SSRC;
+use crate::types::{EndpointId, SessionId};
 use bytes::BytesMut;
 use retty::transport::TransportContext;
 use sctp::ReliabilityType;
+use std::collections::HashMap;
 use std::time::Instant;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -68,6 +71,28 @@ pub enum MessageEvent {
     Rtp(RTPMessageEvent),
 }
 
+/// SessionEvent is an out-of-band event of a session for the application,
+/// which is polled by ServerStates::poll_session_event
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SessionEvent {
+    /// RTCP APP packet with the name of ServerConfig::with_rtcp_app_name,
+    /// <https://datatracker.ietf.org/doc/html/rfc3550#section-6.7>
+    RtcpApp {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        ssrc: SSRC,
+        data: BytesMut,
+    },
+    /// track published by the endpoint's offer, with the endpoint's metadata set by
+    /// ServerStates::set_endpoint_metadata, e.g., display name of the publisher
+    TrackAdded {
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: String,
+        metadata: HashMap<String, String>,
+    },
+}
+
 pub struct TaggedMessageEvent {
     pub now: Instant,
     pub transport: TransportContext,
@@ -10,8 +10,13 @@ pub(crate) struct Metrics {
     rtcp_packet_out_count: Counter<u64>,
     remote_srtp_context_not_set_count: Counter<u64>,
     local_srtp_context_not_set_count: Counter<u64>,
+    zrtp_packet_in_count: Counter<u64>,
+    truncated_packet_dropped_count: Counter<u64>,
+    oversized_rtp_packet_count: Counter<u64>,
+    srtp_parse_error_count: Counter<u64>,
     rtp_packet_processing_time: ObservableGauge<u64>,
     rtcp_packet_processing_time: ObservableGauge<u64>,
+    sctp_associations_active: ObservableGauge<u64>,
 }
 
 impl Metrics {
@@ -27,6 +32,12 @@ impl Metrics {
             local_srtp_context_not_set_count: meter
                 .u64_counter("local_srtp_context_not_set_count")
                 .init(),
+            zrtp_packet_in_count: meter.u64_counter("zrtp_packet_in_count").init(),
+            truncated_packet_dropped_count: meter
+                .u64_counter("truncated_packet_dropped_count")
+                .init(),
+            oversized_rtp_packet_count: meter.u64_counter("oversized_rtp_packet_count").init(),
+            srtp_parse_error_count: meter.u64_counter("srtp_parse_error_count").init(),
             rtp_packet_processing_time: meter
                 .u64_observable_gauge("rtp_packet_processing_time")
                 .with_unit(Unit::new("us"))
@@ -35,6 +46,9 @@ impl Metrics {
                 .u64_observable_gauge("rtcp_packet_processing_time")
                 .with_unit(Unit::new("us"))
                 .init(),
+            sctp_associations_active: meter
+                .u64_observable_gauge("sctp_associations_active")
+                .init(),
         }
     }
 
@@ -71,6 +85,26 @@ impl Metrics {
         self.local_srtp_context_not_set_count.add(value, attributes);
     }
 
+    pub(crate) fn record_zrtp_packet_in_count(&self, value: u64, attributes: &[KeyValue]) {
+        self.zrtp_packet_in_count.add(value, attributes);
+    }
+
+    pub(crate) fn record_truncated_packet_dropped_count(
+        &self,
+        value: u64,
+        attributes: &[KeyValue],
+    ) {
+        self.truncated_packet_dropped_count.add(value, attributes);
+    }
+
+    pub(crate) fn record_oversized_rtp_packet_count(&self, value: u64, attributes: &[KeyValue]) {
+        self.oversized_rtp_packet_count.add(value, attributes);
+    }
+
+    pub(crate) fn record_srtp_parse_error_count(&self, value: u64, attributes: &[KeyValue]) {
+        self.srtp_parse_error_count.add(value, attributes);
+    }
+
     pub(crate) fn record_rtp_packet_processing_time(&self, value: u64, attributes: &[KeyValue]) {
         self.rtp_packet_processing_time.observe(value, attributes);
     }
@@ -78,4 +112,8 @@ impl Metrics {
     pub(crate) fn record_rtcp_packet_processing_time(&self, value: u64, attributes: &[KeyValue]) {
         self.rtcp_packet_processing_time.observe(value, attributes);
     }
+
+    pub(crate) fn record_sctp_associations_active(&self, value: u64, attributes: &[KeyValue]) {
+        self.sctp_associations_active.observe(value, attributes);
+    }
 }
@@ -0,0 +1,107 @@
+use retty::transport::TaggedBytesMut;
+use shared::error::{Error, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+
+/// FanOut partitions outbound messages of a pipeline across N send workers by peer address,
+/// so that sending to hundreds of subscribers of a publisher can be parallelized by worker
+/// threads, each of which owns the slice of subscriber transports assigned to it.
+///
+/// Since all messages toward the same peer are assigned to the same worker in the order of
+/// [`FanOut::partition`], per-subscriber ordering is preserved as long as each worker sends
+/// its messages in FIFO order. SRTP encryption still happens in the pipeline, since SRTP
+/// contexts are owned by ServerStates, which is not thread-safe.
+pub struct FanOut {
+    workers: usize,
+}
+
+impl FanOut {
+    /// create new fan out with the number of send workers
+    pub fn new(workers: usize) -> Result<Self> {
+        if workers == 0 {
+            return Err(Error::Other("FanOut needs at least one worker".to_string()));
+        }
+        Ok(Self { workers })
+    }
+
+    /// workers returns the number of send workers
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// worker_index returns which worker the peer's transport is assigned to
+    pub fn worker_index(&self, peer_addr: &SocketAddr) -> usize {
+        let mut hasher = DefaultHasher::new();
+        peer_addr.hash(&mut hasher);
+        (hasher.finish() % self.workers as u64) as usize
+    }
+
+    /// partition messages into per-worker batches, indexed by worker index,
+    /// keeping the order of messages toward each peer
+    pub fn partition(
+        &self,
+        messages: impl IntoIterator<Item = TaggedBytesMut>,
+    ) -> Vec<Vec<TaggedBytesMut>> {
+        let mut batches = vec![vec![]; self.workers];
+        for message in messages {
+            batches[self.worker_index(&message.transport.peer_addr)].push(message);
+        }
+        batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BytesMut;
+    use retty::transport::TransportContext;
+    use std::time::Instant;
+
+    #[test]
+    fn test_fan_out_partition_keeps_per_peer_order() -> anyhow::Result<()> {
+        let fan_out = FanOut::new(4)?;
+        let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+        let peer_addrs: Vec<SocketAddr> = (0..100)
+            .map(|i| SocketAddr::from(([127, 0, 0, 1], 50000 + i)))
+            .collect();
+
+        // 10 rounds of a publisher packet fanned out to 100 subscribers
+        let now = Instant::now();
+        let messages = (0..10u8).flat_map(|round| {
+            peer_addrs.iter().map(move |&peer_addr| TaggedBytesMut {
+                now,
+                transport: TransportContext {
+                    local_addr,
+                    peer_addr,
+                    ecn: None,
+                },
+                message: BytesMut::from(&[round][..]),
+            })
+        });
+        let batches = fan_out.partition(messages);
+        assert_eq!(batches.len(), 4);
+        assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), 1000);
+        // subscribers are spread across workers
+        assert!(batches.iter().all(|batch| !batch.is_empty()));
+
+        for (worker_index, batch) in batches.iter().enumerate() {
+            for peer_addr in &peer_addrs {
+                let rounds: Vec<u8> = batch
+                    .iter()
+                    .filter(|message| message.transport.peer_addr == *peer_addr)
+                    .map(|message| message.message[0])
+                    .collect();
+                if fan_out.worker_index(peer_addr) == worker_index {
+                    assert_eq!(rounds, (0..10).collect::<Vec<u8>>());
+                } else {
+                    assert!(rounds.is_empty());
+                }
+            }
+        }
+
+        assert!(FanOut::new(0).is_err());
+
+        Ok(())
+    }
+}
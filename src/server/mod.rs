@@ -1,2 +1,5 @@
 pub(crate) mod certificate;
+pub(crate) mod fan_out;
+pub(crate) mod pipeline;
+pub(crate) mod sharded;
 pub(crate) mod states;
@@ -0,0 +1,86 @@
+use crate::handlers::{
+    datachannel::DataChannelHandler, demuxer::DemuxerHandler, dtls::DtlsHandler,
+    exception::ExceptionHandler, gateway::GatewayHandler, interceptor::InterceptorHandler,
+    sctp::SctpHandler, srtp::SrtpHandler, stun::StunHandler,
+};
+use crate::server::states::ServerStates;
+use retty::channel::{InboundPipeline, Pipeline};
+use retty::transport::TaggedBytesMut;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// ServerPipeline owns the full demux, STUN, DTLS, SCTP, DataChannel, SRTP, interceptor and
+/// gateway processing of a ServerStates, so that embedders with other runtimes can drive the SFU
+/// with raw packets in and out, without building a retty pipeline themselves.
+///
+/// The pipeline is built once and keeps its handlers' states, e.g., pending transmits and timers,
+/// across calls, so it should live as long as its ServerStates.
+pub struct ServerPipeline {
+    server_states: Rc<RefCell<ServerStates>>,
+    pipeline: Rc<Pipeline<TaggedBytesMut, TaggedBytesMut>>,
+}
+
+impl ServerPipeline {
+    /// create new pipeline of the server states
+    pub fn new(server_states: Rc<RefCell<ServerStates>>) -> Self {
+        let local_addr = server_states.borrow().local_addr();
+        let pipeline: Pipeline<TaggedBytesMut, TaggedBytesMut> = Pipeline::new();
+
+        pipeline.add_back(DemuxerHandler::with_server_states(Rc::clone(
+            &server_states,
+        )));
+        pipeline.add_back(StunHandler::new());
+        // DTLS
+        pipeline.add_back(DtlsHandler::new(local_addr, Rc::clone(&server_states)));
+        pipeline.add_back(SctpHandler::new(local_addr, Rc::clone(&server_states)));
+        pipeline.add_back(DataChannelHandler::new());
+        // SRTP
+        pipeline.add_back(SrtpHandler::new(Rc::clone(&server_states)));
+        pipeline.add_back(InterceptorHandler::new(Rc::clone(&server_states)));
+        // Gateway
+        pipeline.add_back(GatewayHandler::new(Rc::clone(&server_states)));
+        pipeline.add_back(ExceptionHandler::new());
+
+        let pipeline = pipeline.finalize();
+        pipeline.transport_active();
+
+        Self {
+            server_states,
+            pipeline,
+        }
+    }
+
+    /// server states driven by the pipeline, e.g., for signaling
+    pub fn server_states(&self) -> &Rc<RefCell<ServerStates>> {
+        &self.server_states
+    }
+
+    /// handle a received packet, and return the packets to be sent out
+    pub fn handle_read(&self, msg: TaggedBytesMut) -> Vec<TaggedBytesMut> {
+        self.pipeline.read(msg);
+        self.poll_transmits()
+    }
+
+    /// handle timers due at now, and return the packets to be sent out
+    pub fn handle_timeout(&self, now: Instant) -> Vec<TaggedBytesMut> {
+        self.pipeline.handle_timeout(now);
+        self.poll_transmits()
+    }
+
+    /// poll_timeout returns the earliest time handle_timeout should be called at, which is no
+    /// later than eto
+    pub fn poll_timeout(&self, eto: Instant) -> Instant {
+        let mut eto = eto;
+        self.pipeline.poll_timeout(&mut eto);
+        eto
+    }
+
+    fn poll_transmits(&self) -> Vec<TaggedBytesMut> {
+        let mut transmits = vec![];
+        while let Some(transmit) = self.pipeline.poll_transmit() {
+            transmits.push(transmit);
+        }
+        transmits
+    }
+}
@@ -0,0 +1,132 @@
+use crate::configs::server_config::ServerConfig;
+use crate::description::RTCSessionDescription;
+use crate::server::states::ServerStates;
+use crate::types::{EndpointId, FourTuple, SessionId};
+use opentelemetry::metrics::Meter;
+use shared::error::{Error, Result};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+
+/// ShardConfig is what a thread needs to create its own shard of the server, i.e., the shared
+/// server config and the local address, i.e., media port, that the shard serves, along with
+/// the session count shared by all shards, which ServerConfig::with_max_sessions caps. Unlike
+/// ServerStates, it is Send, so that shard configs can be handed to their threads, each of which
+/// builds and owns its ServerStates without cross-thread contention.
+#[derive(Clone)]
+pub struct ShardConfig {
+    shard_index: usize,
+    server_config: Arc<ServerConfig>,
+    local_addr: SocketAddr,
+    session_count: Arc<AtomicUsize>,
+}
+
+impl ShardConfig {
+    /// create shard configs, one shard for each local address, indexed in the given order
+    pub fn new_shards(
+        server_config: Arc<ServerConfig>,
+        local_addrs: &[SocketAddr],
+    ) -> Result<Vec<ShardConfig>> {
+        if local_addrs.is_empty() {
+            return Err(Error::Other(
+                "ShardedServerStates needs at least one shard".to_string(),
+            ));
+        }
+        let session_count = Arc::new(AtomicUsize::new(0));
+        Ok(local_addrs
+            .iter()
+            .enumerate()
+            .map(|(shard_index, &local_addr)| ShardConfig {
+                shard_index,
+                server_config: Arc::clone(&server_config),
+                local_addr,
+                session_count: Arc::clone(&session_count),
+            })
+            .collect())
+    }
+
+    /// shard_index returns which of num_shards shards the session belongs to
+    pub fn shard_index(session_id: SessionId, num_shards: usize) -> usize {
+        (session_id % num_shards as u64) as usize
+    }
+
+    /// index of the shard
+    pub fn index(&self) -> usize {
+        self.shard_index
+    }
+
+    /// server config shared by all shards
+    pub fn server_config(&self) -> &Arc<ServerConfig> {
+        &self.server_config
+    }
+
+    /// local address that the shard serves
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// build the shard's server states, which should be called in the thread owning the shard
+    pub fn build(self, meter: Meter) -> Result<ServerStates> {
+        Ok(
+            ServerStates::new(self.server_config, self.local_addr, meter)?
+                .with_session_count(self.session_count),
+        )
+    }
+}
+
+/// ShardedServerStates is a single-thread container of N ServerStates shards, which partitions
+/// sessions across them by `session_id % N`, the same way as sessions are partitioned across
+/// media ports, e.g., for running the pipelines of all media ports in one thread.
+///
+/// It is neither Send nor Sync, since its shards are `Rc<RefCell<ServerStates>>` shared with
+/// their pipelines, so that it can't be used to run shards in N threads. For that, hand each
+/// [`ShardConfig`] to its thread instead, which builds and owns its shard, and use
+/// [`ShardConfig::shard_index`] to route sessions to it.
+pub struct ShardedServerStates<const N: usize> {
+    shards: Vec<Rc<RefCell<ServerStates>>>,
+}
+
+impl<const N: usize> ShardedServerStates<N> {
+    /// create new sharded server states, one shard for each local address
+    pub fn new(
+        server_config: Arc<ServerConfig>,
+        local_addrs: [SocketAddr; N],
+        meter: Meter,
+    ) -> Result<Self> {
+        let mut shards = Vec::with_capacity(N);
+        for shard_config in ShardConfig::new_shards(server_config, &local_addrs)? {
+            shards.push(Rc::new(RefCell::new(shard_config.build(meter.clone())?)));
+        }
+        Ok(Self { shards })
+    }
+
+    /// shard_index returns which shard the session belongs to
+    pub fn shard_index(session_id: SessionId) -> usize {
+        ShardConfig::shard_index(session_id, N)
+    }
+
+    /// shard returns the shard that the session belongs to
+    pub fn shard(&self, session_id: SessionId) -> &Rc<RefCell<ServerStates>> {
+        &self.shards[Self::shard_index(session_id)]
+    }
+
+    /// shards returns all shards, indexed by shard index
+    pub fn shards(&self) -> &[Rc<RefCell<ServerStates>>] {
+        &self.shards
+    }
+
+    /// accept offer in the shard that the session belongs to, and return answer
+    pub fn accept_offer(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        four_tuple: Option<FourTuple>,
+        offer: RTCSessionDescription,
+    ) -> Result<RTCSessionDescription> {
+        self.shard(session_id)
+            .borrow_mut()
+            .accept_offer(session_id, endpoint_id, four_tuple, offer)
+    }
+}
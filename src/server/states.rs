@@ -1,21 +1,33 @@
 use crate::configs::server_config::ServerConfig;
 use crate::configs::session_config::SessionConfig;
-use crate::description::RTCSessionDescription;
+use crate::description::{
+    ice_candidate::RTCIceCandidate, rtp_codec::RTCRtpCodecParameters, sdp_type::RTCSdpType,
+    simulcast::RTCRtpRidRestrictions, RTCSessionDescription,
+};
 use crate::endpoint::{
-    candidate::{Candidate, ConnectionCredentials},
-    transport::Transport,
+    candidate::{Candidate, ConnectionCredentials, RTCIceParameters},
+    transport::{Transport, TransportStats},
     Endpoint,
 };
+use crate::interceptors::Registry;
+use crate::messages::{
+    ApplicationMessage, DTLSMessageEvent, DataChannelEvent, MessageEvent, SessionEvent,
+    TaggedMessageEvent,
+};
 use crate::metrics::Metrics;
-use crate::session::Session;
-use crate::types::{EndpointId, FourTuple, SessionId, UserName};
+use crate::session::{sdp_log::SdpLogEntry, Session};
+use crate::types::{EndpointId, FourTuple, Mid, SessionId, UserName};
+use bytes::BytesMut;
 use log::{debug, info};
-use opentelemetry::metrics::Meter;
+use opentelemetry::{metrics::Meter, KeyValue};
+use retty::transport::TransportContext;
 use shared::error::{Error, Result};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
 
@@ -26,8 +38,18 @@ pub struct ServerStates {
     metrics: Metrics,
 
     sessions: HashMap<SessionId, Session>,
+    /// number of sessions capped by ServerConfig::with_max_sessions, which is shared by all
+    /// shards built from the same ShardConfigs
+    session_count: Arc<AtomicUsize>,
     endpoints: HashMap<FourTuple, (SessionId, EndpointId)>,
     candidates: HashMap<UserName, Rc<Candidate>>,
+    /// answers generated per endpoint, keyed by the hash of their offers, so that retried
+    /// identical offers get the same answers without being parsed and negotiated again
+    answer_cache: HashMap<(SessionId, EndpointId), (u64, RTCSessionDescription)>,
+    /// local ICE parameters pre-allocated for joining endpoints, with their allocation time,
+    /// until their first offers are accepted
+    prepared_ice_params: HashMap<(SessionId, EndpointId), (RTCIceParameters, Instant)>,
+    session_events: VecDeque<SessionEvent>,
 }
 
 impl ServerStates {
@@ -37,6 +59,8 @@ impl ServerStates {
         local_addr: SocketAddr,
         meter: Meter,
     ) -> Result<Self> {
+        server_config.validate()?;
+
         let _ = server_config
             .certificates
             .first()
@@ -50,23 +74,76 @@ impl ServerStates {
             local_addr,
             metrics: Metrics::new(meter),
             sessions: HashMap::new(),
+            session_count: Arc::new(AtomicUsize::new(0)),
             endpoints: HashMap::new(),
             candidates: HashMap::new(),
+            answer_cache: HashMap::new(),
+            prepared_ice_params: HashMap::new(),
+            session_events: VecDeque::new(),
         })
     }
 
-    /// accept offer and return answer
+    /// share the session count of other shards, so that ServerConfig::with_max_sessions caps
+    /// the sessions of all shards together
+    pub(crate) fn with_session_count(mut self, session_count: Arc<AtomicUsize>) -> Self {
+        self.session_count = session_count;
+        self
+    }
+
+    /// accept offer and return answer, or Error::ErrTryAgain if a joined endpoint renegotiates
+    /// within its minimum renegotiation interval, in which case the latest of such offers is
+    /// answered over the endpoint's data channel once the interval passes
     pub fn accept_offer(
         &mut self,
         session_id: SessionId,
         endpoint_id: EndpointId,
         four_tuple: Option<FourTuple>,
+        offer: RTCSessionDescription,
+    ) -> Result<RTCSessionDescription> {
+        self.accept_offer_at(Instant::now(), session_id, endpoint_id, four_tuple, offer)
+    }
+
+    pub(crate) fn accept_offer_at(
+        &mut self,
+        now: Instant,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        four_tuple: Option<FourTuple>,
         mut offer: RTCSessionDescription,
     ) -> Result<RTCSessionDescription> {
+        // a retried identical offer, e.g., from a retried HTTP POST, gets the same answer
+        // instead of being negotiated again
+        let offer_hash = ServerStates::hash_offer(&offer);
+        if let Some(answer) = self.get_cached_answer(session_id, endpoint_id, offer_hash) {
+            debug!(
+                "{}/{} accepts retried offer with cached answer",
+                session_id, endpoint_id
+            );
+            return Ok(answer);
+        }
+
+        if let (Some(four_tuple), Some(endpoint)) = (
+            four_tuple,
+            self.get_mut_session(&session_id)
+                .and_then(|session| session.get_mut_endpoint(&endpoint_id)),
+        ) {
+            if endpoint.throttle_offer(now, four_tuple, &offer) {
+                debug!(
+                    "{}/{} offer is throttled until the minimum renegotiation interval passes",
+                    session_id, endpoint_id
+                );
+                return Err(Error::ErrTryAgain);
+            }
+        }
+
         let parsed = offer.unmarshal()?;
         let remote_conn_cred = ConnectionCredentials::from_sdp(&parsed)?;
         offer.parsed = Some(parsed);
 
+        let (ice_ufrag_len, ice_pwd_len) = (
+            self.server_config.ice_ufrag_len,
+            self.server_config.ice_pwd_len,
+        );
         let fingerprints = self
             .server_config
             .certificates
@@ -74,12 +151,10 @@ impl ServerStates {
             .unwrap()
             .get_fingerprints();
 
-        let session = self.create_or_get_mut_session(session_id);
+        let session = self.create_or_get_mut_session(session_id)?;
         let has_endpoint = session.has_endpoint(&endpoint_id);
 
-        let local_conn_cred = if has_endpoint {
-            session.set_remote_description(endpoint_id, &offer)?;
-
+        if has_endpoint {
             let endpoint = session
                 .get_endpoint(&endpoint_id)
                 .ok_or(Error::Other(format!(
@@ -92,33 +167,561 @@ impl ServerStates {
                 "can't find transport for endpoint id {} with {:?}",
                 endpoint_id, four_tuple
             )))?;
-            transport.candidate().local_connection_credentials().clone()
-        } else {
-            ConnectionCredentials::new(fingerprints, remote_conn_cred.dtls_params.role)
-        };
+            let local_ice_params = transport
+                .candidate()
+                .local_connection_credentials()
+                .ice_params
+                .clone();
+            let recv_mids = endpoint.get_all_recv_mids();
+            let answer = session.accept_offer(endpoint_id, &offer, &local_ice_params)?;
+            if let Some(endpoint) = session.get_endpoint(&endpoint_id) {
+                let mut added_mids: Vec<Mid> = endpoint
+                    .get_all_recv_mids()
+                    .into_iter()
+                    .filter(|mid| !recv_mids.contains(mid))
+                    .collect();
+                added_mids.sort();
+                let metadata = endpoint.metadata().clone();
+                for mid in added_mids {
+                    self.push_session_event(SessionEvent::TrackAdded {
+                        session_id,
+                        endpoint_id,
+                        mid,
+                        metadata: metadata.clone(),
+                    });
+                }
+            }
+            self.answer_cache
+                .insert((session_id, endpoint_id), (offer_hash, answer.clone()));
+            return Ok(answer);
+        }
 
+        let mut local_conn_cred = ConnectionCredentials::new(
+            fingerprints,
+            remote_conn_cred.dtls_params.role,
+            ice_ufrag_len,
+            ice_pwd_len,
+        );
+        if let Some((ice_params, prepared_at)) =
+            self.prepared_ice_params.remove(&(session_id, endpoint_id))
+        {
+            if prepared_at.elapsed() <= self.server_config.candidate_ttl {
+                local_conn_cred.ice_params = ice_params;
+            }
+        }
+        let session = self.create_or_get_mut_session(session_id)?;
         let answer = session.create_answer(endpoint_id, &offer, &local_conn_cred.ice_params)?;
-        if has_endpoint {
-            session.set_local_description(endpoint_id, &answer)?;
-        } else {
-            self.add_candidate(Rc::new(Candidate::new(
-                session_id,
-                endpoint_id,
-                remote_conn_cred,
-                local_conn_cred,
-                offer,
-                answer.clone(),
-                Instant::now() + self.server_config.idle_timeout,
+        session.record_sdp(endpoint_id, false, &offer);
+        session.record_sdp(endpoint_id, true, &answer);
+        self.add_candidate(Rc::new(Candidate::new_with_ttl(
+            session_id,
+            endpoint_id,
+            remote_conn_cred,
+            local_conn_cred,
+            offer,
+            answer.clone(),
+            self.server_config.candidate_ttl,
+        )));
+        self.answer_cache
+            .insert((session_id, endpoint_id), (offer_hash, answer.clone()));
+
+        Ok(answer)
+    }
+
+    /// prepare candidate for a joining endpoint by pre-allocating its local ICE ufrag and password,
+    /// which are used in the answer to its first offer, if received within candidate time-to-live
+    pub fn prepare_candidate(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Result<(String, String)> {
+        if self
+            .get_session(&session_id)
+            .is_some_and(|session| session.has_endpoint(&endpoint_id))
+            || self.candidates.values().any(|candidate| {
+                candidate.session_id() == session_id && candidate.endpoint_id() == endpoint_id
+            })
+        {
+            return Err(Error::Other(format!(
+                "endpoint id {} already joined session id {}",
+                endpoint_id, session_id
             )));
         }
 
-        Ok(answer)
+        let candidate_ttl = self.server_config.candidate_ttl;
+        self.prepared_ice_params
+            .retain(|_, (_, prepared_at)| prepared_at.elapsed() <= candidate_ttl);
+
+        let ice_params = RTCIceParameters::new(
+            self.server_config.ice_ufrag_len,
+            self.server_config.ice_pwd_len,
+        );
+        let (ice_ufrag, ice_pwd) = (
+            ice_params.username_fragment.clone(),
+            ice_params.password.clone(),
+        );
+        self.prepared_ice_params
+            .insert((session_id, endpoint_id), (ice_params, Instant::now()));
+        debug!("{}/{} prepares candidate", session_id, endpoint_id);
+
+        Ok((ice_ufrag, ice_pwd))
+    }
+
+    fn hash_offer(offer: &RTCSessionDescription) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        offer.sdp.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// get the answer cached for the offer hash from the same endpoint, as long as either the
+    /// endpoint is connected or its candidate is not expired yet
+    fn get_cached_answer(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        offer_hash: u64,
+    ) -> Option<RTCSessionDescription> {
+        let (cached_offer_hash, answer) = self.answer_cache.get(&(session_id, endpoint_id))?;
+        if *cached_offer_hash != offer_hash {
+            return None;
+        }
+        let is_valid = self
+            .get_session(&session_id)
+            .is_some_and(|session| session.has_endpoint(&endpoint_id))
+            || self.candidates.values().any(|candidate| {
+                candidate.session_id() == session_id
+                    && candidate.endpoint_id() == endpoint_id
+                    && !candidate.is_expired()
+            });
+        is_valid.then(|| answer.clone())
+    }
+
+    /// invalidate the cached answer of the endpoint, once its negotiation state changes
+    /// by other descriptions than the answered offer
+    pub(crate) fn invalidate_answer_cache(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) {
+        self.answer_cache.remove(&(session_id, endpoint_id));
+    }
+
+    /// migrate endpoint with its transports from one session to another, e.g., for breakout rooms,
+    /// where endpoints of both sessions are renegotiated
+    pub fn migrate_endpoint(
+        &mut self,
+        src_session_id: SessionId,
+        endpoint_id: EndpointId,
+        dst_session_id: SessionId,
+    ) -> Result<()> {
+        if src_session_id == dst_session_id {
+            return Err(Error::Other(format!(
+                "ErrMigrateEndpointToSameSession: {}/{}",
+                src_session_id, endpoint_id
+            )));
+        }
+        if !self
+            .get_session(&src_session_id)
+            .is_some_and(|session| session.has_endpoint(&endpoint_id))
+        {
+            return Err(Error::Other(format!(
+                "can't find endpoint id {} in session id {}",
+                endpoint_id, src_session_id
+            )));
+        }
+        if self
+            .get_session(&dst_session_id)
+            .is_some_and(|session| session.has_endpoint(&endpoint_id))
+        {
+            return Err(Error::Other(format!(
+                "ErrEndpointAlreadyExists: {}/{}",
+                dst_session_id, endpoint_id
+            )));
+        }
+        self.create_or_get_mut_session(dst_session_id)?;
+
+        let src_session = self
+            .get_mut_session(&src_session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                src_session_id
+            )))?;
+        let mut endpoint = src_session
+            .detach_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        if src_session.get_endpoints().is_empty() {
+            self.remove_session(&src_session_id);
+        }
+
+        // STUN binding requests find session of endpoint by candidate
+        for candidate in self.candidates.values_mut() {
+            if candidate.session_id() == src_session_id && candidate.endpoint_id() == endpoint_id {
+                *candidate = Rc::new(candidate.with_session_id(dst_session_id));
+            }
+        }
+        for (four_tuple, transport) in endpoint.get_mut_transports().iter_mut() {
+            let candidate = self
+                .candidates
+                .get(&transport.candidate().username())
+                .cloned()
+                .unwrap_or_else(|| Rc::new(transport.candidate().with_session_id(dst_session_id)));
+            transport.set_candidate(candidate);
+            self.endpoints
+                .insert(*four_tuple, (dst_session_id, endpoint_id));
+        }
+
+        let dst_session = self
+            .get_mut_session(&dst_session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                dst_session_id
+            )))?;
+        dst_session.attach_endpoint(endpoint);
+        self.invalidate_answer_cache(src_session_id, endpoint_id);
+        info!(
+            "{}/{} is migrated to session id {}",
+            src_session_id, endpoint_id, dst_session_id
+        );
+
+        Ok(())
     }
 
     pub(crate) fn metrics(&self) -> &Metrics {
         &self.metrics
     }
 
+    /// metrics_attributes returns session_id and endpoint_id attributes of the endpoint
+    /// connected via four_tuple, for filtering and aggregating its metrics
+    pub(crate) fn metrics_attributes(&self, four_tuple: &FourTuple) -> Vec<KeyValue> {
+        if let Some((session_id, endpoint_id)) = self.find_endpoint(four_tuple) {
+            vec![
+                KeyValue::new("session_id", session_id as i64),
+                KeyValue::new("endpoint_id", endpoint_id as i64),
+            ]
+        } else {
+            vec![]
+        }
+    }
+
+    /// get the ICE candidates signaled by the endpoint in its remote descriptions,
+    /// e.g., to pre-authorize its source addresses or detect TURN relays
+    pub fn get_remote_candidates(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Option<Vec<RTCIceCandidate>> {
+        self.get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .map(|endpoint| endpoint.remote_candidates().to_vec())
+    }
+
+    /// get the codec negotiated with the endpoint for the transceiver of the mid,
+    /// i.e., the first codec of the latest answer generated for the endpoint
+    pub fn get_negotiated_codec(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: &str,
+    ) -> Option<RTCRtpCodecParameters> {
+        self.get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .and_then(|endpoint| endpoint.negotiated_codec(mid).cloned())
+    }
+
+    /// get the restrictions of the simulcast rids sent by the endpoint for the transceiver of
+    /// the mid, keyed by rid, e.g., to choose the layers forwarded to subscribers by resolution
+    /// with set_max_spatial_layer
+    pub fn get_rid_restrictions(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: &str,
+    ) -> Option<HashMap<String, RTCRtpRidRestrictions>> {
+        self.get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .and_then(|endpoint| endpoint.get_transceivers().get(mid))
+            .map(|transceiver| {
+                transceiver
+                    .rids()
+                    .iter()
+                    .map(|(id, rid)| (id.clone(), rid.restrictions.clone()))
+                    .collect()
+            })
+    }
+
+    /// set the path MTU of the transport, e.g., as discovered by the application, overriding
+    /// ServerConfig::with_path_mtu for the RTP packets forwarded to it
+    pub fn set_path_mtu(&mut self, four_tuple: FourTuple, path_mtu: usize) -> Result<()> {
+        self.get_mut_transport(&four_tuple)?.set_path_mtu(path_mtu);
+        Ok(())
+    }
+
+    /// get the number of RTP packets forwarded to the transport exceeding its path MTU
+    pub fn get_oversized_rtp_packet_count(&self, four_tuple: &FourTuple) -> Option<u64> {
+        self.get_transport_stats(four_tuple)
+            .map(|stats| stats.oversized_rtp_packets)
+    }
+
+    /// get the stats of the transport, including when it last received STUN, DTLS and media
+    pub fn get_transport_stats(&self, four_tuple: &FourTuple) -> Option<TransportStats> {
+        let (session_id, endpoint_id) = self.find_endpoint(four_tuple)?;
+        self.get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .and_then(|endpoint| endpoint.get_transport_stats(four_tuple))
+    }
+
+    /// drop_ssrc stops forwarding the ssrc in the session, e.g., for moderation, where its
+    /// publisher is sent PLI and BYE for it on the next timeout
+    pub fn drop_ssrc(&mut self, session_id: SessionId, ssrc: u32) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        session.drop_ssrc(ssrc);
+        info!("{} drops ssrc {}", session_id, ssrc);
+        Ok(())
+    }
+
+    /// set mime types of codecs, e.g., video/VP9, which are preferred in the session's SDPs, in
+    /// the preferred order, where the session's endpoints with media of the codecs' kinds are
+    /// re-offered with the new codec order
+    pub fn set_codec_preferences(
+        &mut self,
+        session_id: SessionId,
+        mime_types: Vec<String>,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        info!("{} sets codec preferences {:?}", session_id, mime_types);
+        session.set_codec_preferences(mime_types);
+        Ok(())
+    }
+
+    /// set whether the session's media payloads are encrypted end-to-end, e.g., by SFrame of
+    /// insertable streams, where RTP headers are still rewritten but payloads are never inspected,
+    /// so that VP8/VP9 layers and ulpfec inside RED are no longer detected by payload descriptors
+    pub fn set_e2ee(&mut self, session_id: SessionId, e2ee: bool) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        session.set_e2ee(e2ee);
+        info!("{} sets e2ee {}", session_id, e2ee);
+        Ok(())
+    }
+
+    /// set CNAMEs of the session's SSRCs, which are rewritten in forwarded SDES packets, e.g., to a
+    /// canonical per-session value for server-side mixing, and signaled in a=ssrc cname attributes
+    /// of forwarded sections, where the session's endpoints forwarded the SSRCs are re-offered
+    pub fn set_sdes_cnames(
+        &mut self,
+        session_id: SessionId,
+        cnames: HashMap<u32, String>,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        info!("{} sets sdes cnames {:?}", session_id, cnames);
+        session.set_sdes_cnames(cnames);
+        Ok(())
+    }
+
+    /// request a keyframe of the publisher's track on the mid, e.g., for recording or thumbnails,
+    /// by PLI, and FIR if negotiated, whose encrypted messages are returned to be sent out
+    pub fn request_keyframe(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        mid: &str,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        let messages = endpoint.request_keyframe(Instant::now(), mid)?;
+        debug!(
+            "{}/{} requests keyframe of mid {}",
+            session_id, endpoint_id, mid
+        );
+        Ok(messages)
+    }
+
+    /// set whether the type of RTCP feedback, e.g., goog-remb in favor of transport-cc,
+    /// is advertised to the session's endpoints, which takes effect on their next negotiation
+    pub fn set_rtcp_feedback_enabled(
+        &mut self,
+        session_id: SessionId,
+        typ: &str,
+        enabled: bool,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        session.set_rtcp_feedback_enabled(typ, enabled);
+        let endpoint_ids = session.endpoint_ids();
+        for endpoint_id in endpoint_ids {
+            self.invalidate_answer_cache(session_id, endpoint_id);
+        }
+        info!(
+            "{} sets rtcp feedback {} enabled {}",
+            session_id, typ, enabled
+        );
+        Ok(())
+    }
+
+    /// update_interceptor replaces the interceptor chain of the endpoint with a newly built one
+    /// from registry, e.g., to enable NACK or TWCC mid-session, where the state of the previous
+    /// interceptors, e.g., their NACK buffers, is dropped
+    pub fn update_interceptor(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        registry: &Registry,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let interceptor = session.build_interceptor(registry);
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        endpoint.set_interceptor(interceptor);
+        info!("{}/{} updates interceptor", session_id, endpoint_id);
+        Ok(())
+    }
+
+    /// get the latest offers and answers of the session, oldest first,
+    /// when enabled by ServerConfig::with_sdp_log_capacity
+    pub fn get_sdp_log(&self, session_id: SessionId) -> Option<Vec<SdpLogEntry>> {
+        self.get_session(&session_id)
+            .map(|session| session.sdp_log())
+    }
+
+    /// set the highest spatial layer of VP9 or dependency descriptor, e.g., of AV1 SVC, forwarded
+    /// to the endpoint, e.g., to reduce its resolution, or None to forward all spatial layers
+    pub fn set_max_spatial_layer(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        max_spatial_layer: Option<u8>,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        endpoint.set_max_spatial_layer(max_spatial_layer);
+        Ok(())
+    }
+
+    /// set the highest temporal layer of VP8, VP9 or dependency descriptor forwarded to the
+    /// endpoint, e.g., to reduce its frame rate on a constrained link, or None to forward all
+    /// temporal layers
+    pub fn set_max_temporal_layer(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        max_temporal_layer: Option<u8>,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        endpoint.set_max_temporal_layer(max_temporal_layer);
+        Ok(())
+    }
+
+    /// set application-specific metadata of the endpoint, e.g., display name or role,
+    /// which is reported along with its tracks in SessionEvent::TrackAdded
+    pub fn set_endpoint_metadata(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        key: String,
+        value: String,
+    ) -> Result<()> {
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        endpoint.set_metadata(key, value);
+        Ok(())
+    }
+
+    /// get application-specific metadata of the endpoint
+    pub fn get_endpoint_metadata(
+        &self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+    ) -> Option<HashMap<String, String>> {
+        self.get_session(&session_id)
+            .and_then(|session| session.get_endpoint(&endpoint_id))
+            .map(|endpoint| endpoint.metadata().clone())
+    }
+
+    /// poll the next out-of-band session event for the application, e.g., RTCP APP packets
+    pub fn poll_session_event(&mut self) -> Option<SessionEvent> {
+        self.session_events.pop_front()
+    }
+
+    pub(crate) fn push_session_event(&mut self, session_event: SessionEvent) {
+        self.session_events.push_back(session_event);
+    }
+
     pub(crate) fn accept_answer(
         &mut self,
         session_id: SessionId,
@@ -129,14 +732,182 @@ impl ServerStates {
         let parsed = answer.unmarshal()?;
         answer.parsed = Some(parsed);
 
-        let session = self.create_or_get_mut_session(session_id);
-        if session.has_endpoint(&endpoint_id) {
+        self.invalidate_answer_cache(session_id, endpoint_id);
+        let session = self.create_or_get_mut_session(session_id)?;
+        session.record_sdp(endpoint_id, false, &answer);
+        if let Some(endpoint) = session.get_mut_endpoint(&endpoint_id) {
+            if !endpoint.is_remote_description_changed(&answer) {
+                debug!(
+                    "{}/{} accepts unchanged answer again",
+                    session_id, endpoint_id
+                );
+                return Ok(());
+            }
+            // an answer to an offer superseded by a newer one is rejected
+            if !endpoint.is_answer_to_pending_offer(&answer) {
+                return Err(Error::Other(format!(
+                    "ErrStaleAnswer: {}/{} answers offer of stale generation, expected {:?}",
+                    session_id,
+                    endpoint_id,
+                    endpoint.pending_offer_generation()
+                )));
+            }
+            endpoint.apply_negotiation(false, answer.sdp_type)?;
             session.set_remote_description(endpoint_id, &answer)?;
+            let mut rotated_ice_params = None;
+            if let Some(endpoint) = session.get_mut_endpoint(&endpoint_id) {
+                if answer.sdp_type == RTCSdpType::Answer {
+                    if !endpoint.commit_pending_local_description() {
+                        debug!(
+                            "{}/{} accepts answer without pending offer",
+                            session_id, endpoint_id
+                        );
+                    }
+                    rotated_ice_params = endpoint.take_pending_ice_params();
+                }
+            }
+            if let Some(ice_params) = rotated_ice_params {
+                self.rotate_candidate(session_id, endpoint_id, ice_params, &answer)?;
+            }
         };
 
         Ok(())
     }
 
+    /// rotate_candidate replaces the endpoint's candidate with the one of the rotated local ICE
+    /// parameters and the remote ones of the answer, so that only the new credentials
+    /// authenticate its STUN binding requests from now on
+    fn rotate_candidate(
+        &mut self,
+        session_id: SessionId,
+        endpoint_id: EndpointId,
+        ice_params: RTCIceParameters,
+        answer: &RTCSessionDescription,
+    ) -> Result<()> {
+        let parsed = answer
+            .parsed
+            .as_ref()
+            .ok_or(Error::Other("answer is not parsed".to_string()))?;
+        let remote_conn_cred = ConnectionCredentials::from_sdp(parsed)?;
+        let candidate_ttl = self.server_config.candidate_ttl;
+
+        let session = self
+            .get_mut_session(&session_id)
+            .ok_or(Error::Other(format!(
+                "can't find session id {}",
+                session_id
+            )))?;
+        let endpoint = session
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        let old_candidate = endpoint
+            .get_transports()
+            .values()
+            .next()
+            .map(|transport| Rc::clone(transport.candidate()))
+            .ok_or(Error::Other(format!(
+                "can't find transport for endpoint id {}",
+                endpoint_id
+            )))?;
+        let local_description = endpoint
+            .local_description()
+            .ok_or(Error::Other("local_description is not set".to_string()))?
+            .clone();
+        let mut local_conn_cred = old_candidate.local_connection_credentials().clone();
+        local_conn_cred.ice_params = ice_params;
+
+        let candidate = Rc::new(Candidate::new_with_ttl(
+            session_id,
+            endpoint_id,
+            remote_conn_cred,
+            local_conn_cred,
+            answer.clone(),
+            local_description,
+            candidate_ttl,
+        ));
+        candidate.set_ice_role(old_candidate.ice_role());
+        for transport in endpoint.get_mut_transports().values_mut() {
+            transport.set_candidate(Rc::clone(&candidate));
+        }
+
+        self.candidates.retain(|_, candidate| {
+            candidate.session_id() != session_id || candidate.endpoint_id() != endpoint_id
+        });
+        self.add_candidate(candidate);
+        info!("{}/{} rotates ICE credentials", session_id, endpoint_id);
+
+        Ok(())
+    }
+
+    /// broadcast_to_session returns data channel messages with the payload to all endpoints of the
+    /// session, whose data channels are ready, e.g., for session-wide notifications
+    pub fn broadcast_to_session(
+        &self,
+        session_id: SessionId,
+        payload: BytesMut,
+    ) -> Result<Vec<TaggedMessageEvent>> {
+        let session = self.get_session(&session_id).ok_or(Error::Other(format!(
+            "can't find session id {}",
+            session_id
+        )))?;
+
+        let now = Instant::now();
+        let mut messages = vec![];
+        for endpoint in session.get_endpoints().values() {
+            for (four_tuple, transport) in endpoint.get_transports().iter() {
+                if let (Some(association_handle), Some(stream_id)) =
+                    transport.association_handle_and_stream_id()
+                {
+                    messages.push(TaggedMessageEvent {
+                        now,
+                        transport: TransportContext {
+                            local_addr: four_tuple.local_addr,
+                            peer_addr: four_tuple.peer_addr,
+                            ecn: None,
+                        },
+                        message: MessageEvent::Dtls(DTLSMessageEvent::DataChannel(
+                            ApplicationMessage {
+                                association_handle,
+                                stream_id,
+                                data_channel_event: DataChannelEvent::Message(payload.clone()),
+                            },
+                        )),
+                    });
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// close tears down all connections for graceful shutdown, and returns the messages the run
+    /// loop sends out before exiting, i.e., RTCP BYE of forwarded ssrcs, SCTP SHUTDOWN and DTLS
+    /// close_notify, which are encrypted for the wire already as their Raw payloads
+    pub fn close(&mut self) -> Vec<TaggedMessageEvent> {
+        let now = Instant::now();
+        let mut messages = vec![];
+        for session in self.sessions.values_mut() {
+            for endpoint in session.get_mut_endpoints().values_mut() {
+                let send_ssrcs = endpoint.get_all_send_ssrcs();
+                for transport in endpoint.get_mut_transports().values_mut() {
+                    messages.extend(transport.close(now, &send_ssrcs));
+                }
+            }
+        }
+        self.session_count
+            .fetch_sub(self.sessions.len(), Ordering::AcqRel);
+        self.sessions.clear();
+        self.endpoints.clear();
+        self.candidates.clear();
+        self.prepared_ice_params.clear();
+        self.answer_cache.clear();
+        info!("{} closes all connections", self.local_addr);
+
+        messages
+    }
+
     pub(crate) fn server_config(&self) -> &Arc<ServerConfig> {
         &self.server_config
     }
@@ -145,8 +916,27 @@ impl ServerStates {
         self.local_addr
     }
 
-    pub(crate) fn create_or_get_mut_session(&mut self, session_id: SessionId) -> &mut Session {
+    pub(crate) fn create_or_get_mut_session(
+        &mut self,
+        session_id: SessionId,
+    ) -> Result<&mut Session> {
         if let Entry::Vacant(e) = self.sessions.entry(session_id) {
+            // the session is counted at once, so that shards racing for the last one can't
+            // both take it
+            let max_sessions = self.server_config.max_sessions.unwrap_or(usize::MAX);
+            if self
+                .session_count
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                    (count < max_sessions).then_some(count + 1)
+                })
+                .is_err()
+            {
+                return Err(Error::Other(format!(
+                    "ErrMaxSessionsExceeded: can't create session id {} beyond max sessions {}",
+                    session_id, max_sessions
+                )));
+            }
+
             let session = Session::new(
                 SessionConfig::new(Arc::clone(&self.server_config), self.local_addr),
                 session_id,
@@ -154,7 +944,7 @@ impl ServerStates {
             e.insert(session);
         }
 
-        self.sessions.get_mut(&session_id).unwrap()
+        Ok(self.sessions.get_mut(&session_id).unwrap())
     }
 
     pub(crate) fn get_mut_sessions(&mut self) -> &mut HashMap<SessionId, Session> {
@@ -174,7 +964,9 @@ impl ServerStates {
     }
 
     pub(crate) fn remove_session(&mut self, session_id: &SessionId) -> Option<Session> {
-        self.sessions.remove(session_id)
+        let session = self.sessions.remove(session_id)?;
+        self.session_count.fetch_sub(1, Ordering::AcqRel);
+        Some(session)
     }
 
     pub(crate) fn add_candidate(&mut self, candidate: Rc<Candidate>) -> Option<Rc<Candidate>> {
@@ -186,7 +978,19 @@ impl ServerStates {
         self.candidates.remove(username)
     }
 
-    pub(crate) fn find_candidate(&self, username: &UserName) -> Option<&Rc<Candidate>> {
+    /// find candidate by username, where a candidate whose endpoint has not connected
+    /// within its time-to-live is removed instead
+    pub(crate) fn find_candidate(&mut self, username: &UserName) -> Option<&Rc<Candidate>> {
+        let candidate = self.candidates.get(username)?;
+        if candidate.is_expired()
+            && self
+                .get_session(&candidate.session_id())
+                .and_then(|session| session.get_endpoint(&candidate.endpoint_id()))
+                .is_none()
+        {
+            self.candidates.remove(username);
+            return None;
+        }
         self.candidates.get(username)
     }
 
@@ -286,9 +1090,18 @@ impl ServerStates {
                 self.remove_session(&session_id);
             }
             self.remove_endpoint(&four_tuple);
+            self.invalidate_answer_cache(session_id, endpoint_id);
         }
         if let Some(transport) = transport {
             self.remove_candidate(&transport.candidate().username());
         }
     }
 }
+
+impl Drop for ServerStates {
+    fn drop(&mut self) {
+        // release the sessions of a dropped shard from the count shared with other shards
+        self.session_count
+            .fetch_sub(self.sessions.len(), Ordering::AcqRel);
+    }
+}
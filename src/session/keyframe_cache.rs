@@ -0,0 +1,50 @@
+use crate::endpoint::layer::LayerIndex;
+
+/// KeyframeCache keeps the RTP packets of a publisher's SSRC since its latest keyframe, i.e., the
+/// start of the current GOP, with their layers, so that a newly joined subscriber can be sent a
+/// decodable sequence right away instead of waiting for a PLI round-trip. When the GOP grows over
+/// the capacity, the cache is emptied until the next keyframe.
+#[derive(Debug)]
+pub(crate) struct KeyframeCache {
+    capacity: usize,
+    packets: Vec<(rtp::packet::Packet, Option<LayerIndex>)>,
+}
+
+impl KeyframeCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            packets: vec![],
+        }
+    }
+
+    /// push the packet into the cache, which starts over at the first packet of a keyframe,
+    /// while packets before any keyframe are ignored
+    pub(crate) fn push(
+        &mut self,
+        rtp_packet: &rtp::packet::Packet,
+        layer_index: Option<LayerIndex>,
+        is_keyframe: bool,
+    ) {
+        // packets of the same keyframe continue the cached GOP
+        let starts_gop = is_keyframe
+            && self.packets.first().is_none_or(|(first_packet, _)| {
+                first_packet.header.timestamp != rtp_packet.header.timestamp
+            });
+        if starts_gop {
+            self.packets.clear();
+        } else if self.packets.is_empty() {
+            return;
+        }
+
+        if self.packets.len() >= self.capacity {
+            self.packets.clear();
+            return;
+        }
+        self.packets.push((rtp_packet.clone(), layer_index));
+    }
+
+    pub(crate) fn packets(&self) -> &[(rtp::packet::Packet, Option<LayerIndex>)] {
+        &self.packets
+    }
+}
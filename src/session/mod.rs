@@ -1,3 +1,7 @@
+pub(crate) mod keyframe_cache;
+pub(crate) mod sdp_log;
+
+use log::{debug, warn};
 use retty::transport::TransportContext;
 use sdp::description::session::Origin;
 use sdp::util::ConnectionRole;
@@ -9,34 +13,65 @@ use std::rc::Rc;
 use crate::configs::session_config::SessionConfig;
 use crate::description::{
     codecs_from_media_description, get_cname, get_mid_value, get_msid, get_peer_direction,
-    get_rids, get_ssrc_groups, get_ssrcs, populate_sdp, rtp_extensions_from_media_description,
-    update_sdp_origin, MediaSection, RTCSessionDescription, MEDIA_SECTION_APPLICATION,
+    get_rids, get_rtp_rids, get_ssrc_groups, get_ssrcs, populate_sdp,
+    rtp_extensions_from_media_description, update_sdp_origin, MediaSection, RTCSessionDescription,
+    ATTR_KEY_EXTMAP_ALLOW_MIXED, MEDIA_SECTION_APPLICATION,
 };
 use crate::description::{
     rtp_codec::{RTCRtpParameters, RTPCodecType},
-    rtp_transceiver::{RTCRtpSender, RTCRtpTransceiver},
+    rtp_transceiver::{RTCRtpSender, RTCRtpTransceiver, SSRC},
     rtp_transceiver_direction::RTCRtpTransceiverDirection,
     sdp_type::RTCSdpType,
 };
+use crate::endpoint::layer::LayerIndex;
 use crate::endpoint::{
     candidate::{Candidate, DTLSRole, RTCIceParameters, DEFAULT_DTLS_ROLE_OFFER},
+    mid_allocator::MidAllocator,
     transport::Transport,
-    Endpoint,
+    Endpoint, NegotiationState,
 };
+use crate::interceptors::sdes::SdesForwarder;
+use crate::interceptors::{Interceptor, Registry};
+use crate::session::keyframe_cache::KeyframeCache;
+use crate::session::sdp_log::{SdpLog, SdpLogEntry};
 use crate::types::{EndpointId, Mid, SessionId};
 
+/// ratio by which the bitrate limit must change before SFU sends another TMMBR for an ssrc
+const TMMBR_LIMIT_CHANGE_RATIO: f64 = 0.1;
+
 pub(crate) struct Session {
     session_config: SessionConfig,
     session_id: SessionId,
     endpoints: HashMap<EndpointId, Endpoint>,
+    /// MIDs of endpoints' transceivers by codec type, in the order of their media sections
+    mid_index: HashMap<(EndpointId, RTPCodecType), Vec<Mid>>,
+    ssrc_remaps: HashMap<EndpointId, HashMap<SSRC, SSRC>>,
+    dropped_ssrcs: HashSet<SSRC>,
+    pending_dropped_ssrcs: Vec<(EndpointId, SSRC)>,
+    sdp_log: SdpLog,
+    keyframe_caches: HashMap<EndpointId, HashMap<SSRC, KeyframeCache>>,
+    /// endpoints which sent TMMBR to a publisher, keyed by the publisher and the TMMBR sender
+    /// ssrc, which owns bounding tuples of the publisher's TMMBN
+    tmmbr_requesters: HashMap<(EndpointId, SSRC), EndpointId>,
+    /// bitrates requested by SFU's own TMMBR, keyed by the publisher and its ssrc
+    tmmbr_limits: HashMap<(EndpointId, SSRC), u64>,
 }
 
 impl Session {
     pub(crate) fn new(session_config: SessionConfig, session_id: SessionId) -> Self {
+        let sdp_log = SdpLog::new(session_config.server_config.sdp_log_capacity);
         Self {
             session_config,
             session_id,
             endpoints: HashMap::new(),
+            mid_index: HashMap::new(),
+            ssrc_remaps: HashMap::new(),
+            dropped_ssrcs: HashSet::new(),
+            pending_dropped_ssrcs: vec![],
+            sdp_log,
+            keyframe_caches: HashMap::new(),
+            tmmbr_requesters: HashMap::new(),
+            tmmbr_limits: HashMap::new(),
         }
     }
 
@@ -48,6 +83,131 @@ impl Session {
         &self.session_config
     }
 
+    /// set whether the type of RTCP feedback is advertised in the session's next SDPs
+    pub(crate) fn set_rtcp_feedback_enabled(&mut self, typ: &str, enabled: bool) {
+        if enabled {
+            self.session_config.disabled_rtcp_feedbacks.remove(typ);
+        } else {
+            self.session_config
+                .disabled_rtcp_feedbacks
+                .insert(typ.to_string());
+        }
+    }
+
+    /// set mime types of codecs listed first in the session's next SDPs, in the preferred order,
+    /// and renegotiate the endpoints with media of the codecs' kinds
+    pub(crate) fn set_codec_preferences(&mut self, mime_types: Vec<String>) {
+        for endpoint in self.endpoints.values_mut() {
+            let is_affected = endpoint.get_transceivers().values().any(|transceiver| {
+                let kind = format!("{}/", transceiver.kind);
+                mime_types
+                    .iter()
+                    .any(|mime_type| mime_type.to_lowercase().starts_with(&kind))
+            });
+            if is_affected {
+                debug!(
+                    "{}/{} needs renegotiation for codec preferences {:?}",
+                    self.session_id,
+                    endpoint.endpoint_id(),
+                    mime_types
+                );
+                endpoint.set_renegotiation_needed(true);
+            }
+        }
+        self.session_config.codec_preferences = mime_types;
+    }
+
+    /// set CNAMEs of SSRCs, which are rewritten in SDES packets forwarded to the session's
+    /// endpoints, where endpoints with forwarded sections of old or new SSRCs are re-offered
+    pub(crate) fn set_sdes_cnames(&mut self, cnames: HashMap<SSRC, String>) {
+        for endpoint in self.endpoints.values_mut() {
+            let is_affected = endpoint.get_transceivers().values().any(|transceiver| {
+                transceiver.direction == RTCRtpTransceiverDirection::Sendonly
+                    && transceiver.sender.as_ref().is_some_and(|sender| {
+                        sender.ssrcs.iter().any(|ssrc| {
+                            cnames.contains_key(ssrc)
+                                || self.session_config.sdes_cnames.borrow().contains_key(ssrc)
+                        })
+                    })
+            });
+            if is_affected {
+                debug!(
+                    "{}/{} needs renegotiation for sdes cnames",
+                    self.session_id,
+                    endpoint.endpoint_id(),
+                );
+                endpoint.set_renegotiation_needed(true);
+            }
+        }
+        *self.session_config.sdes_cnames.borrow_mut() = cnames;
+    }
+
+    /// build_interceptor builds the interceptor chain of an endpoint from registry, headed by
+    /// SdesForwarder sharing the session's CNAMEs
+    pub(crate) fn build_interceptor(&self, registry: &Registry) -> Box<dyn Interceptor> {
+        let interceptor = registry.build(""); //TODO: use named registry id
+        Box::new(SdesForwarder::new(Rc::clone(
+            &self.session_config.sdes_cnames,
+        )))
+        .chain(interceptor)
+    }
+
+    /// set whether the session's media payloads are encrypted end-to-end
+    pub(crate) fn set_e2ee(&mut self, e2ee: bool) {
+        self.session_config.e2ee = e2ee;
+    }
+
+    pub(crate) fn is_e2ee(&self) -> bool {
+        self.session_config.e2ee
+    }
+
+    /// cache the packet of the publisher's forwarded ssrc into its keyframe cache, if enabled by
+    /// ServerConfig::with_keyframe_cache_size
+    pub(crate) fn cache_keyframe(
+        &mut self,
+        endpoint_id: EndpointId,
+        rtp_packet: &rtp::packet::Packet,
+        layer_index: Option<LayerIndex>,
+        is_keyframe: bool,
+    ) {
+        let capacity = self.session_config.server_config.keyframe_cache_size;
+        if capacity == 0 {
+            return;
+        }
+        self.keyframe_caches
+            .entry(endpoint_id)
+            .or_default()
+            .entry(rtp_packet.header.ssrc)
+            .or_insert_with(|| KeyframeCache::new(capacity))
+            .push(rtp_packet, layer_index, is_keyframe);
+    }
+
+    /// get the cached packets of the publisher's forwarded ssrc since its latest keyframe
+    pub(crate) fn get_cached_keyframe(
+        &self,
+        endpoint_id: EndpointId,
+        ssrc: SSRC,
+    ) -> &[(rtp::packet::Packet, Option<LayerIndex>)] {
+        self.keyframe_caches
+            .get(&endpoint_id)
+            .and_then(|keyframe_caches| keyframe_caches.get(&ssrc))
+            .map_or(&[], |keyframe_cache| keyframe_cache.packets())
+    }
+
+    /// record an offer or answer exchanged with the endpoint into the SDP log, if enabled
+    pub(crate) fn record_sdp(
+        &mut self,
+        endpoint_id: EndpointId,
+        local: bool,
+        description: &RTCSessionDescription,
+    ) {
+        self.sdp_log.record(endpoint_id, local, description);
+    }
+
+    pub(crate) fn sdp_log(&self) -> Vec<SdpLogEntry> {
+        self.sdp_log.entries()
+    }
+
     pub(crate) fn add_endpoint(
         &mut self,
         candidate: &Rc<Candidate>,
@@ -82,9 +242,15 @@ impl Session {
                 Ok(true)
             }
         } else {
-            let registry = self.session_config.server_config.media_config.registry();
-            let interceptor = registry.build(""); //TODO: use named registry id
-            let mut endpoint = Endpoint::new(endpoint_id, interceptor);
+            let interceptor =
+                self.build_interceptor(self.session_config.server_config.media_config.registry());
+            let mut endpoint = Endpoint::new(
+                endpoint_id,
+                interceptor,
+                self.session_config.server_config.renegotiation_debounce,
+                self.session_config.server_config.min_renegotiation_interval,
+                self.session_config.server_config.pending_offer_timeout,
+            );
             let transport = Transport::new(
                 four_tuple,
                 Rc::clone(candidate),
@@ -100,6 +266,8 @@ impl Session {
         }
     }
 
+    /// get the endpoint by its id; endpoints are owned by the session's map keyed by endpoint id,
+    /// so there is no separate Rc<Endpoint> index, e.g., of a Room, to keep in sync with it
     pub(crate) fn get_endpoint(&self, endpoint_id: &EndpointId) -> Option<&Endpoint> {
         self.endpoints.get(endpoint_id)
     }
@@ -109,9 +277,366 @@ impl Session {
     }
 
     pub(crate) fn remove_endpoint(&mut self, endpoint_id: &EndpointId) -> Option<Endpoint> {
+        self.mid_index.retain(|(id, _), _| id != endpoint_id);
+        self.ssrc_remaps.remove(endpoint_id);
+        self.keyframe_caches.remove(endpoint_id);
+        self.tmmbr_requesters
+            .retain(|(publisher_endpoint_id, _), requester_endpoint_id| {
+                publisher_endpoint_id != endpoint_id && requester_endpoint_id != endpoint_id
+            });
+        self.tmmbr_limits.retain(|(id, _), _| id != endpoint_id);
         self.endpoints.remove(endpoint_id)
     }
 
+    /// detach_endpoint removes the endpoint for migrating it to another session, where media
+    /// forwarded between it and the remaining endpoints becomes inactive
+    pub(crate) fn detach_endpoint(&mut self, endpoint_id: &EndpointId) -> Option<Endpoint> {
+        let mut endpoint = self.remove_endpoint(endpoint_id)?;
+
+        let forwarded_mid_prefix = format!("{}-", endpoint_id);
+        for other_endpoint in self.endpoints.values_mut() {
+            let mut is_renegotiation_needed = false;
+            for (other_mid_value, other_transceiver) in other_endpoint.get_mut_transceivers() {
+                if other_mid_value.starts_with(&forwarded_mid_prefix)
+                    && other_transceiver.direction != RTCRtpTransceiverDirection::Inactive
+                {
+                    other_transceiver.direction = RTCRtpTransceiverDirection::Inactive;
+                    is_renegotiation_needed = true;
+                }
+            }
+            if is_renegotiation_needed {
+                other_endpoint.set_renegotiation_needed(true);
+            }
+        }
+
+        for transceiver in endpoint.get_mut_transceivers().values_mut() {
+            if transceiver.direction == RTCRtpTransceiverDirection::Sendonly {
+                transceiver.direction = RTCRtpTransceiverDirection::Inactive;
+            }
+        }
+
+        Some(endpoint)
+    }
+
+    /// attach_endpoint adds an endpoint migrated from another session, and forwards media
+    /// between it and the existing endpoints
+    pub(crate) fn attach_endpoint(&mut self, mut endpoint: Endpoint) {
+        let endpoint_id = endpoint.endpoint_id();
+
+        // forward existing publishers' media to the attached endpoint
+        let mut forwarded_transceivers = vec![];
+        for (&other_endpoint_id, other_endpoint) in self.endpoints.iter() {
+            for (other_mid_value, other_transceiver) in other_endpoint.get_transceivers() {
+                if other_transceiver.direction == RTCRtpTransceiverDirection::Recvonly {
+                    let mut transceiver = other_transceiver.clone();
+                    transceiver.mid = format!("{}-{}", other_endpoint_id, other_mid_value);
+                    transceiver.direction = RTCRtpTransceiverDirection::Sendonly;
+                    transceiver.current_direction = RTCRtpTransceiverDirection::Unspecified;
+                    if let Some(sender) = transceiver.sender.as_mut() {
+                        for ssrc in sender.ssrcs.iter_mut() {
+                            *ssrc = self.forwarded_ssrc(other_endpoint_id, *ssrc);
+                        }
+                        for ssrc_group in sender.ssrc_groups.iter_mut() {
+                            for ssrc in ssrc_group.ssrcs.iter_mut() {
+                                *ssrc = self.forwarded_ssrc(other_endpoint_id, *ssrc);
+                            }
+                        }
+                    }
+                    forwarded_transceivers.push(transceiver);
+                }
+            }
+        }
+        let (mids, transceivers) = endpoint.get_mut_mids_and_transceivers();
+        for transceiver in forwarded_transceivers {
+            if !transceivers.contains_key(&transceiver.mid) {
+                mids.push(transceiver.mid.clone());
+            }
+            transceivers.insert(transceiver.mid.clone(), transceiver);
+        }
+        for mid_value in endpoint.get_mids() {
+            if let Some(transceiver) = endpoint.get_transceivers().get(mid_value) {
+                Session::index_mid(
+                    &mut self.mid_index,
+                    endpoint_id,
+                    transceiver.kind,
+                    mid_value,
+                );
+            }
+        }
+
+        // forward the attached endpoint's media to existing endpoints
+        let published_transceivers: Vec<RTCRtpTransceiver> = endpoint
+            .get_transceivers()
+            .values()
+            .filter(|transceiver| transceiver.direction == RTCRtpTransceiverDirection::Recvonly)
+            .cloned()
+            .collect();
+        for transceiver in published_transceivers {
+            let forwarded_sender = transceiver
+                .sender
+                .as_ref()
+                .map(|sender| self.remap_colliding_ssrcs(endpoint_id, sender));
+            let other_mid_value = format!("{}-{}", endpoint_id, transceiver.mid);
+            for (&other_endpoint_id, other_endpoint) in self.endpoints.iter_mut() {
+                let (other_mids, other_transceivers) =
+                    other_endpoint.get_mut_mids_and_transceivers();
+                if let Some(other_transceiver) = other_transceivers.get_mut(&other_mid_value) {
+                    other_transceiver.direction = RTCRtpTransceiverDirection::Sendonly;
+                    other_transceiver.sender = forwarded_sender.clone();
+                } else {
+                    other_mids.push(other_mid_value.clone());
+                    Session::index_mid(
+                        &mut self.mid_index,
+                        other_endpoint_id,
+                        transceiver.kind,
+                        &other_mid_value,
+                    );
+                    other_transceivers.insert(
+                        other_mid_value.clone(),
+                        RTCRtpTransceiver {
+                            mid: other_mid_value.clone(),
+                            sender: forwarded_sender.clone(),
+                            direction: RTCRtpTransceiverDirection::Sendonly,
+                            current_direction: RTCRtpTransceiverDirection::Unspecified,
+                            rtp_params: transceiver.rtp_params.clone(),
+                            rids: transceiver.rids.clone(),
+                            kind: transceiver.kind,
+                            stopped: false,
+                        },
+                    );
+                }
+                other_endpoint.set_renegotiation_needed(true);
+            }
+        }
+
+        endpoint.set_renegotiation_needed(true);
+        self.endpoints.insert(endpoint_id, endpoint);
+    }
+
+    /// add_forwarded_transceivers adds transceivers forwarding other endpoints' media to the
+    /// endpoint, or replaces the ones of the same mids
+    pub(crate) fn add_forwarded_transceivers(
+        &mut self,
+        endpoint_id: EndpointId,
+        forwarded_transceivers: Vec<RTCRtpTransceiver>,
+    ) {
+        let Some(endpoint) = self.endpoints.get_mut(&endpoint_id) else {
+            return;
+        };
+        let (mids, transceivers) = endpoint.get_mut_mids_and_transceivers();
+        for transceiver in forwarded_transceivers {
+            if !transceivers.contains_key(&transceiver.mid) {
+                mids.push(transceiver.mid.clone());
+                Session::index_mid(
+                    &mut self.mid_index,
+                    endpoint_id,
+                    transceiver.kind,
+                    &transceiver.mid,
+                );
+            }
+            transceivers.insert(transceiver.mid.clone(), transceiver);
+        }
+    }
+
+    /// index_mid appends the MID of a new transceiver to the MID index
+    fn index_mid(
+        mid_index: &mut HashMap<(EndpointId, RTPCodecType), Vec<Mid>>,
+        endpoint_id: EndpointId,
+        kind: RTPCodecType,
+        mid_value: &str,
+    ) {
+        let mids = mid_index.entry((endpoint_id, kind)).or_default();
+        if !mids.iter().any(|mid| mid == mid_value) {
+            mids.push(mid_value.to_string());
+        }
+    }
+
+    /// mids_for_endpoint_and_kind returns the MIDs of the given endpoint and codec type
+    pub(crate) fn mids_for_endpoint_and_kind(
+        &self,
+        endpoint_id: EndpointId,
+        kind: RTPCodecType,
+    ) -> &[Mid] {
+        self.mid_index
+            .get(&(endpoint_id, kind))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// mid_for_endpoint_and_kind returns the first MID of the given endpoint and codec type
+    pub(crate) fn mid_for_endpoint_and_kind(
+        &self,
+        endpoint_id: EndpointId,
+        kind: RTPCodecType,
+    ) -> Option<Mid> {
+        self.mids_for_endpoint_and_kind(endpoint_id, kind)
+            .first()
+            .cloned()
+    }
+
+    /// get_active_sender_ssrcs returns forwarded ssrcs published by endpoints, and which endpoint
+    /// publishes them
+    pub(crate) fn get_active_sender_ssrcs(&self) -> HashMap<SSRC, EndpointId> {
+        let mut active_sender_ssrcs = HashMap::new();
+        for (&endpoint_id, endpoint) in self.endpoints.iter() {
+            for ssrc in endpoint.get_all_recv_ssrcs() {
+                active_sender_ssrcs.insert(self.forwarded_ssrc(endpoint_id, ssrc), endpoint_id);
+            }
+        }
+        active_sender_ssrcs
+    }
+
+    /// forwarded_ssrc returns the ssrc that subscribers see for an endpoint's published ssrc
+    pub(crate) fn forwarded_ssrc(&self, endpoint_id: EndpointId, ssrc: SSRC) -> SSRC {
+        self.ssrc_remaps
+            .get(&endpoint_id)
+            .and_then(|remaps| remaps.get(&ssrc))
+            .copied()
+            .unwrap_or(ssrc)
+    }
+
+    /// original_ssrc returns the endpoint's published ssrc for a forwarded ssrc
+    pub(crate) fn original_ssrc(&self, endpoint_id: EndpointId, forwarded_ssrc: SSRC) -> SSRC {
+        self.ssrc_remaps
+            .get(&endpoint_id)
+            .and_then(|remaps| {
+                remaps
+                    .iter()
+                    .find_map(|(&ssrc, &forwarded)| (forwarded == forwarded_ssrc).then_some(ssrc))
+            })
+            .unwrap_or(forwarded_ssrc)
+    }
+
+    /// drop_ssrc stops forwarding the ssrc, as subscribers see it, and queues signaling
+    /// its publisher, if known, to stop sending it
+    pub(crate) fn drop_ssrc(&mut self, ssrc: SSRC) {
+        if !self.dropped_ssrcs.insert(ssrc) {
+            return;
+        }
+        if let Some(&endpoint_id) = self.get_active_sender_ssrcs().get(&ssrc) {
+            let original_ssrc = self.original_ssrc(endpoint_id, ssrc);
+            self.pending_dropped_ssrcs
+                .push((endpoint_id, original_ssrc));
+        }
+    }
+
+    /// is_ssrc_dropped returns whether the forwarded ssrc is dropped by drop_ssrc
+    pub(crate) fn is_ssrc_dropped(&self, ssrc: SSRC) -> bool {
+        self.dropped_ssrcs.contains(&ssrc)
+    }
+
+    /// take_pending_dropped_ssrcs returns the publishers' ssrcs dropped since last call
+    pub(crate) fn take_pending_dropped_ssrcs(&mut self) -> Vec<(EndpointId, SSRC)> {
+        std::mem::take(&mut self.pending_dropped_ssrcs)
+    }
+
+    /// set_tmmbr_requester remembers the endpoint which sent TMMBR from the sender ssrc to the
+    /// publisher, so that the publisher's TMMBN is sent back to it
+    pub(crate) fn set_tmmbr_requester(
+        &mut self,
+        publisher_endpoint_id: EndpointId,
+        sender_ssrc: SSRC,
+        requester_endpoint_id: EndpointId,
+    ) {
+        self.tmmbr_requesters
+            .insert((publisher_endpoint_id, sender_ssrc), requester_endpoint_id);
+    }
+
+    /// get_tmmbr_requester returns the endpoint which owns a bounding tuple of the publisher's
+    /// TMMBN, or None if it is owned by SFU itself or unknown
+    pub(crate) fn get_tmmbr_requester(
+        &self,
+        publisher_endpoint_id: EndpointId,
+        owner_ssrc: SSRC,
+    ) -> Option<EndpointId> {
+        self.tmmbr_requesters
+            .get(&(publisher_endpoint_id, owner_ssrc))
+            .copied()
+    }
+
+    /// update_tmmbr_limit returns whether SFU should send TMMBR with the bitrate limit for the
+    /// publisher's ssrc, i.e., when the limit first drops below its received bitrate, or when it
+    /// moves by more than TMMBR_LIMIT_CHANGE_RATIO from the previously requested one
+    pub(crate) fn update_tmmbr_limit(
+        &mut self,
+        publisher_endpoint_id: EndpointId,
+        ssrc: SSRC,
+        received_bitrate: u64,
+        limit: u64,
+    ) -> bool {
+        let key = (publisher_endpoint_id, ssrc);
+        let should_request = match self.tmmbr_limits.get(&key) {
+            Some(&requested) => {
+                limit.abs_diff(requested) as f64 > requested as f64 * TMMBR_LIMIT_CHANGE_RATIO
+            }
+            None => limit < received_bitrate,
+        };
+        if should_request {
+            self.tmmbr_limits.insert(key, limit);
+        }
+        should_request
+    }
+
+    /// remap_colliding_ssrcs returns the sender as it is forwarded to subscribers, where ssrcs
+    /// already published by other endpoints are remapped to unused ones. The endpoint which
+    /// published an ssrc first keeps it, and a remap, once allocated, is kept across
+    /// renegotiations, so that subscribers keep seeing the same forwarded ssrc.
+    fn remap_colliding_ssrcs(
+        &mut self,
+        endpoint_id: EndpointId,
+        sender: &RTCRtpSender,
+    ) -> RTCRtpSender {
+        // since later publishers are always remapped, an ssrc forwarded as-is by another
+        // endpoint is owned by whichever endpoint published it first
+        let mut active_sender_ssrcs: HashMap<SSRC, EndpointId> = self
+            .get_active_sender_ssrcs()
+            .into_iter()
+            .filter(|&(_, other_endpoint_id)| other_endpoint_id != endpoint_id)
+            .collect();
+        if let Some(remaps) = self.ssrc_remaps.get(&endpoint_id) {
+            for &forwarded_ssrc in remaps.values() {
+                active_sender_ssrcs.insert(forwarded_ssrc, endpoint_id);
+            }
+        }
+
+        let mut forwarded_sender = sender.clone();
+        for ssrc in forwarded_sender.ssrcs.iter_mut() {
+            if let Some(&forwarded_ssrc) = self
+                .ssrc_remaps
+                .get(&endpoint_id)
+                .and_then(|remaps| remaps.get(ssrc))
+            {
+                *ssrc = forwarded_ssrc;
+                continue;
+            }
+            if let Some(&other_endpoint_id) = active_sender_ssrcs.get(ssrc) {
+                let mut forwarded_ssrc = rand::random::<SSRC>();
+                while forwarded_ssrc == 0
+                    || active_sender_ssrcs.contains_key(&forwarded_ssrc)
+                    || sender.ssrcs.contains(&forwarded_ssrc)
+                {
+                    forwarded_ssrc = rand::random::<SSRC>();
+                }
+                warn!(
+                    "{}/{}'s ssrc {} collides with endpoint {}, remap it to {}",
+                    self.session_id, endpoint_id, ssrc, other_endpoint_id, forwarded_ssrc
+                );
+                self.ssrc_remaps
+                    .entry(endpoint_id)
+                    .or_default()
+                    .insert(*ssrc, forwarded_ssrc);
+                active_sender_ssrcs.insert(forwarded_ssrc, endpoint_id);
+                *ssrc = forwarded_ssrc;
+            }
+        }
+        for ssrc_group in forwarded_sender.ssrc_groups.iter_mut() {
+            for ssrc in ssrc_group.ssrcs.iter_mut() {
+                *ssrc = self.forwarded_ssrc(endpoint_id, *ssrc);
+            }
+        }
+        forwarded_sender
+    }
+
     pub(crate) fn has_endpoint(&self, endpoint_id: &EndpointId) -> bool {
         self.endpoints.contains_key(endpoint_id)
     }
@@ -120,10 +645,66 @@ impl Session {
         &self.endpoints
     }
 
+    /// endpoint_ids returns a sorted snapshot of current endpoint ids, so that endpoints can be
+    /// iterated while one of them is borrowed mutably
+    pub(crate) fn endpoint_ids(&self) -> Vec<EndpointId> {
+        let mut endpoint_ids: Vec<EndpointId> = self.endpoints.keys().copied().collect();
+        endpoint_ids.sort_unstable();
+        endpoint_ids
+    }
+
     pub(crate) fn get_mut_endpoints(&mut self) -> &mut HashMap<EndpointId, Endpoint> {
         &mut self.endpoints
     }
 
+    /// accept_offer of a connected endpoint, which sets the offer as remote description,
+    /// creates the answer, sets it as local description and returns it. The negotiation state
+    /// transitions of both offer and answer are validated before applying any of them.
+    pub(crate) fn accept_offer(
+        &mut self,
+        endpoint_id: EndpointId,
+        offer: &RTCSessionDescription,
+        local_ice_params: &RTCIceParameters,
+    ) -> Result<RTCSessionDescription> {
+        let session_id = self.session_id;
+        let endpoint = self
+            .get_mut_endpoint(&endpoint_id)
+            .ok_or(Error::Other(format!(
+                "can't find endpoint id {}",
+                endpoint_id
+            )))?;
+        // on glare, SFU yields to the remote offer by rolling back its own pending one, whose
+        // changes are offered again once the remote offer is answered
+        if offer.sdp_type == RTCSdpType::Offer
+            && endpoint.negotiation_state() == NegotiationState::HaveLocalOffer
+        {
+            debug!(
+                "{}/{} rolls back pending offer on glare",
+                session_id, endpoint_id
+            );
+            endpoint.rollback_pending_offer()?;
+        }
+        endpoint
+            .negotiation_state()
+            .next(false, offer.sdp_type)?
+            .next(true, RTCSdpType::Answer)?;
+        if endpoint.is_remote_description_changed(offer) {
+            self.set_remote_description(endpoint_id, offer)?;
+        }
+
+        let answer = self.create_answer(endpoint_id, offer, local_ice_params)?;
+        self.set_local_description(endpoint_id, &answer)?;
+        self.record_sdp(endpoint_id, false, offer);
+        self.record_sdp(endpoint_id, true, &answer);
+        if let Some(endpoint) = self.get_mut_endpoint(&endpoint_id) {
+            endpoint.set_local_description(answer.clone());
+            endpoint.apply_negotiation(false, offer.sdp_type)?;
+            endpoint.apply_negotiation(true, answer.sdp_type)?;
+        }
+
+        Ok(answer)
+    }
+
     pub(crate) fn set_remote_description(
         &mut self,
         endpoint_id: EndpointId,
@@ -140,7 +721,10 @@ impl Session {
             .as_ref()
             .ok_or(Error::Other("Unparsed remote description".to_string()))?;
 
-        let we_offer = remote_description.sdp_type == RTCSdpType::Answer;
+        let we_offer = matches!(
+            remote_description.sdp_type,
+            RTCSdpType::Answer | RTCSdpType::Pranswer
+        );
 
         for media in &parsed.media_descriptions {
             if media.media_name.media == MEDIA_SECTION_APPLICATION {
@@ -148,7 +732,14 @@ impl Session {
             }
 
             let kind = RTPCodecType::from(media.media_name.media.as_str());
-            let direction = get_peer_direction(media);
+            // a media section offered with port 0 is rejected, i.e., its track is removed,
+            // <https://datatracker.ietf.org/doc/html/rfc8829#section-5.2.2>
+            let is_rejected = !we_offer && media.media_name.port.value == 0;
+            let direction = if is_rejected {
+                RTCRtpTransceiverDirection::Inactive
+            } else {
+                get_peer_direction(media)
+            };
             if kind == RTPCodecType::Unspecified
                 || direction == RTCRtpTransceiverDirection::Unspecified
             {
@@ -168,6 +759,10 @@ impl Session {
                 None => continue,
             };
 
+            if is_rejected {
+                self.stop_transceiver(endpoint_id, mid_value);
+            }
+
             if !we_offer {
                 // This is an offer from the remote.
                 let has_mid_value = self
@@ -180,16 +775,19 @@ impl Session {
                 if !has_mid_value {
                     let cname = get_cname(media);
                     let msid = get_msid(media);
-                    let ssrc_groups = get_ssrc_groups(media)?;
-                    let ssrcs = get_ssrcs(media)?;
+                    let ssrc_groups = get_ssrc_groups(media);
+                    let ssrcs = get_ssrcs(media);
                     let codecs = codecs_from_media_description(media)?;
                     let header_extensions = rtp_extensions_from_media_description(media)?;
+                    let rids = get_rtp_rids(media);
                     let rtp_params = RTCRtpParameters {
                         header_extensions,
                         codecs,
                     };
 
-                    let local_direction = if direction == RTCRtpTransceiverDirection::Recvonly {
+                    let local_direction = if is_rejected {
+                        RTCRtpTransceiverDirection::Inactive
+                    } else if direction == RTCRtpTransceiverDirection::Recvonly {
                         RTCRtpTransceiverDirection::Sendonly
                     } else {
                         RTCRtpTransceiverDirection::Recvonly
@@ -206,13 +804,19 @@ impl Session {
                         None
                     };
 
+                    let forwarded_sender = sender
+                        .as_ref()
+                        .map(|sender| self.remap_colliding_ssrcs(endpoint_id, sender));
+
                     let transceiver = RTCRtpTransceiver {
                         mid: mid_value.to_string(),
-                        sender: sender.clone(),
+                        sender,
                         direction: local_direction,
                         current_direction: RTCRtpTransceiverDirection::Unspecified,
                         rtp_params: rtp_params.clone(),
+                        rids: rids.clone(),
                         kind,
+                        stopped: is_rejected,
                     };
 
                     {
@@ -222,11 +826,11 @@ impl Session {
                             .get_mut_transceivers()
                             .insert(mid_value.to_string(), transceiver);
                     }
+                    Session::index_mid(&mut self.mid_index, endpoint_id, kind, mid_value);
 
                     // add it to other endpoints' transceivers as send only
 
-                    for (&other_endpoint_id, other_endpoint) in self.get_mut_endpoints().iter_mut()
-                    {
+                    for (&other_endpoint_id, other_endpoint) in self.endpoints.iter_mut() {
                         if other_endpoint_id != endpoint_id {
                             let other_mid_value = format!("{}-{}", endpoint_id, mid_value);
                             let (other_mids, other_transceivers) =
@@ -241,14 +845,22 @@ impl Session {
                             } else if direction == RTCRtpTransceiverDirection::Sendonly {
                                 let other_transceiver = RTCRtpTransceiver {
                                     mid: other_mid_value.clone(),
-                                    sender: sender.clone(),
+                                    sender: forwarded_sender.clone(),
                                     direction,
                                     current_direction: RTCRtpTransceiverDirection::Unspecified,
                                     rtp_params: rtp_params.clone(),
+                                    rids: rids.clone(),
                                     kind,
+                                    stopped: false,
                                 };
 
                                 other_mids.push(other_mid_value.clone());
+                                Session::index_mid(
+                                    &mut self.mid_index,
+                                    other_endpoint_id,
+                                    kind,
+                                    &other_mid_value,
+                                );
                                 other_transceivers.insert(other_mid_value, other_transceiver);
                                 other_endpoint.set_renegotiation_needed(true);
                             }
@@ -271,13 +883,66 @@ impl Session {
                     // 4.5.9.2.13.2
                     // Set transceiver.[[CurrentDirection]] and transceiver.[[Direction]]s to direction.
                     transceiver.set_current_direction(reversed_direction);
+
+                    // record header extension ids negotiated by the remote
+                    transceiver.rtp_params.header_extensions =
+                        rtp_extensions_from_media_description(media)?;
                 }
             }
         }
 
+        if let Some(endpoint) = self.get_mut_endpoint(&endpoint_id) {
+            endpoint.set_remote_description(remote_description.clone());
+        }
+
         Ok(())
     }
 
+    /// stop_transceiver stops the endpoint's transceiver of a rejected media section, and the
+    /// transceivers forwarding its track to other endpoints, which are renegotiated with port 0
+    fn stop_transceiver(&mut self, endpoint_id: EndpointId, mid_value: &str) {
+        let Some(transceiver) = self
+            .get_mut_endpoint(&endpoint_id)
+            .and_then(|endpoint| endpoint.get_mut_transceivers().get_mut(mid_value))
+        else {
+            return;
+        };
+        if transceiver.is_stopped() {
+            return;
+        }
+        transceiver.stop();
+        let ssrcs = transceiver
+            .sender
+            .as_ref()
+            .map(|sender| sender.ssrcs.clone())
+            .unwrap_or_default();
+        if let Some(endpoint) = self.get_mut_endpoint(&endpoint_id) {
+            for ssrc in ssrcs {
+                endpoint.get_mut_interceptor().unbind_remote_stream(ssrc);
+            }
+        }
+        debug!(
+            "{}/{} stops transceiver {}",
+            self.session_id, endpoint_id, mid_value
+        );
+
+        let other_mid_value = format!("{}-{}", endpoint_id, mid_value);
+        for (&other_endpoint_id, other_endpoint) in self.endpoints.iter_mut() {
+            if other_endpoint_id == endpoint_id {
+                continue;
+            }
+            if let Some(other_transceiver) = other_endpoint
+                .get_mut_transceivers()
+                .get_mut(&other_mid_value)
+            {
+                if !other_transceiver.is_stopped() {
+                    other_transceiver.stop();
+                    other_endpoint.set_renegotiation_needed(true);
+                }
+            }
+        }
+    }
+
     pub(crate) fn set_local_description(
         &mut self,
         endpoint_id: EndpointId,
@@ -336,11 +1001,14 @@ impl Session {
     }
 
     pub(crate) fn create_offer(
-        &self,
+        &mut self,
         endpoint_id: EndpointId,
         remote_description: &RTCSessionDescription,
         local_ice_params: &RTCIceParameters,
     ) -> Result<RTCSessionDescription> {
+        if let Some(endpoint) = self.get_mut_endpoint(&endpoint_id) {
+            endpoint.get_or_allocate_data_mid();
+        }
         let use_identity = false; //TODO: self.config.idp_login_url.is_some();
 
         let mut d = self.generate_matched_sdp(
@@ -352,8 +1020,12 @@ impl Session {
             DEFAULT_DTLS_ROLE_OFFER.to_connection_role(),
         )?;
 
-        let mut sdp_origin = Origin::default();
-        update_sdp_origin(&mut sdp_origin, &mut d);
+        // session version of offers tells their generation, so that answers can be ordered
+        if let Some(endpoint) = self.get_mut_endpoint(&endpoint_id) {
+            endpoint.update_offer_origin(&mut d);
+        } else {
+            update_sdp_origin(&mut Origin::default(), &mut d);
+        }
 
         let sdp = d.marshal();
 
@@ -420,8 +1092,14 @@ impl Session {
             let mut media_sections = vec![];
             let mut already_have_application_media_section = false;
             let mut matched: HashSet<Mid> = HashSet::new();
+            let mut offered_mids: Vec<&str> = vec![];
             if let Some(parsed) = remote_description.parsed.as_ref() {
+                let extmap_allow_mixed = parsed
+                    .attributes
+                    .iter()
+                    .any(|a| a.key == ATTR_KEY_EXTMAP_ALLOW_MIXED);
                 for media in &parsed.media_descriptions {
+                    offered_mids.push(get_mid_value(media).map(String::as_str).unwrap_or_default());
                     if let Some(mid_value) = get_mid_value(media) {
                         if mid_value.is_empty() {
                             return Err(Error::Other(
@@ -447,14 +1125,28 @@ impl Session {
                             continue;
                         }
 
-                        if transceivers.contains_key(mid_value) {
+                        // offered media sections match transceivers of the same kind only
+                        if self
+                            .mids_for_endpoint_and_kind(endpoint_id, kind)
+                            .contains(mid_value)
+                        {
                             media_sections.push(MediaSection {
                                 mid: mid_value.to_owned(),
-                                rid_map: get_rids(media),
+                                rids: get_rids(media),
                                 offered_direction: (!include_unmatched).then_some(direction),
+                                extmap_allow_mixed: !include_unmatched
+                                    && (extmap_allow_mixed
+                                        || media.attribute(ATTR_KEY_EXTMAP_ALLOW_MIXED).is_some()),
                                 ..Default::default()
                             });
                             matched.insert(mid_value.to_string());
+                        } else if transceivers.contains_key(mid_value) {
+                            return Err(Error::Other(format!(
+                                "ErrPeerConnTransceiverKindMismatch: mid {} is not {}, whose first mid is {:?}",
+                                mid_value,
+                                kind,
+                                self.mid_for_endpoint_and_kind(endpoint_id, kind)
+                            )));
                         } else {
                             return Err(Error::Other("ErrPeerConnTransceiverMidNil".to_string()));
                         }
@@ -462,6 +1154,24 @@ impl Session {
                 }
             }
 
+            // JSEP requires answer media sections to map 1:1 in order to the offered ones,
+            // <https://datatracker.ietf.org/doc/html/rfc8829#section-5.3.1>
+            if !include_unmatched
+                && !media_sections
+                    .iter()
+                    .map(|media_section| media_section.mid.as_str())
+                    .eq(offered_mids.iter().copied())
+            {
+                return Err(Error::Other(format!(
+                    "ErrAnswerMediaSectionsMismatch: offered {:?}, answered {:?}",
+                    offered_mids,
+                    media_sections
+                        .iter()
+                        .map(|media_section| media_section.mid.as_str())
+                        .collect::<Vec<_>>()
+                )));
+            }
+
             // If we are offering also include unmatched local transceivers
             if include_unmatched {
                 for mid in mids.iter() {
@@ -474,8 +1184,20 @@ impl Session {
                 }
 
                 if !already_have_application_media_section {
+                    let data_mid = if let Some(data_mid) = self
+                        .get_endpoint(&endpoint_id)
+                        .and_then(|endpoint| endpoint.data_mid())
+                    {
+                        data_mid.clone()
+                    } else {
+                        let mut mid_allocator = MidAllocator::new();
+                        for media_section in &media_sections {
+                            mid_allocator.reserve(&media_section.mid);
+                        }
+                        mid_allocator.allocate()
+                    };
                     media_sections.push(MediaSection {
-                        mid: format!("{}", media_sections.len()),
+                        mid: data_mid,
                         data: true,
                         ..Default::default()
                     });
@@ -0,0 +1,61 @@
+use crate::description::RTCSessionDescription;
+use crate::types::EndpointId;
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// SdpLogEntry is an offer or answer exchanged with an endpoint of a session
+#[derive(Debug, Clone)]
+pub struct SdpLogEntry {
+    /// wall-clock time when the description was generated or received
+    pub timestamp: SystemTime,
+    /// endpoint the description was exchanged with
+    pub endpoint_id: EndpointId,
+    /// whether the description was generated by the SFU or received from the endpoint
+    pub local: bool,
+    /// the offer or answer itself
+    pub description: RTCSessionDescription,
+}
+
+/// SdpLog keeps the latest offers and answers of a session in a ring buffer for debugging
+#[derive(Debug)]
+pub(crate) struct SdpLog {
+    capacity: usize,
+    entries: VecDeque<SdpLogEntry>,
+}
+
+impl SdpLog {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        endpoint_id: EndpointId,
+        local: bool,
+        description: &RTCSessionDescription,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(SdpLogEntry {
+            timestamp: SystemTime::now(),
+            endpoint_id,
+            local,
+            description: RTCSessionDescription {
+                sdp_type: description.sdp_type,
+                sdp: description.sdp.clone(),
+                parsed: None,
+            },
+        });
+    }
+
+    pub(crate) fn entries(&self) -> Vec<SdpLogEntry> {
+        self.entries.iter().cloned().collect()
+    }
+}
@@ -12,11 +12,18 @@ pub struct FourTuple {
     pub peer_addr: SocketAddr,
 }
 
+/// FourTuple from TransportContext has its addresses in canonical form, i.e., IPv4-mapped IPv6
+/// addresses of dual-stack sockets, e.g., `[::ffff:192.0.2.1]:3478`, are converted to IPv4 ones,
+/// so that they match IPv4 candidates
 impl From<&TransportContext> for FourTuple {
     fn from(value: &TransportContext) -> Self {
         Self {
-            local_addr: value.local_addr,
-            peer_addr: value.peer_addr,
+            local_addr: canonical_addr(value.local_addr),
+            peer_addr: canonical_addr(value.peer_addr),
         }
     }
 }
+
+fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
@@ -0,0 +1,825 @@
+use anyhow::Result;
+use bytes::BytesMut;
+use datachannel::message::message_channel_open::{ChannelType, DataChannelOpen};
+use datachannel::message::message_type::MessageType;
+use datachannel::message::Message as DataChannelMessage;
+use dtls::endpoint::EndpointEvent;
+use dtls::extension::extension_use_srtp::SrtpProtectionProfile;
+use opentelemetry::metrics::{Meter, MeterProvider};
+use opentelemetry_sdk::metrics::data::{Gauge, ResourceMetrics, Temporality};
+use opentelemetry_sdk::metrics::reader::{AggregationSelector, MetricReader, TemporalitySelector};
+use opentelemetry_sdk::metrics::{
+    Aggregation, InstrumentKind, ManualReader, Pipeline as MetricsPipeline, SdkMeterProvider,
+};
+use opentelemetry_sdk::Resource;
+use retty::channel::{InboundPipeline, Pipeline};
+use retty::transport::{TaggedBytesMut, TransportContext};
+use sctp::PayloadProtocolIdentifier;
+use sfu::{
+    DTLSMessageEvent, DataChannelHandler, DemuxerHandler, DtlsHandler, ExceptionHandler,
+    GatewayHandler, InterceptorHandler, MessageEvent, RTCCertificate, RTCSessionDescription,
+    RTPMessageEvent, SctpHandler, ServerConfig, ServerStates, SrtpHandler, StunHandler,
+    TaggedMessageEvent,
+};
+use shared::marshal::{Marshal, Unmarshal};
+use srtp::protection_profile::ProtectionProfile;
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use stun::attributes::{
+    AttrType, ATTR_ICE_CONTROLLING, ATTR_PRIORITY, ATTR_USERNAME, ATTR_USE_CANDIDATE,
+};
+use stun::fingerprint::FINGERPRINT;
+use stun::integrity::MessageIntegrity;
+use stun::message::{Message, Setter, TransactionId, BINDING_REQUEST, BINDING_SUCCESS};
+use stun::textattrs::TextAttribute;
+
+const DATA_CHANNEL_OFFER: &str = "v=0\r
+o=- 4215775240449105457 2 IN IP4 127.0.0.1\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0\r
+m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=fingerprint:sha-256 0F:74:31:25:CB:A2:13:EC:28:6F:6D:2C:61:FF:5D:C2:BC:B9:DB:3D:98:14:8D:1A:BB:EA:33:0C:A4:60:A8:8E\r
+a=setup:actpass\r
+a=mid:0\r
+a=sctp-port:5000\r
+";
+
+/// new_loopback_server_config creates ServerConfig with DTLS certificates and SRTP profiles
+pub fn new_loopback_server_config() -> Result<ServerConfig> {
+    let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+    let certificates = vec![RTCCertificate::from_key_pair(key_pair)?];
+    let dtls_handshake_config = Arc::new(
+        dtls::config::ConfigBuilder::default()
+            .with_certificates(
+                certificates
+                    .iter()
+                    .map(|c| c.dtls_certificate.clone())
+                    .collect(),
+            )
+            .with_srtp_protection_profiles(vec![SrtpProtectionProfile::Srtp_Aes128_Cm_Hmac_Sha1_80])
+            .with_extended_master_secret(dtls::config::ExtendedMasterSecretType::Require)
+            .build(false, None)?,
+    );
+    Ok(ServerConfig::new(certificates).with_dtls_handshake_config(dtls_handshake_config))
+}
+
+/// LoopbackTransport feeds datagrams directly into the SFU pipeline and captures its outputs,
+/// so that the full stack can be exercised in-memory without sockets
+pub struct LoopbackTransport {
+    server_states: Rc<RefCell<ServerStates>>,
+    local_addr: SocketAddr,
+    pipeline: Rc<Pipeline<TaggedBytesMut, TaggedBytesMut>>,
+    outputs: Vec<TaggedBytesMut>,
+}
+
+impl LoopbackTransport {
+    pub fn new(server_config: ServerConfig, local_addr: SocketAddr) -> Result<Self> {
+        Self::with_meter(
+            server_config,
+            local_addr,
+            opentelemetry::global::meter(format!("{}", local_addr)),
+        )
+    }
+
+    /// create a transport whose SFU records its metrics with the given meter
+    pub fn with_meter(
+        server_config: ServerConfig,
+        local_addr: SocketAddr,
+        meter: Meter,
+    ) -> Result<Self> {
+        let server_states = ServerStates::new(Arc::new(server_config), local_addr, meter)?;
+        let server_states = Rc::new(RefCell::new(server_states));
+
+        let pipeline: Pipeline<TaggedBytesMut, TaggedBytesMut> = Pipeline::new();
+        pipeline.add_back(DemuxerHandler::with_server_states(Rc::clone(
+            &server_states,
+        )));
+        pipeline.add_back(StunHandler::new());
+        pipeline.add_back(DtlsHandler::new(local_addr, Rc::clone(&server_states)));
+        pipeline.add_back(SctpHandler::new(local_addr, Rc::clone(&server_states)));
+        pipeline.add_back(DataChannelHandler::new());
+        pipeline.add_back(SrtpHandler::new(Rc::clone(&server_states)));
+        pipeline.add_back(InterceptorHandler::new(Rc::clone(&server_states)));
+        pipeline.add_back(GatewayHandler::new(Rc::clone(&server_states)));
+        pipeline.add_back(ExceptionHandler::new());
+        let pipeline = pipeline.finalize();
+        pipeline.transport_active();
+
+        Ok(Self {
+            server_states,
+            local_addr,
+            pipeline,
+            outputs: vec![],
+        })
+    }
+
+    pub fn server_states(&self) -> &Rc<RefCell<ServerStates>> {
+        &self.server_states
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// send a datagram from peer_addr into the pipeline, drive its timers, and capture its outputs
+    pub fn send(&mut self, now: Instant, peer_addr: SocketAddr, message: BytesMut) {
+        self.pipeline.read(TaggedBytesMut {
+            now,
+            transport: TransportContext {
+                local_addr: self.local_addr,
+                peer_addr,
+                ecn: None,
+            },
+            message,
+        });
+        self.pipeline.handle_timeout(Instant::now());
+        while let Some(transmit) = self.pipeline.poll_transmit() {
+            self.outputs.push(transmit);
+        }
+    }
+
+    /// drive timers of the pipeline at now, and capture its outputs
+    pub fn handle_timeout(&mut self, now: Instant) {
+        self.pipeline.handle_timeout(now);
+        while let Some(transmit) = self.pipeline.poll_transmit() {
+            self.outputs.push(transmit);
+        }
+    }
+
+    /// close the SFU, and capture the messages it returns as a run loop would send them out
+    pub fn close(&mut self) {
+        let messages = self.server_states.borrow_mut().close();
+        self.capture(messages);
+    }
+
+    /// capture encrypted messages returned by ServerStates APIs as a run loop would send them out
+    pub fn capture(&mut self, messages: Vec<TaggedMessageEvent>) {
+        for message in messages {
+            if let MessageEvent::Dtls(DTLSMessageEvent::Raw(raw))
+            | MessageEvent::Rtp(RTPMessageEvent::Raw(raw)) = message.message
+            {
+                self.outputs.push(TaggedBytesMut {
+                    now: message.now,
+                    transport: message.transport,
+                    message: raw,
+                });
+            }
+        }
+    }
+
+    /// recv captured datagrams sent to peer_addr
+    pub fn recv(&mut self, peer_addr: SocketAddr) -> Vec<BytesMut> {
+        let mut messages = vec![];
+        self.outputs.retain(|output| {
+            if output.transport.peer_addr == peer_addr {
+                messages.push(output.message.clone());
+                false
+            } else {
+                true
+            }
+        });
+        messages
+    }
+
+    /// recv captured datagrams sent to any peer, with their peer addresses
+    pub fn recv_all(&mut self) -> Vec<(SocketAddr, BytesMut)> {
+        self.outputs
+            .drain(..)
+            .map(|output| (output.transport.peer_addr, output.message))
+            .collect()
+    }
+}
+
+/// LoopbackMetrics collects the metrics recorded with its meter on demand
+pub struct LoopbackMetrics {
+    provider: SdkMeterProvider,
+    reader: SharedManualReader,
+}
+
+impl LoopbackMetrics {
+    pub fn new() -> Self {
+        let reader = SharedManualReader(Arc::new(ManualReader::builder().build()));
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader.clone())
+            .build();
+        Self { provider, reader }
+    }
+
+    pub fn meter(&self) -> Meter {
+        self.provider.meter("loopback")
+    }
+
+    /// the last value observed by the u64 gauge with the given name
+    pub fn gauge(&self, name: &str) -> Result<Option<u64>> {
+        let mut resource_metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: vec![],
+        };
+        self.reader.collect(&mut resource_metrics)?;
+        Ok(resource_metrics
+            .scope_metrics
+            .iter()
+            .flat_map(|scope_metrics| scope_metrics.metrics.iter())
+            .filter(|metric| metric.name == name)
+            .filter_map(|metric| metric.data.as_any().downcast_ref::<Gauge<u64>>())
+            .flat_map(|gauge| gauge.data_points.iter())
+            .map(|data_point| data_point.value)
+            .next_back())
+    }
+}
+
+impl Default for LoopbackMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// ManualReader shared by the meter provider, which owns its readers, and LoopbackMetrics
+#[derive(Debug, Clone)]
+struct SharedManualReader(Arc<ManualReader>);
+
+impl TemporalitySelector for SharedManualReader {
+    fn temporality(&self, kind: InstrumentKind) -> Temporality {
+        self.0.temporality(kind)
+    }
+}
+
+impl AggregationSelector for SharedManualReader {
+    fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+        self.0.aggregation(kind)
+    }
+}
+
+impl MetricReader for SharedManualReader {
+    fn register_pipeline(&self, pipeline: Weak<MetricsPipeline>) {
+        self.0.register_pipeline(pipeline)
+    }
+
+    fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+        self.0.collect(rm)
+    }
+
+    fn force_flush(&self) -> opentelemetry::metrics::Result<()> {
+        self.0.force_flush()
+    }
+
+    fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+        self.0.shutdown()
+    }
+}
+
+/// LoopbackPeer is a minimal Sans-IO WebRTC client with ICE-lite STUN, DTLS, SRTP and
+/// a single data channel over SCTP
+pub struct LoopbackPeer {
+    addr: SocketAddr,
+    ufrag: String,
+    pwd: String,
+    remote_ufrag: String,
+    remote_pwd: String,
+    dtls_endpoint: dtls::endpoint::Endpoint,
+    local_srtp_context: Option<srtp::context::Context>,
+    remote_srtp_context: Option<srtp::context::Context>,
+    sctp_endpoint: sctp::Endpoint,
+    sctp_association: Option<(sctp::AssociationHandle, sctp::Association)>,
+    data_channel_messages: Vec<(PayloadProtocolIdentifier, BytesMut)>,
+}
+
+impl LoopbackPeer {
+    pub fn new(addr: SocketAddr, ufrag: &str, pwd: &str) -> Self {
+        Self {
+            addr,
+            ufrag: ufrag.to_string(),
+            pwd: pwd.to_string(),
+            remote_ufrag: String::new(),
+            remote_pwd: String::new(),
+            dtls_endpoint: dtls::endpoint::Endpoint::new(None),
+            local_srtp_context: None,
+            remote_srtp_context: None,
+            sctp_endpoint: sctp::Endpoint::new(Arc::new(sctp::EndpointConfig::default()), None),
+            sctp_association: None,
+            data_channel_messages: vec![],
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// whether the SCTP association has been closed, e.g., aborted by the SFU
+    pub fn is_sctp_association_closed(&self) -> bool {
+        self.sctp_association
+            .as_ref()
+            .is_some_and(|(_, association)| association.is_closed())
+    }
+
+    /// offer a data channel only session description with this peer's ICE credentials
+    pub fn offer(&self) -> Result<RTCSessionDescription> {
+        Ok(RTCSessionDescription::offer(
+            DATA_CHANNEL_OFFER
+                .replace("{ufrag}", &self.ufrag)
+                .replace("{pwd}", &self.pwd),
+        )?)
+    }
+
+    /// offer a data channel and the given media sections, whose mids are bundled with
+    /// the data channel's one, with this peer's ICE credentials
+    pub fn offer_with_media(
+        &self,
+        mids: &[&str],
+        media_sections: &str,
+    ) -> Result<RTCSessionDescription> {
+        let bundle = std::iter::once("0")
+            .chain(mids.iter().copied())
+            .collect::<Vec<_>>()
+            .join(" ");
+        Ok(RTCSessionDescription::offer(
+            (DATA_CHANNEL_OFFER.to_string() + media_sections)
+                .replace("a=group:BUNDLE 0", &format!("a=group:BUNDLE {}", bundle))
+                .replace("{ufrag}", &self.ufrag)
+                .replace("{pwd}", &self.pwd),
+        )?)
+    }
+
+    /// accept the SFU's answer to learn its ICE credentials
+    pub fn accept_answer(&mut self, answer: &RTCSessionDescription) {
+        let attribute = |key: &str| -> String {
+            answer
+                .sdp
+                .lines()
+                .find_map(|line| line.strip_prefix(key))
+                .unwrap_or_default()
+                .to_string()
+        };
+        self.remote_ufrag = attribute("a=ice-ufrag:");
+        self.remote_pwd = attribute("a=ice-pwd:");
+    }
+
+    /// send a STUN binding request claiming the ICE role with the tiebreaker, i.e.,
+    /// ATTR_ICE_CONTROLLING or ATTR_ICE_CONTROLLED, and return the SFU's response
+    pub fn binding_request(
+        &self,
+        transport: &mut LoopbackTransport,
+        ice_role: AttrType,
+        tiebreaker: u64,
+    ) -> Result<Message> {
+        let mut request = Message::new();
+        request.build(&[
+            Box::new(TransactionId::new()),
+            Box::new(BINDING_REQUEST),
+            Box::new(TextAttribute::new(
+                ATTR_USERNAME,
+                format!("{}:{}", self.remote_ufrag, self.ufrag),
+            )),
+        ])?;
+        request.add(ATTR_PRIORITY, &1_853_824_767u32.to_be_bytes());
+        request.add(ice_role, &tiebreaker.to_be_bytes());
+        if ice_role == ATTR_ICE_CONTROLLING {
+            request.add(ATTR_USE_CANDIDATE, &[]);
+        }
+        MessageIntegrity::new_short_term_integrity(self.remote_pwd.clone()).add_to(&mut request)?;
+        FINGERPRINT.add_to(&mut request)?;
+
+        transport.send(Instant::now(), self.addr, BytesMut::from(&request.raw[..]));
+        let responses = transport.recv(self.addr);
+        let mut response = Message {
+            raw: responses
+                .first()
+                .ok_or(anyhow::anyhow!("no STUN binding response"))?
+                .to_vec(),
+            ..Default::default()
+        };
+        response.decode()?;
+        Ok(response)
+    }
+
+    /// connect to the SFU via STUN binding and DTLS handshake
+    pub fn connect(&mut self, transport: &mut LoopbackTransport) -> Result<()> {
+        self.handshake(transport, false).map(|_| ())
+    }
+
+    /// connect to the SFU like connect, but hold back this peer's last DTLS flight once it can
+    /// derive SRTP keys, so that its SRTP reaches the SFU before the SFU's handshake completes.
+    /// The held datagrams are returned for the caller to deliver by LoopbackTransport::send.
+    pub fn connect_holding_last_flight(
+        &mut self,
+        transport: &mut LoopbackTransport,
+    ) -> Result<Vec<BytesMut>> {
+        self.handshake(transport, true)
+    }
+
+    fn handshake(
+        &mut self,
+        transport: &mut LoopbackTransport,
+        hold_last_flight: bool,
+    ) -> Result<Vec<BytesMut>> {
+        let response =
+            self.binding_request(transport, ATTR_ICE_CONTROLLING, rand::random::<u64>())?;
+        if response.typ != BINDING_SUCCESS {
+            return Err(anyhow::anyhow!("unexpected STUN response {}", response.typ));
+        }
+        self.start_handshake(transport)?;
+
+        // pump DTLS flights until both sides complete the handshake, firing the
+        // client's retransmit timer whenever the server has nothing to say
+        let mut now = Instant::now();
+        for _ in 0..16 {
+            while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+                transport.send(transmit.now, self.addr, transmit.payload);
+            }
+            let messages = transport.recv(self.addr);
+            if messages.is_empty() {
+                let mut eto = now + Duration::from_secs(60);
+                self.dtls_endpoint
+                    .poll_timeout(transport.local_addr(), &mut eto)?;
+                now = eto;
+                self.dtls_endpoint
+                    .handle_timeout(transport.local_addr(), now)?;
+                continue;
+            }
+            for message in messages {
+                let events = self.dtls_endpoint.read(
+                    now,
+                    transport.local_addr(),
+                    Some(self.addr.ip()),
+                    None,
+                    message,
+                )?;
+                if events
+                    .iter()
+                    .any(|event| matches!(event, EndpointEvent::HandshakeComplete))
+                {
+                    self.update_srtp_contexts(transport.local_addr())?;
+                }
+            }
+            // SRTP keys can be exported once the last flight with ChangeCipherSpec is sent
+            if hold_last_flight {
+                let mut pending = vec![];
+                while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+                    pending.push(transmit.payload);
+                }
+                if pending
+                    .iter()
+                    .any(|datagram| has_change_cipher_spec(datagram))
+                {
+                    self.update_srtp_contexts(transport.local_addr())?;
+                    return Ok(pending);
+                }
+                for datagram in pending {
+                    transport.send(now, self.addr, datagram);
+                }
+            }
+            if self.local_srtp_context.is_some() {
+                while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+                    transport.send(transmit.now, self.addr, transmit.payload);
+                }
+                return Ok(vec![]);
+            }
+        }
+
+        Err(anyhow::anyhow!("DTLS handshake is not completed"))
+    }
+
+    /// send the initial ClientHello of a new DTLS handshake without a binding request before it,
+    /// e.g., as an attacker spoofing this peer's address, and return the SFU's replies
+    pub fn send_client_hello(
+        &mut self,
+        transport: &mut LoopbackTransport,
+    ) -> Result<Vec<BytesMut>> {
+        self.start_handshake(transport)?;
+        while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+            transport.send(transmit.now, self.addr, transmit.payload);
+        }
+        Ok(transport.recv(self.addr))
+    }
+
+    fn start_handshake(&mut self, transport: &LoopbackTransport) -> Result<()> {
+        let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+        let certificate = RTCCertificate::from_key_pair(key_pair)?;
+        let client_config = Arc::new(
+            dtls::config::ConfigBuilder::default()
+                .with_certificates(vec![certificate.dtls_certificate])
+                .with_srtp_protection_profiles(vec![
+                    SrtpProtectionProfile::Srtp_Aes128_Cm_Hmac_Sha1_80,
+                ])
+                .with_extended_master_secret(dtls::config::ExtendedMasterSecretType::Require)
+                .with_insecure_skip_verify(true)
+                .build(true, Some(transport.local_addr()))?,
+        );
+        self.dtls_endpoint
+            .connect(transport.local_addr(), client_config, None)?;
+        Ok(())
+    }
+
+    /// close the DTLS connection to the SFU with a close_notify alert
+    pub fn close(&mut self, transport: &mut LoopbackTransport) {
+        self.dtls_endpoint.close(transport.local_addr());
+        while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+            transport.send(transmit.now, self.addr, transmit.payload);
+        }
+    }
+
+    fn update_srtp_contexts(&mut self, remote: SocketAddr) -> Result<()> {
+        let state = self
+            .dtls_endpoint
+            .get_connection_state(remote)
+            .ok_or(anyhow::anyhow!("no DTLS connection state"))?;
+        let mut srtp_config = srtp::config::Config {
+            profile: ProtectionProfile::Aes128CmHmacSha1_80,
+            ..Default::default()
+        };
+        srtp_config.extract_session_keys_from_dtls(state, true)?;
+
+        self.local_srtp_context = Some(srtp::context::Context::new(
+            &srtp_config.keys.local_master_key,
+            &srtp_config.keys.local_master_salt,
+            srtp_config.profile,
+            None,
+            None,
+        )?);
+        self.remote_srtp_context = Some(srtp::context::Context::new(
+            &srtp_config.keys.remote_master_key,
+            &srtp_config.keys.remote_master_salt,
+            srtp_config.profile,
+            None,
+            None,
+        )?);
+        Ok(())
+    }
+
+    /// encrypt and send a RTP packet to the SFU
+    pub fn send_rtp(
+        &mut self,
+        transport: &mut LoopbackTransport,
+        packet: &rtp::packet::Packet,
+    ) -> Result<()> {
+        let context = self
+            .local_srtp_context
+            .as_mut()
+            .ok_or(anyhow::anyhow!("local srtp context is not set"))?;
+        let encrypted = context.encrypt_rtp(&packet.marshal()?)?;
+        transport.send(Instant::now(), self.addr, encrypted);
+        Ok(())
+    }
+
+    /// encrypt and send a compound RTCP packet to the SFU
+    pub fn send_rtcp(
+        &mut self,
+        transport: &mut LoopbackTransport,
+        packets: &[Box<dyn rtcp::packet::Packet>],
+    ) -> Result<()> {
+        let context = self
+            .local_srtp_context
+            .as_mut()
+            .ok_or(anyhow::anyhow!("local srtp context is not set"))?;
+        let encrypted = context.encrypt_rtcp(&rtcp::packet::marshal(packets)?)?;
+        transport.send(Instant::now(), self.addr, encrypted);
+        Ok(())
+    }
+
+    /// receive and decrypt RTP packets forwarded by the SFU
+    pub fn recv_rtp(
+        &mut self,
+        transport: &mut LoopbackTransport,
+    ) -> Result<Vec<rtp::packet::Packet>> {
+        let context = self
+            .remote_srtp_context
+            .as_mut()
+            .ok_or(anyhow::anyhow!("remote srtp context is not set"))?;
+        let mut packets = vec![];
+        for message in transport.recv(self.addr) {
+            // RTP/RTCP packets, <https://www.rfc-editor.org/rfc/rfc7983#section-7>
+            if (128..=191).contains(&message[0]) && !(192..=223).contains(&message[1]) {
+                let mut decrypted = context.decrypt_rtp(&message)?;
+                packets.push(rtp::packet::Packet::unmarshal(&mut decrypted)?);
+            }
+        }
+        Ok(packets)
+    }
+
+    /// receive and decrypt RTCP packets forwarded by the SFU
+    pub fn recv_rtcp(
+        &mut self,
+        transport: &mut LoopbackTransport,
+    ) -> Result<Vec<Box<dyn rtcp::packet::Packet>>> {
+        let context = self
+            .remote_srtp_context
+            .as_mut()
+            .ok_or(anyhow::anyhow!("remote srtp context is not set"))?;
+        let mut packets = vec![];
+        for message in transport.recv(self.addr) {
+            // RTP/RTCP packets, <https://www.rfc-editor.org/rfc/rfc7983#section-7>
+            if (128..=191).contains(&message[0]) && (192..=223).contains(&message[1]) {
+                let mut decrypted = context.decrypt_rtcp(&message)?;
+                packets.extend(rtcp::packet::unmarshal(&mut decrypted)?);
+            }
+        }
+        Ok(packets)
+    }
+
+    /// open a reliable data channel on stream 0 of a new SCTP association over the DTLS
+    /// connection, and wait for the SFU's DataChannelAck
+    pub fn open_data_channel(&mut self, transport: &mut LoopbackTransport) -> Result<()> {
+        self.sctp_association = Some(
+            self.sctp_endpoint
+                .connect(sctp::ClientConfig::default(), transport.local_addr())?,
+        );
+        self.pump_sctp(transport)?;
+        if !self
+            .sctp_association
+            .as_ref()
+            .is_some_and(|(_, association)| {
+                !association.is_handshaking() && !association.is_closed()
+            })
+        {
+            return Err(anyhow::anyhow!("SCTP association is not established"));
+        }
+
+        let open = DataChannelMessage::DataChannelOpen(DataChannelOpen {
+            channel_type: ChannelType::Reliable,
+            priority: 0,
+            reliability_parameter: 0,
+            label: b"loopback".to_vec(),
+            protocol: vec![],
+        })
+        .marshal()?;
+        self.write_sctp(transport, &open, PayloadProtocolIdentifier::Dcep)?;
+        let is_acked = self.data_channel_messages.iter().any(|(ppi, message)| {
+            *ppi == PayloadProtocolIdentifier::Dcep
+                && MessageType::unmarshal(&mut &message[..])
+                    .is_ok_and(|typ| typ == MessageType::DataChannelAck)
+        });
+        self.data_channel_messages
+            .retain(|(ppi, _)| *ppi != PayloadProtocolIdentifier::Dcep);
+        if !is_acked {
+            return Err(anyhow::anyhow!("DataChannelAck is not received"));
+        }
+        Ok(())
+    }
+
+    /// send a text message over the data channel
+    pub fn send_data_channel(
+        &mut self,
+        transport: &mut LoopbackTransport,
+        message: &[u8],
+    ) -> Result<()> {
+        self.write_sctp(transport, message, PayloadProtocolIdentifier::String)
+    }
+
+    /// receive messages sent by the SFU over the data channel
+    pub fn recv_data_channel(
+        &mut self,
+        transport: &mut LoopbackTransport,
+    ) -> Result<Vec<BytesMut>> {
+        self.pump_sctp(transport)?;
+        Ok(self
+            .data_channel_messages
+            .drain(..)
+            .map(|(_, message)| message)
+            .collect())
+    }
+
+    fn write_sctp(
+        &mut self,
+        transport: &mut LoopbackTransport,
+        message: &[u8],
+        ppi: PayloadProtocolIdentifier,
+    ) -> Result<()> {
+        let (_, association) = self
+            .sctp_association
+            .as_mut()
+            .ok_or(anyhow::anyhow!("SCTP association is not set"))?;
+        let mut stream = match association.stream(0) {
+            Ok(stream) => stream,
+            Err(_) => association.open_stream(0, ppi)?,
+        };
+        stream.write_with_ppi(message, ppi)?;
+        self.pump_sctp(transport)
+    }
+
+    /// exchange SCTP packets over DTLS with the SFU until both sides are idle, firing
+    /// the timers of both sides, e.g., delayed SACKs, whenever nothing is in flight.
+    /// Datagrams other than DTLS ones, e.g., RTP, received meanwhile are dropped.
+    fn pump_sctp(&mut self, transport: &mut LoopbackTransport) -> Result<()> {
+        let remote = transport.local_addr();
+        let mut buf = vec![0u8; 262144];
+        let mut now = Instant::now();
+        let mut idle_rounds = 0;
+        while idle_rounds < 2 {
+            let (association_handle, association) = self
+                .sctp_association
+                .as_mut()
+                .ok_or(anyhow::anyhow!("SCTP association is not set"))?;
+            while let Some(transmit) = association.poll_transmit(now) {
+                if let sctp::Payload::RawEncode(raws) = transmit.payload {
+                    for raw in raws {
+                        self.dtls_endpoint.write(remote, &raw)?;
+                    }
+                }
+            }
+            while let Some(transmit) = self.dtls_endpoint.poll_transmit() {
+                transport.send(now, self.addr, transmit.payload);
+            }
+
+            let messages = transport.recv(self.addr);
+            if messages.is_empty() {
+                idle_rounds += 1;
+                // jump to the earliest timer of both sides
+                now += Duration::from_millis(250);
+                if let Some(timeout) = association.poll_timeout() {
+                    if timeout <= now {
+                        association.handle_timeout(timeout);
+                    }
+                }
+                transport.handle_timeout(now);
+                continue;
+            }
+            idle_rounds = 0;
+            for message in messages {
+                if !(20..=63).contains(&message[0]) {
+                    continue;
+                }
+                let events =
+                    self.dtls_endpoint
+                        .read(now, remote, Some(self.addr.ip()), None, message)?;
+                for event in events {
+                    if let EndpointEvent::ApplicationData(data) = event {
+                        if let Some((_, sctp::DatagramEvent::AssociationEvent(event))) = self
+                            .sctp_endpoint
+                            .handle(now, remote, Some(self.addr.ip()), None, data.freeze())
+                        {
+                            association.handle_event(event);
+                        }
+                    }
+                }
+            }
+            while let Some(event) = association.poll_endpoint_event() {
+                self.sctp_endpoint.handle_event(*association_handle, event);
+            }
+            while let Some(event) = association.poll() {
+                if let sctp::Event::Stream(sctp::StreamEvent::Readable { id }) = event {
+                    let mut stream = association.stream(id)?;
+                    while let Some(chunks) = stream.read_sctp()? {
+                        let n = chunks.read(&mut buf)?;
+                        self.data_channel_messages
+                            .push((chunks.ppi, BytesMut::from(&buf[..n])));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// new_loopback_peers creates n peers at consecutive addresses from 127.0.0.1:50000, each with
+/// distinct ICE credentials
+pub fn new_loopback_peers(n: usize) -> Vec<LoopbackPeer> {
+    (0..n)
+        .map(|i| {
+            let letter = char::from(b'A' + i as u8);
+            LoopbackPeer::new(
+                SocketAddr::new([127, 0, 0, 1].into(), 50000 + i as u16),
+                &format!("ufr{}", letter),
+                &format!("pwd{}", letter.to_string().repeat(21)),
+            )
+        })
+        .collect()
+}
+
+/// connect_peers creates n peers by new_loopback_peers, joins them to session 1 with endpoint
+/// ids 0..n, and connects each of them to the SFU
+pub fn connect_peers(transport: &mut LoopbackTransport, n: usize) -> Result<Vec<LoopbackPeer>> {
+    let mut peers = new_loopback_peers(n);
+    for (endpoint_id, peer) in peers.iter_mut().enumerate() {
+        let answer = transport.server_states().borrow_mut().accept_offer(
+            1,
+            endpoint_id as u64,
+            None,
+            peer.offer()?,
+        )?;
+        peer.accept_answer(&answer);
+        peer.connect(transport)?;
+    }
+    Ok(peers)
+}
+
+/// whether the datagram carries a DTLS ChangeCipherSpec record among its records
+fn has_change_cipher_spec(datagram: &[u8]) -> bool {
+    const RECORD_HEADER_SIZE: usize = 13;
+    const CONTENT_TYPE_CHANGE_CIPHER_SPEC: u8 = 20;
+    let mut offset = 0;
+    while offset + RECORD_HEADER_SIZE <= datagram.len() {
+        if datagram[offset] == CONTENT_TYPE_CHANGE_CIPHER_SPEC {
+            return true;
+        }
+        let length = u16::from_be_bytes([datagram[offset + 11], datagram[offset + 12]]) as usize;
+        offset += RECORD_HEADER_SIZE + length;
+    }
+    false
+}
@@ -1,15 +1,19 @@
 #![allow(dead_code)]
 
+pub mod loopback;
+
 use anyhow::Result;
 use hyper::{Body, Client, Method, Request};
 use log::LevelFilter::Debug;
 use log::{error, info};
+use std::future::Future;
 use std::io::Write;
 use std::sync::Arc;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::Notify;
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::media_engine::MediaEngine;
+use webrtc::api::setting_engine::SettingEngine;
 use webrtc::api::APIBuilder;
 use webrtc::data_channel::data_channel_message::DataChannelMessage;
 use webrtc::data_channel::RTCDataChannel;
@@ -28,7 +32,7 @@ use webrtc::track::track_local::track_local_static_rtp::TrackLocalStaticRTP;
 use webrtc::track::track_local::TrackLocal;
 use webrtc::track::track_remote::TrackRemote;
 
-pub const HOST: &'static str = "127.0.0.1";
+pub const HOST: &str = "127.0.0.1";
 pub const SIGNAL_PORT: u16 = 8080;
 
 fn pretty_sdp(input: &str) -> String {
@@ -38,6 +42,16 @@ fn pretty_sdp(input: &str) -> String {
 pub async fn setup_peer_connection(
     config: RTCConfiguration,
     endpoint_id: u64,
+) -> Result<Arc<RTCPeerConnection>> {
+    setup_peer_connection_with_setting_engine(config, endpoint_id, SettingEngine::default()).await
+}
+
+/// setup_peer_connection like setup_peer_connection, with setting_engine, e.g., to run over
+/// a virtual network
+pub async fn setup_peer_connection_with_setting_engine(
+    config: RTCConfiguration,
+    endpoint_id: u64,
+    setting_engine: SettingEngine,
 ) -> Result<Arc<RTCPeerConnection>> {
     let _ = env_logger::Builder::new()
         .format(|buf, record| {
@@ -77,6 +91,7 @@ pub async fn setup_peer_connection(
     let api = APIBuilder::new()
         .with_media_engine(m)
         .with_interceptor_registry(registry)
+        .with_setting_engine(setting_engine)
         .build();
 
     // Create a new RTCPeerConnection
@@ -92,7 +107,7 @@ pub async fn setup_peer_connection(
             // Use webrtc.PeerConnectionStateDisconnected if you are interested in detecting faster timeout.
             // Note that the PeerConnection may come back from PeerConnectionStateDisconnected.
             error!("Peer Connection has gone to failed exiting");
-            assert!(false);
+            panic!();
         }
 
         Box::pin(async {})
@@ -205,6 +220,27 @@ pub async fn connect(
     Arc<RTCDataChannel>,
     UnboundedReceiver<RTCSessionDescription>,
 )> {
+    connect_with_signaling(session_id, endpoint_id, peer_connection, |offer_payload| {
+        signaling(host, signal_port, session_id, endpoint_id, offer_payload)
+    })
+    .await
+}
+
+/// connect like connect, where the initial offer payload is answered by signal instead of
+/// the HTTP signaling server, e.g., by an in-process SFU
+pub async fn connect_with_signaling<F, Fut>(
+    session_id: u64,
+    endpoint_id: u64,
+    peer_connection: &Arc<RTCPeerConnection>,
+    signal: F,
+) -> Result<(
+    Arc<RTCDataChannel>,
+    UnboundedReceiver<RTCSessionDescription>,
+)>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Result<RTCSessionDescription>>,
+{
     // Create a datachannel with label 'data'
     let data_channel = peer_connection.create_data_channel("data", None).await?;
 
@@ -213,7 +249,7 @@ pub async fn connect(
     let data_channel_opened_ready_notify_rx = data_channel_opened_notify_tx.clone();
     data_channel.on_open(Box::new(move || {
         info!("DataChannel is opened");
-        data_channel_opened_notify_tx.notify_waiters();
+        data_channel_opened_notify_tx.notify_one();
         Box::pin(async {})
     }));
 
@@ -230,11 +266,11 @@ pub async fn connect(
             endpoint_id,
             pretty_sdp(&sdp_str)
         );
+        // other messages than SDP, e.g., session events of other endpoints joining, are ignored
         let sdp = match serde_json::from_str::<RTCSessionDescription>(&sdp_str) {
             Ok(sdp) => sdp,
             Err(err) => {
-                error!("deserialize sdp str failed: {}", err);
-                assert!(false);
+                info!("ignore non-SDP message: {}", err);
                 return Box::pin(async {});
             }
         };
@@ -246,8 +282,7 @@ pub async fn connect(
                 RTCSdpType::Offer => {
                     if let Err(err) = pc.set_remote_description(sdp.clone()).await {
                         error!("set_remote_description offer error {:?}", err);
-                        assert!(false);
-                        return;
+                        panic!();
                     }
 
                     // Create an answer to send to the other process
@@ -255,8 +290,7 @@ pub async fn connect(
                         Ok(a) => a,
                         Err(err) => {
                             error!("create_answer error {:?}", err);
-                            assert!(false);
-                            return;
+                            panic!();
                         }
                     };
 
@@ -264,8 +298,7 @@ pub async fn connect(
                         Ok(a) => a,
                         Err(err) => {
                             error!("serialize answer error {:?}", err);
-                            assert!(false);
-                            return;
+                            panic!();
                         }
                     };
                     info!(
@@ -278,45 +311,32 @@ pub async fn connect(
                     // Sets the LocalDescription, and starts our UDP listeners
                     if let Err(err) = pc.set_local_description(answer).await {
                         error!("create_answer error {:?}", err);
-                        assert!(false);
-                        return;
+                        panic!();
                     }
 
                     if let Err(err) = dc.send_text(answer_str).await {
                         error!("data channel send answer error {:?}", err);
-                        assert!(false);
-                        return;
+                        panic!();
                     }
                 }
                 RTCSdpType::Answer => {
                     if let Err(err) = pc.set_remote_description(sdp.clone()).await {
                         error!("set_remote_description answer error {:?}", err);
-                        assert!(false);
-                        return;
+                        panic!();
                     }
                 }
                 _ => {
                     error!("Unsupported SDP type {}", sdp.sdp_type);
-                    assert!(false);
+                    panic!();
                 }
             };
             if let Err(err) = tx.send(sdp) {
                 error!("data_channel_tx send error {}", err);
-                assert!(false);
+                panic!();
             }
         })
     }));
 
-    renegotiate(
-        host,
-        signal_port,
-        session_id,
-        endpoint_id,
-        peer_connection,
-        None,
-    )
-    .await?;
-
     let ice_ready_notify_tx = Arc::new(Notify::new());
     let ice_ready_notify_rx = ice_ready_notify_tx.clone();
 
@@ -326,12 +346,25 @@ pub async fn connect(
         move |connection_state: RTCIceConnectionState| {
             info!("Connection State has changed {connection_state}");
             if connection_state == RTCIceConnectionState::Connected {
-                ice_ready_notify_tx.notify_waiters();
+                ice_ready_notify_tx.notify_one();
             }
             Box::pin(async {})
         },
     ));
 
+    // Create an offer, and set the answer from signal
+    let offer = peer_connection.create_offer(None).await?;
+    let offer_payload = serde_json::to_string(&offer)?;
+    info!(
+        "{}/{}: offer sdp {}",
+        session_id,
+        endpoint_id,
+        pretty_sdp(&offer_payload)
+    );
+    peer_connection.set_local_description(offer).await?;
+    let answer = signal(offer_payload).await?;
+    peer_connection.set_remote_description(answer).await?;
+
     // Wait for connection established
     ice_ready_notify_rx.notified().await;
 
@@ -380,7 +413,7 @@ pub async fn on_track(
         Box::pin(async move {
             if let Err(err) = tx.send(track) {
                 error!("track_tx send error {}", err);
-                assert!(false);
+                panic!();
             }
         })
     }));
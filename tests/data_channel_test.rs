@@ -24,7 +24,7 @@ async fn test_data_channel() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("error: {}", err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -32,7 +32,7 @@ async fn test_data_channel() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("error: {}", err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -40,7 +40,7 @@ async fn test_data_channel() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("error: {}", err);
-            return Err(err.into());
+            return Err(err);
         }
     }
     Ok(())
@@ -70,7 +70,7 @@ async fn test_data_channels() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}: error {}", session_id, err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -87,7 +87,7 @@ async fn test_data_channels() -> anyhow::Result<()> {
             Ok(ok) => ok,
             Err(err) => {
                 error!("{}/{}: error {}", session_id, endpoint_id, err);
-                return Err(err.into());
+                return Err(err);
             }
         };
     }
@@ -96,7 +96,7 @@ async fn test_data_channels() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}: error {}", session_id, err);
-            return Err(err.into());
+            return Err(err);
         }
     }
     Ok(())
@@ -0,0 +1,250 @@
+use crate::common::loopback::{new_loopback_server_config, LoopbackTransport};
+use crate::common::{add_track, connect_with_signaling, on_track, renegotiate};
+use crate::common::{setup_peer_connection_with_setting_engine, HOST, SIGNAL_PORT};
+use bytes::{Bytes, BytesMut};
+use sfu::{
+    Interceptor, InterceptorBuilder, InterceptorEvent, MediaConfig, MessageEvent, RTPMessageEvent,
+    ServerStates, TaggedMessageEvent,
+};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::ice::mdns::MulticastDnsMode;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::rtp_transceiver::rtp_transceiver_direction::RTCRtpTransceiverDirection;
+use webrtc::track::track_local::TrackLocalWriter;
+use webrtc::util::vnet::net::{Net, NetConfig};
+use webrtc::util::vnet::router::{Router, RouterConfig};
+use webrtc::util::Conn;
+
+mod common;
+
+/// ReadRecorder records RTP packets read by the SFU right before GatewayHandler handles them
+struct ReadRecorder {
+    reads: Arc<Mutex<Vec<rtp::packet::Packet>>>,
+    next: Option<Box<dyn Interceptor>>,
+}
+
+impl Interceptor for ReadRecorder {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn read(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtp(packet)) = &msg.message {
+            self.reads.lock().unwrap().push(packet.clone());
+        }
+        if let Some(next) = self.next() {
+            next.read(msg)
+        } else {
+            vec![]
+        }
+    }
+}
+
+struct ReadRecorderBuilder {
+    reads: Arc<Mutex<Vec<rtp::packet::Packet>>>,
+}
+
+impl InterceptorBuilder for ReadRecorderBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(ReadRecorder {
+            reads: Arc::clone(&self.reads),
+            next: None,
+        })
+    }
+}
+
+const SFU_ADDR: &str = "10.0.0.1:3478";
+
+/// virtual_net creates a network of ip attached to router, so that RTCPeerConnections and the SFU
+/// exchange datagrams in-process without sockets
+async fn virtual_net(
+    router: &Arc<tokio::sync::Mutex<Router>>,
+    ip: &str,
+) -> anyhow::Result<Arc<Net>> {
+    let net = Arc::new(Net::new(Some(NetConfig {
+        static_ips: vec![ip.to_owned()],
+        ..Default::default()
+    })));
+    let nic = net.get_nic()?;
+    router.lock().await.add_net(Arc::clone(&nic)).await?;
+    nic.lock().await.set_router(Arc::clone(router)).await?;
+    Ok(net)
+}
+
+/// run the SFU over conn, feeding received datagrams into transport, driving its timers and
+/// sending out its outputs
+async fn run_sfu(conn: Arc<dyn Conn + Send + Sync>, transport: Rc<RefCell<LoopbackTransport>>) {
+    let mut buf = vec![0u8; 2000];
+    loop {
+        tokio::select! {
+            result = conn.recv_from(&mut buf) => {
+                let Ok((n, peer_addr)) = result else {
+                    return;
+                };
+                transport
+                    .borrow_mut()
+                    .send(Instant::now(), peer_addr, BytesMut::from(&buf[..n]));
+            }
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {
+                transport.borrow_mut().handle_timeout(Instant::now());
+            }
+        }
+        let outputs = transport.borrow_mut().recv_all();
+        for (peer_addr, message) in outputs {
+            let _ = conn.send_to(&message, peer_addr).await;
+        }
+    }
+}
+
+/// signal the offer payload of endpoint_id to the in-process SFU, and return its answer
+async fn signal(
+    server_states: Rc<RefCell<ServerStates>>,
+    endpoint_id: u64,
+    offer_payload: String,
+) -> anyhow::Result<RTCSessionDescription> {
+    let offer = serde_json::from_str::<sfu::RTCSessionDescription>(&offer_payload)?;
+    let answer = server_states
+        .borrow_mut()
+        .accept_offer(1, endpoint_id, None, offer)?;
+    assert!(answer.sdp.contains("a=setup:passive"), "{}", answer.sdp);
+    Ok(serde_json::from_str(&serde_json::to_string(&answer)?)?)
+}
+
+/// ICE, DTLS, SCTP, signaling over data channel and SRTP of a publisher and a subscriber,
+/// both RTCPeerConnections connected to the SFU in-process over a virtual network
+#[tokio::test]
+async fn test_integration_full_flow() -> anyhow::Result<()> {
+    let local = tokio::task::LocalSet::new();
+    tokio::time::timeout(Duration::from_secs(30), local.run_until(full_flow())).await?
+}
+
+async fn full_flow() -> anyhow::Result<()> {
+    let router = Arc::new(tokio::sync::Mutex::new(Router::new(RouterConfig {
+        cidr: "10.0.0.0/24".to_owned(),
+        ..Default::default()
+    })?));
+    let sfu_net = virtual_net(&router, "10.0.0.1").await?;
+    let peer_nets = [
+        virtual_net(&router, "10.0.0.2").await?,
+        virtual_net(&router, "10.0.0.3").await?,
+    ];
+    router.lock().await.start().await?;
+
+    let reads = Arc::new(Mutex::new(vec![]));
+    let mut media_config = MediaConfig::default();
+    media_config.register_interceptor(Box::new(ReadRecorderBuilder {
+        reads: Arc::clone(&reads),
+    }));
+    let sfu_addr: SocketAddr = SFU_ADDR.parse()?;
+    let transport = Rc::new(RefCell::new(LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?));
+    let server_states = Rc::clone(transport.borrow().server_states());
+    let sfu = tokio::task::spawn_local(run_sfu(
+        sfu_net.bind(sfu_addr).await?,
+        Rc::clone(&transport),
+    ));
+
+    // initial offer/answer by in-process signaling, then ICE, DTLS and data channel
+    let mut peer_connections = vec![];
+    let mut data_channels = vec![];
+    for (endpoint_id, net) in peer_nets.into_iter().enumerate() {
+        let mut setting_engine = SettingEngine::default();
+        setting_engine.set_vnet(Some(net));
+        setting_engine.set_ice_multicast_dns_mode(MulticastDnsMode::Disabled);
+        let peer_connection = setup_peer_connection_with_setting_engine(
+            RTCConfiguration::default(),
+            endpoint_id as u64,
+            setting_engine,
+        )
+        .await?;
+        let server_states = Rc::clone(&server_states);
+        let data_channel =
+            connect_with_signaling(1, endpoint_id as u64, &peer_connection, |offer_payload| {
+                signal(server_states, endpoint_id as u64, offer_payload)
+            })
+            .await?;
+        peer_connections.push(peer_connection);
+        data_channels.push(data_channel);
+    }
+    let mut track_rx = on_track(&peer_connections[1]).await?;
+
+    // publisher adds an audio track by renegotiation over its data channel
+    let (_, track) = add_track(
+        &peer_connections[0],
+        "audio/opus",
+        "audio",
+        RTCRtpTransceiverDirection::Sendonly,
+    )
+    .await?;
+    renegotiate(
+        HOST,
+        SIGNAL_PORT,
+        1,
+        0,
+        &peer_connections[0],
+        Some(&data_channels[0].0),
+    )
+    .await?;
+    let answer = data_channels[0].1.recv().await.unwrap();
+    assert_eq!(answer.sdp_type, RTCSdpType::Answer);
+    assert!(answer.sdp.contains("a=recvonly\r\n"), "{}", answer.sdp);
+
+    // subscriber is offered the publisher's track, and answers it
+    let offer = data_channels[1].1.recv().await.unwrap();
+    assert_eq!(offer.sdp_type, RTCSdpType::Offer);
+    assert!(offer.sdp.contains("a=mid:0-"), "{}", offer.sdp);
+
+    // publisher's SRTP is decrypted by the SFU and forwarded to the subscriber
+    let payload = Bytes::from_static(b"full flow");
+    let writer = {
+        let payload = payload.clone();
+        tokio::spawn(async move {
+            for sequence_number in 1.. {
+                let packet = webrtc::rtp::packet::Packet {
+                    header: webrtc::rtp::header::Header {
+                        version: 2,
+                        sequence_number,
+                        timestamp: sequence_number as u32 * 960,
+                        ..Default::default()
+                    },
+                    payload: payload.clone(),
+                };
+                if track.write_rtp(&packet).await.is_err() {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+    };
+    let remote_track = track_rx.recv().await.unwrap();
+    let (packet, _) = remote_track.read_rtp().await?;
+    assert_eq!(packet.payload, payload);
+    writer.abort();
+    {
+        let reads = reads.lock().unwrap();
+        assert!(!reads.is_empty());
+        assert_eq!(reads[0].payload, payload);
+    }
+
+    for peer_connection in peer_connections {
+        peer_connection.close().await?;
+    }
+    sfu.abort();
+    router.lock().await.stop().await?;
+
+    Ok(())
+}
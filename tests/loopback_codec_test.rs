@@ -0,0 +1,352 @@
+use crate::common::loopback::{new_loopback_server_config, LoopbackPeer, LoopbackTransport};
+use sfu::{FourTuple, MediaConfig};
+use std::net::SocketAddr;
+
+mod common;
+
+#[test]
+fn test_loopback_negotiated_codec() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    // renegotiate with an audio section offering opus and PCMU
+    let offer = peer.offer_with_media(
+        &["1"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 111 0\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=fmtp:111 minptime=10;useinbandfec=1\r
+a=rtpmap:0 PCMU/8000\r
+",
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        offer,
+    )?;
+    // only codecs supported by both the peer and SFU are answered
+    let audio_line = answer
+        .sdp
+        .lines()
+        .find(|line| line.starts_with("m=audio"))
+        .expect("no audio section in answer");
+    assert!(
+        audio_line.ends_with("UDP/TLS/RTP/SAVPF 111 0"),
+        "{}",
+        audio_line
+    );
+
+    let codec = transport
+        .server_states()
+        .borrow()
+        .get_negotiated_codec(1, 1, "1")
+        .expect("no negotiated codec for mid 1");
+    assert_eq!(codec.capability.mime_type.to_lowercase(), "audio/opus");
+    assert_eq!(codec.payload_type, 111);
+    assert!(transport
+        .server_states()
+        .borrow()
+        .get_negotiated_codec(1, 1, "2")
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_answer_media_section_order() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    // renegotiate with media sections whose mids are not in ascending order
+    let section = |media: &str, mid: &str, rtpmap: &str| {
+        let payload_type = rtpmap.split(' ').next().unwrap_or_default();
+        format!(
+            "m={media} 9 UDP/TLS/RTP/SAVPF {payload_type}\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:{rtpmap}\r
+"
+        )
+    };
+    let offer = peer.offer_with_media(
+        &["3", "1", "2"],
+        &[
+            section("video", "3", "96 VP8/90000"),
+            section("audio", "1", "111 opus/48000/2"),
+            section("video", "2", "96 VP8/90000"),
+        ]
+        .concat(),
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        offer,
+    )?;
+    let answered_mids: Vec<&str> = answer
+        .sdp
+        .lines()
+        .filter_map(|line| line.strip_prefix("a=mid:"))
+        .collect();
+    assert_eq!(answered_mids, vec!["0", "3", "1", "2"]);
+    let answered_media: Vec<&str> = answer
+        .sdp
+        .lines()
+        .filter_map(|line| line.strip_prefix("m="))
+        .filter_map(|line| line.split(' ').next())
+        .collect();
+    assert_eq!(
+        answered_media,
+        vec!["application", "video", "audio", "video"]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_sdp_fmtp_line_for_codec() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default();
+    media_config.configure_opus(Some(32000), true)?;
+    media_config.configure_h264_max_level(0x1f);
+    assert!(media_config.configure_opus(Some(1000), true).is_err());
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    // renegotiate with opus enabling dtx, and H264 of level 5.0
+    let offer = peer.offer_with_media(
+        &["1", "2"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=fmtp:111 minptime=10;useinbandfec=1;usedtx=1\r
+m=video 9 UDP/TLS/RTP/SAVPF 102\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:2\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:102 H264/90000\r
+a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e032\r
+",
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        offer,
+    )?;
+    assert!(
+        answer
+            .sdp
+            .contains("a=fmtp:111 minptime=10;useinbandfec=1;maxaveragebitrate=32000;usedtx=1"),
+        "{}",
+        answer.sdp
+    );
+    assert!(
+        answer.sdp.contains("profile-level-id=42e01f"),
+        "{}",
+        answer.sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_default_video_config() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(MediaConfig::default_video_config()),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    let offer = peer.offer_with_media(
+        &["1"],
+        "m=video 9 UDP/TLS/RTP/SAVPF 97 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:97 H264/90000\r
+a=fmtp:97 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f\r
+a=rtpmap:96 VP8/90000\r
+",
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        offer,
+    )?;
+    let video_line = answer
+        .sdp
+        .lines()
+        .find(|line| line.starts_with("m=video"))
+        .expect("no video section in answer");
+    assert!(
+        video_line.ends_with("UDP/TLS/RTP/SAVPF 96 97"),
+        "{}",
+        video_line
+    );
+    assert!(
+        answer.sdp.contains("a=rtcp-fb:97 nack pli"),
+        "{}",
+        answer.sdp
+    );
+    assert!(
+        answer.sdp.contains("a=rtcp-fb:96 goog-remb"),
+        "{}",
+        answer.sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_default_audio_config() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(MediaConfig::default_audio_config()),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    let offer = peer.offer_with_media(
+        &["1"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 0 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:0 PCMU/8000\r
+a=rtpmap:111 opus/48000/2\r
+a=fmtp:111 minptime=10;useinbandfec=1\r
+",
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        offer,
+    )?;
+    // PCMU is not part of the audio preset, so only opus is answered
+    let audio_line = answer
+        .sdp
+        .lines()
+        .find(|line| line.starts_with("m=audio"))
+        .expect("no audio section in answer");
+    assert!(
+        audio_line.ends_with("UDP/TLS/RTP/SAVPF 111"),
+        "{}",
+        audio_line
+    );
+    assert!(
+        answer.sdp.contains("a=rtpmap:111 opus/48000/2"),
+        "{}",
+        answer.sdp
+    );
+
+    Ok(())
+}
@@ -0,0 +1,435 @@
+use crate::common::loopback::{
+    connect_peers, new_loopback_server_config, LoopbackMetrics, LoopbackPeer, LoopbackTransport,
+};
+use bytes::BytesMut;
+use sfu::{FourTuple, RTCSdpType, RTCSessionDescription, SessionEvent};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[test]
+fn test_loopback_broadcast_to_session() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let _peers = connect_peers(&mut transport, 2)?;
+
+    // connected endpoints without an open data channel are skipped
+    assert!(transport
+        .server_states()
+        .borrow()
+        .broadcast_to_session(1, BytesMut::from("hello"))?
+        .is_empty());
+    assert!(transport
+        .server_states()
+        .borrow()
+        .broadcast_to_session(2, BytesMut::from("hello"))
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_large_data_channel_message() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+    peer.open_data_channel(&mut transport)?;
+
+    // an offer of many media sections and its answer span many SCTP DATA chunks in both
+    // directions
+    let mids: Vec<String> = (1..=128).map(|mid| mid.to_string()).collect();
+    let media_sections: String = mids
+        .iter()
+        .map(|mid| {
+            format!(
+                "m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+"
+            )
+        })
+        .collect();
+    let offer = peer.offer_with_media(
+        &mids.iter().map(String::as_str).collect::<Vec<_>>(),
+        &media_sections,
+    )?;
+    let message = serde_json::to_string(&offer)?;
+    assert!(message.len() > 16 * 1024);
+    peer.send_data_channel(&mut transport, message.as_bytes())?;
+    let received = peer.recv_data_channel(&mut transport)?;
+    assert_eq!(received.len(), 1);
+    assert!(received[0].len() > 16 * 1024);
+    let answer = serde_json::from_slice::<RTCSessionDescription>(&received[0])?;
+    assert_eq!(answer.sdp_type, RTCSdpType::Answer);
+    assert_eq!(
+        answer
+            .sdp
+            .lines()
+            .filter(|line| line.starts_with("m=audio"))
+            .count(),
+        mids.len()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_sctp_heartbeat_timeout() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?
+            .with_sctp_association_heartbeat_timeout(Duration::from_secs(5)),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+    peer.open_data_channel(&mut transport)?;
+
+    // an offer over the data channel is answered over it
+    let message = serde_json::to_string(&peer.offer()?)?;
+    peer.send_data_channel(&mut transport, message.as_bytes())?;
+    assert_eq!(peer.recv_data_channel(&mut transport)?.len(), 1);
+
+    // without any chunk from the peer within the heartbeat timeout, the association is failed
+    // and closed, while the endpoint stays connected
+    transport.handle_timeout(Instant::now() + Duration::from_secs(10));
+    peer.send_data_channel(&mut transport, message.as_bytes())?;
+    assert!(peer.recv_data_channel(&mut transport)?.is_empty());
+    assert!(transport
+        .server_states()
+        .borrow()
+        .get_remote_candidates(1, 1)
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_sctp_idle_association_abort() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let metrics = LoopbackMetrics::new();
+    let mut transport = LoopbackTransport::with_meter(
+        new_loopback_server_config()?.with_sctp_association_idle_timeout(Duration::from_secs(5)),
+        sfu_addr,
+        metrics.meter(),
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+    peer.open_data_channel(&mut transport)?;
+    assert_eq!(metrics.gauge("sctp_associations_active")?, Some(1));
+    assert!(!peer.is_sctp_association_closed());
+
+    // the idle association is freed, and its peer is aborted instead of left retransmitting
+    transport.handle_timeout(Instant::now() + Duration::from_secs(10));
+    assert_eq!(metrics.gauge("sctp_associations_active")?, Some(0));
+    assert!(peer.recv_data_channel(&mut transport)?.is_empty());
+    assert!(peer.is_sctp_association_closed());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_renegotiation_debounce() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_renegotiation_debounce(Duration::from_secs(5)),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+    for peer in peers.iter_mut() {
+        peer.open_data_channel(&mut transport)?;
+    }
+
+    let section = |media: &str, mid: &str, ssrc: u32| {
+        format!(
+            "m={media} 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:96 {codec}\r
+a=msid:stream {media}\r
+a=ssrc:{ssrc} cname:publisher\r
+",
+            codec = if media == "audio" {
+                "opus/48000/2"
+            } else {
+                "VP8/90000"
+            }
+        )
+    };
+    let sdp_types = |messages: Vec<BytesMut>| -> Vec<String> {
+        messages
+            .iter()
+            .filter_map(|message| serde_json::from_slice::<serde_json::Value>(message).ok())
+            .filter_map(|value| value.get("type")?.as_str().map(str::to_string))
+            .collect()
+    };
+
+    // publisher adds audio, then video, within the debounce window
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let offers = [
+        publisher[0].offer_with_media(&["1"], &section("audio", "1", 1234))?,
+        publisher[0].offer_with_media(
+            &["1", "2"],
+            &(section("audio", "1", 1234) + &section("video", "2", 5678)),
+        )?,
+    ];
+    for offer in offers {
+        publisher[0]
+            .send_data_channel(&mut transport, serde_json::to_string(&offer)?.as_bytes())?;
+        assert!(sdp_types(publisher[0].recv_data_channel(&mut transport)?)
+            .contains(&"answer".to_string()));
+    }
+    assert!(
+        !sdp_types(subscriber[0].recv_data_channel(&mut transport)?).contains(&"offer".to_string())
+    );
+
+    // both tracks are batched into one offer once the debounce window passes
+    transport.handle_timeout(Instant::now() + Duration::from_secs(6));
+    let offers: Vec<RTCSessionDescription> = subscriber[0]
+        .recv_data_channel(&mut transport)?
+        .iter()
+        .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+        .collect();
+    assert_eq!(offers.len(), 1);
+    assert!(offers[0].sdp.contains("a=mid:0-1\r\n"), "{}", offers[0].sdp);
+    assert!(offers[0].sdp.contains("a=mid:0-2\r\n"), "{}", offers[0].sdp);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_renegotiation_throttling() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?
+            .with_min_renegotiation_interval(Duration::from_secs(1))
+            .with_sdp_log_capacity(16),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+    peer.open_data_channel(&mut transport)?;
+
+    let section = |mid: &str, ssrc: u32| {
+        format!(
+            "m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=msid:stream audio{mid}\r
+a=ssrc:{ssrc} cname:publisher\r
+"
+        )
+    };
+    let answers = |peer: &mut LoopbackPeer, transport: &mut LoopbackTransport| {
+        anyhow::Ok(
+            peer.recv_data_channel(transport)?
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    // the first offer is answered right away, while the following ones within the interval
+    // are coalesced into the latest one
+    let offers = [
+        peer.offer_with_media(&["1"], &section("1", 1001))?,
+        peer.offer_with_media(&["1", "2"], &(section("1", 1001) + &section("2", 1002)))?,
+        peer.offer_with_media(
+            &["1", "2", "3"],
+            &(section("1", 1001) + &section("2", 1002) + &section("3", 1003)),
+        )?,
+    ];
+    for (i, offer) in offers.iter().enumerate() {
+        peer.send_data_channel(&mut transport, serde_json::to_string(offer)?.as_bytes())?;
+        assert_eq!(
+            answers(&mut peer, &mut transport)?.len(),
+            usize::from(i == 0)
+        );
+    }
+
+    // only the latest offer is answered once the interval passes
+    transport.handle_timeout(Instant::now() + Duration::from_secs(2));
+    let latest_answers = answers(&mut peer, &mut transport)?;
+    assert_eq!(latest_answers.len(), 1);
+    assert!(
+        latest_answers[0].sdp.contains("a=mid:3\r\n"),
+        "{}",
+        latest_answers[0].sdp
+    );
+    let remote_offers = transport
+        .server_states()
+        .borrow()
+        .get_sdp_log(1)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|entry| !entry.local)
+        .map(|entry| entry.description.sdp)
+        .collect::<Vec<_>>();
+    assert_eq!(remote_offers.len(), 3);
+    assert_eq!(remote_offers[2], offers[2].sdp);
+
+    // offers posted straight to accept_offer are throttled as well, and answered over the data
+    // channel once the interval passes
+    let posted_offer = peer.offer_with_media(&["1"], &section("1", 1001))?;
+    let result = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        posted_offer,
+    );
+    assert!(
+        matches!(result, Err(shared::error::Error::ErrTryAgain)),
+        "{:?}",
+        result
+    );
+    assert!(answers(&mut peer, &mut transport)?.is_empty());
+    transport.handle_timeout(Instant::now() + Duration::from_secs(4));
+    let posted_answers = answers(&mut peer, &mut transport)?;
+    assert_eq!(posted_answers.len(), 1);
+    assert!(
+        !posted_answers[0].sdp.contains("a=mid:3\r\n"),
+        "{}",
+        posted_answers[0].sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_endpoint_metadata() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .set_endpoint_metadata(1, 2, "name".to_string(), "bob".to_string())
+        .is_err());
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_endpoint_metadata(1, 1, "name".to_string(), "alice".to_string())?;
+    let metadata = transport
+        .server_states()
+        .borrow()
+        .get_endpoint_metadata(1, 1)
+        .expect("no metadata for endpoint 1");
+    assert_eq!(metadata.get("name").map(String::as_str), Some("alice"));
+
+    // the published track is reported along with the endpoint's metadata
+    let offer = peer.offer_with_media(
+        &["1"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=ssrc:1234 cname:alice\r
+",
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        offer,
+    )?;
+    assert_eq!(
+        transport.server_states().borrow_mut().poll_session_event(),
+        Some(SessionEvent::TrackAdded {
+            session_id: 1,
+            endpoint_id: 1,
+            mid: "1".to_string(),
+            metadata,
+        })
+    );
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .poll_session_event()
+        .is_none());
+
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use crate::common::loopback::{connect_peers, new_loopback_server_config, LoopbackTransport};
+use bytes::Bytes;
+use sfu::{
+    Interceptor, InterceptorBuilder, InterceptorEvent, MediaConfig, MessageEvent, RTPMessageEvent,
+    Registry, TaggedMessageEvent,
+};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+mod common;
+
+struct PanickingInterceptor {
+    /// whether it passes the message on to the rest of the chain before panicking
+    after_next: bool,
+    next: Option<Box<dyn Interceptor>>,
+}
+
+impl Interceptor for PanickingInterceptor {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if self.after_next {
+            if let Some(next) = self.next() {
+                next.write(msg);
+            }
+        }
+        panic!("deliberately panicking interceptor");
+    }
+}
+
+struct PanickingInterceptorBuilder {
+    after_next: bool,
+}
+
+impl InterceptorBuilder for PanickingInterceptorBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(PanickingInterceptor {
+            after_next: self.after_next,
+            next: None,
+        })
+    }
+}
+
+struct CountingInterceptor {
+    writes: Arc<AtomicUsize>,
+    next: Option<Box<dyn Interceptor>>,
+}
+
+impl Interceptor for CountingInterceptor {
+    fn chain(mut self: Box<Self>, next: Box<dyn Interceptor>) -> Box<dyn Interceptor> {
+        self.next = Some(next);
+        self
+    }
+
+    fn next(&mut self) -> Option<&mut Box<dyn Interceptor>> {
+        self.next.as_mut()
+    }
+
+    fn write(&mut self, msg: &mut TaggedMessageEvent) -> Vec<InterceptorEvent> {
+        if let MessageEvent::Rtp(RTPMessageEvent::Rtp(_)) = &msg.message {
+            self.writes.fetch_add(1, Ordering::SeqCst);
+        }
+        if let Some(next) = self.next() {
+            next.write(msg)
+        } else {
+            vec![]
+        }
+    }
+}
+
+struct CountingInterceptorBuilder {
+    writes: Arc<AtomicUsize>,
+}
+
+impl InterceptorBuilder for CountingInterceptorBuilder {
+    fn build(&self, _id: &str) -> Box<dyn Interceptor> {
+        Box::new(CountingInterceptor {
+            writes: Arc::clone(&self.writes),
+            next: None,
+        })
+    }
+}
+
+#[test]
+fn test_loopback_panicking_interceptor() -> anyhow::Result<()> {
+    for after_next in [false, true] {
+        test_loopback_panicking_interceptor_with(after_next)?;
+    }
+    Ok(())
+}
+
+fn test_loopback_panicking_interceptor_with(after_next: bool) -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let writes = Arc::new(AtomicUsize::new(0));
+    let mut media_config = MediaConfig::default();
+    media_config.register_interceptor(Box::new(PanickingInterceptorBuilder { after_next }));
+    media_config.register_interceptor(Box::new(CountingInterceptorBuilder {
+        writes: Arc::clone(&writes),
+    }));
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for sequence_number in 1..=2 {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(b"loopback"),
+        };
+        publisher[0].send_rtp(&mut transport, &packet)?;
+    }
+
+    // the panicking interceptor is disabled, while the rest of the chain keeps working, and
+    // doesn't handle again the packet already passed on before the panic
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(forwarded.len(), 2);
+    assert_eq!(writes.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_update_interceptor() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    let packet = |sequence_number: u16| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"loopback"),
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    publisher[0].send_rtp(&mut transport, &packet(1))?;
+    assert_eq!(subscriber[0].recv_rtp(&mut transport)?.len(), 1);
+
+    let writes = Arc::new(AtomicUsize::new(0));
+    let mut registry = Registry::new();
+    registry.add(Box::new(CountingInterceptorBuilder {
+        writes: Arc::clone(&writes),
+    }));
+    transport
+        .server_states()
+        .borrow_mut()
+        .update_interceptor(1, 1, &registry)?;
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .update_interceptor(1, 2, &registry)
+        .is_err());
+
+    // only packets forwarded after the update go through the new chain of the subscriber
+    publisher[0].send_rtp(&mut transport, &packet(2))?;
+    assert_eq!(subscriber[0].recv_rtp(&mut transport)?.len(), 1);
+    assert_eq!(writes.load(Ordering::SeqCst), 1);
+
+    Ok(())
+}
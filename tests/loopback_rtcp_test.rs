@@ -0,0 +1,714 @@
+use crate::common::loopback::{
+    connect_peers, new_loopback_server_config, LoopbackPeer, LoopbackTransport,
+};
+use bytes::{Bytes, BytesMut};
+use rtcp::goodbye::Goodbye;
+use rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use rtcp::raw_packet::RawPacket;
+use rtcp::receiver_report::ReceiverReport;
+use rtcp::source_description::{
+    SdesType, SourceDescription, SourceDescriptionChunk, SourceDescriptionItem,
+};
+use rtcp::transport_feedbacks::transport_layer_cc::{
+    PacketStatusChunk, RunLengthChunk, StatusChunkTypeTcc, SymbolTypeTcc, TransportLayerCc,
+};
+use sdp::extmap::TRANSPORT_CC_URI;
+use sfu::{FourTuple, MediaConfig, RTCSessionDescription, SessionEvent};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+mod common;
+
+/// connect a peer publishing VP8 video of payload type 96, whose streams are reported
+fn connect_receiver_report_peer(transport: &mut LoopbackTransport) -> anyhow::Result<LoopbackPeer> {
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 0, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(transport)?;
+
+    let offer = peer.offer_with_media(
+        &["1"],
+        "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+    )?;
+    let four_tuple = FourTuple {
+        local_addr: transport.local_addr(),
+        peer_addr: peer.addr(),
+    };
+    transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 0, Some(four_tuple), offer)?;
+    Ok(peer)
+}
+
+/// send batches of RTP packets of the sequence numbers, and return total lost of the receiver
+/// report generated by the SFU after each batch
+fn receiver_report_total_lost(reorder_window: u16, batches: &[&[u16]]) -> anyhow::Result<Vec<u32>> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default();
+    media_config.configure_rtcp_reports_with_reorder_window(reorder_window);
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+    let mut peer = connect_receiver_report_peer(&mut transport)?;
+
+    let now = Instant::now();
+    let mut total_lost = vec![];
+    for (i, batch) in batches.iter().enumerate() {
+        for &sequence_number in batch.iter() {
+            peer.send_rtp(
+                &mut transport,
+                &rtp::packet::Packet {
+                    header: rtp::header::Header {
+                        version: 2,
+                        payload_type: 96,
+                        sequence_number,
+                        timestamp: sequence_number as u32 * 3000,
+                        ssrc: 1234,
+                        ..Default::default()
+                    },
+                    payload: Bytes::from_static(b"reorder"),
+                },
+            )?;
+        }
+        transport.handle_timeout(now + Duration::from_secs(i as u64 + 1));
+        let reports = peer.recv_rtcp(&mut transport)?;
+        let report = reports
+            .iter()
+            .find_map(|packet| packet.as_any().downcast_ref::<ReceiverReport>())
+            .ok_or(anyhow::anyhow!("no receiver report"))?;
+        assert_eq!(report.reports[0].ssrc, 1234);
+        total_lost.push(report.reports[0].total_lost);
+    }
+    Ok(total_lost)
+}
+
+#[test]
+fn test_loopback_receiver_report_reorder_window() -> anyhow::Result<()> {
+    // packet 3 arrives after the report, which counts it as lost without reorder window
+    let reordered: &[&[u16]] = &[&[1, 2, 4, 5], &[3, 6]];
+    assert_eq!(receiver_report_total_lost(0, reordered)?, vec![1, 1]);
+    assert_eq!(receiver_report_total_lost(3, reordered)?, vec![0, 0]);
+
+    // packet 3 beyond the reorder window is counted as lost
+    assert_eq!(
+        receiver_report_total_lost(3, &[&[1, 2, 4, 5, 6, 7, 8, 9]])?,
+        vec![1]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_receiver_report_streams() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default();
+    media_config.configure_rtcp_reports();
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+    let mut peer = connect_receiver_report_peer(&mut transport)?;
+    let reported_ssrcs = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_rtcp(transport).map(|packets| {
+            packets
+                .iter()
+                .filter_map(|packet| packet.as_any().downcast_ref::<ReceiverReport>())
+                .flat_map(|report| report.reports.iter().map(|report| report.ssrc))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // only the stream of the negotiated payload type is reported
+    for (ssrc, payload_type) in [(1234, 96), (4321, 100)] {
+        peer.send_rtp(
+            &mut transport,
+            &rtp::packet::Packet {
+                header: rtp::header::Header {
+                    version: 2,
+                    payload_type,
+                    sequence_number: 1,
+                    ssrc,
+                    ..Default::default()
+                },
+                payload: Bytes::from_static(b"report"),
+            },
+        )?;
+    }
+    let now = Instant::now();
+    transport.handle_timeout(now + Duration::from_secs(1));
+    assert_eq!(reported_ssrcs(&mut transport, &mut peer)?, vec![1234]);
+
+    // and is no longer reported after its BYE
+    peer.send_rtcp(
+        &mut transport,
+        &[Box::new(Goodbye {
+            sources: vec![1234],
+            reason: Bytes::new(),
+        })],
+    )?;
+    transport.handle_timeout(now + Duration::from_secs(2));
+    assert!(reported_ssrcs(&mut transport, &mut peer)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_compound_rtcp_split_routing() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 3)?;
+
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtcp-fb:96 nack pli\r
+a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+
+    // a subscriber's compound RR and PLI about the publisher's media
+    let compound: Vec<Box<dyn rtcp::packet::Packet>> = vec![
+        Box::new(ReceiverReport {
+            ssrc: 5678,
+            reports: vec![rtcp::reception_report::ReceptionReport {
+                ssrc: 1234,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }),
+        Box::new(PictureLossIndication {
+            sender_ssrc: 5678,
+            media_ssrc: 1234,
+        }),
+    ];
+    peers[1].send_rtcp(&mut transport, &compound)?;
+
+    // the PLI is routed to the publisher only, while the RR is terminated by the SFU
+    let packets = peers[0].recv_rtcp(&mut transport)?;
+    let plis: Vec<u32> = packets
+        .iter()
+        .filter_map(|packet| packet.as_any().downcast_ref::<PictureLossIndication>())
+        .map(|pli| pli.media_ssrc)
+        .collect();
+    assert_eq!(plis, vec![1234]);
+    assert!(packets
+        .iter()
+        .all(|packet| packet.as_any().downcast_ref::<ReceiverReport>().is_none()));
+    assert!(peers[1].recv_rtcp(&mut transport)?.is_empty());
+    assert!(peers[2].recv_rtcp(&mut transport)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_rtcp_app() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_rtcp_app_name(*b"VNDR"),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    // V=2, subtype 0, PT=APP(204), length 3 words, followed by ssrc, name and data
+    let rtcp_app = |name: &[u8; 4]| -> Box<dyn rtcp::packet::Packet> {
+        let mut raw = vec![0x80, 204, 0, 3, 0, 0, 0x04, 0xd2];
+        raw.extend_from_slice(name);
+        raw.extend_from_slice(b"ping");
+        Box::new(RawPacket(Bytes::from(raw)))
+    };
+    peer.send_rtcp(&mut transport, &[rtcp_app(b"VNDR")])?;
+    peer.send_rtcp(&mut transport, &[rtcp_app(b"XXXX")])?;
+
+    // only APP packets with the configured name are delivered to the application
+    let mut server_states = transport.server_states().borrow_mut();
+    assert_eq!(
+        server_states.poll_session_event(),
+        Some(SessionEvent::RtcpApp {
+            session_id: 1,
+            endpoint_id: 1,
+            ssrc: 1234,
+            data: BytesMut::from("ping"),
+        })
+    );
+    assert_eq!(server_states.poll_session_event(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_sdes_cname_consistency() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(MediaConfig::default_video_config()),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+    // CNAMEs are configured per session, so an unknown session is rejected
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .set_sdes_cnames(2, HashMap::from([(1234, "canonical".to_string())]))
+        .is_err());
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_sdes_cnames(1, HashMap::from([(1234, "canonical".to_string())]))?;
+
+    let section = |mid: &str, direction: &str, source: &str| {
+        format!(
+            "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a={direction}\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+{source}"
+        )
+    };
+    // publisher's section is forwarded to the subscriber's transceiver of mid 0-1
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        &section(
+            "1",
+            "sendonly",
+            "a=msid:stream track\r\na=ssrc:1234 cname:publisher\r\n",
+        ),
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+    let offer = peers[1].offer_with_media(&["0-1"], &section("0-1", "recvonly", ""))?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[1].addr(),
+        }),
+        offer,
+    )?;
+    assert!(
+        answer.sdp.contains("a=ssrc:1234 cname:canonical"),
+        "{}",
+        answer.sdp
+    );
+
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    publisher[0].send_rtcp(
+        &mut transport,
+        &[Box::new(SourceDescription {
+            chunks: vec![SourceDescriptionChunk {
+                source: 1234,
+                items: vec![SourceDescriptionItem {
+                    sdes_type: SdesType::SdesCname,
+                    text: Bytes::from_static(b"publisher"),
+                }],
+            }],
+        })],
+    )?;
+    let forwarded = subscriber[0].recv_rtcp(&mut transport)?;
+    let sdes = forwarded
+        .iter()
+        .find_map(|packet| packet.as_any().downcast_ref::<SourceDescription>())
+        .expect("no SDES forwarded");
+    assert_eq!(sdes.chunks[0].source, 1234);
+    assert_eq!(
+        sdes.chunks[0].items[0].text,
+        Bytes::from_static(b"canonical")
+    );
+
+    // once the session's CNAMEs are cleared, SDES packets are forwarded intact
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_sdes_cnames(1, HashMap::new())?;
+    publisher[0].send_rtcp(
+        &mut transport,
+        &[Box::new(SourceDescription {
+            chunks: vec![SourceDescriptionChunk {
+                source: 1234,
+                items: vec![SourceDescriptionItem {
+                    sdes_type: SdesType::SdesCname,
+                    text: Bytes::from_static(b"publisher"),
+                }],
+            }],
+        })],
+    )?;
+    let forwarded = subscriber[0].recv_rtcp(&mut transport)?;
+    let sdes = forwarded
+        .iter()
+        .find_map(|packet| packet.as_any().downcast_ref::<SourceDescription>())
+        .expect("no SDES forwarded");
+    assert_eq!(
+        sdes.chunks[0].items[0].text,
+        Bytes::from_static(b"publisher")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_disable_rtcp_feedback() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(MediaConfig::default_video_config()),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    let renegotiate = |transport: &mut LoopbackTransport| -> anyhow::Result<RTCSessionDescription> {
+        let offer = peer.offer_with_media(
+            &["1"],
+            "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtcp-fb:96 goog-remb\r
+a=rtcp-fb:96 transport-cc\r
+",
+        )?;
+        Ok(transport.server_states().borrow_mut().accept_offer(
+            1,
+            1,
+            Some(FourTuple {
+                local_addr: sfu_addr,
+                peer_addr: peer.addr(),
+            }),
+            offer,
+        )?)
+    };
+    let answer = renegotiate(&mut transport)?;
+    assert!(answer.sdp.contains("a=rtcp-fb:96 goog-remb"));
+    assert!(answer.sdp.contains("a=rtcp-fb:96 transport-cc"));
+
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_rtcp_feedback_enabled(1, "goog-remb", false)?;
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .set_rtcp_feedback_enabled(2, "goog-remb", false)
+        .is_err());
+
+    // the same offer is answered again without the disabled feedback
+    let answer = renegotiate(&mut transport)?;
+    assert!(!answer.sdp.contains("goog-remb"));
+    assert!(answer.sdp.contains("a=rtcp-fb:96 transport-cc"));
+
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_rtcp_feedback_enabled(1, "goog-remb", true)?;
+    let answer = renegotiate(&mut transport)?;
+    assert!(answer.sdp.contains("a=rtcp-fb:96 goog-remb"));
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_transport_cc() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default_video_config();
+    media_config.configure_twcc()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // publisher renegotiates two video sources with transport-cc extension
+    let offer = peers[0].offer_with_media(
+        &["1", "2"],
+        &["1", "2"]
+            .iter()
+            .map(|mid| {
+                format!(
+                    "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a=extmap:3 {TRANSPORT_CC_URI}\r
+a=sendonly\r
+a=msid:stream{mid} track{mid}\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtcp-fb:96 transport-cc\r
+a=ssrc:{mid}234 cname:publisher\r
+"
+                )
+            })
+            .collect::<String>(),
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for (sequence_number, ssrc) in [(1, 1234), (1, 2234), (2, 1234), (2, 2234)] {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                ssrc,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(b"loopback"),
+        };
+        publisher[0].send_rtp(&mut transport, &packet)?;
+    }
+
+    // transport-wide sequence numbers increment across sources of the subscriber transport
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    let transport_sequences: Vec<u16> = forwarded
+        .iter()
+        .map(|packet| {
+            let extension = packet
+                .header
+                .get_extension(3)
+                .expect("no transport-cc extension");
+            u16::from_be_bytes([extension[0], extension[1]])
+        })
+        .collect();
+    assert_eq!(transport_sequences, vec![0, 1, 2, 3]);
+
+    Ok(())
+}
+
+/// tmmbx returns TMMBR (format 3) or TMMBN (format 4) with FCI entries of ssrc and MxTBR
+fn tmmbx(format: u8, sender_ssrc: u32, entries: &[(u32, u32)]) -> Box<dyn rtcp::packet::Packet> {
+    let mut raw = BytesMut::new();
+    raw.extend_from_slice(&[0x80 | format, 205]);
+    raw.extend_from_slice(&(2 + 2 * entries.len() as u16).to_be_bytes());
+    raw.extend_from_slice(&sender_ssrc.to_be_bytes());
+    raw.extend_from_slice(&0u32.to_be_bytes());
+    for (ssrc, mxtbr) in entries {
+        raw.extend_from_slice(&ssrc.to_be_bytes());
+        raw.extend_from_slice(&mxtbr.to_be_bytes());
+    }
+    Box::new(RawPacket(raw.freeze()))
+}
+
+/// tmmbx_entries returns sender ssrc and FCI entries of ssrc and bitrate of TMMBR or TMMBN
+fn tmmbx_entries(packet: &dyn rtcp::packet::Packet, format: u8) -> Option<(u32, Vec<(u32, u64)>)> {
+    let raw = &packet.as_any().downcast_ref::<RawPacket>()?.0;
+    if raw[0] & 0x1F != format || raw[1] != 205 {
+        return None;
+    }
+    let sender_ssrc = u32::from_be_bytes([raw[4], raw[5], raw[6], raw[7]]);
+    let entries = raw[12..]
+        .chunks_exact(8)
+        .map(|fci| {
+            let mxtbr = u32::from_be_bytes([fci[4], fci[5], fci[6], fci[7]]);
+            let bitrate = (((mxtbr >> 9) & 0x1FFFF) as u64) << (mxtbr >> 26);
+            (
+                u32::from_be_bytes([fci[0], fci[1], fci[2], fci[3]]),
+                bitrate,
+            )
+        })
+        .collect();
+    Some((sender_ssrc, entries))
+}
+
+#[test]
+fn test_loopback_tmmbr() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default_video_config();
+    media_config.configure_twcc()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        &format!(
+            "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:1\r
+a=extmap:3 {TRANSPORT_CC_URI}\r
+a=sendonly\r
+a=msid:stream video\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtcp-fb:96 transport-cc\r
+a=ssrc:1234 cname:publisher\r
+"
+        ),
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+
+    // publisher sends about 580 kbps, below the initial estimate toward the subscriber
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let (publisher, subscriber) = (&mut publisher[0], &mut subscriber[0]);
+    for sequence_number in 0..60 {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from(vec![0u8; 1200]),
+        };
+        publisher.send_rtp(&mut transport, &packet)?;
+    }
+    assert_eq!(subscriber.recv_rtp(&mut transport)?.len(), 60);
+    assert!(publisher.recv_rtcp(&mut transport)?.is_empty());
+
+    // subscriber reports all of them lost, which halves its estimate to 500 kbps
+    let feedback = TransportLayerCc {
+        sender_ssrc: 1,
+        media_ssrc: 1234,
+        base_sequence_number: 0,
+        packet_status_count: 60,
+        reference_time: 0,
+        fb_pkt_count: 0,
+        packet_chunks: vec![PacketStatusChunk::RunLengthChunk(RunLengthChunk {
+            type_tcc: StatusChunkTypeTcc::RunLengthChunk,
+            packet_status_symbol: SymbolTypeTcc::PacketNotReceived,
+            run_length: 60,
+        })],
+        recv_deltas: vec![],
+    };
+    subscriber.send_rtcp(&mut transport, &[Box::new(feedback)])?;
+
+    // TMMBR from SFU caps the publisher's ssrc to the subscriber's estimate
+    let tmmbrs: Vec<(u32, Vec<(u32, u64)>)> = publisher
+        .recv_rtcp(&mut transport)?
+        .iter()
+        .filter_map(|packet| tmmbx_entries(packet.as_ref(), 3))
+        .collect();
+    assert_eq!(tmmbrs.len(), 1);
+    // sent from an SFU-owned ssrc of the publisher's transport
+    let (sfu_ssrc, entries) = &tmmbrs[0];
+    assert_ne!(*sfu_ssrc, 0);
+    assert_eq!(entries, &vec![(1234, 500_000)]);
+
+    // TMMBN owned by SFU's own TMMBR is consumed
+    publisher.send_rtcp(
+        &mut transport,
+        &[tmmbx(4, 1234, &[(*sfu_ssrc, 500_000 >> 2 << 9 | 2 << 26)])],
+    )?;
+    assert!(subscriber.recv_rtcp(&mut transport)?.is_empty());
+
+    // TMMBR from the subscriber goes to the publisher, whose TMMBN goes back to the subscriber
+    let mxtbr = 125_000 << 9 | 1 << 26;
+    subscriber.send_rtcp(&mut transport, &[tmmbx(3, 5555, &[(1234, mxtbr)])])?;
+    let tmmbrs: Vec<(u32, Vec<(u32, u64)>)> = publisher
+        .recv_rtcp(&mut transport)?
+        .iter()
+        .filter_map(|packet| tmmbx_entries(packet.as_ref(), 3))
+        .collect();
+    assert_eq!(tmmbrs, vec![(5555, vec![(1234, 250_000)])]);
+    publisher.send_rtcp(&mut transport, &[tmmbx(4, 1234, &[(5555, mxtbr)])])?;
+    let tmmbns: Vec<(u32, Vec<(u32, u64)>)> = subscriber
+        .recv_rtcp(&mut transport)?
+        .iter()
+        .filter_map(|packet| tmmbx_entries(packet.as_ref(), 4))
+        .collect();
+    assert_eq!(tmmbns, vec![(1234, vec![(5555, 250_000)])]);
+
+    Ok(())
+}
@@ -0,0 +1,875 @@
+use crate::common::loopback::{
+    connect_peers, new_loopback_peers, new_loopback_server_config, LoopbackTransport,
+};
+use bytes::Bytes;
+use rtcp::payload_feedbacks::full_intra_request::FullIntraRequest;
+use rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication;
+use sfu::{FourTuple, FrameMarking, MediaConfig, DEPENDENCY_DESCRIPTOR_URI, FRAME_MARKING_URI};
+use shared::marshal::Marshal;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+mod common;
+
+#[test]
+fn test_loopback_rtp_forwarding() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    let packet = rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number: 1,
+            timestamp: 3000,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"loopback"),
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    publisher[0].send_rtp(&mut transport, &packet)?;
+
+    // decrypted packets with invalid RTP version are dropped
+    let mut invalid_packet = packet.clone();
+    invalid_packet.header.version = 1;
+    publisher[0].send_rtp(&mut transport, &invalid_packet)?;
+
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(forwarded.len(), 1);
+    assert_eq!(forwarded[0].header.ssrc, packet.header.ssrc);
+    assert_eq!(forwarded[0].payload, packet.payload);
+    assert!(publisher[0].recv_rtp(&mut transport)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_drop_ssrc() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    let packet = |ssrc: u32, sequence_number: u16| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"loopback"),
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    publisher[0].send_rtp(&mut transport, &packet(1234, 1))?;
+    assert_eq!(subscriber[0].recv_rtp(&mut transport)?.len(), 1);
+
+    transport.server_states().borrow_mut().drop_ssrc(1, 1234)?;
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .drop_ssrc(2, 1234)
+        .is_err());
+
+    // only the dropped ssrc stops being forwarded
+    publisher[0].send_rtp(&mut transport, &packet(1234, 2))?;
+    publisher[0].send_rtp(&mut transport, &packet(5678, 1))?;
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(forwarded.len(), 1);
+    assert_eq!(forwarded[0].header.ssrc, 5678);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_max_temporal_layer() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_max_temporal_layer(1, 1, Some(1))?;
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .set_max_temporal_layer(1, 2, Some(1))
+        .is_err());
+
+    // VP8 payload descriptor with X and S bits, T bit, and TID
+    let packet = |sequence_number: u16, temporal_layer_id: u8| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from(vec![0x90, 0x20, temporal_layer_id << 6, 0x00]),
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for (sequence_number, temporal_layer_id) in [0, 2, 1, 2, 0].into_iter().enumerate() {
+        publisher[0].send_rtp(
+            &mut transport,
+            &packet(sequence_number as u16 + 1, temporal_layer_id),
+        )?;
+    }
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(
+        forwarded
+            .iter()
+            .map(|packet| packet.payload[2] >> 6)
+            .collect::<Vec<_>>(),
+        vec![0, 1, 0]
+    );
+    // sequence numbers stay continuous over the dropped packets
+    assert_eq!(
+        forwarded
+            .iter()
+            .map(|packet| packet.header.sequence_number)
+            .collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_e2ee_payload_passthrough() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+    transport.server_states().borrow_mut().set_e2ee(1, true)?;
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .set_e2ee(2, true)
+        .is_err());
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_max_temporal_layer(1, 1, Some(0))?;
+
+    // SFrame ciphertext which happens to look like VP8 payload descriptors of temporal layer 2
+    let payloads: Vec<Bytes> = (0..3u8)
+        .map(|i| Bytes::from(vec![0x90, 0x20, 0x80, i, 0xde, 0xad, 0xbe, 0xef]))
+        .collect();
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for (sequence_number, payload) in payloads.iter().enumerate() {
+        publisher[0].send_rtp(
+            &mut transport,
+            &rtp::packet::Packet {
+                header: rtp::header::Header {
+                    version: 2,
+                    payload_type: 96,
+                    sequence_number: sequence_number as u16 + 1000,
+                    ssrc: 1234,
+                    ..Default::default()
+                },
+                payload: payload.clone(),
+            },
+        )?;
+    }
+    // SRTP still applies hop-by-hop, only the E2EE payloads are forwarded untouched
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(
+        forwarded
+            .iter()
+            .map(|packet| packet.payload.clone())
+            .collect::<Vec<_>>(),
+        payloads
+    );
+
+    // plain RTP from the publisher's four-tuple fails SRTP authentication and is dropped
+    let plain = rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number: 1003,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: payloads[0].clone(),
+    };
+    transport.send(Instant::now(), publisher[0].addr(), plain.marshal()?);
+    assert!(subscriber[0].recv_rtp(&mut transport)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_dependency_descriptor_layers() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default_video_config();
+    media_config.configure_dependency_descriptor()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // publisher negotiates AV1 with dependency descriptor extension
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        &format!(
+            "m=video 9 UDP/TLS/RTP/SAVPF 99\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:99 AV1/90000\r
+a=extmap:5 {DEPENDENCY_DESCRIPTOR_URI}\r
+a=msid:stream track\r
+a=ssrc:1234 cname:publisher\r
+"
+        ),
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+    assert!(
+        answer
+            .sdp
+            .contains(&format!("a=extmap:5 {DEPENDENCY_DESCRIPTOR_URI}")),
+        "{}",
+        answer.sdp
+    );
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_max_spatial_layer(1, 1, Some(0))?;
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_max_temporal_layer(1, 1, Some(0))?;
+
+    let bits = |bits: &str| -> Vec<u8> {
+        let bits: Vec<u8> = bits.bytes().filter(|bit| *bit != b' ').collect();
+        bits.chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |byte, (i, bit)| byte | ((bit - b'0') << (7 - i)))
+            })
+            .collect()
+    };
+    // L2T2 template dependency structure: templates of S0T0, S0T1, S1T0 and S1T1
+    // with 4 decode targets and no chains
+    let structure = concat!(
+        "10000 000000 00011 ",
+        "01 10 01 11 ",
+        "11111111 00010001 00001111 00000001 ",
+        "100010 100000 100000 100000 ",
+        "00 00 01 10 11 0"
+    );
+    let packet = |sequence_number: u16, template_id: u8, marker: bool| {
+        let mut descriptor = format!("11 {:06b} {:016b} ", template_id, sequence_number);
+        if sequence_number == 1 {
+            descriptor += structure;
+        }
+        let mut header = rtp::header::Header {
+            version: 2,
+            payload_type: 99,
+            sequence_number,
+            ssrc: 1234,
+            marker,
+            ..Default::default()
+        };
+        header.set_extension(5, Bytes::from(bits(&descriptor)))?;
+        anyhow::Ok(rtp::packet::Packet {
+            header,
+            payload: Bytes::from_static(b"av1"),
+        })
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for (sequence_number, template_id, marker) in [
+        (1, 0, false),
+        (2, 2, true),
+        (3, 1, false),
+        (4, 3, true),
+        (5, 0, false),
+        (6, 2, true),
+    ] {
+        publisher[0].send_rtp(
+            &mut transport,
+            &packet(sequence_number, template_id, marker)?,
+        )?;
+    }
+
+    // only S0T0 frames are forwarded, with continuous sequence numbers and marker bits set at
+    // the end of the base layer frames
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(
+        forwarded
+            .iter()
+            .map(|packet| (
+                packet.header.sequence_number,
+                packet.header.marker,
+                packet.header.get_extension(5).map(|raw| raw[0] & 0x3f)
+            ))
+            .collect::<Vec<_>>(),
+        vec![(1, true, Some(0)), (2, true, Some(0))]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_frame_marking_layers() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default_video_config();
+    media_config.configure_frame_marking()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // publisher negotiates VP9 with frame-marking extension
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        &format!(
+            "m=video 9 UDP/TLS/RTP/SAVPF 98\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:98 VP9/90000\r
+a=extmap:6 {FRAME_MARKING_URI}\r
+a=msid:stream track\r
+a=ssrc:1234 cname:publisher\r
+"
+        ),
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+    assert!(
+        answer
+            .sdp
+            .contains(&format!("a=extmap:6 {FRAME_MARKING_URI}")),
+        "{}",
+        answer.sdp
+    );
+    // payloads are opaque, so layers are known only by frame-marking
+    transport.server_states().borrow_mut().set_e2ee(1, true)?;
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_max_spatial_layer(1, 1, Some(0))?;
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_max_temporal_layer(1, 1, Some(0))?;
+
+    let packet = |sequence_number: u16, independent: bool, temporal_layer_id: u8, layer_id: u8| {
+        let mut header = rtp::header::Header {
+            version: 2,
+            payload_type: 98,
+            sequence_number,
+            ssrc: 1234,
+            ..Default::default()
+        };
+        let frame_marking = FrameMarking {
+            start_of_frame: true,
+            end_of_frame: true,
+            independent,
+            temporal_layer_id,
+            layer_id,
+            tl0_pic_idx: Some(0),
+            scalable: true,
+            ..Default::default()
+        };
+        header.set_extension(6, frame_marking.marshal()?)?;
+        anyhow::Ok(rtp::packet::Packet {
+            header,
+            payload: Bytes::from_static(b"sframe"),
+        })
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for (sequence_number, independent, temporal_layer_id, layer_id) in [
+        (1, true, 0, 0),
+        (2, false, 0, 1),
+        (3, false, 1, 0),
+        (4, false, 1, 1),
+        (5, false, 0, 0),
+        (6, false, 0, 1),
+    ] {
+        publisher[0].send_rtp(
+            &mut transport,
+            &packet(sequence_number, independent, temporal_layer_id, layer_id)?,
+        )?;
+    }
+
+    // only S0T0 frames are forwarded, starting with the keyframe, with continuous sequence
+    // numbers and marker bits set at the end of the base layer frames
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(
+        forwarded
+            .iter()
+            .map(|packet| anyhow::Ok((
+                packet.header.sequence_number,
+                packet.header.marker,
+                FrameMarking::unmarshal(&packet.header.get_extension(6).unwrap_or_default())?
+                    .is_keyframe(),
+            )))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        vec![(1, true, true), (2, true, false)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_request_keyframe() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // publisher's video with RTX, which negotiates FIR
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        "m=video 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtcp-fb:96 nack pli\r
+a=rtcp-fb:96 ccm fir\r
+a=msid:stream video\r
+a=ssrc-group:FID 1234 5678\r
+a=ssrc:1234 cname:publisher\r
+a=ssrc:5678 cname:publisher\r
+",
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .request_keyframe(1, 0, "2")
+        .is_err());
+    let messages = transport
+        .server_states()
+        .borrow_mut()
+        .request_keyframe(1, 0, "1")?;
+    transport.capture(messages);
+
+    // PLI and FIR of the media ssrc are sent to the publisher only, not of its RTX ssrc
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let packets = publisher[0].recv_rtcp(&mut transport)?;
+    let plis: Vec<(u32, u32)> = packets
+        .iter()
+        .filter_map(|packet| packet.as_any().downcast_ref::<PictureLossIndication>())
+        .map(|pli| (pli.sender_ssrc, pli.media_ssrc))
+        .collect();
+    assert_eq!(plis.len(), 1);
+    // sent from an SFU-owned ssrc of the publisher's transport
+    let (sfu_ssrc, media_ssrc) = plis[0];
+    assert_ne!(sfu_ssrc, 0);
+    assert_eq!(media_ssrc, 1234);
+    assert!(packets
+        .iter()
+        .filter_map(|packet| packet.as_any().downcast_ref::<FullIntraRequest>())
+        .all(|fir| fir.sender_ssrc == sfu_ssrc));
+    let firs: Vec<(u32, u8)> = packets
+        .iter()
+        .filter_map(|packet| packet.as_any().downcast_ref::<FullIntraRequest>())
+        .flat_map(|fir| {
+            fir.fir
+                .iter()
+                .map(|entry| (entry.ssrc, entry.sequence_number))
+        })
+        .collect();
+    assert_eq!(firs, vec![(1234, 1)]);
+    assert!(subscriber[0].recv_rtcp(&mut transport)?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_keyframe_cache() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_keyframe_cache_size(16),
+        sfu_addr,
+    )?;
+
+    let mut peers = new_loopback_peers(2);
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let answer =
+        transport
+            .server_states()
+            .borrow_mut()
+            .accept_offer(1, 0, None, publisher[0].offer()?)?;
+    publisher[0].accept_answer(&answer);
+    publisher[0].connect(&mut transport)?;
+
+    // VP8 payload descriptor with S bit, followed by payload header whose P bit is 0 for
+    // keyframes, where the delta frame before the first keyframe is not cached
+    let packet = |sequence_number: u16, timestamp: u32, is_keyframe: bool| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            timestamp,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from(vec![0x10, if is_keyframe { 0x00 } else { 0x01 }]),
+    };
+    for (sequence_number, timestamp, is_keyframe) in [
+        (1, 0, false),
+        (2, 3000, true),
+        (3, 3000, false),
+        (4, 6000, false),
+    ] {
+        publisher[0].send_rtp(
+            &mut transport,
+            &packet(sequence_number, timestamp, is_keyframe),
+        )?;
+    }
+
+    // the newly joined subscriber gets the keyframe and the following packets along with the
+    // next packet, while no PLI is sent to the publisher
+    let answer =
+        transport
+            .server_states()
+            .borrow_mut()
+            .accept_offer(1, 1, None, subscriber[0].offer()?)?;
+    subscriber[0].accept_answer(&answer);
+    subscriber[0].connect(&mut transport)?;
+    publisher[0].send_rtp(&mut transport, &packet(5, 9000, false))?;
+    publisher[0].send_rtp(&mut transport, &packet(6, 12000, false))?;
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(
+        forwarded
+            .iter()
+            .map(|packet| packet.header.sequence_number)
+            .collect::<Vec<_>>(),
+        vec![2, 3, 4, 5, 6]
+    );
+    assert!(publisher[0]
+        .recv_rtcp(&mut transport)?
+        .iter()
+        .all(|packet| packet
+            .as_any()
+            .downcast_ref::<PictureLossIndication>()
+            .is_none()));
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_path_mtu() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    for drop_oversized_rtp in [false, true] {
+        let mut transport = LoopbackTransport::new(
+            new_loopback_server_config()?
+                .with_path_mtu(200)
+                .with_drop_oversized_rtp(drop_oversized_rtp),
+            sfu_addr,
+        )?;
+
+        let mut peers = connect_peers(&mut transport, 2)?;
+        let subscriber_four_tuple = FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[1].addr(),
+        };
+
+        let packet = |sequence_number: u16, payload_size: usize| rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from(vec![0u8; payload_size]),
+        };
+        let (publisher, subscriber) = peers.split_at_mut(1);
+        publisher[0].send_rtp(&mut transport, &packet(1, 100))?;
+        publisher[0].send_rtp(&mut transport, &packet(2, 400))?;
+        let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+        assert_eq!(
+            forwarded.len(),
+            if drop_oversized_rtp { 1 } else { 2 },
+            "drop_oversized_rtp {}",
+            drop_oversized_rtp
+        );
+        assert_eq!(forwarded[0].payload.len(), 100);
+        assert_eq!(
+            transport
+                .server_states()
+                .borrow()
+                .get_oversized_rtp_packet_count(&subscriber_four_tuple),
+            Some(1)
+        );
+
+        // per transport path MTU overrides the configured one
+        transport
+            .server_states()
+            .borrow_mut()
+            .set_path_mtu(subscriber_four_tuple, 1200)?;
+        publisher[0].send_rtp(&mut transport, &packet(3, 400))?;
+        assert_eq!(subscriber[0].recv_rtp(&mut transport)?.len(), 1);
+        assert_eq!(
+            transport
+                .server_states()
+                .borrow()
+                .get_oversized_rtp_packet_count(&subscriber_four_tuple),
+            Some(1)
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_vp9_ksvc_filter() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default_video_config();
+    media_config.configure_vp9_ksvc_filter(0, 0);
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // VP9 payload descriptor with L|B (|E) flags, P flag for inter frames,
+    // TID|U|SID|D layer index and TL0PICIDX
+    let vp9_packet = |sequence_number: u16, keyframe: bool, sid: u8, tid: u8, end: bool| {
+        let flags = 0x28 | if keyframe { 0 } else { 0x40 } | if end { 0x04 } else { 0 };
+        rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 98,
+                sequence_number,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from(vec![flags, (tid << 5) | (sid << 1), 0, 0xAA]),
+        }
+    };
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for packet in [
+        vp9_packet(1, true, 0, 0, true),
+        vp9_packet(2, true, 1, 0, true),
+        vp9_packet(3, false, 0, 1, true),
+        vp9_packet(4, false, 0, 0, true),
+    ] {
+        publisher[0].send_rtp(&mut transport, &packet)?;
+    }
+
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(forwarded.len(), 2);
+    assert_eq!(forwarded[0].header.sequence_number, 1);
+    assert_eq!(forwarded[1].header.sequence_number, 2);
+    assert!(forwarded[1].header.marker);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_telephone_event() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_rtp_rewriting(true),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // publisher renegotiates an audio section with telephone-event
+    let offer = peers[0].offer_with_media(
+        &["1"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 111 110\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=msid:stream track\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=rtpmap:110 telephone-event/48000\r
+a=fmtp:110 0-15\r
+a=ssrc:1234 cname:publisher\r
+",
+    )?;
+    let answer = transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[0].addr(),
+        }),
+        offer,
+    )?;
+    assert!(
+        answer.sdp.contains("a=rtpmap:110 telephone-event/48000"),
+        "{}",
+        answer.sdp
+    );
+
+    // digit 1 of 3 packets, whose event starts with marker and ends with E bit,
+    // all at the start timestamp with growing duration
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let mut published = vec![];
+    for (sequence_number, marker, end, duration) in [
+        (1, true, false, 960u16),
+        (2, false, false, 1920),
+        (3, false, true, 2880),
+    ] {
+        let mut payload = vec![1, if end { 0x80 | 10 } else { 10 }];
+        payload.extend_from_slice(&duration.to_be_bytes());
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                marker,
+                payload_type: 110,
+                sequence_number,
+                timestamp: 48000,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from(payload),
+        };
+        publisher[0].send_rtp(&mut transport, &packet)?;
+        published.push(packet);
+    }
+
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    assert_eq!(forwarded.len(), published.len());
+    for (forwarded, published) in forwarded.iter().zip(&published) {
+        assert_eq!(forwarded.header.payload_type, 110);
+        assert_eq!(forwarded.header.marker, published.header.marker);
+        assert_eq!(forwarded.header.timestamp, published.header.timestamp);
+        assert_eq!(
+            forwarded.header.sequence_number,
+            published.header.sequence_number
+        );
+        assert_eq!(forwarded.payload, published.payload);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_ulpfec() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default();
+    media_config.configure_ulpfec(25)?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // subscriber renegotiates a video section with ulpfec
+    let offer = peers[1].offer_with_media(
+        &["1"],
+        "m=video 9 UDP/TLS/RTP/SAVPF 96 116\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendrecv\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtpmap:116 ulpfec/90000\r
+",
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        1,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peers[1].addr(),
+        }),
+        offer,
+    )?;
+
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    for sequence_number in 1..=8 {
+        let packet = rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                timestamp: 3000 * sequence_number as u32,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(b"loopback"),
+        };
+        publisher[0].send_rtp(&mut transport, &packet)?;
+    }
+
+    // 25% overhead protects every 4 media packets with an FEC packet in the same sequence space
+    let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+    let sequence_numbers: Vec<(u8, u16)> = forwarded
+        .iter()
+        .map(|packet| (packet.header.payload_type, packet.header.sequence_number))
+        .collect();
+    assert_eq!(
+        sequence_numbers,
+        vec![
+            (96, 1),
+            (96, 2),
+            (96, 3),
+            (96, 4),
+            (116, 5),
+            (96, 6),
+            (96, 7),
+            (96, 8),
+            (96, 9),
+            (116, 10),
+        ]
+    );
+
+    Ok(())
+}
@@ -0,0 +1,603 @@
+use crate::common::loopback::{
+    connect_peers, new_loopback_peers, new_loopback_server_config, LoopbackPeer, LoopbackTransport,
+};
+use bytes::{Bytes, BytesMut};
+use rtcp::goodbye::Goodbye;
+use sfu::{DemuxerConfig, FourTuple, RTCIceCandidateType, RTCSessionDescription, ZrtpMode};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use stun::attributes::{ATTR_ICE_CONTROLLED, ATTR_ICE_CONTROLLING};
+use stun::error_code::{ErrorCodeAttribute, CODE_ROLE_CONFLICT};
+use stun::message::{Getter, BINDING_ERROR, BINDING_SUCCESS};
+
+mod common;
+
+#[test]
+fn test_loopback_srtp_rekey() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let publisher_addr: SocketAddr = "127.0.0.1:50000".parse()?;
+    let mut publisher = LoopbackPeer::new(publisher_addr, "ufrA", "pwdAAAAAAAAAAAAAAAAAAAAA");
+    let mut subscriber = LoopbackPeer::new(
+        "127.0.0.1:50001".parse()?,
+        "ufrB",
+        "pwdBBBBBBBBBBBBBBBBBBBBB",
+    );
+    let mut publisher_answer = None;
+    for (endpoint_id, peer) in [&mut publisher, &mut subscriber].into_iter().enumerate() {
+        let answer = transport.server_states().borrow_mut().accept_offer(
+            1,
+            endpoint_id as u64,
+            None,
+            peer.offer()?,
+        )?;
+        peer.accept_answer(&answer);
+        peer.connect(&mut transport)?;
+        publisher_answer.get_or_insert(answer);
+    }
+
+    let packet = |sequence_number: u16| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"rekey"),
+    };
+    publisher.send_rtp(&mut transport, &packet(1))?;
+    assert_eq!(subscriber.recv_rtp(&mut transport)?.len(), 1);
+
+    // a new DTLS handshake over the same transport derives new SRTP keys
+    transport.recv(publisher_addr);
+    let mut rekeyed_publisher =
+        LoopbackPeer::new(publisher_addr, "ufrA", "pwdAAAAAAAAAAAAAAAAAAAAA");
+    rekeyed_publisher.accept_answer(publisher_answer.as_ref().unwrap());
+    rekeyed_publisher.connect(&mut transport)?;
+
+    // packets with the new keys are decrypted, while ones with the stale keys are not
+    rekeyed_publisher.send_rtp(&mut transport, &packet(2))?;
+    publisher.send_rtp(&mut transport, &packet(3))?;
+    let forwarded: Vec<u16> = subscriber
+        .recv_rtp(&mut transport)?
+        .iter()
+        .map(|packet| packet.header.sequence_number)
+        .collect();
+    assert_eq!(forwarded, vec![2]);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_dtls_restart_without_binding_request() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let peer_addr: SocketAddr = "127.0.0.1:50000".parse()?;
+    let mut peer = LoopbackPeer::new(peer_addr, "ufrA", "pwdAAAAAAAAAAAAAAAAAAAAA");
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+    peer.open_data_channel(&mut transport)?;
+
+    // a ClientHello spoofing the peer's address without a binding request is dropped
+    let mut spoofer = LoopbackPeer::new(peer_addr, "ufrA", "pwdAAAAAAAAAAAAAAAAAAAAA");
+    spoofer.accept_answer(&answer);
+    assert!(spoofer.send_client_hello(&mut transport)?.is_empty());
+
+    // and the DTLS connection carrying the data channel is kept
+    let message = serde_json::to_string(&peer.offer()?)?;
+    peer.send_data_channel(&mut transport, message.as_bytes())?;
+    assert_eq!(peer.recv_data_channel(&mut transport)?.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_srtp_before_dtls_completes() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    for (srtp_pending_buffer_size, expected_forwarded) in [(0, 1), (16, 3)] {
+        let mut transport = LoopbackTransport::new(
+            new_loopback_server_config()?.with_srtp_pending_buffer_size(srtp_pending_buffer_size),
+            sfu_addr,
+        )?;
+
+        let mut peers = new_loopback_peers(2);
+        for (endpoint_id, peer) in peers.iter_mut().enumerate() {
+            let answer = transport.server_states().borrow_mut().accept_offer(
+                1,
+                endpoint_id as u64,
+                None,
+                peer.offer()?,
+            )?;
+            peer.accept_answer(&answer);
+        }
+        peers[1].connect(&mut transport)?;
+
+        let packet = |sequence_number: u16| rtp::packet::Packet {
+            header: rtp::header::Header {
+                version: 2,
+                payload_type: 96,
+                sequence_number,
+                ssrc: 1234,
+                ..Default::default()
+            },
+            payload: Bytes::from_static(b"loopback"),
+        };
+        let (publisher, subscriber) = peers.split_at_mut(1);
+        // publisher's SRTP overtakes its last DTLS flight
+        let last_flight = publisher[0].connect_holding_last_flight(&mut transport)?;
+        publisher[0].send_rtp(&mut transport, &packet(1))?;
+        publisher[0].send_rtp(&mut transport, &packet(2))?;
+        assert!(subscriber[0].recv_rtp(&mut transport)?.is_empty());
+
+        // SFU processes the queued Finished on the retransmitted flight
+        for datagram in [last_flight.clone(), last_flight].concat() {
+            transport.send(Instant::now(), publisher[0].addr(), datagram);
+        }
+        publisher[0].send_rtp(&mut transport, &packet(3))?;
+        let forwarded = subscriber[0].recv_rtp(&mut transport)?;
+        assert_eq!(
+            forwarded
+                .iter()
+                .map(|packet| packet.header.sequence_number)
+                .collect::<Vec<_>>(),
+            (4 - expected_forwarded..=3).collect::<Vec<u16>>(),
+            "srtp_pending_buffer_size {}",
+            srtp_pending_buffer_size
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_dtls_close_notify() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+
+    // the endpoint is torn down right away instead of after the idle timeout
+    peers[0].close(&mut transport);
+    let server_states = transport.server_states().borrow();
+    assert!(server_states.get_remote_candidates(1, 0).is_none());
+    assert!(server_states.get_remote_candidates(1, 1).is_some());
+    drop(server_states);
+
+    // and its close_notify is answered with a close_notify
+    assert_eq!(transport.recv(peers[0].addr()).len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_turn_relay_candidate() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_turn_relay_addr("203.0.113.1:49152".parse()?),
+        sfu_addr,
+    )?;
+
+    let peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    assert!(
+        answer
+            .sdp
+            .contains("a=candidate:1 1 UDP 1 127.0.0.1 3478 typ host\r\n"),
+        "{}",
+        answer.sdp
+    );
+    assert!(
+        answer.sdp.contains(
+            "a=candidate:2 1 UDP 1 203.0.113.1 49152 typ relay raddr 127.0.0.1 rport 3478\r\n"
+        ),
+        "{}",
+        answer.sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_expired_candidate() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_candidate_ttl(Duration::ZERO),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    std::thread::sleep(Duration::from_millis(1));
+
+    assert!(peer.connect(&mut transport).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_remote_candidates() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let offer = RTCSessionDescription::offer(format!(
+        "{}{}{}",
+        peer.offer()?.sdp,
+        "a=candidate:1 1 udp 2130706431 127.0.0.1 50000 typ host generation 0\r\n",
+        "a=candidate:2 1 UDP 41885439 203.0.113.1 3478 typ relay raddr 192.0.2.1 rport 50000\r\n",
+    ))?;
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, offer)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    let candidates = transport
+        .server_states()
+        .borrow()
+        .get_remote_candidates(1, 1)
+        .expect("endpoint is not connected");
+    assert_eq!(candidates.len(), 2);
+    assert_eq!(candidates[0].typ, RTCIceCandidateType::Host);
+    assert_eq!(candidates[0].socket_addr(), Some(peer.addr()));
+    assert_eq!(candidates[1].typ, RTCIceCandidateType::Relay);
+    assert_eq!(candidates[1].protocol, "udp");
+    assert_eq!(candidates[1].related_address.as_deref(), Some("192.0.2.1"));
+    assert_eq!(candidates[1].related_port, Some(50000));
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_ice_credentials_rotation() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_ice_credentials_rotation(true),
+        sfu_addr,
+    )?;
+
+    let mut peers = new_loopback_peers(2);
+    let mut ice_ufrags = vec![];
+    for (endpoint_id, peer) in peers.iter_mut().enumerate() {
+        let answer = transport.server_states().borrow_mut().accept_offer(
+            1,
+            endpoint_id as u64,
+            None,
+            peer.offer()?,
+        )?;
+        ice_ufrags.push(attribute(&answer.sdp, "a=ice-ufrag:"));
+        peer.accept_answer(&answer);
+        peer.connect(&mut transport)?;
+        peer.open_data_channel(&mut transport)?;
+    }
+
+    // publisher adds an audio track, so that subscriber is offered it with fresh credentials
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let offer = publisher[0].offer_with_media(
+        &["1"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=msid:stream audio\r
+a=ssrc:1234 cname:publisher\r
+",
+    )?;
+    publisher[0].send_data_channel(&mut transport, serde_json::to_string(&offer)?.as_bytes())?;
+    publisher[0].recv_data_channel(&mut transport)?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let offers: Vec<RTCSessionDescription> = subscriber[0]
+        .recv_data_channel(&mut transport)?
+        .iter()
+        .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+        .collect();
+    assert_eq!(offers.len(), 1);
+    let (ice_ufrag, ice_pwd) = (
+        attribute(&offers[0].sdp, "a=ice-ufrag:"),
+        attribute(&offers[0].sdp, "a=ice-pwd:"),
+    );
+    assert_ne!(ice_ufrag, ice_ufrags[1]);
+
+    // subscriber answers with its own credentials
+    let answer = RTCSessionDescription::answer(
+        offers[0]
+            .sdp
+            .replace("a=sendonly", "a=recvonly")
+            .replace("a=setup:actpass", "a=setup:active")
+            .replace(&ice_ufrag, "ufrB")
+            .replace(&ice_pwd, "pwdBBBBBBBBBBBBBBBBBBBBB"),
+    )?;
+    subscriber[0].send_data_channel(&mut transport, serde_json::to_string(&answer)?.as_bytes())?;
+
+    // only the new credentials authenticate STUN binding requests from now on
+    assert!(subscriber[0]
+        .binding_request(&mut transport, ATTR_ICE_CONTROLLING, 1)
+        .is_err());
+    subscriber[0].accept_answer(&offers[0]);
+    let response = subscriber[0].binding_request(&mut transport, ATTR_ICE_CONTROLLING, 1)?;
+    assert_eq!(response.typ, BINDING_SUCCESS);
+
+    Ok(())
+}
+
+/// value of the first attribute line of the SDP with the prefix
+fn attribute(sdp: &str, prefix: &str) -> String {
+    sdp.lines()
+        .find_map(|line| line.strip_prefix(prefix))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[test]
+fn test_loopback_transport_last_seen() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+    let four_tuple = FourTuple {
+        local_addr: sfu_addr,
+        peer_addr: peer.addr(),
+    };
+
+    let stats = transport
+        .server_states()
+        .borrow()
+        .get_transport_stats(&four_tuple)
+        .expect("no transport stats");
+    let stun_last_seen = stats.stun_last_seen.expect("no STUN seen");
+    assert!(stats.dtls_last_seen.is_some());
+    assert!(stats.media_last_seen.is_none());
+
+    std::thread::sleep(Duration::from_millis(1));
+    let response = peer.binding_request(&mut transport, ATTR_ICE_CONTROLLING, 1)?;
+    assert_eq!(response.typ, BINDING_SUCCESS);
+    let stats = transport
+        .server_states()
+        .borrow()
+        .get_transport_stats(&four_tuple)
+        .expect("no transport stats");
+    assert!(stats
+        .stun_last_seen
+        .is_some_and(|last_seen| last_seen > stun_last_seen));
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_ipv4_mapped_peer_addr() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    // a dual-stack socket sees the peer of the IPv4 candidate at an IPv4-mapped IPv6 address
+    let mut peer = LoopbackPeer::new(
+        "[::ffff:127.0.0.1]:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let offer = RTCSessionDescription::offer(
+        peer.offer()?.sdp + "a=candidate:1 1 udp 2130706431 127.0.0.1 50000 typ host\r\n",
+    )?;
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, offer)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    let candidates = transport
+        .server_states()
+        .borrow()
+        .get_remote_candidates(1, 1)
+        .expect("no remote candidates");
+    assert_eq!(candidates.len(), 1);
+    let four_tuple = FourTuple {
+        local_addr: sfu_addr,
+        peer_addr: SocketAddr::new(candidates[0].address.parse()?, candidates[0].port),
+    };
+    assert!(transport
+        .server_states()
+        .borrow()
+        .get_transport_stats(&four_tuple)
+        .is_some_and(|stats| stats.stun_last_seen.is_some() && stats.dtls_last_seen.is_some()));
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_close() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peers = connect_peers(&mut transport, 2)?;
+    for peer in peers.iter_mut() {
+        peer.open_data_channel(&mut transport)?;
+    }
+
+    // publisher's audio track is forwarded to the subscriber
+    let (publisher, subscriber) = peers.split_at_mut(1);
+    let offer = publisher[0].offer_with_media(
+        &["1"],
+        "m=audio 9 UDP/TLS/RTP/SAVPF 111\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:111 opus/48000/2\r
+a=msid:stream audio\r
+a=ssrc:1234 cname:publisher\r
+",
+    )?;
+    transport.server_states().borrow_mut().accept_offer(
+        1,
+        0,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: publisher[0].addr(),
+        }),
+        offer,
+    )?;
+
+    transport.close();
+
+    // subscriber gets BYE of the forwarded ssrc, and both get SCTP SHUTDOWN and close_notify
+    let goodbyes: Vec<Goodbye> = subscriber[0]
+        .recv_rtcp(&mut transport)?
+        .iter()
+        .filter_map(|packet| packet.as_any().downcast_ref::<Goodbye>().cloned())
+        .collect();
+    assert_eq!(goodbyes.len(), 1);
+    assert_eq!(goodbyes[0].sources, vec![1234]);
+    assert!(!transport.recv(publisher[0].addr()).is_empty());
+
+    // no transports are left, and closing again has nothing to tear down
+    for peer in [&publisher[0], &subscriber[0]] {
+        assert!(transport
+            .server_states()
+            .borrow()
+            .get_transport_stats(&FourTuple {
+                local_addr: sfu_addr,
+                peer_addr: peer.addr(),
+            })
+            .is_none());
+    }
+    assert!(transport
+        .server_states()
+        .borrow()
+        .get_endpoint_metadata(1, 0)
+        .is_none());
+    assert!(transport.server_states().borrow_mut().close().is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_zrtp_mode() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let peer_addr: SocketAddr = "127.0.0.1:50000".parse()?;
+    let proxy_addr: SocketAddr = "127.0.0.1:5000".parse()?;
+    // ZRTP packets start with the first byte in [16..19]
+    let zrtp_packet = BytesMut::from(&[0x10u8, 0x00, 0x00, 0x01, 0x5a, 0x52, 0x54, 0x50][..]);
+
+    for (zrtp_mode, expected_forwarded) in [
+        (ZrtpMode::Drop, 0),
+        (ZrtpMode::Warn, 0),
+        (ZrtpMode::Forward(proxy_addr), 1),
+    ] {
+        let mut transport = LoopbackTransport::new(
+            new_loopback_server_config()?
+                .with_demuxer_config(DemuxerConfig::new().with_zrtp_mode(zrtp_mode)),
+            sfu_addr,
+        )?;
+        transport.send(Instant::now(), peer_addr, zrtp_packet.clone());
+
+        let forwarded = transport.recv(proxy_addr);
+        assert_eq!(forwarded.len(), expected_forwarded, "{:?}", zrtp_mode);
+        assert!(forwarded.iter().all(|message| *message == zrtp_packet));
+        assert!(transport.recv(peer_addr).is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_truncated_packet() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let peer_addr: SocketAddr = "127.0.0.1:50000".parse()?;
+    let proxy_addr: SocketAddr = "127.0.0.1:5000".parse()?;
+    let zrtp_packet = BytesMut::from(&[0x10u8, 0x00, 0x00, 0x01, 0x5a, 0x52, 0x54, 0x50][..]);
+
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?
+            .with_demuxer_config(DemuxerConfig::new().with_zrtp_mode(ZrtpMode::Forward(proxy_addr)))
+            .with_udp_recv_buffer_size(zrtp_packet.len()),
+        sfu_addr,
+    )?;
+    // a datagram filling the whole receive buffer may have been truncated
+    transport.send(Instant::now(), peer_addr, zrtp_packet.clone());
+    assert!(transport.recv(proxy_addr).is_empty());
+
+    let short_packet = BytesMut::from(&zrtp_packet[..zrtp_packet.len() - 1]);
+    transport.send(Instant::now(), peer_addr, short_packet.clone());
+    assert_eq!(transport.recv(proxy_addr), vec![short_packet]);
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback_ice_role_conflict() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+
+    // both controlled, SFU with larger tiebreaker switches to controlling
+    let response = peer.binding_request(&mut transport, ATTR_ICE_CONTROLLED, 0)?;
+    assert_eq!(response.typ, BINDING_SUCCESS);
+
+    // both controlling, SFU with larger tiebreaker keeps controlling, so the peer must switch
+    let response = peer.binding_request(&mut transport, ATTR_ICE_CONTROLLING, 0)?;
+    assert_eq!(response.typ, BINDING_ERROR);
+    let mut error_code = ErrorCodeAttribute::default();
+    error_code.get_from(&response)?;
+    assert!(error_code.code == CODE_ROLE_CONFLICT);
+
+    // both controlling, SFU with smaller tiebreaker switches back to controlled
+    let response = peer.binding_request(&mut transport, ATTR_ICE_CONTROLLING, u64::MAX)?;
+    assert_eq!(response.typ, BINDING_SUCCESS);
+
+    Ok(())
+}
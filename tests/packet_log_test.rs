@@ -0,0 +1,94 @@
+use crate::common::loopback::{new_loopback_server_config, LoopbackPeer, LoopbackTransport};
+use bytes::Bytes;
+use log::{Level, Log, Metadata, Record};
+use sfu::MediaConfig;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+mod common;
+
+/// PacketLogRecorder records debug messages of the packet logging interceptor, since logger can
+/// only be set once per process, this test binary has no other tests
+struct PacketLogRecorder {
+    messages: Mutex<Vec<String>>,
+}
+
+impl Log for PacketLogRecorder {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() == Level::Debug && metadata.target().ends_with("packet_log")
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.enabled(record.metadata()) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static RECORDER: PacketLogRecorder = PacketLogRecorder {
+    messages: Mutex::new(vec![]),
+};
+
+#[test]
+fn test_packet_logging_first_packets() -> anyhow::Result<()> {
+    log::set_logger(&RECORDER).map_err(|err| anyhow::anyhow!("{}", err))?;
+    log::set_max_level(log::LevelFilter::Debug);
+
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut media_config = MediaConfig::default();
+    media_config.configure_packet_logging(3);
+    let mut transport = LoopbackTransport::new(
+        new_loopback_server_config()?.with_media_config(media_config),
+        sfu_addr,
+    )?;
+
+    let mut peer = LoopbackPeer::new(
+        "127.0.0.1:50000".parse()?,
+        "ufrA",
+        "pwdAAAAAAAAAAAAAAAAAAAAA",
+    );
+    let answer = transport
+        .server_states()
+        .borrow_mut()
+        .accept_offer(1, 1, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(&mut transport)?;
+
+    let packet = |ssrc: u32, sequence_number: u16| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"packet log"),
+    };
+    let logged = |ssrc: u32| {
+        RECORDER
+            .messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.contains(&format!("ssrc {} ", ssrc)))
+            .count()
+    };
+
+    // only the first 3 packets of a new ssrc are logged
+    for sequence_number in 1..=5 {
+        peer.send_rtp(&mut transport, &packet(1234, sequence_number))?;
+    }
+    assert_eq!(logged(1234), 3);
+
+    // and another newly seen ssrc is logged on its own
+    peer.send_rtp(&mut transport, &packet(5678, 1))?;
+    assert_eq!(logged(1234), 3);
+    assert_eq!(logged(5678), 1);
+
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use sfu::{LatencyMode, PlayoutDelay};
+
+#[test]
+fn test_playout_delay_extension() -> anyhow::Result<()> {
+    let mut header = rtp::header::Header {
+        version: 2,
+        ssrc: 1234,
+        ..Default::default()
+    };
+
+    let playout_delay = PlayoutDelay::from(LatencyMode::Smooth);
+    playout_delay.set_extension(&mut header, 5)?;
+
+    let payload = header
+        .get_extension(5)
+        .expect("playout-delay extension is missing");
+    assert_eq!(PlayoutDelay::unmarshal(&payload)?, playout_delay);
+    assert_eq!(playout_delay.min_delay, 10);
+    assert_eq!(playout_delay.max_delay, 100);
+
+    // realtime overrides previously set bounds
+    PlayoutDelay::from(LatencyMode::Realtime).set_extension(&mut header, 5)?;
+    let payload = header.get_extension(5).unwrap();
+    assert_eq!(payload.as_ref(), &[0, 0, 0]);
+    assert_eq!(header.get_extension_ids(), vec![5]);
+
+    assert!(PlayoutDelay {
+        min_delay: 200,
+        max_delay: 100,
+    }
+    .marshal()
+    .is_err());
+
+    Ok(())
+}
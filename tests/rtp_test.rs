@@ -1,3 +1,5 @@
+#![allow(clippy::assertions_on_constants)]
+
 use crate::common::{HOST, SIGNAL_PORT};
 use bytes::Bytes;
 use log::{error, info};
@@ -38,7 +40,7 @@ async fn test_rtp_uni_direction_0sendonly_1recvonly() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}: error {}", session_id, err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -56,7 +58,7 @@ async fn test_rtp_uni_direction_0sendonly_1recvonly() -> anyhow::Result<()> {
             Ok(ok) => ok,
             Err(err) => {
                 error!("{}/{}: error {}", session_id, endpoint_id, err);
-                return Err(err.into());
+                return Err(err);
             }
         };
         data_channels.push((data_channel_tx, data_channel_rx));
@@ -73,7 +75,7 @@ async fn test_rtp_uni_direction_0sendonly_1recvonly() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}/{}: error {}", session_id, endpoint_ids[0], err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -91,7 +93,7 @@ async fn test_rtp_uni_direction_0sendonly_1recvonly() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}/{}: error {}", session_id, endpoint_ids[1], err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -108,7 +110,7 @@ async fn test_rtp_uni_direction_0sendonly_1recvonly() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}/{}: error {}", session_id, endpoint_ids[0], err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -208,7 +210,7 @@ async fn test_rtp_uni_direction_0sendonly_1recvonly() -> anyhow::Result<()> {
         Ok(ok) => ok,
         Err(err) => {
             error!("{}: error {}", session_id, err);
-            return Err(err.into());
+            return Err(err);
         }
     }
 
@@ -237,7 +239,7 @@ async fn test_rtp_bi_direction_sendrecv(endpoint_count: usize) -> anyhow::Result
         Ok(ok) => ok,
         Err(err) => {
             error!("{}: error {}", session_id, err);
-            return Err(err.into());
+            return Err(err);
         }
     };
 
@@ -255,7 +257,7 @@ async fn test_rtp_bi_direction_sendrecv(endpoint_count: usize) -> anyhow::Result
             Ok(ok) => ok,
             Err(err) => {
                 error!("{}/{}: error {}", session_id, endpoint_id, err);
-                return Err(err.into());
+                return Err(err);
             }
         };
         data_channels.push((data_channel_tx, data_channel_rx));
@@ -274,7 +276,7 @@ async fn test_rtp_bi_direction_sendrecv(endpoint_count: usize) -> anyhow::Result
             Ok(ok) => ok,
             Err(err) => {
                 error!("{}/{}: error {}", session_id, endpoint_id, err);
-                return Err(err.into());
+                return Err(err);
             }
         };
         // Read incoming RTCP packets
@@ -294,7 +296,7 @@ async fn test_rtp_bi_direction_sendrecv(endpoint_count: usize) -> anyhow::Result
             Ok(ok) => ok,
             Err(err) => {
                 error!("{}/{}: error {}", session_id, endpoint_id, err);
-                return Err(err.into());
+                return Err(err);
             }
         };
 
@@ -313,7 +315,7 @@ async fn test_rtp_bi_direction_sendrecv(endpoint_count: usize) -> anyhow::Result
             Ok(ok) => ok,
             Err(err) => {
                 error!("{}/{}: error {}", session_id, endpoint_id, err);
-                return Err(err.into());
+                return Err(err);
             }
         };
 
@@ -451,7 +453,7 @@ async fn test_rtp_bi_direction_sendrecv(endpoint_count: usize) -> anyhow::Result
         Ok(ok) => ok,
         Err(err) => {
             error!("{}: error {}", session_id, err);
-            return Err(err.into());
+            return Err(err);
         }
     }
 
@@ -0,0 +1,426 @@
+use bytes::BytesMut;
+use retty::transport::{TaggedBytesMut, TransportContext};
+use sfu::{
+    RTCCertificate, RTCSdpType, RTCSessionDescription, ServerConfig, ServerPipeline, ServerStates,
+    ShardConfig, ShardedServerStates,
+};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stun::attributes::{AttrType, ATTR_ICE_CONTROLLING};
+use stun::error_code::{ErrorCodeAttribute, CODE_UNKNOWN_ATTRIBUTE};
+use stun::message::{
+    Getter, Message, TransactionId, BINDING_ERROR, BINDING_REQUEST, BINDING_SUCCESS,
+};
+use stun::uattrs::UnknownAttributes;
+use stun::xoraddr::XorMappedAddress;
+
+const DATA_CHANNEL_OFFER: &str = "v=0\r
+o=- 4215775240449105457 2 IN IP4 127.0.0.1\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0\r
+m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:EsAw\r
+a=ice-pwd:bP+XJMM09aR8AiX1jdukzR6Y\r
+a=fingerprint:sha-256 0F:74:31:25:CB:A2:13:EC:28:6F:6D:2C:61:FF:5D:C2:BC:B9:DB:3D:98:14:8D:1A:BB:EA:33:0C:A4:60:A8:8E\r
+a=setup:actpass\r
+a=mid:0\r
+a=sctp-port:5000\r
+";
+
+fn new_server_config() -> anyhow::Result<ServerConfig> {
+    let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+    let certificates = vec![RTCCertificate::from_key_pair(key_pair)?];
+    Ok(ServerConfig::new(certificates))
+}
+
+fn new_server_states(local_addr: SocketAddr) -> anyhow::Result<Rc<RefCell<ServerStates>>> {
+    new_server_states_with_config(local_addr, new_server_config()?)
+}
+
+fn new_server_states_with_config(
+    local_addr: SocketAddr,
+    server_config: ServerConfig,
+) -> anyhow::Result<Rc<RefCell<ServerStates>>> {
+    let server_config = Arc::new(server_config);
+    Ok(Rc::new(RefCell::new(ServerStates::new(
+        server_config,
+        local_addr,
+        opentelemetry::global::meter(format!("{}", local_addr)),
+    )?)))
+}
+
+#[test]
+fn test_server_pipeline_stun_binding() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let peer_addr: SocketAddr = "127.0.0.1:50000".parse()?;
+    let pipeline = ServerPipeline::new(new_server_states(local_addr)?);
+
+    // the same pipeline handles consecutive requests
+    for _ in 0..2 {
+        let mut request = Message::new();
+        request.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+
+        let now = Instant::now();
+        let transmits = pipeline.handle_read(TaggedBytesMut {
+            now,
+            transport: TransportContext {
+                local_addr,
+                peer_addr,
+                ecn: None,
+            },
+            message: BytesMut::from(&request.raw[..]),
+        });
+        assert_eq!(transmits.len(), 1);
+        assert_eq!(transmits[0].transport.peer_addr, peer_addr);
+
+        let mut response = Message {
+            raw: transmits[0].message.to_vec(),
+            ..Default::default()
+        };
+        response.decode()?;
+        assert_eq!(response.typ, BINDING_SUCCESS);
+        assert_eq!(response.transaction_id, request.transaction_id);
+
+        let mut xor_addr = XorMappedAddress::default();
+        xor_addr.get_from(&response)?;
+        assert_eq!(xor_addr.ip, peer_addr.ip());
+        assert_eq!(xor_addr.port, peer_addr.port());
+
+        // nothing else is due, e.g., without any endpoint
+        let eto = now + Duration::from_secs(60);
+        assert!(pipeline.poll_timeout(eto) <= eto);
+        assert!(pipeline.handle_timeout(now).is_empty());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_server_pipeline_stun_unknown_attributes() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let peer_addr: SocketAddr = "127.0.0.1:50000".parse()?;
+    let pipeline = ServerPipeline::new(new_server_states(local_addr)?);
+
+    let binding = |attributes: &[(AttrType, &[u8])]| -> anyhow::Result<Message> {
+        let mut request = Message::new();
+        request.build(&[Box::new(TransactionId::new()), Box::new(BINDING_REQUEST)])?;
+        for (typ, value) in attributes {
+            request.add(*typ, value);
+        }
+        let transmits = pipeline.handle_read(TaggedBytesMut {
+            now: Instant::now(),
+            transport: TransportContext {
+                local_addr,
+                peer_addr,
+                ecn: None,
+            },
+            message: BytesMut::from(&request.raw[..]),
+        });
+        assert_eq!(transmits.len(), 1);
+        let mut response = Message {
+            raw: transmits[0].message.to_vec(),
+            ..Default::default()
+        };
+        response.decode()?;
+        Ok(response)
+    };
+
+    // comprehension-optional attributes are tolerated, known or not
+    let response = binding(&[
+        (ATTR_ICE_CONTROLLING, &[0u8; 8]),
+        (AttrType(0xC0DE), b"benign"),
+    ])?;
+    assert_eq!(response.typ, BINDING_SUCCESS);
+
+    // unknown comprehension-required attributes are rejected with 420
+    let response = binding(&[(AttrType(0x0042), b"required")])?;
+    assert_eq!(response.typ, BINDING_ERROR);
+    let mut error_code = ErrorCodeAttribute::default();
+    error_code.get_from(&response)?;
+    assert!(error_code.code == CODE_UNKNOWN_ATTRIBUTE);
+    let mut unknown_attributes = UnknownAttributes(vec![]);
+    unknown_attributes.get_from(&response)?;
+    assert_eq!(unknown_attributes.0, vec![AttrType(0x0042)]);
+
+    Ok(())
+}
+
+#[test]
+fn test_ice_credential_lengths() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states = new_server_states_with_config(
+        local_addr,
+        new_server_config()?.with_ice_credential_lengths(4, 22),
+    )?;
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    let answer = server_states.borrow_mut().accept_offer(1, 1, None, offer)?;
+
+    let ufrag = answer
+        .sdp
+        .lines()
+        .find_map(|line| line.strip_prefix("a=ice-ufrag:"))
+        .expect("answer without ice-ufrag");
+    let pwd = answer
+        .sdp
+        .lines()
+        .find_map(|line| line.strip_prefix("a=ice-pwd:"))
+        .expect("answer without ice-pwd");
+    assert_eq!(ufrag.len(), 4);
+    assert_eq!(pwd.len(), 22);
+
+    Ok(())
+}
+
+#[test]
+fn test_prepare_candidate() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states = new_server_states(local_addr)?;
+
+    let (ice_ufrag, ice_pwd) = server_states.borrow_mut().prepare_candidate(1, 1)?;
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    let answer = server_states.borrow_mut().accept_offer(1, 1, None, offer)?;
+    assert!(answer
+        .sdp
+        .contains(&format!("a=ice-ufrag:{}\r\n", ice_ufrag)));
+    assert!(answer.sdp.contains(&format!("a=ice-pwd:{}\r\n", ice_pwd)));
+
+    // joined endpoint can't be prepared again
+    assert!(server_states.borrow_mut().prepare_candidate(1, 1).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_ice_credential_lengths_out_of_bounds() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    assert!(new_server_states_with_config(
+        local_addr,
+        new_server_config()?.with_ice_credential_lengths(3, 22),
+    )
+    .is_err());
+    assert!(new_server_states_with_config(
+        local_addr,
+        new_server_config()?.with_ice_credential_lengths(4, 257),
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_sharded_server_states() -> anyhow::Result<()> {
+    let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+    let certificates = vec![RTCCertificate::from_key_pair(key_pair)?];
+    let sharded = ShardedServerStates::<2>::new(
+        Arc::new(ServerConfig::new(certificates)),
+        ["127.0.0.1:3478".parse()?, "127.0.0.1:3479".parse()?],
+        opentelemetry::global::meter("sharded"),
+    )?;
+
+    assert_eq!(ShardedServerStates::<2>::shard_index(4), 0);
+    assert_eq!(ShardedServerStates::<2>::shard_index(7), 1);
+    assert!(Rc::ptr_eq(sharded.shard(7), &sharded.shards()[1]));
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    let answer = sharded.accept_offer(7, 1, None, offer)?;
+    assert!(answer.sdp.contains("a=mid:0"));
+
+    Ok(())
+}
+
+#[test]
+fn test_shard_configs_per_thread() -> anyhow::Result<()> {
+    let key_pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+    let certificates = vec![RTCCertificate::from_key_pair(key_pair)?];
+    let local_addrs: [SocketAddr; 2] = ["127.0.0.1:3478".parse()?, "127.0.0.1:3479".parse()?];
+    let shard_configs =
+        ShardConfig::new_shards(Arc::new(ServerConfig::new(certificates)), &local_addrs)?;
+    assert!(ShardConfig::new_shards(Arc::new(new_server_config()?), &[]).is_err());
+
+    // sessions are routed the same way as ShardedServerStates does
+    let session_id = 7;
+    assert_eq!(
+        ShardConfig::shard_index(session_id, shard_configs.len()),
+        ShardedServerStates::<2>::shard_index(session_id)
+    );
+
+    // each thread builds and owns its shard
+    let handles: Vec<_> = shard_configs
+        .into_iter()
+        .map(|shard_config| {
+            std::thread::spawn(move || -> anyhow::Result<(usize, SocketAddr, bool)> {
+                let (index, local_addr) = (shard_config.index(), shard_config.local_addr());
+                let mut server_states =
+                    shard_config.build(opentelemetry::global::meter("shard"))?;
+                let is_routed = ShardConfig::shard_index(session_id, 2) == index;
+                if is_routed {
+                    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+                    server_states.accept_offer(session_id, 1, None, offer)?;
+                }
+                Ok((index, local_addr, is_routed))
+            })
+        })
+        .collect();
+    let mut shards = vec![];
+    for handle in handles {
+        shards.push(handle.join().unwrap()?);
+    }
+    assert_eq!(
+        shards,
+        vec![(0, local_addrs[0], false), (1, local_addrs[1], true)]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_max_sessions() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states =
+        new_server_states_with_config(local_addr, new_server_config()?.with_max_sessions(1))?;
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    server_states
+        .borrow_mut()
+        .accept_offer(1, 1, None, offer.clone())?;
+
+    // existing session still accepts endpoints
+    server_states
+        .borrow_mut()
+        .accept_offer(1, 2, None, offer.clone())?;
+
+    let result = server_states.borrow_mut().accept_offer(2, 3, None, offer);
+    assert!(result.is_err_and(|err| err.to_string().contains("ErrMaxSessionsExceeded")));
+
+    Ok(())
+}
+
+#[test]
+fn test_max_sessions_across_shards() -> anyhow::Result<()> {
+    let local_addrs: [SocketAddr; 2] = ["127.0.0.1:3478".parse()?, "127.0.0.1:3479".parse()?];
+    let mut shards = ShardConfig::new_shards(
+        Arc::new(new_server_config()?.with_max_sessions(1)),
+        &local_addrs,
+    )?
+    .into_iter()
+    .map(|shard_config| shard_config.build(opentelemetry::global::meter("shard")))
+    .collect::<Result<Vec<_>, _>>()?;
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    shards[0].accept_offer(2, 1, None, offer.clone())?;
+
+    // the session of the other shard counts against the same cap
+    let result = shards[1].accept_offer(3, 2, None, offer.clone());
+    assert!(result.is_err_and(|err| err.to_string().contains("ErrMaxSessionsExceeded")));
+
+    // until the other shard's session is gone
+    shards[0].close();
+    shards[1].accept_offer(3, 2, None, offer)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_accept_offer_idempotency() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states = new_server_states(local_addr)?;
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    let answer = server_states
+        .borrow_mut()
+        .accept_offer(1, 1, None, offer.clone())?;
+    let retried_answer = server_states.borrow_mut().accept_offer(1, 1, None, offer)?;
+    assert_eq!(answer.sdp, retried_answer.sdp);
+    assert_eq!(retried_answer.sdp.matches("a=mid:").count(), 1);
+
+    // a genuinely new offer is negotiated again
+    let new_offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.replace("EsAw", "EsAx"))?;
+    let new_answer = server_states
+        .borrow_mut()
+        .accept_offer(1, 1, None, new_offer)?;
+    assert_ne!(answer.sdp, new_answer.sdp);
+
+    Ok(())
+}
+
+#[test]
+fn test_answer_cache() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states = new_server_states(local_addr)?;
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    let answers = (0..2)
+        .map(|_| {
+            server_states
+                .borrow_mut()
+                .accept_offer(1, 1, None, offer.clone())
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(answers[0].sdp.as_bytes(), answers[1].sdp.as_bytes());
+
+    // a changed offer busts the cache, so that the original offer is negotiated again
+    let changed_offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.replace("EsAw", "EsAx"))?;
+    let changed_answer = server_states
+        .borrow_mut()
+        .accept_offer(1, 1, None, changed_offer)?;
+    assert_ne!(answers[0].sdp, changed_answer.sdp);
+    let renegotiated_answer = server_states.borrow_mut().accept_offer(1, 1, None, offer)?;
+    assert_ne!(answers[0].sdp, renegotiated_answer.sdp);
+
+    Ok(())
+}
+
+#[test]
+fn test_migrate_endpoint_errors() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states = new_server_states(local_addr)?;
+
+    let result = server_states.borrow_mut().migrate_endpoint(1, 1, 1);
+    assert!(result.is_err_and(|err| err.to_string().contains("ErrMigrateEndpointToSameSession")));
+
+    // endpoint is not connected yet, only its candidate is pending
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    server_states.borrow_mut().accept_offer(1, 1, None, offer)?;
+    assert!(server_states
+        .borrow_mut()
+        .migrate_endpoint(1, 1, 2)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_sdp_log() -> anyhow::Result<()> {
+    let local_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let server_states =
+        new_server_states_with_config(local_addr, new_server_config()?.with_sdp_log_capacity(3))?;
+
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.to_string())?;
+    let first_answer = server_states.borrow_mut().accept_offer(1, 1, None, offer)?;
+    let offer = RTCSessionDescription::offer(DATA_CHANNEL_OFFER.replace("EsAw", "EsAx"))?;
+    let second_answer = server_states.borrow_mut().accept_offer(1, 2, None, offer)?;
+
+    // ring buffer keeps the latest 3 of 4 descriptions
+    let sdp_log = server_states
+        .borrow()
+        .get_sdp_log(1)
+        .expect("session without SDP log");
+    assert_eq!(sdp_log.len(), 3);
+    assert_eq!(sdp_log[0].endpoint_id, 1);
+    assert!(sdp_log[0].local);
+    assert_eq!(sdp_log[0].description.sdp, first_answer.sdp);
+    assert_eq!(sdp_log[1].endpoint_id, 2);
+    assert!(!sdp_log[1].local);
+    assert_eq!(sdp_log[1].description.sdp_type, RTCSdpType::Offer);
+    assert!(sdp_log[1].description.sdp.contains("EsAx"));
+    assert_eq!(sdp_log[2].description.sdp, second_answer.sdp);
+    assert!(sdp_log[0].timestamp <= sdp_log[2].timestamp);
+
+    Ok(())
+}
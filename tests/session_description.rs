@@ -0,0 +1,1109 @@
+use crate::common::loopback::{new_loopback_server_config, LoopbackPeer, LoopbackTransport};
+use bytes::Bytes;
+use sfu::{
+    intersect_fmtp, resolve_header_extension_ids, FourTuple, MediaConfig,
+    RTCRtpHeaderExtensionParameters, RTCRtpRid, RTCRtpSimulcast, RTCSdpType, RTCSessionDescription,
+    SimulcastDirection,
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[test]
+fn test_rid_restrictions() -> anyhow::Result<()> {
+    let rid = RTCRtpRid::try_from("hi recv max-width=1280;max-height=720")?;
+
+    assert_eq!(rid.id, "hi");
+    assert_eq!(rid.direction, SimulcastDirection::Recv);
+    assert!(rid.payload_types.is_empty());
+    assert_eq!(rid.restrictions.max_width, Some(1280));
+    assert_eq!(rid.restrictions.max_height, Some(720));
+    assert_eq!(rid.restrictions.max_fps, None);
+
+    let rid = RTCRtpRid::try_from("lo send pt=96,97;max-fps=15;depend=hi")?;
+    assert_eq!(rid.direction, SimulcastDirection::Send);
+    assert_eq!(rid.payload_types, vec![96, 97]);
+    assert_eq!(rid.restrictions.max_fps, Some(15.0));
+    assert_eq!(rid.restrictions.depend, vec!["hi".to_string()]);
+
+    assert!(RTCRtpRid::try_from("hi").is_err());
+    assert!(RTCRtpRid::try_from("hi recv max-width=wide").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_simulcast_rids() -> anyhow::Result<()> {
+    let simulcast = RTCRtpSimulcast::try_from("recv hi;mid;lo")?;
+    assert_eq!(
+        simulcast.rids(SimulcastDirection::Recv),
+        vec!["hi".to_string(), "mid".to_string(), "lo".to_string()]
+    );
+    assert!(simulcast.rids(SimulcastDirection::Send).is_empty());
+
+    // alternatives of a stream, paused rids and both directions
+    let simulcast = RTCRtpSimulcast::try_from("send rid1;rid2,~rid3 recv rid4")?;
+    assert_eq!(
+        simulcast.send,
+        vec![
+            vec!["rid1".to_string()],
+            vec!["rid2".to_string(), "rid3".to_string()]
+        ]
+    );
+    assert_eq!(simulcast.recv, vec![vec!["rid4".to_string()]]);
+
+    assert!(RTCRtpSimulcast::try_from("").is_err());
+    assert!(RTCRtpSimulcast::try_from("send").is_err());
+    assert!(RTCRtpSimulcast::try_from("send a;;b").is_err());
+    assert!(RTCRtpSimulcast::try_from("both a;b").is_err());
+
+    Ok(())
+}
+
+fn new_media_offer(media: &str, rtpmap: &str) -> String {
+    format!(
+        "v=0\r
+o=- 4215775240449105457 2 IN IP4 127.0.0.1\r
+s=-\r
+t=0 0\r
+a=group:BUNDLE 0\r
+m={media} 9 UDP/TLS/RTP/SAVPF 96\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:EsAw\r
+a=ice-pwd:bP+XJMM09aR8AiX1jdukzR6Y\r
+a=fingerprint:sha-256 0F:74:31:25:CB:A2:13:EC:28:6F:6D:2C:61:FF:5D:C2:BC:B9:DB:3D:98:14:8D:1A:BB:EA:33:0C:A4:60:A8:8E\r
+a=setup:actpass\r
+a=mid:0\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:96 {rtpmap}\r
+"
+    )
+}
+
+#[test]
+fn test_codec_clock_rate_validation() -> anyhow::Result<()> {
+    let result = RTCSessionDescription::offer(new_media_offer("video", "VP8/8000"));
+    assert!(result.is_err_and(|err| err.to_string().contains("video clock rate must be 90000")));
+    RTCSessionDescription::offer(new_media_offer("video", "VP8/90000"))?;
+
+    let result = RTCSessionDescription::offer(new_media_offer("audio", "opus/44100/2"));
+    assert!(result.is_err_and(|err| err.to_string().contains("audio clock rate 44100")));
+    RTCSessionDescription::offer(new_media_offer("audio", "opus/48000/2"))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_sdp_type_setup_role_validation() -> anyhow::Result<()> {
+    // an offer may be recvonly, and must leave the DTLS role to the answerer by actpass
+    RTCSessionDescription::offer(new_media_offer("video", "VP8/90000"))?;
+    RTCSessionDescription::offer(
+        new_media_offer("video", "VP8/90000").replace("a=sendonly", "a=recvonly"),
+    )?;
+
+    // while an answer must choose a DTLS role, whatever its directions are
+    let result = RTCSessionDescription::answer(
+        new_media_offer("video", "VP8/90000").replace("a=sendonly", "a=recvonly"),
+    );
+    assert!(result.is_err_and(|err| err.to_string().contains("AnswerWithActpass")));
+    RTCSessionDescription::answer(
+        new_media_offer("video", "VP8/90000")
+            .replace("a=sendonly", "a=recvonly")
+            .replace("a=setup:actpass", "a=setup:active"),
+    )?;
+    let result = RTCSessionDescription::pranswer(new_media_offer("video", "VP8/90000"));
+    assert!(result.is_err_and(|err| err.to_string().contains("AnswerWithActpass")));
+    RTCSessionDescription::pranswer(
+        new_media_offer("video", "VP8/90000").replace("a=setup:actpass", "a=setup:passive"),
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn test_ice_credentials_validation() {
+    let result = RTCSessionDescription::offer(
+        new_media_offer("video", "VP8/90000").replace("a=ice-ufrag:EsAw\r\n", ""),
+    );
+    assert!(result.is_err_and(|err| err
+        .to_string()
+        .contains("ErrSessionDescriptionMissingIceUfrag")));
+    let result = RTCSessionDescription::offer(
+        new_media_offer("video", "VP8/90000").replace("a=ice-pwd:bP+XJMM09aR8AiX1jdukzR6Y\r\n", ""),
+    );
+    assert!(result.is_err_and(|err| err
+        .to_string()
+        .contains("ErrSessionDescriptionMissingIcePwd")));
+}
+
+#[test]
+fn test_get_payload_type_for_mime() {
+    let media_config = MediaConfig::default();
+    assert_eq!(
+        media_config.get_payload_type_for_mime("audio/opus"),
+        Some(111)
+    );
+    assert_eq!(
+        media_config.get_payload_type_for_mime("video/vp8"),
+        Some(96)
+    );
+    assert_eq!(
+        media_config.get_payload_type_for_mime("video/unknown"),
+        None
+    );
+}
+
+#[test]
+fn test_intersect_fmtp() {
+    // configured opus capabilities narrowed by offered params
+    assert_eq!(
+        intersect_fmtp(
+            "audio/opus",
+            "minptime=10;useinbandfec=1;maxplaybackrate=48000;stereo=1;usedtx=1",
+            "minptime=10;useinbandfec=1;maxplaybackrate=16000;stereo=0;usedtx=1",
+        ),
+        "minptime=10;useinbandfec=1;maxplaybackrate=16000;stereo=0;usedtx=1"
+    );
+    assert_eq!(
+        intersect_fmtp(
+            "audio/opus",
+            "minptime=10;useinbandfec=1",
+            "maxplaybackrate=24000;stereo=1",
+        ),
+        "minptime=10;useinbandfec=1;maxplaybackrate=24000;stereo=0"
+    );
+
+    // only level of H264 profile-level-id is narrowed
+    assert_eq!(
+        intersect_fmtp(
+            "video/H264",
+            "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f",
+            "packetization-mode=1;profile-level-id=42e015",
+        ),
+        "level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e015"
+    );
+    assert_eq!(
+        intersect_fmtp(
+            "video/H264",
+            "packetization-mode=1;profile-level-id=42e01f",
+            "packetization-mode=1;profile-level-id=640c34",
+        ),
+        "packetization-mode=1;profile-level-id=42e01f"
+    );
+}
+
+fn extension(uri: &str, id: isize) -> RTCRtpHeaderExtensionParameters {
+    RTCRtpHeaderExtensionParameters {
+        uri: uri.to_string(),
+        id,
+    }
+}
+
+#[test]
+fn test_resolve_header_extension_ids() {
+    let local = vec![
+        extension("urn:ietf:params:rtp-hdrext:sdes:mid", 1),
+        extension("urn:ietf:params:rtp-hdrext:toffset", 2),
+        extension("urn:3gpp:video-orientation", 3),
+    ];
+    // the offer uses our ids 1 and 3 for other uris
+    let remote = vec![
+        extension("urn:ietf:params:rtp-hdrext:sdes:mid", 3),
+        extension("urn:ietf:params:rtp-hdrext:ssrc-audio-level", 1),
+    ];
+
+    let resolved = resolve_header_extension_ids(&local, &remote, false);
+    assert_eq!(
+        resolved,
+        vec![
+            extension("urn:ietf:params:rtp-hdrext:sdes:mid", 3),
+            extension("urn:ietf:params:rtp-hdrext:toffset", 2),
+            extension("urn:3gpp:video-orientation", 4),
+        ]
+    );
+
+    // no free one-byte id left unless extmap-allow-mixed is negotiated
+    let remote: Vec<_> = (1..=14)
+        .map(|id| extension(&format!("urn:example:ext{}", id), id))
+        .collect();
+    let local = vec![extension("urn:ietf:params:rtp-hdrext:toffset", 2)];
+    assert!(resolve_header_extension_ids(&local, &remote, false).is_empty());
+    assert_eq!(
+        resolve_header_extension_ids(&local, &remote, true),
+        vec![extension("urn:ietf:params:rtp-hdrext:toffset", 15)]
+    );
+}
+
+/// connect a peer with a data channel only offer, so that media sections are negotiated
+/// by renegotiation, which sets the offers as remote descriptions of the connected endpoint
+fn connect_peer(
+    transport: &mut LoopbackTransport,
+    endpoint_id: u64,
+    peer_addr: &str,
+) -> anyhow::Result<LoopbackPeer> {
+    let mut peer = LoopbackPeer::new(
+        peer_addr.parse()?,
+        &format!("ufr{}", endpoint_id),
+        &format!("pwd{:0>21}", endpoint_id),
+    );
+    let answer =
+        transport
+            .server_states()
+            .borrow_mut()
+            .accept_offer(1, endpoint_id, None, peer.offer()?)?;
+    peer.accept_answer(&answer);
+    peer.connect(transport)?;
+    Ok(peer)
+}
+
+fn renegotiate(
+    transport: &mut LoopbackTransport,
+    endpoint_id: u64,
+    peer: &LoopbackPeer,
+    mids: &[&str],
+    media_sections: &str,
+) -> anyhow::Result<RTCSessionDescription> {
+    let sfu_addr = transport.local_addr();
+    Ok(transport.server_states().borrow_mut().accept_offer(
+        1,
+        endpoint_id,
+        Some(FourTuple {
+            local_addr: sfu_addr,
+            peer_addr: peer.addr(),
+        }),
+        peer.offer_with_media(mids, media_sections)?,
+    )?)
+}
+
+fn media_section(media: &str, port: u16, mid: &str, direction: &str, extra: &str) -> String {
+    let (payload_type, rtpmap) = if media == "audio" {
+        (111, "opus/48000/2")
+    } else {
+        (96, "VP8/90000")
+    };
+    format!(
+        "m={media} {port} UDP/TLS/RTP/SAVPF {payload_type}\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{{ufrag}}\r
+a=ice-pwd:{{pwd}}\r
+a=setup:actpass\r
+a=mid:{mid}\r
+a={direction}\r
+a=rtcp-mux\r
+a=rtpmap:{payload_type} {rtpmap}\r
+{extra}"
+    )
+}
+
+/// answered returns (media, mid, direction) of the answer's media sections in order
+fn answered(answer: &RTCSessionDescription) -> Vec<(String, String, String)> {
+    let mut sections = vec![];
+    for line in answer.sdp.lines() {
+        if let Some(media_name) = line.strip_prefix("m=") {
+            let media = media_name.split(' ').next().unwrap_or_default();
+            sections.push((media.to_string(), String::new(), String::new()));
+        } else if let Some(section) = sections.last_mut() {
+            if let Some(mid) = line.strip_prefix("a=mid:") {
+                section.1 = mid.to_string();
+            } else if matches!(
+                line,
+                "a=sendrecv" | "a=sendonly" | "a=recvonly" | "a=inactive"
+            ) {
+                section.2 = line[2..].to_string();
+            }
+        }
+    }
+    sections
+}
+
+fn section(media: &str, mid: &str, direction: &str) -> (String, String, String) {
+    (media.to_string(), mid.to_string(), direction.to_string())
+}
+
+#[test]
+fn test_set_remote_description_audio_and_video() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let peer = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+
+    let answer = renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1", "2"],
+        &(media_section("audio", 9, "1", "sendrecv", "")
+            + &media_section("video", 9, "2", "sendonly", "")),
+    )?;
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("audio", "1", "recvonly"),
+            section("video", "2", "recvonly"),
+        ]
+    );
+    let server_states = transport.server_states();
+    let server_states = server_states.borrow();
+    let audio_codec = server_states
+        .get_negotiated_codec(1, 1, "1")
+        .expect("no negotiated codec for mid 1");
+    assert_eq!(
+        audio_codec.capability.mime_type.to_lowercase(),
+        "audio/opus"
+    );
+    let video_codec = server_states
+        .get_negotiated_codec(1, 1, "2")
+        .expect("no negotiated codec for mid 2");
+    assert_eq!(video_codec.capability.mime_type.to_lowercase(), "video/vp8");
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_simulcast() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let peer = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+
+    let answer = renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1"],
+        &media_section(
+            "video",
+            9,
+            "1",
+            "sendonly",
+            "a=rid:h send max-width=1280\r
+a=rid:m send max-width=640\r
+a=rid:l send max-width=320\r
+a=simulcast:send h;m;l\r
+",
+        ),
+    )?;
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("video", "1", "recvonly"),
+        ]
+    );
+    // all offered rids are received
+    let rids: Vec<&str> = answer
+        .sdp
+        .lines()
+        .filter_map(|line| line.strip_prefix("a=rid:"))
+        .collect();
+    assert_eq!(rids, vec!["h recv", "m recv", "l recv"]);
+    let simulcast = answer
+        .sdp
+        .lines()
+        .find_map(|line| line.strip_prefix("a=simulcast:recv "))
+        .expect("no simulcast attribute in answer");
+    // in the order of the offered simulcast streams
+    assert_eq!(simulcast, "h;m;l");
+
+    // restrictions of the publisher's rids are available for layer selection
+    let restrictions = transport
+        .server_states()
+        .borrow()
+        .get_rid_restrictions(1, 1, "1")
+        .expect("no transceiver for mid 1");
+    let max_widths: HashMap<&str, Option<u32>> = restrictions
+        .iter()
+        .map(|(rid, restrictions)| (rid.as_str(), restrictions.max_width))
+        .collect();
+    assert_eq!(
+        max_widths,
+        HashMap::from([("h", Some(1280)), ("m", Some(640)), ("l", Some(320))])
+    );
+    assert!(transport
+        .server_states()
+        .borrow()
+        .get_rid_restrictions(1, 1, "2")
+        .is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_add_video_track() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let peer = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+
+    let first_video = media_section("video", 9, "1", "sendonly", "");
+    renegotiate(&mut transport, 1, &peer, &["1"], &first_video)?;
+    let answer = renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1", "2"],
+        &(first_video.clone() + &media_section("video", 9, "2", "sendonly", "")),
+    )?;
+    // the existing transceiver keeps its mid, and the new one is appended after it
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("video", "1", "recvonly"),
+            section("video", "2", "recvonly"),
+        ]
+    );
+    assert!(transport
+        .server_states()
+        .borrow()
+        .get_negotiated_codec(1, 1, "2")
+        .is_some());
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_media_kind_mismatch() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let peer = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+
+    renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1"],
+        &media_section("audio", 9, "1", "sendonly", ""),
+    )?;
+    // mid 1 is an audio section, so it can't be re-offered as video
+    let err = renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1"],
+        &media_section("video", 9, "1", "sendonly", ""),
+    )
+    .expect_err("video section of audio mid is accepted");
+    assert!(
+        err.to_string()
+            .contains("ErrPeerConnTransceiverKindMismatch"),
+        "{}",
+        err
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_rejected_media_section() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let peer = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+
+    let answer = renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1", "2"],
+        &(media_section("audio", 0, "1", "inactive", "")
+            + &media_section("video", 9, "2", "sendonly", "")),
+    )?;
+    // the rejected section is still answered in place, but with port 0 and inactive
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("audio", "1", "inactive"),
+            section("video", "2", "recvonly"),
+        ]
+    );
+    assert!(answer.sdp.contains("m=audio 0 "), "{}", answer.sdp);
+    assert!(answer.sdp.contains("m=video 9 "), "{}", answer.sdp);
+    assert!(
+        answer.sdp.contains("a=group:BUNDLE 0 2\r\n"),
+        "{}",
+        answer.sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_reject_active_media_section() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let mut publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut subscriber = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    publisher.open_data_channel(&mut transport)?;
+    subscriber.open_data_channel(&mut transport)?;
+    let recv_offers = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_data_channel(transport).map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let video = |port: u16| {
+        media_section(
+            "video",
+            port,
+            "1",
+            "sendonly",
+            "a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+        )
+    };
+    renegotiate(&mut transport, 1, &publisher, &["1"], &video(9))?;
+
+    // subscriber is offered the track and answers it
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(offers.len(), 1);
+    assert!(offers[0].sdp.contains("m=video 9 "), "{}", offers[0].sdp);
+    let answer = RTCSessionDescription::answer(
+        offers[0]
+            .sdp
+            .replace("a=sendonly", "a=recvonly")
+            .replace("a=setup:actpass", "a=setup:active"),
+    )?;
+    subscriber.send_data_channel(&mut transport, serde_json::to_string(&answer)?.as_bytes())?;
+
+    let packet = |sequence_number: u16| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"rejected"),
+    };
+    publisher.send_rtp(&mut transport, &packet(1))?;
+    assert_eq!(subscriber.recv_rtp(&mut transport)?.len(), 1);
+
+    // publisher re-offers the section with port 0, which removes its track
+    let answer = renegotiate(&mut transport, 1, &publisher, &["1"], &video(0))?;
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("video", "1", "inactive"),
+        ]
+    );
+    assert!(answer.sdp.contains("m=video 0 "), "{}", answer.sdp);
+
+    publisher.send_rtp(&mut transport, &packet(2))?;
+    assert!(subscriber.recv_rtp(&mut transport)?.is_empty());
+
+    // subscriber's forwarding section is rejected by the next offer too
+    transport.handle_timeout(Instant::now() + Duration::from_secs(2));
+    let offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(offers.len(), 1);
+    assert!(offers[0].sdp.contains("m=video 0 "), "{}", offers[0].sdp);
+    assert!(offers[0].sdp.contains("a=mid:1-1\r\n"), "{}", offers[0].sdp);
+    assert!(
+        offers[0].sdp.contains("a=inactive\r\n"),
+        "{}",
+        offers[0].sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_codec_preferences_renegotiation() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut subscriber = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    subscriber.open_data_channel(&mut transport)?;
+    let recv_offers = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_data_channel(transport).map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // publisher offers both VP8 and VP9
+    renegotiate(
+        &mut transport,
+        1,
+        &publisher,
+        &["1"],
+        "m=video 9 UDP/TLS/RTP/SAVPF 96 98\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sendonly\r
+a=rtcp-mux\r
+a=rtpmap:96 VP8/90000\r
+a=rtpmap:98 VP9/90000\r
+a=fmtp:98 profile-id=0\r
+a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+    )?;
+
+    // subscriber is offered the track in registration order and answers it
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(offers.len(), 1);
+    assert!(
+        offers[0]
+            .sdp
+            .contains("m=video 9 UDP/TLS/RTP/SAVPF 96 98 100\r\n"),
+        "{}",
+        offers[0].sdp
+    );
+    let answer = RTCSessionDescription::answer(
+        offers[0]
+            .sdp
+            .replace("a=sendonly", "a=recvonly")
+            .replace("a=setup:actpass", "a=setup:active"),
+    )?;
+    subscriber.send_data_channel(&mut transport, serde_json::to_string(&answer)?.as_bytes())?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(2));
+    assert!(recv_offers(&mut transport, &mut subscriber)?.is_empty());
+
+    // preferring VP9 mid-session re-offers the track with VP9 first
+    transport
+        .server_states()
+        .borrow_mut()
+        .set_codec_preferences(1, vec!["video/VP9".to_string()])?;
+    assert!(transport
+        .server_states()
+        .borrow_mut()
+        .set_codec_preferences(2, vec![])
+        .is_err());
+    transport.handle_timeout(Instant::now() + Duration::from_secs(3));
+    let offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(offers.len(), 1);
+    assert!(
+        offers[0]
+            .sdp
+            .contains("m=video 9 UDP/TLS/RTP/SAVPF 98 100 96\r\n"),
+        "{}",
+        offers[0].sdp
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_malformed_ssrc() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut subscriber = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    subscriber.open_data_channel(&mut transport)?;
+
+    // the malformed ssrc is skipped instead of failing the whole offer
+    let answer = renegotiate(
+        &mut transport,
+        1,
+        &publisher,
+        &["1"],
+        &media_section(
+            "video",
+            9,
+            "1",
+            "sendonly",
+            "a=msid:stream video\r
+a=ssrc:12x34 cname:publisher\r
+a=ssrc:1234 cname:publisher\r
+a=ssrc-group:FID 1234 56x78\r
+",
+        ),
+    )?;
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("video", "1", "recvonly"),
+        ]
+    );
+
+    // subscriber is offered the valid ssrc only
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let offers: Vec<RTCSessionDescription> = subscriber
+        .recv_data_channel(&mut transport)?
+        .iter()
+        .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+        .collect();
+    assert_eq!(offers.len(), 1);
+    assert!(offers[0].sdp.contains("a=ssrc:1234 "), "{}", offers[0].sdp);
+    assert!(!offers[0].sdp.contains("12x34"), "{}", offers[0].sdp);
+
+    Ok(())
+}
+
+#[test]
+fn test_set_remote_description_conflicting_data_mid() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let mut peer = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let audio = media_section(
+        "audio",
+        9,
+        "1",
+        "sendonly",
+        "a=msid:stream audio\r
+a=ssrc:1234 cname:publisher\r
+",
+    );
+    renegotiate(&mut transport, 1, &peer, &["1"], &audio)?;
+
+    // a buggy client reuses the audio's mid 1 for another data section
+    let answer = renegotiate(
+        &mut transport,
+        1,
+        &peer,
+        &["1"],
+        "m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r
+c=IN IP4 0.0.0.0\r
+a=ice-ufrag:{ufrag}\r
+a=ice-pwd:{pwd}\r
+a=setup:actpass\r
+a=mid:1\r
+a=sctp-port:5000\r
+",
+    )?;
+    // only the conflicting section is rejected, and the data channel stays usable
+    assert_eq!(
+        answered(&answer),
+        vec![
+            section("application", "0", "sendrecv"),
+            section("application", "1", "sendrecv"),
+        ]
+    );
+    assert!(
+        answer
+            .sdp
+            .contains("m=application 0 UDP/DTLS/SCTP webrtc-datachannel\r\n"),
+        "{}",
+        answer.sdp
+    );
+    assert!(
+        answer.sdp.contains("a=group:BUNDLE 0\r\n"),
+        "{}",
+        answer.sdp
+    );
+    peer.open_data_channel(&mut transport)?;
+
+    Ok(())
+}
+
+/// forwarded_ssrc returns the ssrc which the offer announces for the media section of mid
+fn forwarded_ssrc(offer: &RTCSessionDescription, mid: &str) -> Option<u32> {
+    let section = offer
+        .sdp
+        .split("m=")
+        .find(|section| section.contains(&format!("a=mid:{}\r\n", mid)))?;
+    section
+        .lines()
+        .find_map(|line| line.strip_prefix("a=ssrc:"))
+        .and_then(|ssrc| ssrc.split(' ').next())
+        .and_then(|ssrc| ssrc.parse().ok())
+}
+
+#[test]
+fn test_set_remote_description_colliding_ssrc() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let mut first_publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut second_publisher = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    let mut subscriber = connect_peer(&mut transport, 3, "127.0.0.1:50002")?;
+    subscriber.open_data_channel(&mut transport)?;
+    let recv_offers = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_data_channel(transport).map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let video = |port: u16, mid: &str| {
+        media_section(
+            "video",
+            port,
+            mid,
+            "sendonly",
+            "a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+        )
+    };
+    // both publishers send with the same ssrc, the later one is remapped
+    renegotiate(&mut transport, 1, &first_publisher, &["1"], &video(9, "1"))?;
+    renegotiate(&mut transport, 2, &second_publisher, &["1"], &video(9, "1"))?;
+
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(offers.len(), 1);
+    assert_eq!(forwarded_ssrc(&offers[0], "1-1"), Some(1234));
+    let remapped_ssrc = forwarded_ssrc(&offers[0], "2-1").unwrap();
+    assert_ne!(remapped_ssrc, 1234);
+    let answer = RTCSessionDescription::answer(
+        offers[0]
+            .sdp
+            .replace("a=sendonly", "a=recvonly")
+            .replace("a=setup:actpass", "a=setup:active"),
+    )?;
+    subscriber.send_data_channel(&mut transport, serde_json::to_string(&answer)?.as_bytes())?;
+
+    let packet = |sequence_number: u16| rtp::packet::Packet {
+        header: rtp::header::Header {
+            version: 2,
+            payload_type: 96,
+            sequence_number,
+            ssrc: 1234,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(b"colliding"),
+    };
+    first_publisher.send_rtp(&mut transport, &packet(1))?;
+    second_publisher.send_rtp(&mut transport, &packet(1))?;
+    let ssrcs: Vec<u32> = subscriber
+        .recv_rtp(&mut transport)?
+        .iter()
+        .map(|packet| packet.header.ssrc)
+        .collect();
+    assert_eq!(ssrcs, vec![1234, remapped_ssrc]);
+
+    // the second publisher moves its track to a new media section, which keeps the remap
+    renegotiate(
+        &mut transport,
+        2,
+        &second_publisher,
+        &["1", "2"],
+        &format!("{}{}", video(0, "1"), video(9, "2")),
+    )?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(2));
+    let offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(offers.len(), 1);
+    assert_eq!(forwarded_ssrc(&offers[0], "1-1"), Some(1234));
+    assert_eq!(forwarded_ssrc(&offers[0], "2-2"), Some(remapped_ssrc));
+
+    second_publisher.send_rtp(&mut transport, &packet(2))?;
+    first_publisher.send_rtp(&mut transport, &packet(2))?;
+    let ssrcs: Vec<u32> = subscriber
+        .recv_rtp(&mut transport)?
+        .iter()
+        .map(|packet| packet.header.ssrc)
+        .collect();
+    assert_eq!(ssrcs, vec![remapped_ssrc, 1234]);
+
+    Ok(())
+}
+
+/// session version of the o= line of the session description
+fn session_version(description: &RTCSessionDescription) -> Option<u64> {
+    description
+        .sdp
+        .lines()
+        .find_map(|line| line.strip_prefix("o="))
+        .and_then(|origin| origin.split(' ').nth(2))
+        .and_then(|version| version.parse().ok())
+}
+
+#[test]
+fn test_set_remote_description_stale_answer() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut subscriber = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    subscriber.open_data_channel(&mut transport)?;
+    let recv_offers = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_data_channel(transport).map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .collect::<Vec<_>>()
+        })
+    };
+    // answer the offer with a candidate, which tells whether the answer is accepted
+    let answer = |offer: &RTCSessionDescription, candidate: &str| {
+        RTCSessionDescription::answer(format!(
+            "{}a=candidate:{candidate} 1 udp 2130706431 127.0.0.1 50001 typ host\r\n",
+            offer
+                .sdp
+                .replace("a=sendonly", "a=recvonly")
+                .replace("a=setup:actpass", "a=setup:active")
+        ))
+    };
+
+    let video = media_section(
+        "video",
+        9,
+        "1",
+        "sendonly",
+        "a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+    );
+    let audio = media_section(
+        "audio",
+        9,
+        "2",
+        "sendonly",
+        "a=msid:stream audio\r
+a=ssrc:5678 cname:publisher\r
+",
+    );
+
+    let remote_candidates = |transport: &LoopbackTransport| {
+        transport
+            .server_states()
+            .borrow()
+            .get_remote_candidates(1, 2)
+            .unwrap_or_default()
+            .iter()
+            .map(|candidate| candidate.foundation.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // subscriber is offered the video track and answers it
+    renegotiate(&mut transport, 1, &publisher, &["1"], &video)?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let first_offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(first_offers.len(), 1);
+    let first_answer = answer(&first_offers[0], "1")?;
+    subscriber.send_data_channel(
+        &mut transport,
+        serde_json::to_string(&first_answer)?.as_bytes(),
+    )?;
+    assert!(remote_candidates(&transport).contains(&"1".to_string()));
+
+    // and then both tracks by a newer offer
+    renegotiate(
+        &mut transport,
+        1,
+        &publisher,
+        &["1", "2"],
+        &(video + &audio),
+    )?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(2));
+    let second_offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(second_offers.len(), 1);
+
+    // session versions of the offers tell their generations
+    let first_generation = session_version(&first_offers[0]).unwrap();
+    assert_eq!(
+        session_version(&second_offers[0]),
+        Some(first_generation + 1)
+    );
+
+    // a late answer to the superseded offer is rejected, while the one to the latest is accepted
+    let stale_answer = answer(&first_offers[0], "3")?;
+    subscriber.send_data_channel(
+        &mut transport,
+        serde_json::to_string(&stale_answer)?.as_bytes(),
+    )?;
+    assert!(!remote_candidates(&transport).contains(&"3".to_string()));
+    let latest_answer = answer(&second_offers[0], "2")?;
+    subscriber.send_data_channel(
+        &mut transport,
+        serde_json::to_string(&latest_answer)?.as_bytes(),
+    )?;
+    assert!(remote_candidates(&transport).contains(&"2".to_string()));
+
+    Ok(())
+}
+
+#[test]
+fn test_pending_offer_rollback_on_glare() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut subscriber = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    subscriber.open_data_channel(&mut transport)?;
+    let recv_offers = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_data_channel(transport).map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .filter(|description| description.sdp_type == RTCSdpType::Offer)
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let video = media_section(
+        "video",
+        9,
+        "1",
+        "sendonly",
+        "a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+    );
+    renegotiate(&mut transport, 1, &publisher, &["1"], &video)?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let first_offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(first_offers.len(), 1);
+
+    // subscriber's own offer crosses the SFU's pending one, which is rolled back instead of
+    // rejecting the subscriber's offer
+    let answer = renegotiate(&mut transport, 2, &subscriber, &[], "")?;
+    assert_eq!(answer.sdp_type, RTCSdpType::Answer);
+
+    // and the rolled back changes are offered again
+    transport.handle_timeout(Instant::now() + Duration::from_secs(2));
+    let second_offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(second_offers.len(), 1);
+    assert!(second_offers[0].sdp.contains("m=video"));
+    assert!(session_version(&second_offers[0]) > session_version(&first_offers[0]));
+
+    Ok(())
+}
+
+#[test]
+fn test_pending_offer_rollback_on_timeout() -> anyhow::Result<()> {
+    let sfu_addr: SocketAddr = "127.0.0.1:3478".parse()?;
+    let mut transport = LoopbackTransport::new(new_loopback_server_config()?, sfu_addr)?;
+    let publisher = connect_peer(&mut transport, 1, "127.0.0.1:50000")?;
+    let mut subscriber = connect_peer(&mut transport, 2, "127.0.0.1:50001")?;
+    subscriber.open_data_channel(&mut transport)?;
+    let recv_offers = |transport: &mut LoopbackTransport, peer: &mut LoopbackPeer| {
+        peer.recv_data_channel(transport).map(|messages| {
+            messages
+                .iter()
+                .filter_map(|message| serde_json::from_slice::<RTCSessionDescription>(message).ok())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let video = media_section(
+        "video",
+        9,
+        "1",
+        "sendonly",
+        "a=msid:stream video\r
+a=ssrc:1234 cname:publisher\r
+",
+    );
+    renegotiate(&mut transport, 1, &publisher, &["1"], &video)?;
+    transport.handle_timeout(Instant::now() + Duration::from_secs(1));
+    let first_offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(first_offers.len(), 1);
+
+    // the offer is left unanswered, which holds back renegotiation until it expires
+    transport.handle_timeout(Instant::now() + Duration::from_secs(5));
+    assert!(recv_offers(&mut transport, &mut subscriber)?.is_empty());
+
+    transport.handle_timeout(Instant::now() + Duration::from_secs(12));
+    let second_offers = recv_offers(&mut transport, &mut subscriber)?;
+    assert_eq!(second_offers.len(), 1);
+    assert!(second_offers[0].sdp.contains("m=video"));
+    assert!(session_version(&second_offers[0]) > session_version(&first_offers[0]));
+
+    Ok(())
+}